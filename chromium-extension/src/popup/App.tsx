@@ -1,4 +1,5 @@
 import {useEffect, useState} from 'react'
+import type {GenerateEpubRequest} from '@api-types/GenerateEpubRequest'
 import crxLogo from '../assets/logo.png'
 import './App.css'
 
@@ -84,15 +85,16 @@ export default function App() {
             // Fetch the EPUB file data from backend
             const apiEndpoint = 'https://crx-0-2-6-novn.shuttle.app/generate-epub';
             const cookies = await chrome.cookies.getAll({domain: 'wattpad.com'});
+            const request: GenerateEpubRequest = {
+                storyId: Number(storyId),
+                embedImages,
+                cookies: cookies,
+            };
 
             const response = await fetch(apiEndpoint, {
                 method: 'POST',
                 headers: {'Content-Type': 'application/json'},
-                body: JSON.stringify({
-                    storyId: Number(storyId),
-                    isEmbedImages: embedImages,
-                    cookies: cookies,
-                }),
+                body: JSON.stringify(request),
             });
 
             if (!response.ok) {
@@ -10,6 +10,7 @@ export default defineConfig({
   resolve: {
     alias: {
       '@': `${path.resolve(__dirname, 'src')}`,
+      '@api-types': `${path.resolve(__dirname, '../wp-mini-shuttle-axum-cookies-no-cors/api-types/bindings')}`,
     },
   },
   plugins: [
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * `chapter_done`: the position of a part once it is downloaded.
+ */
+export type ChapterDone = { index: number, total: number, };
@@ -0,0 +1,42 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { GifImages } from "./GifImages";
+import type { Rule } from "./Rule";
+import type { SvgImages } from "./SvgImages";
+
+export type ChapterOptions = { 
+/**
+ * Start a new volume after this many chapters.
+ */
+splitEveryChapters?: number, 
+/**
+ * Start a new volume once a volume has this many words.
+ */
+splitEveryWords?: number, 
+/**
+ * Every part ID of the story, in the order to put them in.
+ */
+partOrder?: Array<number>, 
+/**
+ * Skip parts whose title matches any of these.
+ */
+excludeTitlePatterns?: Array<string>, 
+/**
+ * Join consecutive parts of one chapter into a single chapter.
+ */
+mergeSplitChapters?: boolean, replacements?: Array<Rule>, 
+/**
+ * WASM plugins to run over each chapter, in order.
+ */
+plugins?: Array<string>, 
+/**
+ * Describe images that have no alt text.
+ */
+altText?: boolean, 
+/**
+ * Append a page of reading statistics.
+ */
+statsPage?: boolean, 
+/**
+ * Leave the book without a cover when the story has none.
+ */
+noGeneratedCover?: boolean, svgImages?: SvgImages, gifImages?: GifImages, };
@@ -0,0 +1,23 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ChatId } from "./ChatId";
+import type { Lang } from "./Lang";
+import type { WebDavAuth } from "./WebDavAuth";
+
+export type Delivery = { "type": "dropbox", accessToken: string, 
+/**
+ * Destination folder, e.g. `/Apps/WattDownload`. Defaults to `/WattDownload`.
+ */
+folder?: string, } | { "type": "googleDrive", accessToken: string, 
+/**
+ * Parent folder ID. Defaults to the root of "My Drive".
+ */
+folderId?: string, } | { "type": "webdav", 
+/**
+ * Folder URL the file is PUT into, e.g. a Nextcloud
+ * `https://host/remote.php/dav/files/<user>/Books/`.
+ */
+url: string, auth?: WebDavAuth, } | { "type": "telegram", chatId: ChatId, } | { "type": "email", to: string, 
+/**
+ * The message's language; the request's `Accept-Language` when unset.
+ */
+language?: Lang, };
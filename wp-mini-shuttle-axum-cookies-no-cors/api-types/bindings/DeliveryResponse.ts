@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DeliveryReceipt } from "./DeliveryReceipt";
+
+/**
+ * What `/generate-epub` answers instead of the file when it was delivered.
+ */
+export type DeliveryResponse = { storyId: number, fileName: string, size: number, delivery: DeliveryReceipt, skippedChapters?: Array<string>, };
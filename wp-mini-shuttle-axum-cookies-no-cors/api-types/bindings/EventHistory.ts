@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JobEvent } from "./JobEvent";
+import type { JobState } from "./JobState";
+
+/**
+ * The body of `GET /jobs/{id}/events-history`.
+ */
+export type EventHistory = { id: string, state: JobState, events: Array<JobEvent>, };
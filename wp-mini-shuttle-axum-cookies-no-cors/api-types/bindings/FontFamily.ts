@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Generic families only: the fonts themselves are the reader's.
+ */
+export type FontFamily = "serif" | "sansSerif" | "monospace";
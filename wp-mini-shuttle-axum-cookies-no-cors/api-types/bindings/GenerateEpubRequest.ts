@@ -0,0 +1,83 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Cookie } from "./Cookie";
+import type { Delivery } from "./Delivery";
+import type { Format } from "./Format";
+import type { GifImages } from "./GifImages";
+import type { Lang } from "./Lang";
+import type { LinkOptions } from "./LinkOptions";
+import type { MetadataOverrides } from "./MetadataOverrides";
+import type { Notification } from "./Notification";
+import type { Rule } from "./Rule";
+import type { Style } from "./Style";
+import type { SvgImages } from "./SvgImages";
+
+export type GenerateEpubRequest = { storyId: number, embedImages?: boolean, format?: Format, 
+/**
+ * Send the EPUB while it is being made, where possible.
+ */
+stream?: boolean, 
+/**
+ * Parts fetched at a time; the server's default when unset and never
+ * more than its `MAX_CONCURRENT_CHAPTER_REQUESTS`.
+ */
+concurrentChapterRequests?: number, 
+/**
+ * `embedImages` under its original name, deprecated but still taken
+ * until its sunset; moved there by the server, and taken over it when
+ * both are sent.
+ */
+isEmbedImages?: boolean, cookies?: Array<Cookie>, delivery?: Delivery, notifications?: Array<Notification>, downloadLink?: LinkOptions, 
+/**
+ * Replaces the story's title, author, ... in the book.
+ */
+metadata?: MetadataOverrides, 
+/**
+ * A cover to use instead of the story's: the image in base64, or as a
+ * base64 `data:` URL.
+ */
+coverImage?: string, 
+/**
+ * Theme and typography for the book.
+ */
+style?: Style, 
+/**
+ * The language of the pages the server adds to the book, such as the
+ * statistics page; the request's `Accept-Language` when unset.
+ */
+language?: Lang, 
+/**
+ * Start a new volume after this many chapters.
+ */
+splitEveryChapters?: number, 
+/**
+ * Start a new volume once a volume has this many words.
+ */
+splitEveryWords?: number, 
+/**
+ * Every part ID of the story, in the order to put them in.
+ */
+partOrder?: Array<number>, 
+/**
+ * Skip parts whose title matches any of these.
+ */
+excludeTitlePatterns?: Array<string>, 
+/**
+ * Join consecutive parts of one chapter into a single chapter.
+ */
+mergeSplitChapters?: boolean, replacements?: Array<Rule>, 
+/**
+ * WASM plugins to run over each chapter, in order.
+ */
+plugins?: Array<string>, 
+/**
+ * Describe images that have no alt text.
+ */
+altText?: boolean, 
+/**
+ * Append a page of reading statistics.
+ */
+statsPage?: boolean, 
+/**
+ * Leave the book without a cover when the story has none.
+ */
+noGeneratedCover?: boolean, svgImages?: SvgImages, gifImages?: GifImages, };
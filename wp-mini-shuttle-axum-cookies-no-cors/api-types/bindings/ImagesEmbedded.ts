@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * `images_embedded`, once the book has its images.
+ */
+export type ImagesEmbedded = Record<symbol, never>;
@@ -0,0 +1,16 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JobError } from "./JobError";
+import type { Outcome } from "./Outcome";
+
+/**
+ * How one story of a batch went.
+ */
+export type ItemStatus = { storyId: number, outcome: Outcome, title?: string, 
+/**
+ * The book's name in the ZIP.
+ */
+file?: string, 
+/**
+ * Titles of parts left out by `excludeTitlePatterns`.
+ */
+skippedChapters?: Array<string>, error?: JobError, };
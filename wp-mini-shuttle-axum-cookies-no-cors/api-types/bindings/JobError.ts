@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Why a job, a story of a batch or a streamed generation failed.
+ */
+export type JobError = { status: number, 
+/**
+ * A stable identifier of the error; empty for jobs that failed before
+ * there were any.
+ */
+code: string, 
+/**
+ * Whether the same request may well work later.
+ */
+retryable: boolean, error: string, };
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type JobEvent = { 
+/**
+ * Unix seconds.
+ */
+at: number, 
+/**
+ * `queued`, `claimed`, `running`, `stage`, `item` (a story of a batch
+ * is done), `retried`, `paused`, `resumed`, `completed` or `failed`.
+ */
+event: string, detail?: string, };
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DeliveryReceipt } from "./DeliveryReceipt";
+
+export type JobResult = { fileName: string, size: number, 
+/**
+ * Where to fetch the EPUB, unless it was delivered elsewhere.
+ */
+downloadUrl: string | null, delivery: DeliveryReceipt | null, 
+/**
+ * Titles of parts left out by `excludeTitlePatterns`.
+ */
+skippedChapters?: Array<string>, };
@@ -0,0 +1,31 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ItemStatus } from "./ItemStatus";
+import type { JobError } from "./JobError";
+import type { JobResult } from "./JobResult";
+import type { JobState } from "./JobState";
+
+export type JobStatus = { id: string, 
+/**
+ * Unset for batch jobs, whose stories are in `items`.
+ */
+storyId?: number, state: JobState, 
+/**
+ * What a running job is busy with, e.g. `downloading` or `delivering`.
+ */
+stage: string | null, 
+/**
+ * Set once the job ran over its budget and was moved to the back.
+ */
+deprioritized?: boolean, 
+/**
+ * Bumped on every change; pass it back as `since` to wait for the next one.
+ */
+version: number, 
+/**
+ * Unix seconds.
+ */
+createdAt: number, updatedAt: number, result: JobResult | null, error: JobError | null, 
+/**
+ * How each story of a batch job went.
+ */
+items?: Array<ItemStatus>, };
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How long a download link works and how often it may be used; requests pass
+ * this as `downloadLink`.
+ */
+export type LinkOptions = { 
+/**
+ * Capped at the server's `ARTIFACT_TTL_HOURS`, which is also the default.
+ */
+expiresInHours?: number, maxDownloads?: number, };
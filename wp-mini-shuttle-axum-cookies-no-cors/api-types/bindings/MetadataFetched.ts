@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * `metadata_fetched`: the story's title and its number of parts, as far as
+ * Wattpad tells.
+ */
+export type MetadataFetched = { title: string | null, total: number | null, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What the request asks to have in the book instead of the story's own
+ * metadata.
+ */
+export type MetadataOverrides = { title?: string, author?: string, series?: string, 
+/**
+ * The book's place in `series`; decimals such as 1.5 are allowed.
+ */
+seriesIndex?: number, 
+/**
+ * A BCP 47 language tag, such as `en` or `pt-BR`.
+ */
+language?: string, description?: string, };
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Notification = { "type": "discord", webhookUrl: string, } | { "type": "ntfy", topic: string, } | { "type": "pushover", userKey: string, device?: string, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A new chapter waiting for the next digest.
+ */
+export type PendingChapter = { partId: number, storyTitle: string, title: string, };
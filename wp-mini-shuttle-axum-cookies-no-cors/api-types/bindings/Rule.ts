@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A find/replace rule for chapter text.
+ */
+export type Rule = { find: string, replace: string, 
+/**
+ * Treat `find` as a regex; `$1` and `${name}` in `replace` refer to its groups.
+ */
+regex?: boolean, 
+/**
+ * Only match `find` as a whole word.
+ */
+wholeWord?: boolean, caseInsensitive?: boolean, };
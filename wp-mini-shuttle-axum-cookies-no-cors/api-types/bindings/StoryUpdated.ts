@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * The body of `POST /hooks/story-updated`: a story that has changed.
+ */
+export type StoryUpdated = { storyId: number, };
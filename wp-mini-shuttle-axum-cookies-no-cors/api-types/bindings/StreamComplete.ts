@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * `complete`: a download link to the book.
+ */
+export type StreamComplete = { fileName: string, size: number, downloadUrl: string, skippedChapters: Array<string>, };
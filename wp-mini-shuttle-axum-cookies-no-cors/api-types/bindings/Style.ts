@@ -0,0 +1,17 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FontFamily } from "./FontFamily";
+import type { Theme } from "./Theme";
+
+export type Style = { theme?: Theme, fontFamily?: FontFamily, 
+/**
+ * Percent of the reader's default size.
+ */
+fontSize?: number, 
+/**
+ * A multiple of the font size.
+ */
+lineHeight?: number, 
+/**
+ * Justify paragraphs rather than aligning them left.
+ */
+justify?: boolean, };
@@ -0,0 +1,37 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Delivery } from "./Delivery";
+import type { Format } from "./Format";
+import type { PendingChapter } from "./PendingChapter";
+import type { SubscriptionMode } from "./SubscriptionMode";
+
+export type Subscription = { id: string, storyId: number, delivery: Delivery, embedImages: boolean, format: Format, mode: SubscriptionMode, schedule: string | null, timeZone: string | null, 
+/**
+ * Unix seconds.
+ */
+createdAt: number, updatedAt: number, 
+/**
+ * When the story was last looked at; unset until it first is.
+ */
+checkedAt: number | null, 
+/**
+ * Parts of the story when it was last looked at; only parts after them
+ * are new.
+ */
+partsSeen: number | null, 
+/**
+ * New chapters not yet in a digest.
+ */
+pending?: Array<PendingChapter>, 
+/**
+ * When the last digest with this subscription's chapters went out.
+ */
+digestedAt: number | null, 
+/**
+ * Set while the subscription is paused.
+ */
+pausedAt: number | null, 
+/**
+ * Set once the subscription is deleted; it can be restored until the
+ * server's grace period is over.
+ */
+deletedAt: number | null, };
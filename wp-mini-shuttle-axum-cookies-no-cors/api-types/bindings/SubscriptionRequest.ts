@@ -0,0 +1,23 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Delivery } from "./Delivery";
+import type { Format } from "./Format";
+import type { SubscriptionMode } from "./SubscriptionMode";
+
+/**
+ * The body of `POST /subscriptions` and `PUT /subscriptions/{id}`.
+ */
+export type SubscriptionRequest = { storyId: number, 
+/**
+ * Where the book goes whenever the story has new chapters.
+ */
+delivery: Delivery, embedImages?: boolean, format?: Format, mode?: SubscriptionMode, 
+/**
+ * When the story is looked at, or for digests when the digest is sent:
+ * a cron expression of five fields, e.g. `0 8 * * 1`.
+ */
+schedule?: string, 
+/**
+ * The IANA time zone `schedule` is read in, e.g. `Europe/Lisbon`; UTC
+ * when unset.
+ */
+timeZone?: string, };
@@ -0,0 +1,143 @@
+//! Where a generated file goes besides the response, and who hears about it.
+//!
+//! Credentials in these payloads are bearer secrets, so none of the types
+//! below implement `Debug`.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use ts_rs::TS;
+
+use crate::Lang;
+
+#[derive(Serialize, Deserialize, TS)]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[ts(export)]
+pub enum Delivery {
+    #[serde(rename_all = "camelCase")]
+    Dropbox {
+        access_token: String,
+        /// Destination folder, e.g. `/Apps/WattDownload`. Defaults to `/WattDownload`.
+        #[ts(optional)]
+        folder: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    GoogleDrive {
+        access_token: String,
+        /// Parent folder ID. Defaults to the root of "My Drive".
+        #[ts(optional)]
+        folder_id: Option<String>,
+    },
+    #[serde(rename = "webdav")]
+    WebDav {
+        /// Folder URL the file is PUT into, e.g. a Nextcloud
+        /// `https://host/remote.php/dav/files/<user>/Books/`.
+        url: String,
+        #[ts(optional)]
+        auth: Option<WebDavAuth>,
+    },
+    /// Sends the file to a chat through the deployment's own bot. The user
+    /// must have started the bot first.
+    #[serde(rename_all = "camelCase")]
+    Telegram { chat_id: ChatId },
+    /// Mails the file through the deployment's SMTP relay, falling back to a
+    /// download link when it is too large to attach.
+    Email {
+        to: String,
+        /// The message's language; the request's `Accept-Language` when unset.
+        #[ts(optional)]
+        language: Option<Lang>,
+    },
+}
+
+impl Delivery {
+    pub fn target(&self) -> &'static str {
+        match self {
+            Delivery::Dropbox { .. } => "dropbox",
+            Delivery::GoogleDrive { .. } => "googleDrive",
+            Delivery::WebDav { .. } => "webdav",
+            Delivery::Telegram { .. } => "telegram",
+            Delivery::Email { .. } => "email",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[serde(untagged)]
+#[ts(export)]
+pub enum WebDavAuth {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, TS)]
+#[serde(untagged)]
+#[ts(export)]
+pub enum ChatId {
+    Id(#[ts(type = "number")] i64),
+    /// `@channelusername`
+    Username(String),
+}
+
+impl fmt::Display for ChatId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatId::Id(id) => write!(f, "{}", id),
+            ChatId::Username(name) => f.write_str(name),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct DeliveryReceipt {
+    #[ts(type = "string")]
+    pub target: Cow<'static, str>,
+    pub remote_id: String,
+    pub remote_path: Option<String>,
+    pub web_url: Option<String>,
+}
+
+/// What `/generate-epub` answers instead of the file when it was delivered.
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct DeliveryResponse {
+    #[ts(type = "number")]
+    pub story_id: u64,
+    pub file_name: String,
+    pub size: usize,
+    pub delivery: DeliveryReceipt,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[ts(optional = nullable)]
+    pub skipped_chapters: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[ts(export)]
+pub enum Notification {
+    #[serde(rename_all = "camelCase")]
+    Discord { webhook_url: String },
+    /// A topic on ntfy.sh.
+    Ntfy { topic: String },
+    /// Needs the server's `PUSHOVER_APP_TOKEN`; `userKey` is the recipient's
+    /// user or group key.
+    #[serde(rename_all = "camelCase")]
+    Pushover {
+        user_key: String,
+        #[ts(optional)]
+        device: Option<String>,
+    },
+}
+
+impl Notification {
+    pub fn target(&self) -> &'static str {
+        match self {
+            Notification::Discord { .. } => "discord",
+            Notification::Ntfy { .. } => "ntfy",
+            Notification::Pushover { .. } => "pushover",
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// The body of every error response.
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ErrorBody {
+    pub error: String,
+}
@@ -0,0 +1,159 @@
+//! What `/jobs/{id}` and its `events-history` report on a background job,
+//! and how each story of a batch went.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use ts_rs::TS;
+
+use crate::DeliveryReceipt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Completed | JobState::Failed)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct JobStatus {
+    pub id: String,
+    /// Unset for batch jobs, whose stories are in `items`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional, type = "number")]
+    pub story_id: Option<u64>,
+    pub state: JobState,
+    /// What a running job is busy with, e.g. `downloading` or `delivering`.
+    #[ts(type = "string | null")]
+    pub stage: Option<Cow<'static, str>>,
+    /// Set once the job ran over its budget and was moved to the back.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[ts(optional = nullable)]
+    pub deprioritized: bool,
+    /// Bumped on every change; pass it back as `since` to wait for the next one.
+    #[ts(type = "number")]
+    pub version: u64,
+    /// Unix seconds.
+    #[ts(type = "number")]
+    pub created_at: u64,
+    #[ts(type = "number")]
+    pub updated_at: u64,
+    pub result: Option<JobResult>,
+    pub error: Option<JobError>,
+    /// How each story of a batch job went.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[ts(optional = nullable)]
+    pub items: Vec<ItemStatus>,
+}
+
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct JobEvent {
+    /// Unix seconds.
+    #[ts(type = "number")]
+    pub at: u64,
+    /// `queued`, `claimed`, `running`, `stage`, `item` (a story of a batch
+    /// is done), `retried`, `paused`, `resumed`, `completed` or `failed`.
+    #[ts(type = "string")]
+    pub event: Cow<'static, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub detail: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct JobResult {
+    pub file_name: String,
+    pub size: usize,
+    /// Where to fetch the EPUB, unless it was delivered elsewhere.
+    pub download_url: Option<String>,
+    pub delivery: Option<DeliveryReceipt>,
+    /// Titles of parts left out by `excludeTitlePatterns`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[ts(optional = nullable)]
+    pub skipped_chapters: Vec<String>,
+}
+
+/// Why a job, a story of a batch or a streamed generation failed.
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct JobError {
+    pub status: u16,
+    /// A stable identifier of the error; empty for jobs that failed before
+    /// there were any.
+    #[serde(default)]
+    pub code: String,
+    /// Whether the same request may well work later.
+    #[serde(default)]
+    pub retryable: bool,
+    pub error: String,
+}
+
+/// The body of `GET /jobs/{id}/events-history`.
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct EventHistory {
+    pub id: String,
+    pub state: JobState,
+    pub events: Vec<JobEvent>,
+}
+
+/// How one story of a batch went.
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct ItemStatus {
+    #[ts(type = "number")]
+    pub story_id: u64,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub title: Option<String>,
+    /// The book's name in the ZIP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub file: Option<String>,
+    /// Titles of parts left out by `excludeTitlePatterns`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[ts(optional = nullable)]
+    pub skipped_chapters: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub error: Option<JobError>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum Outcome {
+    Pending,
+    Success,
+    Partial,
+    Failed,
+}
+
+impl ItemStatus {
+    pub fn pending(story_id: u64) -> Self {
+        ItemStatus {
+            story_id,
+            outcome: Outcome::Pending,
+            title: None,
+            file: None,
+            skipped_chapters: Vec::new(),
+            error: None,
+        }
+    }
+}
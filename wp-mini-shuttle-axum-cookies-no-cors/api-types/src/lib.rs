@@ -0,0 +1,27 @@
+//! The request and response bodies of the wp-mini-axum API, shared by the
+//! server and its clients so both read and write the same JSON.
+//!
+//! Every type derives `ts_rs::TS`; `cargo test` writes their TypeScript
+//! definitions to `bindings/`, which the extension imports. The server's
+//! behaviour for them (validation, delivery, rendering) stays in the server.
+
+mod delivery;
+mod error;
+mod jobs;
+mod request;
+mod stream;
+mod subscriptions;
+
+pub use delivery::{ChatId, Delivery, DeliveryReceipt, DeliveryResponse, Notification, WebDavAuth};
+pub use error::ErrorBody;
+pub use jobs::{
+    EventHistory, ItemStatus, JobError, JobEvent, JobResult, JobState, JobStatus, Outcome,
+};
+pub use request::{
+    ChapterOptions, Cookie, FontFamily, Format, GenerateEpubRequest, GifImages, Lang, LinkOptions,
+    MetadataOverrides, Rule, Style, SvgImages, Theme,
+};
+pub use stream::{ChapterDone, ImagesEmbedded, MetadataFetched, StreamComplete, StreamQuery};
+pub use subscriptions::{
+    PendingChapter, StoryUpdated, Subscription, SubscriptionMode, SubscriptionRequest,
+};
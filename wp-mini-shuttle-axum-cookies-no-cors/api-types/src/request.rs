@@ -0,0 +1,349 @@
+//! The body of `POST /generate-epub`, which the async, batch and streamed
+//! generations take as well.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct GenerateEpubRequest {
+    #[ts(type = "number")]
+    pub story_id: u64,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub embed_images: bool,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub format: Format,
+    /// Send the EPUB while it is being made, where possible.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub stream: bool,
+    /// Parts fetched at a time; the server's default when unset and never
+    /// more than its `MAX_CONCURRENT_CHAPTER_REQUESTS`.
+    #[ts(optional)]
+    pub concurrent_chapter_requests: Option<usize>,
+    /// `embedImages` under its original name, deprecated but still taken
+    /// until its sunset; moved there by the server, and taken over it when
+    /// both are sent.
+    #[serde(default, skip_serializing)]
+    #[ts(optional)]
+    pub is_embed_images: Option<bool>,
+    #[ts(optional)]
+    pub cookies: Option<Vec<Cookie>>,
+    #[ts(optional)]
+    pub delivery: Option<crate::Delivery>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub notifications: Vec<crate::Notification>,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub download_link: LinkOptions,
+    /// Replaces the story's title, author, ... in the book.
+    #[ts(optional)]
+    pub metadata: Option<MetadataOverrides>,
+    /// A cover to use instead of the story's: the image in base64, or as a
+    /// base64 `data:` URL.
+    #[ts(optional)]
+    pub cover_image: Option<String>,
+    /// Theme and typography for the book.
+    #[ts(optional)]
+    pub style: Option<Style>,
+    /// The language of the pages the server adds to the book, such as the
+    /// statistics page; the request's `Accept-Language` when unset.
+    #[ts(optional)]
+    pub language: Option<Lang>,
+    #[serde(flatten)]
+    pub chapters: ChapterOptions,
+}
+
+impl GenerateEpubRequest {
+    /// Whether the book is made to this request's own `metadata`,
+    /// `coverImage` or `style`, so no other request's book will do for it.
+    pub fn personalized(&self) -> bool {
+        self.metadata.is_some() || self.cover_image.is_some() || self.style.is_some()
+    }
+}
+
+/// What the book is delivered as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum Format {
+    #[default]
+    Epub,
+    Pdf,
+    Txt,
+    Md,
+    /// A web archive of the story's pages rather than a book.
+    Warc,
+    /// Converted from the EPUB by the server's `ebook-convert`.
+    Azw3,
+}
+
+impl Format {
+    pub const ALL: [Format; 6] = [
+        Format::Epub,
+        Format::Pdf,
+        Format::Txt,
+        Format::Md,
+        Format::Warc,
+        Format::Azw3,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Epub => "epub",
+            Format::Pdf => "pdf",
+            Format::Txt => "txt",
+            Format::Md => "md",
+            Format::Warc => "warc",
+            Format::Azw3 => "azw3",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Epub => "application/epub+zip",
+            Format::Pdf => "application/pdf",
+            Format::Txt => "text/plain; charset=utf-8",
+            Format::Md => "text/markdown; charset=utf-8",
+            Format::Warc => "application/warc",
+            Format::Azw3 => "application/vnd.amazon.mobi8-ebook",
+        }
+    }
+}
+
+/// How long a download link works and how often it may be used; requests pass
+/// this as `downloadLink`.
+#[derive(Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct LinkOptions {
+    /// Capped at the server's `ARTIFACT_TTL_HOURS`, which is also the default.
+    #[ts(optional, type = "number")]
+    pub expires_in_hours: Option<u64>,
+    #[ts(optional)]
+    pub max_downloads: Option<u32>,
+}
+
+/// What the request asks to have in the book instead of the story's own
+/// metadata.
+#[derive(Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct MetadataOverrides {
+    #[ts(optional)]
+    pub title: Option<String>,
+    #[ts(optional)]
+    pub author: Option<String>,
+    #[ts(optional)]
+    pub series: Option<String>,
+    /// The book's place in `series`; decimals such as 1.5 are allowed.
+    #[ts(optional)]
+    pub series_index: Option<f64>,
+    /// A BCP 47 language tag, such as `en` or `pt-BR`.
+    #[ts(optional)]
+    pub language: Option<String>,
+    #[ts(optional)]
+    pub description: Option<String>,
+}
+
+impl MetadataOverrides {
+    /// `title`, for the file name and notifications.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref().map(str::trim)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum Theme {
+    Light,
+    Dark,
+    Sepia,
+}
+
+/// Generic families only: the fonts themselves are the reader's.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum FontFamily {
+    Serif,
+    SansSerif,
+    Monospace,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct Style {
+    #[ts(optional)]
+    pub theme: Option<Theme>,
+    #[ts(optional)]
+    pub font_family: Option<FontFamily>,
+    /// Percent of the reader's default size.
+    #[ts(optional)]
+    pub font_size: Option<u16>,
+    /// A multiple of the font size.
+    #[ts(optional)]
+    pub line_height: Option<f32>,
+    /// Justify paragraphs rather than aligning them left.
+    #[ts(optional)]
+    pub justify: Option<bool>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct ChapterOptions {
+    /// Start a new volume after this many chapters.
+    #[ts(optional)]
+    pub split_every_chapters: Option<usize>,
+    /// Start a new volume once a volume has this many words.
+    #[ts(optional)]
+    pub split_every_words: Option<usize>,
+    /// Every part ID of the story, in the order to put them in.
+    #[ts(optional, type = "Array<number>")]
+    pub part_order: Option<Vec<u64>>,
+    /// Skip parts whose title matches any of these.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub exclude_title_patterns: Vec<String>,
+    /// Join consecutive parts of one chapter into a single chapter.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub merge_split_chapters: bool,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub replacements: Vec<Rule>,
+    /// WASM plugins to run over each chapter, in order.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub plugins: Vec<String>,
+    /// Describe images that have no alt text.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub alt_text: bool,
+    /// Append a page of reading statistics.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub stats_page: bool,
+    /// Leave the book without a cover when the story has none.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub no_generated_cover: bool,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub svg_images: SvgImages,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub gif_images: GifImages,
+}
+
+impl ChapterOptions {
+    /// Whether every option is at its default, so the book is the same for
+    /// every such request of the story.
+    pub fn is_default(&self) -> bool {
+        self.keeps_chapters()
+            && !self.no_generated_cover
+            && self.svg_images == SvgImages::default()
+            && self.gif_images == GifImages::default()
+    }
+
+    /// Whether the chapters come out as `wp_mini_epub` built them.
+    pub fn keeps_chapters(&self) -> bool {
+        self.split_every_chapters.is_none()
+            && self.split_every_words.is_none()
+            && self.part_order.is_none()
+            && self.exclude_title_patterns.is_empty()
+            && !self.merge_split_chapters
+            && self.replacements.is_empty()
+            && self.plugins.is_empty()
+            && !self.alt_text
+            && !self.stats_page
+    }
+}
+
+/// A find/replace rule for chapter text.
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct Rule {
+    pub find: String,
+    pub replace: String,
+    /// Treat `find` as a regex; `$1` and `${name}` in `replace` refer to its groups.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub regex: bool,
+    /// Only match `find` as a whole word.
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub whole_word: bool,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub case_insensitive: bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum SvgImages {
+    /// Embed as is.
+    Keep,
+    #[default]
+    Sanitize,
+    Rasterize,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum GifImages {
+    #[default]
+    Keep,
+    FirstFrame,
+}
+
+/// A language the server has messages in.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, TS)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum Lang {
+    #[default]
+    En,
+    Es,
+    Pt,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 3] = [Lang::En, Lang::Es, Lang::Pt];
+
+    /// The language's BCP 47 tag.
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Es => "es",
+            Lang::Pt => "pt",
+        }
+    }
+
+    /// The language a tag such as `pt-BR` names, by its primary subtag.
+    pub fn parse(tag: &str) -> Option<Lang> {
+        let primary = tag.split(['-', '_']).next()?.trim();
+        Lang::ALL
+            .into_iter()
+            .find(|lang| primary.eq_ignore_ascii_case(lang.code()))
+    }
+}
@@ -0,0 +1,50 @@
+//! `GET /generate-epub/stream`: its query, and the data of the Server-Sent
+//! Events it sends. An `error` event carries a `JobError`.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct StreamQuery {
+    #[ts(type = "number")]
+    pub story_id: u64,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub embed_images: bool,
+}
+
+/// `metadata_fetched`: the story's title and its number of parts, as far as
+/// Wattpad tells.
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MetadataFetched {
+    pub title: Option<String>,
+    #[ts(type = "number | null")]
+    pub total: Option<i64>,
+}
+
+/// `chapter_done`: the position of a part once it is downloaded.
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ChapterDone {
+    pub index: usize,
+    pub total: usize,
+}
+
+/// `images_embedded`, once the book has its images.
+#[derive(Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ImagesEmbedded {}
+
+/// `complete`: a download link to the book.
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct StreamComplete {
+    pub file_name: String,
+    pub size: usize,
+    pub download_url: String,
+    pub skipped_chapters: Vec<String>,
+}
@@ -0,0 +1,125 @@
+//! What `/subscriptions` takes and answers: stories followed for their new
+//! chapters, delivered as they come out or gathered into a weekly digest, on
+//! a cron schedule in the reader's time zone, and what
+//! `/hooks/story-updated` takes to look at a story's subscriptions now.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{Delivery, Format};
+
+/// The body of `POST /subscriptions` and `PUT /subscriptions/{id}`.
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct SubscriptionRequest {
+    #[ts(type = "number")]
+    pub story_id: u64,
+    /// Where the book goes whenever the story has new chapters.
+    pub delivery: Delivery,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub embed_images: bool,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub format: Format,
+    #[serde(default)]
+    #[ts(optional = nullable)]
+    pub mode: SubscriptionMode,
+    /// When the story is looked at, or for digests when the digest is sent:
+    /// a cron expression of five fields, e.g. `0 8 * * 1`.
+    #[ts(optional)]
+    pub schedule: Option<String>,
+    /// The IANA time zone `schedule` is read in, e.g. `Europe/Lisbon`; UTC
+    /// when unset.
+    #[ts(optional)]
+    pub time_zone: Option<String>,
+}
+
+/// How a subscription's new chapters are delivered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum SubscriptionMode {
+    /// The whole book, as soon as the story has new chapters.
+    #[default]
+    Immediate,
+    /// Once a week, or on the subscription's `schedule`, only the new
+    /// chapters of every story the key follows this way, together in one
+    /// book.
+    WeeklyDigest,
+}
+
+/// A new chapter waiting for the next digest.
+#[derive(Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct PendingChapter {
+    #[ts(type = "number")]
+    pub part_id: u64,
+    pub story_title: String,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct Subscription {
+    pub id: String,
+    #[ts(type = "number")]
+    pub story_id: u64,
+    pub delivery: Delivery,
+    pub embed_images: bool,
+    pub format: Format,
+    #[serde(default)]
+    pub mode: SubscriptionMode,
+    #[serde(default)]
+    #[ts(type = "string | null")]
+    pub schedule: Option<String>,
+    #[serde(default)]
+    #[ts(type = "string | null")]
+    pub time_zone: Option<String>,
+    /// Unix seconds.
+    #[ts(type = "number")]
+    pub created_at: u64,
+    #[ts(type = "number")]
+    pub updated_at: u64,
+    /// When the story was last looked at; unset until it first is.
+    #[ts(type = "number | null")]
+    pub checked_at: Option<u64>,
+    /// Parts of the story when it was last looked at; only parts after them
+    /// are new.
+    #[ts(type = "number | null")]
+    pub parts_seen: Option<usize>,
+    /// New chapters not yet in a digest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[ts(optional = nullable)]
+    pub pending: Vec<PendingChapter>,
+    /// When the last digest with this subscription's chapters went out.
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub digested_at: Option<u64>,
+    /// Set while the subscription is paused.
+    #[ts(type = "number | null")]
+    pub paused_at: Option<u64>,
+    /// Set once the subscription is deleted; it can be restored until the
+    /// server's grace period is over.
+    #[ts(type = "number | null")]
+    pub deleted_at: Option<u64>,
+}
+
+impl Subscription {
+    /// Whether the story is being followed: neither paused nor deleted.
+    pub fn active(&self) -> bool {
+        self.paused_at.is_none() && self.deleted_at.is_none()
+    }
+}
+
+/// The body of `POST /hooks/story-updated`: a story that has changed.
+#[derive(Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct StoryUpdated {
+    #[ts(type = "number")]
+    pub story_id: u64,
+}
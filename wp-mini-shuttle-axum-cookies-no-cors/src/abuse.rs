@@ -0,0 +1,221 @@
+//! Detection of clients walking through story IDs one after another.
+//!
+//! Real users download stories whose IDs are scattered over hundreds of
+//! millions; an enumerator asks for a tight run of neighbouring IDs. Once most
+//! of a client's recent IDs sit within a few of each other, the client is
+//! throttled for a while and shows up in `/admin/stats`. The block is recorded
+//! in `crate::shared`, so every instance enforces it.
+
+use axum::body::Bytes;
+use axum::http::HeaderMap;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::shared::Shared;
+use crate::unix_now;
+
+/// Requests per client that are looked at.
+const SAMPLE: usize = 20;
+/// Requests older than this are forgotten.
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+/// IDs at most this far apart count as neighbours.
+const MAX_STEP: u64 = 5;
+/// Share of neighbouring IDs (after sorting) that marks a client as enumerating.
+const SEQUENTIAL_RATIO: f64 = 0.75;
+const BLOCK: Duration = Duration::from_secs(60 * 60);
+/// Idle clients are only swept once this many are tracked.
+const SWEEP_AT: usize = 1024;
+/// Most clients tracked at once; past it the longest quiet ones that are not
+/// blocked are forgotten, down to three quarters of it.
+const MAX_CLIENTS: usize = 16 * 1024;
+
+struct Client {
+    recent: VecDeque<(Instant, u64)>,
+    requests: u64,
+    blocked_until: Option<Instant>,
+    flagged_at: Option<u64>,
+    throttled: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlaggedClient {
+    client: String,
+    /// Unix seconds.
+    flagged_at: u64,
+    blocked_for_secs: u64,
+    requests: u64,
+    throttled: u64,
+}
+
+#[derive(Default)]
+pub struct ScrapeDetector {
+    clients: Mutex<HashMap<String, Client>>,
+}
+
+/// Identifies the caller by the last `X-Forwarded-For` hop, the address
+/// Shuttle's proxy saw and appended. Hops before it are whatever the client
+/// sent, so keying on them would let a client be someone new on every
+/// request.
+pub fn client_key(headers: &HeaderMap) -> String {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .next_back()
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn block_key(client: &str) -> String {
+    format!("scrape-block:{}", client)
+}
+
+impl ScrapeDetector {
+    /// Records a request for `story_id`; returns how long to back off if the
+    /// client is, or has just been found, enumerating (here or elsewhere).
+    pub async fn check(
+        &self,
+        shared: &dyn Shared,
+        client: &str,
+        story_id: u64,
+    ) -> Result<(), Duration> {
+        let key = block_key(client);
+        // Holds the Unix second the block ends.
+        if let Ok(Some(until)) = shared.get(&key).await
+            && let Some(until) = std::str::from_utf8(&until)
+                .ok()
+                .and_then(|until| until.parse::<u64>().ok())
+            && until > unix_now()
+        {
+            return Err(Duration::from_secs(until - unix_now()));
+        }
+
+        let result = self.check_local(client, story_id);
+        if result == Err(BLOCK) {
+            let until = Bytes::from((unix_now() + BLOCK.as_secs()).to_string());
+            if let Err(e) = shared.set(&key, until, BLOCK).await {
+                warn!(error = %e, "Could not share scraping block");
+            }
+        }
+        result
+    }
+
+    fn check_local(&self, client: &str, story_id: u64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= SWEEP_AT {
+            clients.retain(|_, state| {
+                state.blocked_until.is_some_and(|until| until > now)
+                    || state
+                        .recent
+                        .back()
+                        .is_some_and(|(at, _)| now.duration_since(*at) < WINDOW)
+            });
+        }
+        if clients.len() >= MAX_CLIENTS {
+            forget_quietest(&mut clients, now);
+        }
+
+        let state = clients.entry(client.to_string()).or_insert_with(|| Client {
+            recent: VecDeque::with_capacity(SAMPLE),
+            requests: 0,
+            blocked_until: None,
+            flagged_at: None,
+            throttled: 0,
+        });
+        state.requests += 1;
+
+        if let Some(until) = state.blocked_until
+            && until > now
+        {
+            state.throttled += 1;
+            return Err(until - now);
+        }
+
+        while state
+            .recent
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= WINDOW)
+        {
+            state.recent.pop_front();
+        }
+        if state.recent.len() == SAMPLE {
+            state.recent.pop_front();
+        }
+        state.recent.push_back((now, story_id));
+
+        if looks_sequential(&state.recent) {
+            warn!(
+                client,
+                "Client appears to be enumerating story IDs; throttling"
+            );
+            state.blocked_until = Some(now + BLOCK);
+            state.flagged_at = Some(unix_now());
+            state.recent.clear();
+            state.throttled += 1;
+            return Err(BLOCK);
+        }
+        Ok(())
+    }
+
+    pub fn flagged(&self) -> Vec<FlaggedClient> {
+        let now = Instant::now();
+        let clients = self.clients.lock().unwrap();
+        clients
+            .iter()
+            .filter_map(|(client, state)| {
+                Some(FlaggedClient {
+                    client: client.clone(),
+                    flagged_at: state.flagged_at?,
+                    blocked_for_secs: state
+                        .blocked_until
+                        .map_or(0, |until| until.saturating_duration_since(now).as_secs()),
+                    requests: state.requests,
+                    throttled: state.throttled,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Forgets the clients heard from longest ago, blocked ones last, until a
+/// quarter of `MAX_CLIENTS` is free.
+fn forget_quietest(clients: &mut HashMap<String, Client>, now: Instant) {
+    let mut by_age: Vec<(bool, Option<Instant>, String)> = clients
+        .iter()
+        .map(|(key, state)| {
+            let blocked = state.blocked_until.is_some_and(|until| until > now);
+            let last = state.recent.back().map(|(at, _)| *at);
+            (blocked, last, key.clone())
+        })
+        .collect();
+    by_age.sort_unstable();
+    let excess = clients.len() - MAX_CLIENTS * 3 / 4;
+    for (_, _, key) in by_age.into_iter().take(excess) {
+        clients.remove(&key);
+    }
+}
+
+fn looks_sequential(recent: &VecDeque<(Instant, u64)>) -> bool {
+    if recent.len() < SAMPLE {
+        return false;
+    }
+    let mut ids: Vec<u64> = recent.iter().map(|(_, id)| *id).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.len() < SAMPLE / 2 {
+        // Mostly repeats of the same few stories: retries, not enumeration.
+        return false;
+    }
+    let neighbours = ids
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] <= MAX_STEP)
+        .count();
+    neighbours as f64 >= (ids.len() - 1) as f64 * SEQUENTIAL_RATIO
+}
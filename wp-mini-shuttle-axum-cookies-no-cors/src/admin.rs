@@ -0,0 +1,226 @@
+//! Operator-only endpoints. They do not exist at all (404) while
+//! `ADMIN_SECRET` is unset.
+//!
+//! The secret itself is only good for minting tokens: `POST /admin/token` with
+//! `Authorization: Bearer <ADMIN_SECRET>` returns a token that names its holder
+//! and expires after at most a day. Every other admin route takes such a token,
+//! and each use is written to an audit log (`GET /admin/audit`). While the
+//! secret is rotated, tokens minted with `ADMIN_SECRET_PREVIOUS` are still
+//! taken until they expire.
+//!
+//! `POST /admin/reload-secrets` replaces API keys, signing keys and delivery
+//! credentials (`crate::config::RELOADABLE`) without a redeploy: its body maps
+//! secret names to new values, or to `null` to unset one, checked as they
+//! would be at startup. The values are kept in this instance's memory only;
+//! each instance needs reloading, and the Shuttle secrets updated before the
+//! next deploy.
+
+use axum::extract::{FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap};
+use axum::Json;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use tracing::info;
+
+use crate::config::Keys;
+use crate::error::MyError;
+use crate::{decode_hex, unix_now, AppState};
+
+const TOKEN_PREFIX: &str = "adm1";
+const DEFAULT_TTL_MINUTES: u64 = 60;
+const MAX_TTL_MINUTES: u64 = 24 * 60;
+const AUDIT_CAPACITY: usize = 500;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Unix seconds.
+    at: u64,
+    subject: String,
+    action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Default)]
+pub struct AuditLog(Mutex<VecDeque<AuditEntry>>);
+
+impl AuditLog {
+    pub fn record(&self, subject: &str, action: &'static str, detail: Option<String>) {
+        info!(target: "audit", subject, action, detail = detail.as_deref(), "Admin action");
+        let mut entries = self.0.lock().unwrap();
+        if entries.len() == AUDIT_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(AuditEntry {
+            at: unix_now(),
+            subject: subject.to_string(),
+            action,
+            detail,
+        });
+    }
+}
+
+fn secret(keys: &Keys) -> Result<&str, MyError> {
+    keys.admin_secret
+        .as_deref()
+        .ok_or_else(|| MyError::NotFound("Not found".to_string()))
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn mac(secret: &str, subject: &str, expires: u64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.{}.{}", TOKEN_PREFIX, subject, expires).as_bytes());
+    mac
+}
+
+fn valid_subject(subject: &str) -> bool {
+    !subject.is_empty()
+        && subject.len() <= 64
+        && subject
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_@".contains(c))
+}
+
+/// The holder of a valid admin token, as an extractor for admin handlers.
+pub struct Admin {
+    pub subject: String,
+}
+
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = MyError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, MyError> {
+        let keys = state.config.keys();
+        let secret = secret(&keys)?;
+        let invalid = || MyError::Unauthorized("Invalid or expired admin token".to_string());
+        let token = bearer(&parts.headers).ok_or_else(invalid)?;
+
+        let mut fields = token.split('.');
+        let (Some(TOKEN_PREFIX), Some(subject), Some(expires), Some(signature), None) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            return Err(invalid());
+        };
+        let expires: u64 = expires.parse().map_err(|_| invalid())?;
+        let signature = decode_hex(signature).ok_or_else(invalid)?;
+        let previous = keys.admin_secret_previous.as_deref();
+        let signed = [Some(secret), previous]
+            .into_iter()
+            .flatten()
+            .any(|secret| {
+                mac(secret, subject, expires)
+                    .verify_slice(&signature)
+                    .is_ok()
+            });
+        if !signed {
+            return Err(invalid());
+        }
+        if unix_now() >= expires {
+            return Err(invalid());
+        }
+
+        Ok(Admin {
+            subject: subject.to_string(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenRequest {
+    /// Who the token is for; shows up in the audit log.
+    subject: String,
+    ttl_minutes: Option<u64>,
+}
+
+pub async fn issue_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<TokenRequest>,
+) -> Result<Json<Value>, MyError> {
+    let keys = state.config.keys();
+    let secret = secret(&keys)?;
+    let presented = bearer(&headers).unwrap_or_default();
+    // Comparing digests keeps the comparison time independent of the secret.
+    if Sha256::digest(presented) != Sha256::digest(secret) {
+        return Err(MyError::Unauthorized("Invalid admin secret".to_string()));
+    }
+    if !valid_subject(&request.subject) {
+        return Err(MyError::InvalidRequest(
+            "subject must be 1-64 letters, digits, '-', '_' or '@'".to_string(),
+        ));
+    }
+
+    let ttl_minutes = request
+        .ttl_minutes
+        .unwrap_or(DEFAULT_TTL_MINUTES)
+        .clamp(1, MAX_TTL_MINUTES);
+    let expires = unix_now() + ttl_minutes * 60;
+    let signature = mac(secret, &request.subject, expires)
+        .finalize()
+        .into_bytes();
+    let token = format!(
+        "{}.{}.{}.{:x}",
+        TOKEN_PREFIX, request.subject, expires, signature
+    );
+
+    state.audit.record(
+        &request.subject,
+        "issue_token",
+        Some(format!("ttl {} min", ttl_minutes)),
+    );
+    Ok(Json(json!({ "token": token, "expiresAt": expires })))
+}
+
+pub async fn stats(admin: Admin, State(state): State<AppState>) -> Json<Value> {
+    state.audit.record(&admin.subject, "view_stats", None);
+    Json(json!({
+        "scraping": {
+            "flaggedClients": state.scraping.flagged(),
+        },
+    }))
+}
+
+pub async fn audit(admin: Admin, State(state): State<AppState>) -> Json<Value> {
+    state.audit.record(&admin.subject, "view_audit", None);
+    let entries: Vec<AuditEntry> = state.audit.0.lock().unwrap().iter().cloned().collect();
+    Json(json!({ "entries": entries }))
+}
+
+/// `POST /admin/reload-secrets`
+pub async fn reload_secrets(
+    admin: Admin,
+    State(state): State<AppState>,
+    Json(changes): Json<BTreeMap<String, Option<String>>>,
+) -> Result<Json<Value>, MyError> {
+    state
+        .config
+        .reload(&changes)
+        .map_err(|e| MyError::InvalidRequest(e.to_string()))?;
+    state.artifacts.use_keys(&state.config.keys());
+    let names: Vec<&str> = changes.keys().map(String::as_str).collect();
+    state
+        .audit
+        .record(&admin.subject, "reload_secrets", Some(names.join(", ")));
+    Ok(Json(json!({ "reloaded": names })))
+}
@@ -0,0 +1,196 @@
+//! `altText` fills in the `alt` attribute of images that have none.
+//!
+//! With `CAPTION_URL` set, each embedded image is sent to that captioning
+//! service (`POST`, the image as body, `Authorization: Bearer
+//! CAPTION_API_KEY` when set), which answers `{"caption": "..."}`. Images it
+//! has no caption for, and every image without the service, get a description
+//! from their file name or, when that is meaningless, their position in the
+//! chapter.
+
+use futures_util::stream::{self, StreamExt};
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use reqwest::Client;
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::book::{escape, Book};
+use crate::config::CaptionConfig;
+
+const CONCURRENT_CAPTIONS: usize = 4;
+const MAX_CAPTIONS: usize = 200;
+const CAPTION_TIMEOUT: Duration = Duration::from_secs(20);
+const MAX_CAPTION_LENGTH: usize = 300;
+/// File name words that say nothing about the image.
+const GENERIC_WORDS: [&str; 6] = ["image", "img", "photo", "picture", "pic", "chapter"];
+
+#[derive(Deserialize)]
+struct CaptionResponse {
+    caption: String,
+}
+
+/// Captions for the embedded images of `book` that have no `alt`, by path.
+pub async fn caption(
+    client: &Client,
+    config: &CaptionConfig,
+    book: &Book,
+) -> HashMap<String, String> {
+    let mut wanted: Vec<(String, Vec<u8>)> = Vec::new();
+    for chapter in &book.chapters {
+        for src in missing_alt(&chapter.body) {
+            if let Some(asset) = book.assets.iter().find(|(name, _)| *name == src)
+                && !wanted.iter().any(|(name, _)| *name == src)
+            {
+                wanted.push(asset.clone());
+            }
+        }
+    }
+    wanted.truncate(MAX_CAPTIONS);
+
+    let captions: HashMap<String, String> = stream::iter(wanted)
+        .map(|(name, data)| async move {
+            match request(client, config, data).await {
+                Ok(caption) => Some((name, caption)),
+                Err(e) => {
+                    warn!(error = %e, image = %name, "Could not caption image");
+                    None
+                }
+            }
+        })
+        .buffer_unordered(CONCURRENT_CAPTIONS)
+        .filter_map(|caption| async move { caption })
+        .collect()
+        .await;
+    info!(images = captions.len(), "Captioned images");
+    captions
+}
+
+async fn request(
+    client: &Client,
+    config: &CaptionConfig,
+    image: Vec<u8>,
+) -> Result<String, String> {
+    let mut request = client
+        .post(&config.url)
+        .timeout(CAPTION_TIMEOUT)
+        .body(image);
+    if let Some(key) = &config.api_key {
+        request = request.bearer_auth(key);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Captioning responded with status {}",
+            response.status()
+        ));
+    }
+    let caption = response
+        .json::<CaptionResponse>()
+        .await
+        .map_err(|e| e.without_url().to_string())?
+        .caption;
+    let caption = caption.trim();
+    if caption.is_empty() {
+        return Err("Captioning returned an empty caption".into());
+    }
+    Ok(caption.chars().take(MAX_CAPTION_LENGTH).collect())
+}
+
+/// The `src` of each image in `html` without a (non-blank) `alt`.
+fn missing_alt(html: &str) -> Vec<String> {
+    let sources = RefCell::new(Vec::new());
+    let _ = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("img[src]", |el| {
+                if el
+                    .get_attribute("alt")
+                    .is_none_or(|alt| alt.trim().is_empty())
+                    && let Some(src) = el.get_attribute("src")
+                {
+                    sources.borrow_mut().push(src);
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    );
+    sources.into_inner()
+}
+
+/// Gives every image in `book` without an `alt` one, from `captions` where
+/// there is one for it.
+pub fn fill(book: &mut Book, captions: &HashMap<String, String>) {
+    let mut filled = 0;
+    for chapter in &mut book.chapters {
+        let title = chapter.title.clone();
+        let position = Cell::new(0);
+        let count = Cell::new(0);
+        let rewritten = rewrite_str(
+            &chapter.body,
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("img", |el| {
+                    position.set(position.get() + 1);
+                    if el
+                        .get_attribute("alt")
+                        .is_some_and(|alt| !alt.trim().is_empty())
+                    {
+                        return Ok(());
+                    }
+                    let src = el.get_attribute("src").unwrap_or_default();
+                    let alt = captions
+                        .get(&src)
+                        .cloned()
+                        .or_else(|| from_file_name(&src))
+                        .unwrap_or_else(|| format!("Illustration {} of {}", position.get(), title));
+                    // `lol_html` only escapes quotes.
+                    el.set_attribute("alt", &escape(&alt))?;
+                    count.set(count.get() + 1);
+                    Ok(())
+                })],
+                ..RewriteStrSettings::new()
+            },
+        );
+        match rewritten {
+            Ok(body) => {
+                chapter.body = body;
+                filled += count.get();
+            }
+            Err(e) => warn!(error = %e, chapter = %title, "Could not add alt text"),
+        }
+    }
+    info!(images = filled, "Added alt text");
+}
+
+/// The words in the file name of `src`, such as "Castle at night" for
+/// `castle-at-night.jpg`, unless it is a hash or a generic name.
+fn from_file_name(src: &str) -> Option<String> {
+    let path = src.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let words: Vec<&str> = stem
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| word.chars().count() > 1)
+        .filter(|word| {
+            !GENERIC_WORDS
+                .iter()
+                .any(|generic| word.eq_ignore_ascii_case(generic))
+        })
+        .filter(|word| word.chars().any(|c| "aeiouyAEIOUY".contains(c)))
+        .collect();
+    // Hashes split into runs of letters that rarely make up most of the name.
+    let letters: usize = words.iter().map(|word| word.len()).sum();
+    if words.is_empty() || letters * 2 < stem.len() {
+        return None;
+    }
+    let mut text = words.join(" ").to_lowercase();
+    if let Some(first) = text.get(..1) {
+        text.replace_range(..1, &first.to_uppercase());
+    }
+    Some(text)
+}
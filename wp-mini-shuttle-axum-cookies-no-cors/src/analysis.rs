@@ -0,0 +1,229 @@
+//! A cheap look at a story before generating it: how many parts, how much
+//! text, how many words and how many images it has, and roughly how big its
+//! EPUB will be.
+//!
+//! Costs two Wattpad requests: the story's metadata (shared with other
+//! lookups through `crate::story_cache`), and the text of its first part, from which the text size and image count of the rest is extrapolated
+//! using the `length` Wattpad reports for every part.
+//!
+//! `POST /estimate` takes a `/generate-epub` body and answers with the
+//! analysis and, when the book would be too big for common devices and
+//! services, suggestions for making it smaller.
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use reqwest::Client;
+use serde::Serialize;
+use wp_mini_epub::AppError;
+
+use crate::book;
+use crate::config::SyncLimits;
+use crate::deprecation;
+use crate::error::{map_anyhow_error, MyError};
+use crate::pipeline::html;
+use crate::profiles::Profiled;
+use crate::{admit, authenticated, client_for, AppState};
+use crate::{part_cache, story_cache};
+
+/// What an embedded image adds to the book, on average.
+const AVERAGE_IMAGE_BYTES: u64 = 150 * 1024;
+/// How much the EPUB's compression shrinks chapter text.
+const TEXT_COMPRESSION: u64 = 3;
+/// How much of an image's size is left after converting it to grayscale.
+const GRAYSCALE_PERCENT: u64 = 60;
+const MB: u64 = 1024 * 1024;
+/// Size limits EPUBs commonly run into, smallest first.
+const DEVICE_LIMITS: [(&str, u64); 3] = [
+    ("emailAttachment", 25 * MB),
+    ("sendToKindle", 50 * MB),
+    ("telegram", 50 * MB),
+];
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Analysis {
+    pub parts: usize,
+    /// Text across all parts, uncompressed.
+    pub text_bytes: u64,
+    /// Words across all parts.
+    pub words: u64,
+    /// Images in all parts, whether or not they would be embedded.
+    pub images: u64,
+    pub estimated_bytes: u64,
+}
+
+impl Analysis {
+    /// Which of `limits` the story is over, if any.
+    pub fn exceeds(&self, limits: &SyncLimits) -> Option<&'static str> {
+        if limits.parts.is_some_and(|max| self.parts > max) {
+            Some("parts")
+        } else if limits.images.is_some_and(|max| self.images > max) {
+            Some("images")
+        } else if limits.bytes.is_some_and(|max| self.estimated_bytes > max) {
+            Some("size")
+        } else {
+            None
+        }
+    }
+}
+
+/// Analyses `story_id` as `client` sees it; `anonymous` as for
+/// `story_cache::story`.
+pub async fn analyze(
+    state: &AppState,
+    client: &Client,
+    story_id: u64,
+    embed_images: bool,
+    anonymous: bool,
+) -> Result<Analysis> {
+    let story = story_cache::story(state, client, story_id, anonymous)
+        .await
+        .map_err(|_| AppError::MetadataFetchFailed)?;
+    let parts = story.parts.unwrap_or_default();
+    let Some((first_id, first_length)) = parts
+        .iter()
+        .find_map(|part| part.id.map(|id| (id, part.length)))
+    else {
+        return Ok(Analysis {
+            parts: 0,
+            text_bytes: 0,
+            words: 0,
+            images: 0,
+            estimated_bytes: 0,
+        });
+    };
+    let sample = part_cache::text(state, client, first_id, anonymous)
+        .await
+        .map_err(|_| AppError::DownloadFailed)?;
+    let sample_images = html::image_urls(&sample)?.len() as u64;
+
+    // Parts are scaled by their share of the reported length; without
+    // lengths every part is taken to be like the first.
+    let total_length: i64 = parts.iter().filter_map(|part| part.length).sum();
+    let scale = match first_length {
+        Some(length) if length > 0 && total_length > 0 => total_length as f64 / length as f64,
+        _ => parts.len() as f64,
+    };
+    let text_bytes = (sample.len() as f64 * scale) as u64;
+    let words = (book::word_count(&sample) as f64 * scale).round() as u64;
+    let images = (sample_images as f64 * scale).round() as u64;
+    let analysis = Analysis {
+        parts: parts.len(),
+        text_bytes,
+        words,
+        images,
+        estimated_bytes: 0,
+    };
+    Ok(Analysis {
+        estimated_bytes: analysis.size(embed_images, 100),
+        ..analysis
+    })
+}
+
+impl Analysis {
+    /// The estimated EPUB size with images kept at `image_percent` of their size.
+    fn size(&self, embed_images: bool, image_percent: u64) -> u64 {
+        let image_bytes = match embed_images {
+            true => self.images * AVERAGE_IMAGE_BYTES * image_percent / 100,
+            false => 0,
+        };
+        self.text_bytes / TEXT_COMPRESSION + image_bytes
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Estimate {
+    story_id: u64,
+    #[serde(flatten)]
+    analysis: Analysis,
+    /// Whether `/generate-epub` would answer with a job instead of the file.
+    runs_as_job: bool,
+    /// Limits from `DEVICE_LIMITS` the estimated size is over.
+    exceeds: Vec<&'static str>,
+    suggestions: Vec<Suggestion>,
+}
+
+/// A change to the request that makes the book smaller.
+#[derive(Serialize)]
+#[serde(tag = "option", rename_all = "camelCase")]
+pub enum Suggestion {
+    /// Set `embedImages` to `false`.
+    #[serde(rename_all = "camelCase")]
+    DisableImages { estimated_bytes: u64 },
+    /// Convert embedded images to grayscale.
+    #[serde(rename_all = "camelCase")]
+    Grayscale { estimated_bytes: u64 },
+    /// Split the story into volumes of `chapters_per_volume` chapters each.
+    #[serde(rename_all = "camelCase")]
+    SplitVolumes {
+        volumes: u64,
+        chapters_per_volume: u64,
+        estimated_bytes_per_volume: u64,
+    },
+}
+
+fn suggestions(analysis: &Analysis, embed_images: bool, limit: u64) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    if embed_images && analysis.images > 0 {
+        suggestions.push(Suggestion::DisableImages {
+            estimated_bytes: analysis.size(false, 100),
+        });
+        suggestions.push(Suggestion::Grayscale {
+            estimated_bytes: analysis.size(true, GRAYSCALE_PERCENT),
+        });
+    }
+    let parts = analysis.parts as u64;
+    let volumes = analysis.estimated_bytes.div_ceil(limit);
+    if parts > 1 && volumes > 1 {
+        let volumes = volumes.min(parts);
+        suggestions.push(Suggestion::SplitVolumes {
+            volumes,
+            chapters_per_volume: parts.div_ceil(volumes),
+            estimated_bytes_per_volume: analysis.estimated_bytes.div_ceil(volumes),
+        });
+    }
+    suggestions
+}
+
+pub async fn estimate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Profiled(mut payload): Profiled,
+) -> Result<Response, MyError> {
+    let (_, warnings) = admit(&state, &headers, &mut payload, "estimate").await?;
+    let client = client_for(&state, &payload)?;
+    let analysis = analyze(
+        &state,
+        &client,
+        payload.story_id,
+        payload.embed_images,
+        !authenticated(&payload),
+    )
+    .await
+    .map_err(map_anyhow_error)?;
+
+    let exceeds: Vec<&'static str> = DEVICE_LIMITS
+        .iter()
+        .filter(|(_, limit)| analysis.estimated_bytes > *limit)
+        .map(|(name, _)| *name)
+        .collect();
+    let suggestions = match exceeds.is_empty() {
+        true => Vec::new(),
+        false => suggestions(&analysis, payload.embed_images, DEVICE_LIMITS[0].1),
+    };
+    let estimate = Estimate {
+        story_id: payload.story_id,
+        runs_as_job: analysis.exceeds(&state.config.sync_limits).is_some(),
+        exceeds,
+        suggestions,
+        analysis,
+    };
+    Ok(deprecation::attach(
+        Json(estimate).into_response(),
+        warnings,
+    ))
+}
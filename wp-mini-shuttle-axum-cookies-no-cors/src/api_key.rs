@@ -0,0 +1,41 @@
+//! Keeps the generation routes to callers holding a key, for operators of
+//! public instances who only want their own extension builds to use them.
+//!
+//! With `REQUIRE_API_KEY` set, every route that starts work or reads from
+//! Wattpad for a caller (the `generation` routes in `main`) needs one of the
+//! `API_KEYS` sent as `X-API-Key`, the same keys `crate::profiles` saves
+//! defaults under; without one the answer is `401`. CORS preflights are
+//! answered before this check, as browsers send them without the header.
+//! Job status and downloads stay open, since their ids and tokens already
+//! say who may read them. The web UI sends no key, so it cannot start
+//! downloads on such an instance.
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::MyError;
+use crate::profiles;
+
+pub async fn require(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, MyError> {
+    let Some(key) = request.headers().get(profiles::HEADER) else {
+        return Err(MyError::Unauthorized(
+            "This server needs an API key, sent as X-API-Key".to_string(),
+        ));
+    };
+    // Comparing hashes keeps the comparison's time from leaking the key.
+    match config
+        .keys()
+        .api_keys
+        .contains(&profiles::hash(key.to_str().unwrap_or_default()))
+    {
+        true => Ok(next.run(request).await),
+        false => Err(MyError::Unauthorized("Unknown API key".to_string())),
+    }
+}
@@ -0,0 +1,667 @@
+//! Short-lived storage for generated files that are fetched later via
+//! `GET /downloads/{token}` instead of in the response that produced them.
+//! `GET /downloads/{token}.sha256` returns a `sha256sum`-style checksum line,
+//! and `GET /downloads/{token}/qr` the link as a QR code PNG, to generate a
+//! book on a computer and scan it onto an e-reader's browser. The QR code
+//! takes the link's own query, does not count as a download and needs
+//! `PUBLIC_BASE_URL`, as a phone cannot follow a relative link.
+//!
+//! Links carry an HMAC over the token, an expiry and an optional download cap
+//! (`?expires=..&uses=..&sig=..`), so a shared link stops working after the
+//! lifetime and number of downloads chosen by whoever requested it. Links
+//! are signed with the current key and checked against the previous one too,
+//! so the key can be rotated without breaking the links already handed out.
+//!
+//! Files are stored once per SHA-256 of their content and reference counted
+//! by the tokens handed out for them, so the same EPUB generated for many
+//! users (or many times by one) only takes space once. The hash is checked
+//! again before a file is served. Both live in `crate::storage`, under
+//! `artifacts/blobs/<sha256>` and `artifacts/tokens/<token>`, with a
+//! reference per token under `artifacts/refs/<sha256>/<token>`. A blob is
+//! deleted only once no reference to it is left in storage, checked under a
+//! lock in `crate::shared`, so instances sharing the storage never delete a
+//! file another one handed out a link for. The index kept in memory is
+//! rebuilt from the tokens on startup, and a token it does not know of (made
+//! by another instance) is looked up in storage. Downloads of a capped link
+//! are counted in `crate::shared` too, so they are capped across instances.
+
+use arc_swap::ArcSwap;
+use axum::body::Bytes;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use image::{GrayImage, ImageFormat, Luma};
+use qrcode::{Color, QrCode};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use wp_mini_epub::AppError;
+
+use crate::config::Keys;
+use crate::error::MyError;
+use crate::file_response::{attachment, sha256_hex};
+use crate::shared::Shared;
+use crate::storage::Storage;
+use crate::{decode_hex, unix_now, AppState};
+
+pub use api_types::LinkOptions;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TOKENS: &str = "artifacts/tokens/";
+const BLOBS: &str = "artifacts/blobs/";
+const REFS: &str = "artifacts/refs/";
+/// How long the lock on a blob's references is held at most, and waited for.
+const LOCK_TTL: Duration = Duration::from_secs(30);
+const LOCK_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Clone)]
+pub struct Artifact {
+    pub file_name: String,
+    pub content_type: String,
+    pub bytes: Bytes,
+}
+
+/// One handed-out token: a name for, and a reference to, a blob.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    /// Hex SHA-256 of the blob.
+    hash: String,
+    size: usize,
+    file_name: String,
+    content_type: String,
+    /// Unix seconds.
+    stored_at: u64,
+    downloads: u32,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// References per blob.
+    blobs: HashMap<String, usize>,
+    total_bytes: usize,
+}
+
+impl Inner {
+    fn add(&mut self, token: String, entry: Entry) {
+        let refs = self.blobs.entry(entry.hash.clone()).or_default();
+        if *refs == 0 {
+            self.total_bytes += entry.size;
+        }
+        *refs += 1;
+        self.entries.insert(token, entry);
+    }
+
+    /// Forgets `token`; returns the blob's hash, and whether no token known
+    /// here refers to it anymore.
+    fn remove(&mut self, token: &str) -> Option<(String, bool)> {
+        let entry = self.entries.remove(token)?;
+        let refs = self.blobs.get_mut(&entry.hash)?;
+        *refs -= 1;
+        if *refs > 0 {
+            return Some((entry.hash, false));
+        }
+        self.blobs.remove(&entry.hash);
+        self.total_bytes -= entry.size;
+        Some((entry.hash, true))
+    }
+}
+
+pub struct ArtifactStore {
+    inner: Mutex<Inner>,
+    /// Held while storing or dropping files, so a blob being written is never
+    /// deleted by a concurrent eviction.
+    writes: tokio::sync::Mutex<()>,
+    storage: Arc<dyn Storage>,
+    shared: Arc<dyn Shared>,
+    ttl: Duration,
+    max_total_bytes: usize,
+    /// The key new links are signed with, then any still accepted.
+    signing_keys: ArcSwap<Vec<Vec<u8>>>,
+    /// Signs links while `DOWNLOAD_SIGNING_KEY` is unset.
+    random_key: Vec<u8>,
+}
+
+fn token_key(token: &str) -> String {
+    format!("{}{}", TOKENS, token)
+}
+
+fn blob_key(hash: &str) -> String {
+    format!("{}{}", BLOBS, hash)
+}
+
+fn refs_key(hash: &str) -> String {
+    format!("{}{}/", REFS, hash)
+}
+
+fn ref_key(hash: &str, token: &str) -> String {
+    format!("{}{}/{}", REFS, hash, token)
+}
+
+fn lock_key(hash: &str) -> String {
+    format!("artifact-lock:{}", hash)
+}
+
+fn downloads_key(token: &str) -> String {
+    format!("artifact-downloads:{}", token)
+}
+
+fn storage_error(e: anyhow::Error) -> MyError {
+    error!(error = %e, "Artifact storage failed");
+    MyError::Storage
+}
+
+impl ArtifactStore {
+    /// Opens the store, picking up the files left by a previous run.
+    pub async fn open(
+        storage: Arc<dyn Storage>,
+        shared: Arc<dyn Shared>,
+        ttl: Duration,
+        max_total_bytes: usize,
+        keys: &Keys,
+    ) -> anyhow::Result<Self> {
+        let mut inner = Inner::default();
+        for key in storage.list(TOKENS).await? {
+            let entry = storage
+                .get(&key)
+                .await?
+                .and_then(|bytes| serde_json::from_slice::<Entry>(&bytes).ok());
+            match (key.strip_prefix(TOKENS), entry) {
+                (Some(token), Some(entry)) => inner.add(token.to_string(), entry),
+                _ => warn!(key, "Ignoring unreadable stored artifact"),
+            }
+        }
+        // Stored before tokens had references of their own.
+        if !inner.entries.is_empty() && storage.list(REFS).await?.is_empty() {
+            for (token, entry) in &inner.entries {
+                storage
+                    .put(&ref_key(&entry.hash, token), Bytes::new())
+                    .await?;
+            }
+        }
+        info!(
+            artifacts = inner.entries.len(),
+            bytes = inner.total_bytes,
+            "Loaded stored artifacts"
+        );
+        let store = ArtifactStore {
+            inner: Mutex::new(inner),
+            writes: tokio::sync::Mutex::new(()),
+            storage,
+            shared,
+            ttl,
+            max_total_bytes,
+            signing_keys: ArcSwap::default(),
+            random_key: [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat(),
+        };
+        store.use_keys(keys);
+        Ok(store)
+    }
+
+    /// Signs new links with `keys`' `DOWNLOAD_SIGNING_KEY` from now on, and
+    /// accepts only those it and `DOWNLOAD_SIGNING_KEY_PREVIOUS` signed.
+    pub fn use_keys(&self, keys: &Keys) {
+        let mut signing_keys = vec![match &keys.download_signing_key {
+            Some(key) => key.as_bytes().to_vec(),
+            None => {
+                warn!("DOWNLOAD_SIGNING_KEY is not set; download links will not survive a restart");
+                self.random_key.clone()
+            }
+        }];
+        if let Some(previous) = &keys.download_signing_key_previous {
+            signing_keys.push(previous.as_bytes().to_vec());
+        }
+        self.signing_keys.store(Arc::new(signing_keys));
+    }
+
+    /// How long a link created with `options` stays valid.
+    pub fn link_lifetime(&self, options: &LinkOptions) -> Duration {
+        options
+            .expires_in_hours
+            .map(|hours| Duration::from_secs(hours * 3600))
+            .map_or(self.ttl, |lifetime| lifetime.min(self.ttl))
+    }
+
+    /// Stores `artifact` and returns the signed `/downloads/...` path and query
+    /// it can be fetched from.
+    pub async fn publish(
+        &self,
+        artifact: Artifact,
+        options: &LinkOptions,
+    ) -> Result<String, MyError> {
+        let token = self.insert(artifact).await.map_err(storage_error)?;
+        let expires = unix_now() + self.link_lifetime(options).as_secs();
+        let uses = options.max_downloads.unwrap_or(0);
+        Ok(format!(
+            "/downloads/{}?expires={}&uses={}&sig={}",
+            token,
+            expires,
+            uses,
+            self.signature(&token, expires, uses)
+        ))
+    }
+
+    /// Stores `artifact` without handing out a link, for the server's own
+    /// later use through `fetch`.
+    pub async fn keep(&self, artifact: Artifact) -> Result<String, MyError> {
+        self.insert(artifact).await.map_err(storage_error)
+    }
+
+    /// The artifact kept under `token`, if it has not expired.
+    pub async fn fetch(&self, token: &str) -> Result<Artifact, MyError> {
+        self.get(token, false, 0).await
+    }
+
+    fn mac(key: &[u8], token: &str, expires: u64, uses: u32) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(format!("{}:{}:{}", token, expires, uses).as_bytes());
+        mac
+    }
+
+    fn signature(&self, token: &str, expires: u64, uses: u32) -> String {
+        format!(
+            "{:x}",
+            Self::mac(&self.signing_keys.load()[0], token, expires, uses)
+                .finalize()
+                .into_bytes()
+        )
+    }
+
+    fn verify(&self, token: &str, link: &SignedLink) -> Result<(), MyError> {
+        let invalid = || MyError::Forbidden("This download link is not valid".to_string());
+        let signature = decode_hex(&link.sig).ok_or_else(invalid)?;
+        let signed = self.signing_keys.load().iter().any(|key| {
+            Self::mac(key, token, link.expires, link.uses)
+                .verify_slice(&signature)
+                .is_ok()
+        });
+        if !signed {
+            return Err(invalid());
+        }
+        if unix_now() >= link.expires {
+            return Err(MyError::NotFound(
+                "This download link has expired".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn expired(&self, entry: &Entry) -> bool {
+        unix_now().saturating_sub(entry.stored_at) >= self.ttl.as_secs()
+    }
+
+    /// Stores `artifact` and returns the unguessable token it is kept under.
+    /// Expired entries are dropped first, then the oldest ones until it fits.
+    async fn insert(&self, artifact: Artifact) -> anyhow::Result<String> {
+        let token = Uuid::new_v4().simple().to_string();
+        let entry = Entry {
+            hash: sha256_hex(&artifact.bytes),
+            size: artifact.bytes.len(),
+            file_name: artifact.file_name,
+            content_type: artifact.content_type,
+            stored_at: unix_now(),
+            downloads: 0,
+        };
+        let _writes = self.writes.lock().await;
+
+        let (freed, store_blob) = {
+            let mut inner = self.inner.lock().unwrap();
+            let expired: Vec<String> = inner
+                .entries
+                .iter()
+                .filter(|(_, entry)| self.expired(entry))
+                .map(|(token, _)| token.clone())
+                .collect();
+            let mut freed: Vec<(String, String, bool)> = expired
+                .into_iter()
+                .filter_map(|token| inner.remove(&token).map(|(hash, last)| (token, hash, last)))
+                .collect();
+
+            let store_blob = !inner.blobs.contains_key(&entry.hash);
+            if store_blob {
+                while inner.total_bytes + entry.size > self.max_total_bytes {
+                    let Some(oldest) = inner
+                        .entries
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.stored_at)
+                        .map(|(token, _)| token.clone())
+                    else {
+                        break;
+                    };
+                    freed.extend(
+                        inner
+                            .remove(&oldest)
+                            .map(|(hash, last)| (oldest, hash, last)),
+                    );
+                }
+            }
+            (freed, store_blob)
+        };
+
+        for (token, hash, last) in &freed {
+            self.forget(token, hash, *last).await;
+        }
+        let owner = self.lock(&entry.hash).await;
+        let stored = async {
+            // Another instance may have stored the blob, or deleted it, since
+            // this one last saw it.
+            let store_blob = store_blob
+                || owner.is_none()
+                || self.storage.list(&refs_key(&entry.hash)).await?.is_empty();
+            self.storage
+                .put(&ref_key(&entry.hash, &token), Bytes::new())
+                .await?;
+            if store_blob {
+                self.storage
+                    .put(&blob_key(&entry.hash), artifact.bytes)
+                    .await?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        self.unlock(&entry.hash, owner).await;
+        stored?;
+        self.storage
+            .put(&token_key(&token), serde_json::to_vec(&entry)?.into())
+            .await?;
+        self.inner.lock().unwrap().add(token.clone(), entry);
+        Ok(token)
+    }
+
+    /// Deletes `token` and its reference to the blob `hash`, then the blob if
+    /// that was the `last` reference here and none is left in storage.
+    async fn forget(&self, token: &str, hash: &str, last: bool) {
+        self.discard(&token_key(token)).await;
+        self.discard(&ref_key(hash, token)).await;
+        if !last {
+            return;
+        }
+        // Without the lock, the blob is left behind rather than risk deleting
+        // it under a link another instance is handing out.
+        let Some(owner) = self.lock(hash).await else {
+            return;
+        };
+        match self.storage.list(&refs_key(hash)).await {
+            Ok(refs) if refs.is_empty() => self.discard(&blob_key(hash)).await,
+            Ok(_) => {}
+            Err(e) => warn!(hash, error = %e, "Could not list artifact references"),
+        }
+        self.unlock(hash, Some(owner)).await;
+    }
+
+    /// Takes the lock every instance holds while adding or dropping a
+    /// reference to `hash`; `None` when it could not be had in `LOCK_TTL`.
+    async fn lock(&self, hash: &str) -> Option<Bytes> {
+        let owner = Bytes::from(Uuid::new_v4().simple().to_string());
+        let deadline = Instant::now() + LOCK_TTL;
+        loop {
+            match self
+                .shared
+                .set_if_absent(&lock_key(hash), owner.clone(), LOCK_TTL)
+                .await
+            {
+                Ok(true) => return Some(owner),
+                Ok(false) if Instant::now() < deadline => tokio::time::sleep(LOCK_POLL).await,
+                Ok(false) => {
+                    warn!(hash, "Gave up waiting for an artifact lock");
+                    return None;
+                }
+                Err(e) => {
+                    warn!(error = %e, "Could not take an artifact lock");
+                    return None;
+                }
+            }
+        }
+    }
+
+    async fn unlock(&self, hash: &str, owner: Option<Bytes>) {
+        if let Some(owner) = owner
+            && let Err(e) = self.shared.delete_if(&lock_key(hash), owner).await
+        {
+            warn!(error = %e, "Could not release an artifact lock");
+        }
+    }
+
+    /// The entry stored for `token`, for a token this instance did not make.
+    async fn load(&self, token: &str) -> Result<Option<Entry>, MyError> {
+        let bytes = self
+            .storage
+            .get(&token_key(token))
+            .await
+            .map_err(storage_error)?;
+        Ok(bytes.and_then(|bytes| serde_json::from_slice(&bytes).ok()))
+    }
+
+    /// Deletes `key`; a failure only leaves garbage behind, so it is just logged.
+    async fn discard(&self, key: &str) {
+        if let Err(e) = self.storage.delete(key).await {
+            warn!(key, error = %e, "Could not delete stored artifact");
+        }
+    }
+
+    /// Looks up `token`, counting a download against `max_uses` (0 for no cap)
+    /// when `count` is set.
+    async fn get(&self, token: &str, count: bool, max_uses: u32) -> Result<Artifact, MyError> {
+        let not_found =
+            || MyError::NotFound("This download link has expired or does not exist".to_string());
+        let known = self.inner.lock().unwrap().entries.contains_key(token);
+        if !known && let Some(entry) = self.load(token).await? {
+            self.inner.lock().unwrap().add(token.to_string(), entry);
+        }
+        let capped = count && max_uses > 0;
+        let mut entry = {
+            let mut inner = self.inner.lock().unwrap();
+            let entry = inner
+                .entries
+                .get_mut(token)
+                .filter(|entry| !self.expired(entry))
+                .ok_or_else(not_found)?;
+            if count && !capped {
+                entry.downloads += 1;
+            }
+            entry.clone()
+        };
+        if capped {
+            // Counted in `crate::shared`, so instances sharing it never hand
+            // out more downloads between them than the link allows.
+            let downloads = self
+                .shared
+                .increment_below(
+                    &downloads_key(token),
+                    entry.downloads.into(),
+                    max_uses.into(),
+                    self.ttl,
+                )
+                .await
+                .map_err(storage_error)?
+                .ok_or_else(|| {
+                    MyError::NotFound(
+                        "This download link has reached its download limit".to_string(),
+                    )
+                })?;
+            entry.downloads = downloads as u32;
+            if let Some(known) = self.inner.lock().unwrap().entries.get_mut(token) {
+                known.downloads = known.downloads.max(entry.downloads);
+            }
+            // Only capped links need the count to survive a restart.
+            let record = serde_json::to_vec(&entry).expect("entries serialize");
+            if let Err(e) = self.storage.put(&token_key(token), record.into()).await {
+                warn!(error = %e, "Could not persist download count");
+            }
+        }
+
+        let bytes = self
+            .storage
+            .get(&blob_key(&entry.hash))
+            .await
+            .map_err(storage_error)?
+            .ok_or_else(not_found)?;
+        if sha256_hex(&bytes) != entry.hash {
+            error!(
+                token,
+                "Stored artifact failed its integrity check; dropping it"
+            );
+            let _writes = self.writes.lock().await;
+            let freed = self.inner.lock().unwrap().remove(token);
+            match freed {
+                Some((hash, last)) => self.forget(token, &hash, last).await,
+                None => self.discard(&token_key(token)).await,
+            }
+            return Err(not_found());
+        }
+
+        Ok(Artifact {
+            file_name: entry.file_name,
+            content_type: entry.content_type,
+            bytes,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SignedLink {
+    expires: u64,
+    uses: u32,
+    sig: String,
+}
+
+pub async fn download(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    link: Result<Query<SignedLink>, QueryRejection>,
+) -> Result<Response, MyError> {
+    let (token, sidecar) = match token.strip_suffix(".sha256") {
+        Some(token) => (token, true),
+        None => (token.as_str(), false),
+    };
+    let Ok(Query(link)) = link else {
+        return Err(MyError::Forbidden(
+            "This download link is not valid".to_string(),
+        ));
+    };
+    state.artifacts.verify(token, &link)?;
+    let artifact = state.artifacts.get(token, !sidecar, link.uses).await?;
+
+    if sidecar {
+        let line = format!("{}  {}\n", sha256_hex(&artifact.bytes), artifact.file_name);
+        return Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], line).into_response());
+    }
+    info!(file_name = %artifact.file_name, "Serving stored artifact");
+    attachment(&artifact.file_name, &artifact.content_type, artifact.bytes)
+}
+
+/// Module size of the QR code in pixels, and the quiet zone around it in
+/// modules.
+const QR_SCALE: u32 = 8;
+const QR_MARGIN: u32 = 4;
+
+pub async fn qr(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    link: Result<Query<SignedLink>, QueryRejection>,
+) -> Result<Response, MyError> {
+    let Ok(Query(link)) = link else {
+        return Err(MyError::Forbidden(
+            "This download link is not valid".to_string(),
+        ));
+    };
+    state.artifacts.verify(&token, &link)?;
+    if state.config.public_base_url.is_none() {
+        return Err(MyError::Unavailable {
+            message: "This server cannot make QR codes without PUBLIC_BASE_URL".to_string(),
+            retry_after: None,
+        });
+    }
+    let url = state.config.public_url(&format!(
+        "/downloads/{}?expires={}&uses={}&sig={}",
+        token, link.expires, link.uses, link.sig
+    ));
+    let png = qr_png(&url).map_err(|e| {
+        error!(error = %e, "Could not make QR code");
+        MyError::App(AppError::EpubGenerationFailed)
+    })?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+fn qr_png(text: &str) -> anyhow::Result<Vec<u8>> {
+    let code = QrCode::new(text)?;
+    let modules = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (modules + 2 * QR_MARGIN) * QR_SCALE;
+    let image = GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / QR_SCALE, y / QR_SCALE);
+        let dark = (QR_MARGIN..QR_MARGIN + modules).contains(&x)
+            && (QR_MARGIN..QR_MARGIN + modules).contains(&y)
+            && colors[((y - QR_MARGIN) * modules + x - QR_MARGIN) as usize] == Color::Dark;
+        Luma([if dark { 0 } else { 255 }])
+    });
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    use super::{Artifact, ArtifactStore, LinkOptions};
+    use crate::testing;
+
+    #[tokio::test]
+    async fn capped_links_are_counted_across_instances() {
+        let secrets = [("DOWNLOAD_SIGNING_KEY", "key")];
+        let (first, state_dir) = testing::state(&secrets).await;
+        // Another instance on the same storage and shared state, as with Redis.
+        let mut second = crate::state(testing::config(&state_dir, &secrets)).await;
+        second.shared = first.shared.clone();
+        second.artifacts = Arc::new(
+            ArtifactStore::open(
+                first.storage.clone(),
+                first.shared.clone(),
+                first.config.artifact_ttl,
+                first.config.artifact_store_max_bytes,
+                &first.config.keys(),
+            )
+            .await
+            .unwrap(),
+        );
+        let artifact = Artifact {
+            file_name: "book.epub".to_string(),
+            content_type: "application/epub+zip".to_string(),
+            bytes: "book".into(),
+        };
+        let options = LinkOptions {
+            max_downloads: Some(1),
+            ..LinkOptions::default()
+        };
+        let Ok(link) = first.artifacts.publish(artifact, &options).await else {
+            panic!("Could not publish the artifact");
+        };
+        // Each instance knows of the link before either counts a download.
+        assert!(second.artifacts.fetch(&link[11..43]).await.is_ok());
+
+        let download = |state| {
+            let request = Request::get(&link).body(Body::empty()).unwrap();
+            crate::routes(state).oneshot(request)
+        };
+        let (a, b) = tokio::join!(download(first.clone()), download(second.clone()));
+        let mut statuses = [a.unwrap().status(), b.unwrap().status()];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::NOT_FOUND]);
+        let again = download(first).await.unwrap();
+        assert_eq!(again.status(), StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
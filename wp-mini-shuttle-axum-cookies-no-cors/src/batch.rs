@@ -0,0 +1,668 @@
+//! `POST /generate-epub/batch`: several stories as one ZIP of EPUBs, e.g. to
+//! export a reading list in one go.
+//!
+//! The body is a generation request with `storyIds` in place of `storyId`;
+//! every other option applies to each story. The ZIP is streamed: each book
+//! is sent as soon as it and the ones before it are done, while the next
+//! ones are generated. A book whose file name is taken by an earlier one
+//! (ignoring case, as most file systems do) gets its story ID added to the
+//! name, and a `manifest.csv` at the end maps every file in the ZIP to its
+//! story.
+//!
+//! A story that fails does not stop the others. `status.json`, also at the
+//! end, has an entry per story: `success`, `partial` (made, but with parts
+//! left out by `excludeTitlePatterns`) or `failed` along with the error code,
+//! so users know which ones to retry. `POST /generate-epub/batch/async` runs
+//! the batch as a job (see `crate::jobs`) with the same entries in its
+//! `items`, updated as stories finish, and a download link to the ZIP.
+//! `POST /jobs/{id}/retry-failed` then makes only the failed stories again;
+//! the other books are kept in `crate::artifacts` for as long as download
+//! links last. Batch jobs always run on the instance that accepted them.
+//!
+//! Exports of many image-heavy stories can pass 4 GB; the ZIP then uses
+//! ZIP64 records, as it does for books over 4 GB and for more than 65,535
+//! entries.
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, instrument, warn, Instrument};
+use wp_mini_epub::AppError;
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::artifacts::Artifact;
+use crate::deprecation::{self, Warning};
+use crate::error::MyError;
+use crate::file_response::{streamed_attachment, Pending};
+use crate::jobs::{self, Job, JobError, JobResult, JobState};
+use crate::progress::Progress;
+use crate::render;
+use crate::response_cache::CacheStatus;
+use crate::{admit, download, AppState, Epub, GenerateEpubRequest};
+
+pub use api_types::{ItemStatus, Outcome};
+
+const MAX_STORIES: usize = 50;
+const CONCURRENT_STORIES: usize = 3;
+const FILE_NAME: &str = "stories.zip";
+const MANIFEST: &str = "manifest.csv";
+const STATUS: &str = "status.json";
+/// Entries from this size on need ZIP64 sizes.
+const ZIP64_SIZE: u64 = u32::MAX as u64;
+/// Options that need a single book to act on.
+const SINGLE_ONLY: [&str; 3] = ["storyId", "delivery", "notifications"];
+
+#[instrument(skip_all)]
+pub async fn generate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut body): Json<Map<String, Value>>,
+) -> Result<Response, MyError> {
+    let ids = story_ids(&mut body)?;
+    let (payloads, warnings) = admit_all(&state, &headers, &body, &ids).await?;
+    info!(stories = payloads.len(), "Generating batch");
+    let sources: Vec<Source> = payloads
+        .into_iter()
+        .map(|payload| Source::Generate(Box::new(payload)))
+        .collect();
+    let (chunks, chunks_rx) = mpsc::channel::<io::Result<Bytes>>(1);
+    tokio::spawn(
+        async move {
+            if let Err(e) = stream_zip(&state, &sources, &chunks).await {
+                error!(error = %e, "Batch export failed");
+                // Ends the body with an error; the client sees a cut-off ZIP.
+                let _ = chunks.send(Err(io::Error::other(e.to_string()))).await;
+            }
+        }
+        .in_current_span(),
+    );
+
+    let body = stream::unfold(chunks_rx, |mut chunks_rx| async move {
+        let chunk = chunks_rx.recv().await?;
+        Some((chunk, chunks_rx))
+    });
+    let response = streamed_attachment(FILE_NAME, "application/zip", Body::from_stream(body))?;
+    Ok(deprecation::attach(response, warnings))
+}
+
+/// Generates the books and sends the ZIP of them to `chunks` as they come.
+/// Stops once the client is gone.
+async fn stream_zip(
+    state: &AppState,
+    sources: &[Source],
+    chunks: &mpsc::Sender<io::Result<Bytes>>,
+) -> anyhow::Result<()> {
+    let pending = Pending::default();
+    let mut zip = Assembly::new(pending.clone());
+    let mut books = books(state, sources);
+    while let Some((id, epub)) = books.next().await {
+        zip.record(id, epub)?;
+        if chunks.send(Ok(pending.take())).await.is_err() {
+            info!("Client left; stopping the batch export");
+            return Ok(());
+        }
+    }
+    zip.finish()?;
+    let _ = chunks.send(Ok(pending.take())).await;
+    Ok(())
+}
+
+/// Where a batch gets the book of a story from.
+enum Source {
+    Generate(Box<GenerateEpubRequest>),
+    /// Made by an earlier run of the job and kept in `crate::artifacts` under
+    /// the token.
+    Kept(ItemStatus, String),
+}
+
+/// The books of `sources` in order, a few generated at a time.
+fn books<'a>(
+    state: &'a AppState,
+    sources: &'a [Source],
+) -> impl Stream<Item = (u64, Result<Epub, MyError>)> + 'a {
+    // Collected first: mapping lazily inside the stream trips up the
+    // lifetimes `tokio::spawn` needs of the future.
+    let downloads: Vec<_> = sources
+        .iter()
+        .map(|source| async move {
+            match source {
+                Source::Generate(payload) => (
+                    payload.story_id,
+                    download(state, payload, &Progress::default()).await,
+                ),
+                Source::Kept(item, token) => (item.story_id, kept(state, item, token).await),
+            }
+        })
+        .collect();
+    stream::iter(downloads).buffered(CONCURRENT_STORIES)
+}
+
+/// The book an earlier run made for `item`, kept under `token`.
+async fn kept(state: &AppState, item: &ItemStatus, token: &str) -> Result<Epub, MyError> {
+    let artifact = state.artifacts.fetch(token).await?;
+    Ok(Epub {
+        title: item.title.clone().unwrap_or_default(),
+        file_name: artifact.file_name,
+        content_type: render::content_type(&artifact.content_type),
+        skipped: item.skipped_chapters.clone(),
+        cover_url: None,
+        bytes: artifact.bytes,
+        cache: CacheStatus::Bypass,
+    })
+}
+
+/// `POST /generate-epub/batch/async`: the batch as a job.
+#[instrument(skip_all)]
+pub async fn create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut body): Json<Map<String, Value>>,
+) -> Result<Response, MyError> {
+    let ids = story_ids(&mut body)?;
+    let (payloads, warnings) = admit_all(&state, &headers, &body, &ids).await?;
+    let job = state.jobs.adopt(jobs::batch(&ids, body));
+    let status = job.borrow().status.clone();
+    info!(job_id = %status.id, stories = ids.len(), "Queued batch job");
+
+    let sources = payloads
+        .into_iter()
+        .map(|payload| Source::Generate(Box::new(payload)))
+        .collect();
+    tokio::spawn(run(state, job, sources).in_current_span());
+    Ok(deprecation::attach(
+        (StatusCode::ACCEPTED, Json(status)).into_response(),
+        warnings,
+    ))
+}
+
+/// `POST /jobs/{id}/retry-failed`: generates the failed stories of a finished
+/// batch job again, and the ZIP with them. The books that were made the first
+/// time are taken from storage as they are. A job that is still running, or
+/// already being retried, is answered `409`.
+#[instrument(skip(state, headers))]
+pub async fn retry_failed(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, MyError> {
+    let job = state
+        .jobs
+        .get(&id)
+        .ok_or_else(|| MyError::NotFound(format!("Job {} does not exist or has expired", id)))?;
+    let still_running = || MyError::Conflict(format!("Job {} is still running", id));
+    let (version, items, artifacts, options) = {
+        let status = job.borrow();
+        let Some(options) = status.batch_options.clone() else {
+            return Err(MyError::InvalidRequest(format!(
+                "Job {} is not a batch job",
+                id
+            )));
+        };
+        if !status.state.is_finished() {
+            return Err(still_running());
+        }
+        (
+            status.version,
+            status.items.clone(),
+            status.artifacts.clone(),
+            options,
+        )
+    };
+    // Books that were made but could not be kept are made again too.
+    let again: Vec<u64> = items
+        .iter()
+        .zip(&artifacts)
+        .filter(|(item, artifact)| item.outcome == Outcome::Failed || artifact.is_none())
+        .map(|(item, _)| item.story_id)
+        .collect();
+    if again.is_empty() {
+        return Err(MyError::InvalidRequest(format!(
+            "Job {} has no failed stories",
+            id
+        )));
+    }
+    let (payloads, warnings) = admit_all(&state, &headers, &options, &again).await?;
+    let mut payloads = payloads.into_iter();
+    let sources = items
+        .into_iter()
+        .zip(artifacts)
+        .map(|(item, artifact)| match artifact {
+            Some(token) if !again.contains(&item.story_id) => Source::Kept(item, token),
+            _ => Source::Generate(Box::new(
+                payloads.next().expect("a payload per retried story"),
+            )),
+        })
+        .collect();
+
+    // Claimed only if nothing, e.g. another retry, changed the job since it
+    // was read, so it is never run twice at once.
+    let mut claimed = false;
+    jobs::update(&job, |status| {
+        if !status.state.is_finished() || status.version != version {
+            return;
+        }
+        claimed = true;
+        status.state = JobState::Queued;
+        status.result = None;
+        status.error = None;
+        for item in &mut status.items {
+            if again.contains(&item.story_id) {
+                *item = ItemStatus::pending(item.story_id);
+            }
+        }
+        jobs::record(status, "retried", Some(format!("{} stories", again.len())));
+    });
+    if !claimed {
+        return Err(still_running());
+    }
+    let status = job.borrow().status.clone();
+    info!(
+        stories = again.len(),
+        "Retrying failed stories of batch job"
+    );
+
+    tokio::spawn(run(state, job, sources).in_current_span());
+    Ok(deprecation::attach(
+        (StatusCode::ACCEPTED, Json(status)).into_response(),
+        warnings,
+    ))
+}
+
+async fn run(state: AppState, job: watch::Sender<Job>, sources: Vec<Source>) {
+    jobs::update(&job, |status| {
+        status.state = JobState::Running;
+        status.stage = Some("downloading".into());
+        jobs::record(status, "running", None);
+    });
+    let link = sources
+        .iter()
+        .find_map(|source| match source {
+            Source::Generate(payload) => Some(&payload.download_link),
+            Source::Kept(..) => None,
+        })
+        .expect("a batch job generates at least one story");
+    let outcome = async {
+        let mut zip = Assembly::new(Vec::new());
+        let mut books = books(&state, &sources);
+        let mut index = 0;
+        while let Some((id, epub)) = books.next().await {
+            let kept = match (&sources[index], &epub) {
+                (Source::Kept(_, token), Ok(_)) => Some(token.clone()),
+                (Source::Generate(_), Ok(epub)) => keep(&state, epub).await,
+                (_, Err(_)) => None,
+            };
+            let item = zip.record(id, epub).map_err(export_failed)?.clone();
+            jobs::update(&job, |status| {
+                jobs::record(
+                    status,
+                    "item",
+                    Some(format!("{}: {:?}", item.story_id, item.outcome)),
+                );
+                status.items[index] = item;
+                status.artifacts[index] = kept;
+            });
+            index += 1;
+        }
+        // Nothing to download when every story failed; the items say why.
+        if zip.items.iter().all(|item| item.outcome == Outcome::Failed) {
+            return Ok(None);
+        }
+        let bytes = Bytes::from(zip.finish().map_err(export_failed)?);
+        let size = bytes.len();
+        let path = state
+            .artifacts
+            .publish(
+                Artifact {
+                    file_name: FILE_NAME.to_string(),
+                    content_type: "application/zip".to_string(),
+                    bytes,
+                },
+                link,
+            )
+            .await?;
+        Ok::<_, MyError>(Some(JobResult {
+            file_name: FILE_NAME.to_string(),
+            size,
+            download_url: Some(state.config.public_url(&path)),
+            delivery: None,
+            skipped_chapters: Vec::new(),
+        }))
+    }
+    .await;
+
+    jobs::update(&job, |status| {
+        status.stage = None;
+        match outcome {
+            Ok(Some(result)) => {
+                info!("Batch job completed");
+                status.state = JobState::Completed;
+                jobs::record(
+                    status,
+                    "completed",
+                    Some(format!("{} ({} bytes)", result.file_name, result.size)),
+                );
+                status.result = Some(result);
+            }
+            Ok(None) => {
+                info!("Batch job failed: no story could be generated");
+                status.state = JobState::Failed;
+                jobs::record(status, "failed", Some("no story could be generated".into()));
+                status.error = status.items.iter().find_map(|item| item.error.clone());
+            }
+            Err(e) => {
+                let error = JobError::from(&e);
+                status.state = JobState::Failed;
+                jobs::record(status, "failed", Some(e.status_and_message().1));
+                status.error = Some(error);
+            }
+        }
+    });
+}
+
+/// The `storyIds` of a batch request, without duplicates, taken out of the
+/// body so the rest applies to each story.
+fn story_ids(body: &mut Map<String, Value>) -> Result<Vec<u64>, MyError> {
+    if let Some(field) = SINGLE_ONLY.iter().find(|field| body.contains_key(**field)) {
+        return Err(MyError::InvalidRequest(format!(
+            "{} is not supported for batches",
+            field
+        )));
+    }
+    let mut ids: Vec<u64> = body
+        .remove("storyIds")
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(|e| MyError::InvalidRequest(format!("Invalid storyIds: {}", e)))?
+        .unwrap_or_default();
+    if ids.is_empty() || ids.len() > MAX_STORIES {
+        return Err(MyError::InvalidRequest(format!(
+            "storyIds must list 1 to {} stories",
+            MAX_STORIES
+        )));
+    }
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    Ok(ids)
+}
+
+/// One admitted request per story, each with the `options` of the batch.
+async fn admit_all(
+    state: &AppState,
+    headers: &HeaderMap,
+    options: &Map<String, Value>,
+    ids: &[u64],
+) -> Result<(Vec<GenerateEpubRequest>, Vec<Warning>), MyError> {
+    let mut payloads = Vec::with_capacity(ids.len());
+    let mut warnings = Vec::new();
+    for id in ids {
+        let mut request = options.clone();
+        request.insert("storyId".into(), Value::from(*id));
+        let mut payload: GenerateEpubRequest = serde_json::from_value(Value::Object(request))
+            .map_err(|e| MyError::InvalidRequest(format!("Invalid request: {}", e)))?;
+        // Every story has the same options, and so the same warnings.
+        let (_, story_warnings) = admit(state, headers, &mut payload, "batch").await?;
+        warnings = story_warnings;
+        payloads.push(payload);
+    }
+    Ok((payloads, warnings))
+}
+
+/// Keeps a book of a batch job, so a retry does not have to make it again.
+async fn keep(state: &AppState, epub: &Epub) -> Option<String> {
+    let artifact = Artifact {
+        file_name: epub.file_name.clone(),
+        content_type: epub.content_type.to_string(),
+        bytes: epub.bytes.clone(),
+    };
+    match state.artifacts.keep(artifact).await {
+        Ok(token) => Some(token),
+        Err(_) => {
+            warn!("Could not keep a book of a batch job; a retry makes it again");
+            None
+        }
+    }
+}
+
+/// A batch ZIP being written, one book at a time.
+struct Assembly<W: Write> {
+    zip: ZipWriter<StreamWriter<W>>,
+    /// Lowercased names of the files so far.
+    names: HashSet<String>,
+    manifest: String,
+    items: Vec<ItemStatus>,
+}
+
+impl<W: Write> Assembly<W> {
+    fn new(out: W) -> Self {
+        Assembly {
+            zip: ZipWriter::new_stream(out),
+            names: HashSet::from([MANIFEST.to_string(), STATUS.to_string()]),
+            manifest: String::from("file,storyId,title\n"),
+            items: Vec::new(),
+        }
+    }
+
+    /// Adds the book of story `id`, or records why there is none.
+    fn record(&mut self, id: u64, epub: Result<Epub, MyError>) -> anyhow::Result<&ItemStatus> {
+        match epub {
+            Ok(epub) => self.add(id, &epub),
+            Err(e) => {
+                info!(story_id = id, error = %e.status_and_message().1, "Story of batch failed");
+                Ok(self.fail(id, &e))
+            }
+        }
+    }
+
+    /// Adds `epub`, the book of story `id`.
+    fn add(&mut self, id: u64, epub: &Epub) -> anyhow::Result<&ItemStatus> {
+        let name = self.file_name(id, &epub.file_name);
+        // Like the volume ZIPs of `crate::chapters`. Archive offsets and entry
+        // counts switch to ZIP64 by themselves; a single entry over 4 GB has to
+        // be announced before it is written.
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(epub.bytes.len() as u64 >= ZIP64_SIZE);
+        self.zip.start_file(name.as_str(), options)?;
+        self.zip.write_all(&epub.bytes)?;
+        self.manifest.push_str(&format!(
+            "{},{},{}\n",
+            csv_field(&name),
+            id,
+            csv_field(&epub.title)
+        ));
+        self.items.push(ItemStatus {
+            outcome: match epub.skipped.is_empty() {
+                true => Outcome::Success,
+                false => Outcome::Partial,
+            },
+            title: Some(epub.title.clone()),
+            file: Some(name),
+            skipped_chapters: epub.skipped.clone(),
+            ..ItemStatus::pending(id)
+        });
+        Ok(&self.items[self.items.len() - 1])
+    }
+
+    /// Records that story `id` could not be generated.
+    fn fail(&mut self, id: u64, error: &MyError) -> &ItemStatus {
+        self.items.push(ItemStatus {
+            outcome: Outcome::Failed,
+            error: Some(JobError::from(error)),
+            ..ItemStatus::pending(id)
+        });
+        &self.items[self.items.len() - 1]
+    }
+
+    /// Writes the status, the manifest and the central directory.
+    fn finish(mut self) -> anyhow::Result<W> {
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        self.zip.start_file(STATUS, stored)?;
+        serde_json::to_writer_pretty(&mut self.zip, &self.items)?;
+        self.zip.start_file(MANIFEST, stored)?;
+        self.zip.write_all(self.manifest.as_bytes())?;
+        Ok(self.zip.finish()?.into_inner())
+    }
+
+    /// `file_name`, or with the story ID added when an earlier book has it.
+    fn file_name(&mut self, id: u64, file_name: &str) -> String {
+        if self.names.insert(file_name.to_lowercase()) {
+            return file_name.to_string();
+        }
+        let name = match file_name.rsplit_once('.') {
+            Some((stem, extension)) => format!("{} ({}).{}", stem, id, extension),
+            None => format!("{} ({})", file_name, id),
+        };
+        self.names.insert(name.to_lowercase());
+        name
+    }
+}
+
+fn export_failed(error: anyhow::Error) -> MyError {
+    error!(error = %error, "Batch export failed");
+    MyError::App(AppError::EpubGenerationFailed)
+}
+
+fn csv_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    const END_OF_CENTRAL_DIRECTORY_64: &[u8] = b"PK\x06\x06";
+
+    fn epub(file_name: String, bytes: Bytes) -> Epub {
+        Epub {
+            title: file_name.clone(),
+            file_name,
+            content_type: "application/epub+zip",
+            skipped: Vec::new(),
+            cover_url: None,
+            bytes,
+            cache: CacheStatus::Bypass,
+        }
+    }
+
+    /// The ZIP of one book per ID, each `bytes` long.
+    fn assemble<W: Write>(out: W, ids: impl IntoIterator<Item = u64>, bytes: Bytes) -> W {
+        let mut zip = Assembly::new(out);
+        for id in ids {
+            zip.add(id, &epub(format!("{}.epub", id), bytes.clone()))
+                .unwrap();
+        }
+        zip.finish().unwrap()
+    }
+
+    fn has_zip64_end(zip: &[u8]) -> bool {
+        zip.windows(END_OF_CENTRAL_DIRECTORY_64.len())
+            .any(|window| window == END_OF_CENTRAL_DIRECTORY_64)
+    }
+
+    #[test]
+    fn more_than_65535_entries_use_zip64() {
+        let count = 70_000;
+        let zip = assemble(Vec::new(), 1..=count, Bytes::from_static(b"book"));
+
+        assert!(has_zip64_end(&zip));
+        let mut archive = ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(archive.len(), count as usize + 2);
+        let mut last = String::new();
+        archive
+            .by_name(&format!("{}.epub", count))
+            .unwrap()
+            .read_to_string(&mut last)
+            .unwrap();
+        assert_eq!(last, "book");
+        let mut manifest = String::new();
+        archive
+            .by_name(MANIFEST)
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+        assert_eq!(manifest.lines().count(), count as usize + 1);
+    }
+
+    #[test]
+    fn small_exports_stay_plain_zip() {
+        let zip = assemble(Vec::new(), [1], Bytes::from_static(b"book"));
+        assert!(!has_zip64_end(&zip));
+    }
+
+    #[test]
+    fn books_are_written_before_the_export_finishes() {
+        let pending = Pending::default();
+        let mut zip = Assembly::new(pending.clone());
+        zip.add(1, &epub("1.epub".into(), Bytes::from_static(b"book")))
+            .unwrap();
+        let first = pending.take();
+        assert!(first.starts_with(b"PK\x03\x04"));
+        assert!(first.windows(4).any(|window| window == b"book"));
+        zip.finish().unwrap();
+        assert!(!pending.take().is_empty());
+    }
+
+    /// Keeps the small writes of a ZIP (headers and records) but not the
+    /// books, so a test can write more than 4 GB without holding it.
+    #[derive(Default)]
+    struct Headers {
+        len: u64,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl Write for Headers {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            if bytes.len() <= 64 * 1024 {
+                self.writes.push(bytes.to_vec());
+            }
+            self.len += bytes.len() as u64;
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[ignore = "writes 4.5 GB through the ZIP writer; run with --ignored"]
+    fn exports_over_4_gb_use_zip64() {
+        let zip = assemble(Headers::default(), 1..=72, Bytes::from(vec![0; 64 << 20]));
+
+        assert!(zip.len > ZIP64_SIZE);
+        let end = zip
+            .writes
+            .iter()
+            .find(|bytes| bytes.starts_with(END_OF_CENTRAL_DIRECTORY_64))
+            .expect("a ZIP64 end of central directory record");
+        // The central directory offset, past 4 GB.
+        let offset = u64::from_le_bytes(end[48..56].try_into().unwrap());
+        assert!(offset > ZIP64_SIZE);
+    }
+
+    #[tokio::test]
+    async fn only_finished_batches_are_retried() {
+        use tower::ServiceExt;
+
+        let (state, state_dir) = crate::testing::state(&[]).await;
+        let job = state.jobs.adopt(jobs::batch(&[1], Map::new()));
+        let id = job.borrow().id.clone();
+        let request = axum::http::Request::post(format!("/jobs/{}/retry-failed", id))
+            .body(Body::empty())
+            .unwrap();
+        let response = crate::routes(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(job.borrow().state == JobState::Queued);
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
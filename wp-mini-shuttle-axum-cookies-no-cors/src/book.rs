@@ -0,0 +1,172 @@
+//! A generated EPUB taken apart into chapters, for edits `wp_mini_epub` has
+//! no options for. The library only hands back the finished book, so it is
+//! read back with `iepub`, changed and written out again.
+
+use anyhow::{anyhow, Result};
+use iepub::prelude::{read_from_vec, Direction, EpubBuilder, EpubHtml, EpubNav};
+
+use crate::pipeline::lang;
+
+/// What `iepub` puts at the top of every chapter; it is added again on write.
+const HEADING_START: &str = r#"<h1 style="text-align: center">"#;
+const HEADING_END: &str = "</h1>";
+
+pub struct Book {
+    pub title: String,
+    pub creator: String,
+    description: String,
+    language: &'static str,
+    direction: Direction,
+    /// File name and content of the cover image.
+    pub cover: Option<(String, Vec<u8>)>,
+    pub chapters: Vec<Chapter>,
+    /// Images and other files the chapters refer to.
+    pub assets: Vec<(String, Vec<u8>)>,
+}
+
+pub struct Chapter {
+    /// 1-based position of the chapter's part in the story, from its
+    /// `N.xhtml` file name; 0 for pages added here, such as `crate::stats`.
+    pub part: usize,
+    pub title: String,
+    /// The chapter's `<body>` content, without the title heading.
+    pub body: String,
+}
+
+impl Book {
+    pub fn read(epub: Vec<u8>, language_id: u64) -> Result<Book> {
+        let mut book = read_from_vec(epub).map_err(|e| anyhow!("Could not read EPUB: {:?}", e))?;
+
+        let cover = book.cover_mut().and_then(|cover| {
+            let name = cover.file_name().to_string();
+            cover.data_mut().map(|data| (name, data.to_vec()))
+        });
+        let mut chapters = Vec::new();
+        for chapter in book.chapters_mut() {
+            let Some(part) = chapter
+                .file_name()
+                .strip_suffix(".xhtml")
+                .and_then(|stem| stem.parse().ok())
+            else {
+                // The navigation document.
+                continue;
+            };
+            let body = String::from_utf8_lossy(chapter.data_mut().unwrap_or_default()).into_owned();
+            chapters.push(Chapter {
+                part,
+                title: chapter.title().to_string(),
+                body: strip_heading(&body).to_string(),
+            });
+        }
+        let cover_name = cover.as_ref().map(|(name, _)| name.clone());
+        let mut assets = Vec::new();
+        for asset in book.assets_mut() {
+            let name = asset.file_name().to_string();
+            if matches!(name.as_str(), "toc.ncx" | "nav.xhtml")
+                || Some(&name) == cover_name.as_ref()
+            {
+                continue;
+            }
+            if let Some(data) = asset.data_mut() {
+                assets.push((name, data.to_vec()));
+            }
+        }
+
+        Ok(Book {
+            title: book.title().to_string(),
+            creator: book.creator().unwrap_or_default().to_string(),
+            description: book.description().unwrap_or_default().to_string(),
+            language: lang::code(language_id),
+            direction: lang::direction(language_id),
+            cover,
+            chapters,
+            assets,
+        })
+    }
+
+    /// Adds a file for the chapters to refer to by `name`.
+    pub fn add_asset(&mut self, name: &str, data: Vec<u8>) {
+        self.assets.push((name.to_string(), data));
+    }
+
+    /// Writes `chapters` as a book called `title`, numbering them in the table
+    /// of contents from `first_number`.
+    pub fn write(&self, title: &str, chapters: &[Chapter], first_number: usize) -> Result<Vec<u8>> {
+        let mut builder = EpubBuilder::default()
+            .with_title(title)
+            .with_creator(&self.creator)
+            .with_description(&self.description)
+            .with_direction(self.direction.clone())
+            .custome_nav(true);
+        if let Some((name, data)) = &self.cover {
+            builder = builder.cover(name, data.clone());
+        }
+        for (name, data) in &self.assets {
+            if chapters
+                .iter()
+                .any(|chapter| chapter.body.contains(name.as_str()))
+            {
+                builder = builder.add_assets(name, data.clone());
+            }
+        }
+        for (i, chapter) in chapters.iter().enumerate() {
+            let file_name = format!("{}.xhtml", chapter.part);
+            builder = builder
+                .add_chapter(
+                    EpubHtml::default()
+                        .with_title(&chapter.title)
+                        .with_file_name(&file_name)
+                        .with_language(self.language)
+                        .with_data(chapter.body.as_bytes().to_vec()),
+                )
+                .add_nav(
+                    EpubNav::default()
+                        .with_title(format!("{}. {}", first_number + i, chapter.title))
+                        .with_file_name(&file_name),
+                );
+        }
+        builder
+            .mem()
+            .map_err(|e| anyhow!("Failed to generate EPUB in memory: {:?}", e))
+    }
+}
+
+fn strip_heading(body: &str) -> &str {
+    let trimmed = body.trim_start();
+    trimmed
+        .strip_prefix(HEADING_START)
+        .and_then(|rest| rest.split_once(HEADING_END))
+        .map_or(body, |(_, rest)| rest)
+}
+
+/// Escapes `text` for use as HTML text.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Words of text in `html`, ignoring markup.
+pub fn word_count(html: &str) -> usize {
+    let mut words = 0;
+    let mut in_tag = false;
+    let mut in_word = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                in_word = false;
+            }
+            '>' => in_tag = false,
+            _ if in_tag => {}
+            c if c.is_whitespace() => in_word = false,
+            _ => {
+                if !in_word {
+                    words += 1;
+                }
+                in_word = true;
+            }
+        }
+    }
+    words
+}
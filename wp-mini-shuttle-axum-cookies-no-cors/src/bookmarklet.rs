@@ -0,0 +1,76 @@
+//! `GET /go?url=..`: one click from a Wattpad page to its book, for a
+//! bookmarklet such as
+//!
+//! ```text
+//! javascript:location.href='https://<server>/go?url='+encodeURIComponent(location.href)
+//! ```
+//!
+//! Story and part links both work; parts are looked up to find their story.
+//! With the web UI on (see `crate::web_ui`), the answer is a redirect to it
+//! with the story filled in, so options can be picked before downloading.
+//! Without it the story is generated at once with images, as an anonymous
+//! `/generate-epub` with default options. Either way it is one of the
+//! generation routes, so it needs an API key where they do (see
+//! `crate::api_key`), before any link is looked up.
+
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Redirect, Response};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, instrument};
+
+use crate::error::MyError;
+use crate::story_url::{find_story_ref, resolve_story_id};
+use crate::{admit, respond, AppState, GenerateEpubRequest};
+
+#[derive(Deserialize)]
+pub struct GoQuery {
+    url: String,
+}
+
+#[instrument(skip_all)]
+pub async fn go(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<GoQuery>,
+) -> Result<Response, MyError> {
+    let story_ref = find_story_ref(&query.url)
+        .ok_or_else(|| MyError::InvalidRequest("url is not a Wattpad story link".into()))?;
+    let story_id = resolve_story_id(&state.anon_client, story_ref).await?;
+    info!(story_id, "Handling bookmarklet request");
+    if state.config.web_ui {
+        return Ok(Redirect::to(&format!("/?storyId={}", story_id)).into_response());
+    }
+
+    let request = json!({ "storyId": story_id, "embedImages": true });
+    let mut payload: GenerateEpubRequest = serde_json::from_value(request)
+        .map_err(|e| MyError::InvalidRequest(format!("Invalid request: {}", e)))?;
+    let (notifications, _) = admit(&state, &headers, &mut payload, "go").await?;
+    respond(state, &headers, payload, notifications).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::testing;
+
+    #[tokio::test]
+    async fn go_needs_an_api_key_where_generation_does() {
+        let (app, state_dir) = testing::app(&[
+            ("REQUIRE_API_KEY", "true"),
+            ("API_KEYS", "key"),
+            ("WEB_UI", "false"),
+        ])
+        .await;
+        let request = Request::get("/go?url=https%3A%2F%2Fwww.wattpad.com%2Fstory%2F1-title")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
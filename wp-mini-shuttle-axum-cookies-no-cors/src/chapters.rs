@@ -0,0 +1,353 @@
+//! Request options that change which chapters end up in the book and how
+//! they are grouped, applied to the generated EPUB through `crate::book`.
+//!
+//! `splitEveryChapters` / `splitEveryWords` cut a long story into volumes
+//! ("Title — Vol. 2", ...) whose tables of contents keep counting where the
+//! previous volume stopped; the volumes are returned together as a ZIP.
+//!
+//! `partOrder` lists every part ID of the story in the order the chapters
+//! should appear in, e.g. to move bonus chapters to the end.
+//!
+//! `excludeTitlePatterns` drops parts whose title matches any of the given
+//! case-insensitive regexes ("A/N", "Announcement", ...); the titles of the
+//! dropped parts are reported back with the result.
+//!
+//! `mergeSplitChapters` joins chapters Wattpad's part size cap forced
+//! authors to split, such as "Chapter 12 (Part 1)" and "Chapter 12 (Part 2)",
+//! back into one "Chapter 12".
+//!
+//! `replacements` are find/replace rules for the chapter text (see
+//! `crate::replace`).
+//!
+//! Images in formats e-readers cannot show are transcoded, and SVGs and
+//! animated GIFs are handled as `svgImages` and `gifImages` say (see
+//! `crate::images`).
+//!
+//! `altText` describes images that have no `alt` (see `crate::alt_text`).
+//!
+//! Books that came out without a cover get a generated one (see
+//! `crate::cover`) unless `noGeneratedCover` is set. A `coverImage` (see
+//! `crate::cover_upload`) replaces whichever cover the book has.
+//!
+//! `statsPage` appends a reading statistics page (see `crate::stats`).
+
+use anyhow::Result;
+use axum::body::Bytes;
+use regex::{Regex, RegexSet, RegexSetBuilder};
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use std::sync::LazyLock;
+use tracing::{error, info};
+use wp_mini::types::StoryResponse;
+use wp_mini_epub::AppError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::alt_text;
+use crate::book::{self, Book, Chapter};
+use crate::cover;
+use crate::error::MyError;
+use crate::i18n::Lang;
+use crate::images::{self, Policy};
+use crate::replace;
+use crate::stats;
+use crate::{AppState, Epub};
+
+pub use api_types::ChapterOptions;
+
+/// Volumes smaller than this are not worth a separate file.
+const MIN_WORDS_PER_VOLUME: usize = 1_000;
+const MAX_PART_ORDER: usize = 1_000;
+const MAX_EXCLUDE_PATTERNS: usize = 20;
+const MAX_PATTERN_LENGTH: usize = 200;
+/// Compiled size allowed for all exclude patterns together.
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// A title ending in a part number: "... (Part 2)", "... - Pt. 2", "... [2/3]".
+static PART_SUFFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^(?P<base>.*?)[\s\-–—:,]*(?:[(\[]?\s*\b(?:part|pt\.?)\s*(?P<n>\d+)\s*[)\]]?|[(\[]\s*(?P<m>\d+)\s*/\s*\d+\s*[)\]])$",
+    )
+    .expect("the part suffix pattern is valid")
+});
+
+/// `allow_regex` is whether the server accepts regex replacements.
+pub fn validate(options: &ChapterOptions, allow_regex: bool) -> Result<(), String> {
+    if options.split_every_chapters == Some(0) {
+        return Err("splitEveryChapters must be at least 1".into());
+    }
+    if options
+        .split_every_words
+        .is_some_and(|words| words < MIN_WORDS_PER_VOLUME)
+    {
+        return Err(format!(
+            "splitEveryWords must be at least {}",
+            MIN_WORDS_PER_VOLUME
+        ));
+    }
+    if let Some(order) = &options.part_order
+        && order.len() > MAX_PART_ORDER
+    {
+        return Err(format!(
+            "partOrder can list at most {} parts",
+            MAX_PART_ORDER
+        ));
+    }
+    replace::compile(&options.replacements, allow_regex)?;
+    exclusions(options).map(|_| ())
+}
+
+fn exclusions(options: &ChapterOptions) -> Result<Option<RegexSet>, String> {
+    let patterns = &options.exclude_title_patterns;
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    if patterns.len() > MAX_EXCLUDE_PATTERNS {
+        return Err(format!(
+            "excludeTitlePatterns can have at most {} patterns",
+            MAX_EXCLUDE_PATTERNS
+        ));
+    }
+    if patterns
+        .iter()
+        .any(|pattern| pattern.len() > MAX_PATTERN_LENGTH)
+    {
+        return Err(format!(
+            "excludeTitlePatterns entries can be at most {} characters",
+            MAX_PATTERN_LENGTH
+        ));
+    }
+    RegexSetBuilder::new(patterns)
+        .case_insensitive(true)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+        .map(Some)
+        .map_err(|e| format!("Invalid excludeTitlePatterns: {}", e))
+}
+
+/// Applies `options` to the generated `epub` of `story`; pages added to it
+/// are in `lang`.
+pub async fn apply(
+    state: &AppState,
+    epub: Epub,
+    options: &ChapterOptions,
+    story: &StoryResponse,
+    cover: Option<(String, Vec<u8>)>,
+    lang: Lang,
+) -> Result<Epub, MyError> {
+    let policy = Policy::new(&state.config, options.svg_images, options.gif_images);
+    if options.keeps_chapters()
+        && options.no_generated_cover
+        && policy.keeps_all()
+        && cover.is_none()
+    {
+        return Ok(epub);
+    }
+    let language_id = story
+        .language
+        .as_ref()
+        .and_then(|language| language.id)
+        .unwrap_or(1);
+    let failed = |e: anyhow::Error| {
+        error!(error = %e, "Could not apply chapter options");
+        MyError::App(AppError::EpubGenerationFailed)
+    };
+    let mut book = Book::read(epub.bytes.to_vec(), language_id).map_err(failed)?;
+    let replace_cover = cover.is_some();
+    if let Some((name, data)) = cover {
+        info!("Using the cover from the request");
+        book.cover = Some(images::convert(&name, &data, &policy).unwrap_or((name, data)));
+    }
+    let generate_cover = !options.no_generated_cover && book.cover.is_none();
+    if generate_cover {
+        info!("Generating a cover");
+        book.cover = Some((
+            cover::FILE_NAME.to_string(),
+            cover::generate(&book.title, &book.creator),
+        ));
+    }
+    let converted = images::process(&mut book, &policy);
+    if !generate_cover && !replace_cover && !converted && options.keeps_chapters() {
+        return Ok(epub);
+    }
+    if let Some(order) = &options.part_order {
+        reorder(&mut book, story, order).map_err(MyError::InvalidRequest)?;
+    }
+    let mut skipped = Vec::new();
+    if let Some(exclusions) = exclusions(options).map_err(MyError::InvalidRequest)? {
+        book.chapters.retain(|chapter| {
+            let keep = !exclusions.is_match(&chapter.title);
+            if !keep {
+                skipped.push(chapter.title.clone());
+            }
+            keep
+        });
+        if book.chapters.is_empty() {
+            return Err(MyError::InvalidRequest(
+                "excludeTitlePatterns matched every part of the story".into(),
+            ));
+        }
+        info!(skipped = skipped.len(), "Skipped parts by title");
+    }
+    if options.merge_split_chapters {
+        let parts = book.chapters.len();
+        book.chapters = merge_split(std::mem::take(&mut book.chapters));
+        info!(
+            merged = parts - book.chapters.len(),
+            "Merged split chapters"
+        );
+    }
+    if !options.replacements.is_empty() {
+        // Validated on admission.
+        let rules =
+            replace::compile(&options.replacements, true).map_err(MyError::InvalidRequest)?;
+        for chapter in &mut book.chapters {
+            chapter.body = replace::apply(&chapter.body, &rules);
+        }
+    }
+    if !options.plugins.is_empty() {
+        state
+            .plugins
+            .apply(&options.plugins, &mut book)
+            .map_err(failed)?;
+        info!(plugins = options.plugins.len(), "Ran WASM plugins");
+    }
+    if options.alt_text {
+        let captions = match &state.config.caption {
+            Some(config) => alt_text::caption(&state.delivery_client, config, &book).await,
+            None => HashMap::new(),
+        };
+        alt_text::fill(&mut book, &captions);
+    }
+    if options.stats_page {
+        stats::append(&mut book, story, lang);
+    }
+    let epub = Epub { skipped, ..epub };
+    write(epub, &book, options).map_err(failed)
+}
+
+/// Puts the chapters in the order of `order`, which has to name every part of
+/// the story exactly once.
+fn reorder(book: &mut Book, story: &StoryResponse, order: &[u64]) -> Result<(), String> {
+    // Chapter `N.xhtml` is the story's N-th part.
+    let ids: Vec<u64> = story
+        .parts
+        .iter()
+        .flatten()
+        .filter_map(|part| part.id)
+        .collect();
+    let mut positions = HashMap::new();
+    for (position, id) in order.iter().enumerate() {
+        if !ids.contains(id) {
+            return Err(format!("Part {} in partOrder is not part of the story", id));
+        }
+        if positions.insert(*id, position).is_some() {
+            return Err(format!("Part {} is listed twice in partOrder", id));
+        }
+    }
+    if let Some(missing) = ids.iter().find(|id| !positions.contains_key(id)) {
+        return Err(format!("partOrder is missing part {}", missing));
+    }
+    book.chapters.sort_by_key(|chapter| {
+        ids.get(chapter.part.wrapping_sub(1))
+            .and_then(|id| positions.get(id))
+            .copied()
+            .unwrap_or(usize::MAX)
+    });
+    Ok(())
+}
+
+fn write(epub: Epub, book: &Book, options: &ChapterOptions) -> Result<Epub> {
+    let volumes = split(&book.chapters, options);
+    if volumes.len() <= 1 {
+        return Ok(Epub {
+            bytes: Bytes::from(book.write(&book.title, &book.chapters, 1)?),
+            ..epub
+        });
+    }
+
+    info!(volumes = volumes.len(), "Splitting story into volumes");
+    let stem = epub.file_name.trim_end_matches(".epub");
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The volumes are compressed already.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut first_number = 1;
+    for (i, chapters) in volumes.iter().enumerate() {
+        let title = format!("{} \u{2014} Vol. {}", book.title, i + 1);
+        let volume = book.write(&title, chapters, first_number)?;
+        zip.start_file(format!("{}-vol{}.epub", stem, i + 1), stored)?;
+        zip.write_all(&volume)?;
+        first_number += chapters.len();
+    }
+    Ok(Epub {
+        file_name: format!("{}.zip", stem),
+        content_type: "application/zip",
+        bytes: Bytes::from(zip.finish()?.into_inner()),
+        ..epub
+    })
+}
+
+/// Groups consecutive chapters into volumes; a volume ends once it has
+/// `split_every_chapters` chapters or `split_every_words` words.
+fn split<'a>(chapters: &'a [Chapter], options: &ChapterOptions) -> Vec<&'a [Chapter]> {
+    let mut volumes = Vec::new();
+    let mut start = 0;
+    let mut words = 0;
+    for (i, chapter) in chapters.iter().enumerate() {
+        words += book::word_count(&chapter.body);
+        let full = options
+            .split_every_chapters
+            .is_some_and(|max| i + 1 - start >= max)
+            || options.split_every_words.is_some_and(|max| words >= max);
+        if full {
+            volumes.push(&chapters[start..=i]);
+            start = i + 1;
+            words = 0;
+        }
+    }
+    if start < chapters.len() {
+        volumes.push(&chapters[start..]);
+    }
+    volumes
+}
+
+/// The title without its part number, and the number.
+fn split_title(title: &str) -> Option<(&str, u32)> {
+    let captures = PART_SUFFIX.captures(title.trim())?;
+    let number = captures.name("n").or(captures.name("m"))?.as_str();
+    let base = captures.name("base")?.as_str().trim();
+    Some((base, number.parse().ok()?)).filter(|(base, _)| !base.is_empty())
+}
+
+/// Appends each chapter numbered one past the previous one with the same
+/// base title to it. An unnumbered chapter counts as part 1 when a part 2 of
+/// it follows.
+fn merge_split(chapters: Vec<Chapter>) -> Vec<Chapter> {
+    let mut merged: Vec<Chapter> = Vec::new();
+    // The base title and part number of the last entry in `merged`.
+    let mut last: Option<(String, u32)> = None;
+    for chapter in chapters {
+        let split = split_title(&chapter.title).map(|(base, n)| (base.to_string(), n));
+        let continues = match (&split, &last, merged.last()) {
+            (Some((base, n)), Some((last_base, last_n)), _) => {
+                base.eq_ignore_ascii_case(last_base) && *n == last_n + 1
+            }
+            (Some((base, 2)), None, Some(previous)) => {
+                previous.title.trim().eq_ignore_ascii_case(base)
+            }
+            _ => false,
+        };
+        match (continues, merged.last_mut(), split) {
+            (true, Some(previous), Some((base, n))) => {
+                previous.body.push_str(&chapter.body);
+                previous.title = base.clone();
+                last = Some((base, n));
+            }
+            (_, _, split) => {
+                last = split;
+                merged.push(chapter);
+            }
+        }
+    }
+    merged
+}
@@ -0,0 +1,83 @@
+//! Authenticated clients kept per Wattpad session, so a user's next download
+//! reuses the connections and TLS sessions of the last one instead of
+//! opening new ones.
+//!
+//! Clients are keyed by a hash of the Wattpad cookies they were made with,
+//! so only sessions sending exactly the same cookies share one, and the
+//! cookies themselves are not kept as keys. A client unused for `IDLE_TTL`
+//! is dropped; past `MAX_CLIENTS` the least recently used one goes first.
+//!
+//! The pool is a sharded map, so sessions looking up their clients only
+//! contend with the ones in the same shard. Requests building clients at the
+//! same moment can leave it over `MAX_CLIENTS` by as many until the next
+//! insert evicts again.
+
+use dashmap::DashMap;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{encode_hex, Cookie};
+
+const IDLE_TTL: Duration = Duration::from_secs(10 * 60);
+const MAX_CLIENTS: usize = 256;
+
+#[derive(Default)]
+pub struct ClientPool {
+    /// Each client and when it was last handed out.
+    clients: DashMap<String, (Instant, Arc<Client>)>,
+}
+
+impl ClientPool {
+    /// The client for `cookies`, made with `build` when there is none yet.
+    pub fn get_or_build<E>(
+        &self,
+        cookies: &[Cookie],
+        build: impl FnOnce() -> Result<Client, E>,
+    ) -> Result<Arc<Client>, E> {
+        let key = fingerprint(cookies);
+        let now = Instant::now();
+        if let Some(mut entry) = self.clients.get_mut(&key)
+            && now.duration_since(entry.0) < IDLE_TTL
+        {
+            entry.0 = now;
+            return Ok(entry.1.clone());
+        }
+
+        // Built without holding a shard; two requests racing here just build
+        // twice. No entry may be held while the whole map is walked below.
+        let client = Arc::new(build()?);
+        self.clients
+            .retain(|_, (used, _)| now.duration_since(*used) < IDLE_TTL);
+        if self.clients.len() >= MAX_CLIENTS
+            && let Some(oldest) = self
+                .clients
+                .iter()
+                .min_by_key(|entry| entry.0)
+                .map(|entry| entry.key().clone())
+        {
+            self.clients.remove(&oldest);
+        }
+        self.clients.insert(key, (now, client.clone()));
+        Ok(client)
+    }
+}
+
+/// A hash of the Wattpad cookies, the same whatever order they come in.
+pub fn fingerprint(cookies: &[Cookie]) -> String {
+    let mut pairs: Vec<(&str, &str)> = cookies
+        .iter()
+        .filter(|cookie| cookie.domain.contains("wattpad.com"))
+        .map(|cookie| (cookie.name.as_str(), cookie.value.as_str()))
+        .collect();
+    pairs.sort_unstable();
+    let mut hasher = Sha256::new();
+    for (name, value) in pairs {
+        hasher.update(name.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\n");
+    }
+    encode_hex(&hasher.finalize())
+}
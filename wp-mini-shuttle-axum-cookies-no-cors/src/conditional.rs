@@ -0,0 +1,66 @@
+//! `ETag` and `If-None-Match` for `/generate-epub`, so the extension can skip
+//! downloading a story again that has not changed since it last got it.
+//!
+//! The tag is made from the story's `modifyDate`, which Wattpad moves on every
+//! edit and new part, the request's options and this server's version. It is
+//! weak: a book made twice from the same story is not the same byte for byte.
+//! Finding it costs one metadata request before the download, shared with
+//! the rest of the request through `crate::story_cache`; when it matches `If-None-Match` the answer is `304 Not Modified`
+//! and nothing is generated. Deliveries get no tag, as they return no file.
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::story_cache;
+use crate::{authenticated, encode_hex, opf, AppState, GenerateEpubRequest};
+
+/// The tag of the file `payload` would make, or `None` if the story's
+/// `modifyDate` cannot be looked up, in which case it is generated as usual.
+pub async fn etag(
+    state: &AppState,
+    client: &Client,
+    payload: &GenerateEpubRequest,
+) -> Option<HeaderValue> {
+    if payload.delivery.is_some() {
+        return None;
+    }
+    let story = story_cache::story(state, client, payload.story_id, !authenticated(payload));
+    let modified = match story.await {
+        Ok(story) => story.modify_date?,
+        Err(e) => {
+            warn!(error = %e, "Could not look up when the story was modified");
+            return None;
+        }
+    };
+    let key = serde_json::to_vec(&(
+        env!("CARGO_PKG_VERSION"),
+        modified,
+        opf::identifier(payload.story_id, payload.embed_images, &payload.chapters),
+        payload.format,
+        authenticated(payload),
+        &payload.metadata,
+        &payload.cover_image,
+        &payload.style,
+        // The only page in the request's language.
+        payload.chapters.stats_page.then_some(payload.language),
+    ))
+    .ok()?;
+    let tag = format!("W/\"{}\"", encode_hex(&Sha256::digest(&key)[..16]));
+    HeaderValue::from_str(&tag).ok()
+}
+
+/// Whether `If-None-Match` names `etag`, compared weakly as RFC 9110 asks.
+pub fn matches(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
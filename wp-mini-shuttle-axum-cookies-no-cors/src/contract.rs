@@ -0,0 +1,197 @@
+//! Request bodies recorded from released extension versions, replayed against
+//! the whole router, so a serde rename or a new required field fails here
+//! instead of in the hands of users who have not updated yet.
+//!
+//! The server runs in maintenance mode, which turns away every request it
+//! could read with `503` before anything is fetched from Wattpad; a body it
+//! could not read gets `400` instead. What it reads from each body is checked
+//! apart, field by field.
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use serde_json::Value;
+use std::path::PathBuf;
+use tower::ServiceExt;
+
+use crate::{testing, Format, GenerateEpubRequest};
+
+const MAINTENANCE_MESSAGE: &str = "Down for the contract tests";
+const EXTENSION_ORIGIN: &str = "chrome-extension://abcdefghijklmnopabcdefghijklmnop";
+
+/// A body a released extension sends, and what the server reads from it.
+struct Recorded {
+    version: &'static str,
+    endpoint: &'static str,
+    body: &'static str,
+    embed_images: bool,
+    cookies: &'static [&'static str],
+}
+
+const SIGNED_IN: &[&str] = &["wp_id", "token", "locale"];
+
+/// Add the bodies of each release.
+const RECORDED: [Recorded; 4] = [
+    Recorded {
+        version: "0.2.6",
+        endpoint: "/generate-epub",
+        body: include_str!("../fixtures/extension/0.2.6/generate-epub.json"),
+        embed_images: true,
+        cookies: SIGNED_IN,
+    },
+    Recorded {
+        version: "0.2.8",
+        endpoint: "/generate-epub",
+        body: include_str!("../fixtures/extension/0.2.8/generate-epub.json"),
+        embed_images: false,
+        cookies: SIGNED_IN,
+    },
+    Recorded {
+        version: "0.2.8",
+        endpoint: "/generate-epub",
+        body: include_str!("../fixtures/extension/0.2.8/generate-epub-signed-out.json"),
+        embed_images: true,
+        cookies: &[],
+    },
+    Recorded {
+        version: "0.2.9",
+        endpoint: "/generate-epub",
+        body: include_str!("../fixtures/extension/0.2.9/generate-epub.json"),
+        embed_images: true,
+        cookies: SIGNED_IN,
+    },
+];
+
+/// The router as deployed with no secrets set, in maintenance mode.
+async fn router() -> (Router, PathBuf) {
+    let state_dir = testing::state_dir();
+    let maintenance = serde_json::json!({
+        "message": MAINTENANCE_MESSAGE,
+        "eta": null,
+        "since": 0,
+        "by": "contract tests",
+    });
+    std::fs::write(
+        state_dir.join("state/maintenance.json"),
+        maintenance.to_string(),
+    )
+    .unwrap();
+    (
+        crate::app(testing::config(&state_dir, &[])).await,
+        state_dir,
+    )
+}
+
+async fn post(router: &Router, endpoint: &str, body: &str) -> (StatusCode, Value, Option<String>) {
+    let request = Request::post(endpoint)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ORIGIN, EXTENSION_ORIGIN)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let allowed_origin = response
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body).unwrap(),
+        allowed_origin,
+    )
+}
+
+#[tokio::test]
+async fn recorded_extension_requests_are_still_accepted() {
+    let (router, state_dir) = router().await;
+    for recorded in RECORDED {
+        let version = recorded.version;
+        let (status, body, allowed_origin) = post(&router, recorded.endpoint, recorded.body).await;
+        assert_eq!(
+            status,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "extension {} got {} from {}: {}",
+            version,
+            status,
+            recorded.endpoint,
+            body
+        );
+        // What the extension shows when a download fails.
+        assert_eq!(body["error"], MAINTENANCE_MESSAGE, "extension {}", version);
+        assert_eq!(
+            allowed_origin.as_deref(),
+            Some(EXTENSION_ORIGIN),
+            "extension {}",
+            version
+        );
+    }
+    std::fs::remove_dir_all(state_dir).unwrap();
+}
+
+#[test]
+fn recorded_extension_requests_are_read_as_sent() {
+    for recorded in RECORDED {
+        let version = recorded.version;
+        let mut request: GenerateEpubRequest = serde_json::from_str(recorded.body).unwrap();
+        crate::upgrade(&mut request);
+        assert_eq!(request.story_id, 336166598, "extension {}", version);
+        assert_eq!(
+            request.embed_images, recorded.embed_images,
+            "extension {}",
+            version
+        );
+        assert_eq!(request.format, Format::Epub, "extension {}", version);
+        let cookies: Vec<&str> = request
+            .cookies
+            .iter()
+            .flatten()
+            .map(|cookie| cookie.name.as_str())
+            .collect();
+        assert_eq!(cookies, recorded.cookies, "extension {}", version);
+    }
+}
+
+#[tokio::test]
+async fn unreadable_requests_are_refused_with_a_message() {
+    let (router, state_dir) = router().await;
+    let (status, body, _) = post(&router, "/generate-epub", r#"{"embedImages": true}"#).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"].is_string(), "{}", body);
+    std::fs::remove_dir_all(state_dir).unwrap();
+}
+
+#[tokio::test]
+async fn the_file_name_header_is_exposed_to_the_extension() {
+    let (router, state_dir) = router().await;
+    let request = Request::options("/generate-epub")
+        .header(header::ORIGIN, EXTENSION_ORIGIN)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+        .body(Body::empty())
+        .unwrap();
+    let preflight = router.clone().oneshot(request).await.unwrap();
+    assert!(preflight.status().is_success());
+    assert_eq!(
+        preflight.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        EXTENSION_ORIGIN
+    );
+
+    let request = Request::post("/generate-epub")
+        .header(header::ORIGIN, EXTENSION_ORIGIN)
+        .body(Body::empty())
+        .unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    let exposed = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(
+        exposed
+            .split(',')
+            .any(|name| name.trim() == "content-disposition"),
+        "{}",
+        exposed
+    );
+    std::fs::remove_dir_all(state_dir).unwrap();
+}
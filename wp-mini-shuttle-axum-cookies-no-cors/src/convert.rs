@@ -0,0 +1,69 @@
+//! `format: "azw3"`: books for Kindles, converted from the EPUB by Calibre's
+//! `ebook-convert`, which the operator installs next to the server and points
+//! `EBOOK_CONVERT` at. There is no Rust writer for Amazon's formats to build
+//! in, so without it the format is refused on admission.
+//!
+//! Each conversion gets a directory of its own under the system's temporary
+//! directory, removed again afterwards, and is given `CONVERT_TIMEOUT`.
+
+use anyhow::{anyhow, Result};
+use axum::body::Bytes;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{error, info};
+use uuid::Uuid;
+use wp_mini_epub::AppError;
+
+use crate::error::MyError;
+use crate::Epub;
+
+const CONVERT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// `epub` converted to AZW3 with the `ebook-convert` at `program`.
+pub async fn azw3(program: &str, epub: Epub) -> Result<Epub, MyError> {
+    info!("Converting book to AZW3");
+    let bytes = run(program, &epub.bytes).await.map_err(|e| {
+        error!(error = %e, "Could not convert book");
+        MyError::App(AppError::EpubGenerationFailed)
+    })?;
+    let stem = epub.file_name.trim_end_matches(".epub");
+    Ok(Epub {
+        file_name: format!("{}.azw3", stem),
+        content_type: "application/vnd.amazon.mobi8-ebook",
+        bytes: Bytes::from(bytes),
+        ..epub
+    })
+}
+
+async fn run(program: &str, epub: &[u8]) -> Result<Vec<u8>> {
+    let dir = std::env::temp_dir().join(format!("wattdownload-{}", Uuid::new_v4()));
+    tokio::fs::create_dir(&dir).await?;
+    let result = convert(program, &dir, epub).await;
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    result
+}
+
+async fn convert(program: &str, dir: &Path, epub: &[u8]) -> Result<Vec<u8>> {
+    let input = dir.join("book.epub");
+    let output = dir.join("book.azw3");
+    tokio::fs::write(&input, epub).await?;
+    let run = Command::new(program)
+        .arg(&input)
+        .arg(&output)
+        .kill_on_drop(true)
+        .output();
+    let result = tokio::time::timeout(CONVERT_TIMEOUT, run)
+        .await
+        .map_err(|_| anyhow!("ebook-convert took longer than {:?}", CONVERT_TIMEOUT))??;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let last = stderr.lines().last().unwrap_or_default();
+        return Err(anyhow!(
+            "ebook-convert failed ({}): {}",
+            result.status,
+            last
+        ));
+    }
+    Ok(tokio::fs::read(&output).await?)
+}
@@ -0,0 +1,85 @@
+//! CORS policies per route group.
+//!
+//! Read-only endpoints (job status, downloads) are open to any origin so links
+//! work from anywhere. Endpoints that start work or accept files only answer
+//! the origins in `CorsConfig`: the extension builds named by id, other
+//! extensions and web pages listed whole. With none listed any Chrome
+//! extension may use them, which keeps web pages from spending a visitor's
+//! Wattpad session here while every build of the extension, unpacked ones
+//! included, keeps working. `CORS_PERMISSIVE` opens them to every origin, for
+//! development. Admin and webhook routes get no CORS headers at all, which
+//! keeps browsers on other origins from reading them.
+//!
+//! Every header the extension reads is exposed by name, `Content-Disposition`
+//! for the file name first among them.
+
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::warn;
+
+use crate::config::CorsConfig;
+use crate::file_response::CONTENT_SHA256;
+use crate::signing::CONTENT_SIGNATURE;
+use crate::uploads::{TUS_RESUMABLE, UPLOAD_LENGTH, UPLOAD_OFFSET};
+use crate::{CACHE_STATUS, SKIPPED_CHAPTERS};
+
+const EXTENSION_SCHEME: &str = "chrome-extension://";
+
+/// Response headers scripts on an allowed origin may read.
+const EXPOSED: [HeaderName; 15] = [
+    header::CONTENT_DISPOSITION,
+    header::CONTENT_LENGTH,
+    header::ETAG,
+    header::LOCATION,
+    header::RETRY_AFTER,
+    header::WARNING,
+    HeaderName::from_static("deprecation"),
+    HeaderName::from_static("sunset"),
+    CACHE_STATUS,
+    SKIPPED_CHAPTERS,
+    CONTENT_SHA256,
+    CONTENT_SIGNATURE,
+    UPLOAD_OFFSET,
+    UPLOAD_LENGTH,
+    TUS_RESUMABLE,
+];
+
+pub fn public() -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods([Method::GET, Method::HEAD])
+        .allow_headers(Any)
+        .expose_headers(Any)
+}
+
+pub fn generation(config: &CorsConfig) -> CorsLayer {
+    if config.permissive {
+        warn!("CORS_PERMISSIVE is set; any web page may start downloads");
+        return CorsLayer::permissive();
+    }
+    let layer = CorsLayer::new()
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers(EXPOSED);
+
+    let origins: Vec<HeaderValue> = config
+        .extension_ids
+        .iter()
+        .map(|id| format!("{}{}", EXTENSION_SCHEME, id))
+        .chain(config.extension_origins.iter().cloned())
+        .chain(config.web_origins.iter().cloned())
+        .filter_map(|origin| match HeaderValue::from_str(&origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!(origin, "Ignoring invalid CORS origin");
+                None
+            }
+        })
+        .collect();
+    match origins.is_empty() {
+        true => layer.allow_origin(AllowOrigin::predicate(|origin, _| {
+            origin.as_bytes().starts_with(EXTENSION_SCHEME.as_bytes())
+        })),
+        false => layer.allow_origin(AllowOrigin::list(origins)),
+    }
+}
@@ -0,0 +1,100 @@
+//! A plain typographic cover for books that came out without one, because the
+//! story has no cover or it could not be downloaded.
+//!
+//! The cover is an SVG, so the title is set in the reader's own serif font
+//! and no font has to ship with the server.
+
+use std::fmt::Write;
+
+use crate::book::escape;
+
+pub const FILE_NAME: &str = "cover.svg";
+const WIDTH: usize = 600;
+const HEIGHT: usize = 900;
+/// Characters per title line before it wraps.
+const LINE_LENGTH: usize = 18;
+const MAX_LINES: usize = 6;
+/// Background and accent colors; a title always gets the same pair.
+const PALETTE: [(&str, &str); 6] = [
+    ("#2e4057", "#f6ae2d"),
+    ("#5b3758", "#f9c0c0"),
+    ("#1b4332", "#b7e4c7"),
+    ("#7f2f2f", "#f2e3bc"),
+    ("#283845", "#8fc1e3"),
+    ("#3d3b30", "#e0c879"),
+];
+
+/// The cover for `title` by `author`, as SVG.
+pub fn generate(title: &str, author: &str) -> Vec<u8> {
+    let hash = title.bytes().fold(0usize, |hash, b| {
+        hash.wrapping_mul(31).wrapping_add(b as usize)
+    });
+    let (background, accent) = PALETTE[hash % PALETTE.len()];
+
+    let lines = wrap(title);
+    let font_size = match lines.iter().map(|line| line.chars().count()).max() {
+        Some(longest) if longest > 14 => 44,
+        _ => 56,
+    };
+    let line_height = font_size * 5 / 4;
+    let first_line = HEIGHT * 2 / 5 - line_height * (lines.len().saturating_sub(1)) / 2;
+
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {w} {h}" width="{w}" height="{h}"><rect width="{w}" height="{h}" fill="{background}"/><rect x="40" y="40" width="{inner_w}" height="{inner_h}" fill="none" stroke="{accent}" stroke-width="4"/>"#,
+        w = WIDTH,
+        h = HEIGHT,
+        inner_w = WIDTH - 80,
+        inner_h = HEIGHT - 80,
+    );
+    let _ = write!(
+        svg,
+        r#"<text x="{}" text-anchor="middle" font-family="serif" font-size="{}" font-weight="bold" fill="{}">"#,
+        WIDTH / 2,
+        font_size,
+        accent
+    );
+    for (i, line) in lines.iter().enumerate() {
+        let _ = write!(
+            svg,
+            r#"<tspan x="{}" y="{}">{}</tspan>"#,
+            WIDTH / 2,
+            first_line + i * line_height,
+            escape(line)
+        );
+    }
+    svg.push_str("</text>");
+    if !author.is_empty() {
+        let _ = write!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle" font-family="serif" font-size="32" fill="{}">{}</text>"#,
+            WIDTH / 2,
+            HEIGHT * 4 / 5,
+            accent,
+            escape(author)
+        );
+    }
+    svg.push_str("</svg>");
+    svg.into_bytes()
+}
+
+/// Breaks `title` into lines at word boundaries, ending in "…" when it needs
+/// more than `MAX_LINES`.
+fn wrap(title: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in title.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= LINE_LENGTH => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    if lines.len() > MAX_LINES {
+        lines.truncate(MAX_LINES);
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+    lines
+}
@@ -0,0 +1,156 @@
+//! `GET /story/{id}/cover`: the story's cover image, for the extension's UI
+//! and for Calibre libraries.
+//!
+//! The cover URL Wattpad lists is a resized copy (`...-256-k....jpg`, often
+//! with resize parameters). With `size=original`, the default, the query is
+//! dropped and the largest width Wattpad serves is asked for first, falling
+//! back to the listed copy when there is none; `size=listed` proxies the URL
+//! as listed. The image is passed on with its content type and may be cached
+//! by the client for a day.
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use reqwest::Url;
+use serde::Deserialize;
+use tracing::{info, warn};
+use wp_mini::WattpadError;
+use wp_mini_epub::AppError;
+
+use crate::error::{map_upstream_error, MyError};
+use crate::{abuse, http_client, story_cache, AppState};
+
+/// The widest copy of a cover Wattpad keeps.
+const LARGEST_WIDTH: &str = "512";
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Size {
+    #[default]
+    Original,
+    Listed,
+}
+
+#[derive(Deserialize)]
+pub struct CoverQuery {
+    #[serde(default)]
+    size: Size,
+}
+
+pub async fn cover(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+    Query(query): Query<CoverQuery>,
+) -> Result<Response, MyError> {
+    state
+        .scraping
+        .check(&*state.shared, &abuse::client_key(&headers), id)
+        .await
+        .map_err(MyError::Throttled)?;
+
+    let story = story_cache::story(&state, &state.anon_client, id, true)
+        .await
+        .map_err(|e| match e {
+            WattpadError::StoryNotFound => {
+                MyError::NotFound(format!("Story {} could not be found", id))
+            }
+            _ => MyError::App(AppError::MetadataFetchFailed),
+        })?;
+    let listed = story
+        .cover
+        .filter(|cover| !cover.is_empty())
+        .ok_or_else(|| MyError::NotFound(format!("Story {} has no cover", id)))?;
+
+    let candidates = match query.size {
+        Size::Original => original_urls(&listed),
+        Size::Listed => vec![listed],
+    };
+    for url in candidates {
+        let response = http_client::send(state.anon_client.get(&url))
+            .await
+            .map_err(map_upstream_error)?;
+        if !response.status().is_success() {
+            info!(status = %response.status(), url, "Cover is not at this URL");
+            continue;
+        }
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .filter(|value| value.as_bytes().starts_with(b"image/"))
+            .cloned();
+        let bytes = response.bytes().await.map_err(|e| {
+            warn!(error = %e, "Could not read the cover");
+            MyError::App(AppError::DownloadFailed)
+        })?;
+        let content_type =
+            content_type.unwrap_or_else(|| HeaderValue::from_static(sniff_content_type(&bytes)));
+        let extension = extension(&content_type);
+        let disposition = format!("inline; filename=\"{}-cover.{}\"", id, extension);
+        return Ok((
+            [
+                (header::CONTENT_TYPE, content_type),
+                (
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static(CACHE_CONTROL),
+                ),
+                (
+                    header::CONTENT_DISPOSITION,
+                    HeaderValue::from_str(&disposition).expect("file name is ASCII"),
+                ),
+            ],
+            bytes,
+        )
+            .into_response());
+    }
+    Err(MyError::App(AppError::DownloadFailed))
+}
+
+/// Where the full-size cover may be, best first: `url` without its query and
+/// with the width in its file name raised to `LARGEST_WIDTH`, then without
+/// its query, then as listed.
+fn original_urls(url: &str) -> Vec<String> {
+    let mut urls = Vec::new();
+    if let Ok(mut parsed) = Url::parse(url) {
+        parsed.set_query(None);
+        parsed.set_fragment(None);
+        let path = parsed.path().to_string();
+        let (dir, file) = path.rsplit_once('/').unwrap_or(("", &path));
+        // `<story id>-<width>-<key>.<ext>`
+        let mut pieces: Vec<&str> = file.splitn(3, '-').collect();
+        if pieces.len() == 3
+            && !pieces[1].is_empty()
+            && pieces[1].bytes().all(|b| b.is_ascii_digit())
+            && pieces[1] != LARGEST_WIDTH
+        {
+            pieces[1] = LARGEST_WIDTH;
+            let mut largest = parsed.clone();
+            largest.set_path(&format!("{}/{}", dir, pieces.join("-")));
+            urls.push(largest.to_string());
+        }
+        urls.push(parsed.to_string());
+    }
+    if !urls.iter().any(|candidate| candidate == url) {
+        urls.push(url.to_string());
+    }
+    urls
+}
+
+fn sniff_content_type(data: &[u8]) -> &'static str {
+    match data {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+fn extension(content_type: &HeaderValue) -> &'static str {
+    match content_type.as_bytes() {
+        b"image/png" => "png",
+        b"image/gif" => "gif",
+        b"image/webp" => "webp",
+        _ => "jpg",
+    }
+}
@@ -0,0 +1,66 @@
+//! `coverImage` on `/generate-epub`: a cover of the user's own, in base64 or
+//! as a `data:` URL, to use instead of the story's, which on Wattpad is often
+//! a small placeholder.
+//!
+//! JPEG, PNG, GIF and WebP are taken, up to `MAX_BYTES` (base64 makes that
+//! about 1.4 MB of the 2 MB a request body may have) and `MAX_SIDE` pixels a
+//! side. The image goes through `crate::images` like the others in the book,
+//! so a WebP cover is transcoded unless `EPUB_IMAGE_FORMATS` keeps WebP.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::{ImageFormat, ImageReader};
+use std::io::Cursor;
+
+pub const MAX_BYTES: usize = 1024 * 1024;
+const MAX_SIDE: u32 = 4_096;
+
+/// The cover in `encoded`, with a file name for it in the book.
+pub fn decode(encoded: &str) -> Result<(String, Vec<u8>), String> {
+    let encoded = match encoded.strip_prefix("data:") {
+        Some(url) => url
+            .split_once(";base64,")
+            .map(|(_, data)| data)
+            .ok_or("coverImage must be base64 or a base64 data: URL")?,
+        None => encoded,
+    };
+    if encoded.len() > MAX_BYTES.div_ceil(3) * 4 {
+        return Err(too_big());
+    }
+    let data = STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| "coverImage is not valid base64".to_string())?;
+    if data.len() > MAX_BYTES {
+        return Err(too_big());
+    }
+    let reader = ImageReader::new(Cursor::new(&data))
+        .with_guessed_format()
+        .map_err(|_| unsupported())?;
+    let extension = match reader.format() {
+        Some(ImageFormat::Jpeg) => "jpg",
+        Some(ImageFormat::Png) => "png",
+        Some(ImageFormat::Gif) => "gif",
+        Some(ImageFormat::WebP) => "webp",
+        _ => return Err(unsupported()),
+    };
+    // Only the header is read, so oversized images are refused before
+    // anything is decoded.
+    let (width, height) = reader
+        .into_dimensions()
+        .map_err(|_| "coverImage could not be read as an image".to_string())?;
+    if width.max(height) > MAX_SIDE {
+        return Err(format!(
+            "coverImage can be at most {} pixels on a side",
+            MAX_SIDE
+        ));
+    }
+    Ok((format!("cover.{}", extension), data))
+}
+
+fn too_big() -> String {
+    format!("coverImage can be at most {} KB", MAX_BYTES / 1024)
+}
+
+fn unsupported() -> String {
+    "coverImage must be a JPEG, PNG, GIF or WebP image".to_string()
+}
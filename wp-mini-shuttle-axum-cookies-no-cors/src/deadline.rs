@@ -0,0 +1,64 @@
+//! A deadline for each request to the routes that fetch from Wattpad, so a
+//! runaway story or a Wattpad endpoint that never answers cannot hold a
+//! connection (and the work behind it) forever.
+//!
+//! A request that has no response within `GENERATION_TIMEOUT_SECS` gets
+//! `504`; its handler is dropped, which stops its fetches where they stand.
+//! Responses sent as they are made (streamed EPUBs, batch ZIPs, progress and
+//! event streams) have their headers out by then, so their bodies are cut off
+//! at the deadline instead; the work feeding them stops once it finds the
+//! body gone, as it does when the client leaves. Work meant to outlive the
+//! request, like jobs and deliveries, is not bounded here (see
+//! `crate::job_budget` for jobs). Uploads are left out, as a slow connection
+//! is no runaway.
+
+use axum::body::{Body, HttpBody};
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::stream::{self, StreamExt};
+use std::io;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+use tracing::warn;
+
+use crate::error::MyError;
+
+pub async fn apply(
+    State(limit): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Result<Response, MyError> {
+    let deadline = Instant::now() + limit;
+    let path = request.uri().path().to_string();
+    let response = tokio::time::timeout_at(deadline, next.run(request))
+        .await
+        .map_err(|_| {
+            warn!(path, ?limit, "Request ran past its deadline");
+            MyError::TimedOut(limit)
+        })?;
+    // Bodies already made are not cut short.
+    if response.body().size_hint().exact().is_some() {
+        return Ok(response);
+    }
+    let (parts, body) = response.into_parts();
+    let body = stream::unfold(Some(body.into_data_stream()), move |body| {
+        let path = path.clone();
+        async move {
+            let mut body = body?;
+            tokio::select! {
+                chunk = body.next() => {
+                    let chunk = chunk?.map_err(io::Error::other);
+                    Some((chunk, Some(body)))
+                }
+                _ = sleep_until(deadline) => {
+                    warn!(path, ?limit, "Streamed response ran past its deadline");
+                    let error = io::Error::new(io::ErrorKind::TimedOut, "deadline passed");
+                    // Dropping the body tells whatever feeds it to stop.
+                    Some((Err(error), None))
+                }
+            }
+        }
+    });
+    Ok(Response::from_parts(parts, Body::from_stream(body)))
+}
@@ -0,0 +1,84 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use super::{DeliveryError, DeliveryFile, DeliveryReceipt};
+
+const TARGET: &str = "dropbox";
+const UPLOAD_URL: &str = "https://content.dropboxapi.com/2/files/upload";
+const DEFAULT_FOLDER: &str = "/WattDownload";
+
+#[derive(Deserialize)]
+struct UploadResponse {
+    id: String,
+    path_display: Option<String>,
+}
+
+pub(super) async fn upload(
+    client: &Client,
+    access_token: &str,
+    folder: Option<&str>,
+    file: DeliveryFile<'_>,
+) -> Result<DeliveryReceipt, DeliveryError> {
+    let folder = folder.unwrap_or(DEFAULT_FOLDER).trim_end_matches('/');
+    let path = if folder.starts_with('/') {
+        format!("{}/{}", folder, file.file_name)
+    } else {
+        format!("/{}/{}", folder, file.file_name)
+    };
+
+    let api_arg = serde_json::json!({
+        "path": path,
+        "mode": "add",
+        "autorename": true,
+        "mute": false,
+    });
+
+    let response = client
+        .post(UPLOAD_URL)
+        .bearer_auth(access_token)
+        .header("Dropbox-API-Arg", header_safe_json(&api_arg))
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .body(file.bytes)
+        .send()
+        .await
+        .map_err(|e| {
+            warn!(error = %e, "Dropbox upload request failed");
+            DeliveryError::new(TARGET, "could not reach Dropbox")
+        })?;
+
+    if !response.status().is_success() {
+        return Err(DeliveryError::from_status(TARGET, response.status()));
+    }
+
+    let uploaded: UploadResponse = response
+        .json()
+        .await
+        .map_err(|_| DeliveryError::new(TARGET, "unexpected response from Dropbox"))?;
+
+    info!(path = ?uploaded.path_display, "Uploaded file to Dropbox");
+    Ok(DeliveryReceipt {
+        target: TARGET.into(),
+        remote_id: uploaded.id,
+        remote_path: uploaded.path_display,
+        web_url: None,
+    })
+}
+
+/// Dropbox passes arguments in an HTTP header, which must be ASCII, so any
+/// non-ASCII characters (common in story titles) are escaped as `\uXXXX`.
+fn header_safe_json(value: &serde_json::Value) -> String {
+    let mut out = String::new();
+    for c in value.to_string().chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            let mut units = [0u16; 2];
+            for unit in c.encode_utf16(&mut units) {
+                out.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    out
+}
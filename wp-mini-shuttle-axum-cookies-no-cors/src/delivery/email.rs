@@ -0,0 +1,144 @@
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tracing::{info, warn};
+
+use super::{DeliveryContext, DeliveryError, DeliveryFile, DeliveryReceipt};
+use crate::artifacts::Artifact;
+use crate::config::{SmtpConfig, SmtpTls};
+use crate::i18n::{self, Lang};
+use crate::notify::human_size;
+
+const TARGET: &str = "email";
+
+pub(super) async fn send(
+    ctx: &DeliveryContext<'_>,
+    to: &str,
+    lang: Lang,
+    file: DeliveryFile<'_>,
+) -> Result<DeliveryReceipt, DeliveryError> {
+    let keys = ctx.config.keys();
+    let smtp = keys
+        .smtp
+        .as_ref()
+        .ok_or_else(|| DeliveryError::not_configured(TARGET))?;
+    let to: Mailbox = to
+        .parse()
+        .map_err(|_| DeliveryError::invalid(TARGET, "the recipient address is not valid"))?;
+    let from: Mailbox = smtp
+        .from
+        .parse()
+        .map_err(|_| DeliveryError::new(TARGET, "the sender address is misconfigured"))?;
+
+    let branding = &ctx.config.branding;
+    let size = human_size(file.bytes.len());
+    let expires_hours = (ctx.artifacts.link_lifetime(ctx.link).as_secs() / 3600).to_string();
+    let template = |set: &Option<String>, key: &str| {
+        set.clone()
+            .unwrap_or_else(|| i18n::message(lang, key).to_string())
+    };
+    let render = |template: String, link: &str| {
+        template
+            .replace("{title}", file.title)
+            .replace("{file_name}", file.file_name)
+            .replace("{size}", &size)
+            .replace("{link}", link)
+            .replace("{expires_hours}", &expires_hours)
+            .replace("{service_name}", &branding.name)
+    };
+    let body = |template: String, link: &str| match &branding.footer {
+        Some(footer) => format!("{}\n-- \n{}\n", render(template, link), footer),
+        None => render(template, link),
+    };
+
+    let mut builder = Message::builder().from(from).to(to.clone()).subject(render(
+        template(&smtp.templates.subject, "email.subject"),
+        "",
+    ));
+    if let Some(contact) = &branding.contact_email {
+        match contact.parse::<Mailbox>() {
+            Ok(contact) => builder = builder.reply_to(contact),
+            Err(_) => warn!("CONTACT_EMAIL is not a valid address; sending without reply-to"),
+        }
+    }
+
+    let (message, link) = if file.bytes.len() <= smtp.max_attachment_bytes {
+        let content_type = ContentType::parse(file.content_type)
+            .map_err(|_| DeliveryError::new(TARGET, "invalid content type"))?;
+        let message = builder.multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(body(
+                    template(&smtp.templates.attachment_body, "email.attachmentBody"),
+                    "",
+                )))
+                .singlepart(
+                    Attachment::new(file.file_name.to_string())
+                        .body(file.bytes.to_vec(), content_type),
+                ),
+        );
+        (message, None)
+    } else {
+        // Too big for most mailboxes: park the file and mail a link to it instead.
+        let base_url = ctx.config.public_base_url.as_deref().ok_or_else(|| {
+            DeliveryError::too_large(
+                TARGET,
+                "the file is too large to attach and this server has no PUBLIC_BASE_URL for links",
+            )
+        })?;
+        let path = ctx
+            .artifacts
+            .publish(
+                Artifact {
+                    file_name: file.file_name.to_string(),
+                    content_type: file.content_type.to_string(),
+                    bytes: file.bytes,
+                },
+                ctx.link,
+            )
+            .await
+            .map_err(|_| {
+                DeliveryError::new(TARGET, "could not store the file for a download link")
+            })?;
+        let link = format!("{}{}", base_url, path);
+        let message = builder.singlepart(SinglePart::plain(body(
+            template(&smtp.templates.link_body, "email.linkBody"),
+            &link,
+        )));
+        (message, Some(link))
+    };
+    let message =
+        message.map_err(|_| DeliveryError::new(TARGET, "could not build the email message"))?;
+
+    transport(smtp)?.send(message).await.map_err(|e| {
+        warn!(error = %e, "SMTP delivery failed");
+        DeliveryError::new(TARGET, "the mail server rejected the message")
+    })?;
+
+    info!(as_link = link.is_some(), "Sent email delivery");
+    Ok(DeliveryReceipt {
+        target: TARGET.into(),
+        remote_id: to.email.to_string(),
+        remote_path: None,
+        web_url: link,
+    })
+}
+
+fn transport(smtp: &SmtpConfig) -> Result<AsyncSmtpTransport<Tokio1Executor>, DeliveryError> {
+    let mut builder = match smtp.tls {
+        SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host),
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host),
+        SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+            &smtp.host,
+        )),
+    }
+    .map_err(|_| DeliveryError::new(TARGET, "the mail server is misconfigured"))?;
+
+    if let Some(port) = smtp.port {
+        builder = builder.port(port);
+    }
+    if let Some((username, password)) = &smtp.credentials {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
@@ -0,0 +1,85 @@
+use axum::body::Bytes;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use super::{DeliveryError, DeliveryFile, DeliveryReceipt};
+
+const TARGET: &str = "googleDrive";
+const UPLOAD_URL: &str = "https://www.googleapis.com/upload/drive/v3/files?uploadType=multipart&fields=id,name,webViewLink";
+const BOUNDARY: &str = "wp-mini-axum-drive-upload-7f3c9a1e";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadResponse {
+    id: String,
+    name: Option<String>,
+    web_view_link: Option<String>,
+}
+
+pub(super) async fn upload(
+    client: &Client,
+    access_token: &str,
+    folder_id: Option<&str>,
+    file: DeliveryFile<'_>,
+) -> Result<DeliveryReceipt, DeliveryError> {
+    let mut metadata = serde_json::json!({
+        "name": file.file_name,
+        "mimeType": file.content_type,
+    });
+    if let Some(folder_id) = folder_id {
+        metadata["parents"] = serde_json::json!([folder_id]);
+    }
+
+    // Drive's "multipart" upload is multipart/related (metadata part + media part),
+    // which reqwest's form-data builder can't produce, so it's assembled by hand.
+    let mut body = Vec::with_capacity(file.bytes.len() + 512);
+    body.extend_from_slice(
+        format!(
+            "--{BOUNDARY}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{metadata}\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(
+        format!(
+            "--{BOUNDARY}\r\nContent-Type: {}\r\n\r\n",
+            file.content_type
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(&file.bytes);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+    let response = client
+        .post(UPLOAD_URL)
+        .bearer_auth(access_token)
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/related; boundary={BOUNDARY}"),
+        )
+        .body(Bytes::from(body))
+        .send()
+        .await
+        .map_err(|e| {
+            warn!(error = %e, "Google Drive upload request failed");
+            DeliveryError::new(TARGET, "could not reach Google Drive")
+        })?;
+
+    if !response.status().is_success() {
+        return Err(DeliveryError::from_status(TARGET, response.status()));
+    }
+
+    let uploaded: UploadResponse = response
+        .json()
+        .await
+        .map_err(|_| DeliveryError::new(TARGET, "unexpected response from Google Drive"))?;
+
+    info!(file_id = %uploaded.id, "Uploaded file to Google Drive");
+    Ok(DeliveryReceipt {
+        target: TARGET.into(),
+        remote_id: uploaded.id,
+        remote_path: uploaded.name,
+        web_url: uploaded.web_view_link,
+    })
+}
@@ -0,0 +1,134 @@
+//! Server-side delivery of generated files to a user's own storage.
+//!
+//! Instead of streaming the EPUB back to the extension, a request may carry a
+//! `delivery` object; the file is then uploaded from here and only a receipt is
+//! returned. The payloads are `api_types::Delivery`; their credentials are
+//! bearer secrets, so nothing here logs them.
+
+mod dropbox;
+mod email;
+mod google_drive;
+pub mod telegram;
+mod webdav;
+
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use reqwest::Client;
+use std::fmt;
+
+use crate::artifacts::{ArtifactStore, LinkOptions};
+use crate::config::Config;
+
+pub use api_types::{Delivery, DeliveryReceipt, WebDavAuth};
+
+/// Server resources a delivery target may need besides the file itself.
+pub struct DeliveryContext<'a> {
+    pub client: &'a Client,
+    pub config: &'a Config,
+    pub artifacts: &'a ArtifactStore,
+    /// Lifetime and download cap for any link handed out instead of the file.
+    pub link: &'a LinkOptions,
+}
+
+/// A generated file ready to be handed to a delivery target.
+pub struct DeliveryFile<'a> {
+    /// Human-readable story title, for captions and message subjects.
+    pub title: &'a str,
+    pub file_name: &'a str,
+    pub content_type: &'a str,
+    pub bytes: Bytes,
+}
+
+pub struct DeliveryError {
+    target: &'static str,
+    message: String,
+    status: StatusCode,
+}
+
+pub async fn deliver(
+    delivery: &Delivery,
+    ctx: &DeliveryContext<'_>,
+    file: DeliveryFile<'_>,
+) -> Result<DeliveryReceipt, DeliveryError> {
+    let client = ctx.client;
+    match delivery {
+        Delivery::Dropbox {
+            access_token,
+            folder,
+        } => dropbox::upload(client, access_token, folder.as_deref(), file).await,
+        Delivery::GoogleDrive {
+            access_token,
+            folder_id,
+        } => google_drive::upload(client, access_token, folder_id.as_deref(), file).await,
+        Delivery::WebDav { url, auth } => {
+            webdav::upload(ctx.config, url, auth.as_ref(), file).await
+        }
+        Delivery::Telegram { chat_id } => {
+            let keys = ctx.config.keys();
+            let telegram = keys
+                .telegram
+                .as_ref()
+                .ok_or_else(|| DeliveryError::not_configured("telegram"))?;
+            let caption = file.title.to_string();
+            telegram::send_document(client, &telegram.bot_token, chat_id, file, Some(&caption))
+                .await
+        }
+        Delivery::Email { to, language } => {
+            email::send(ctx, to, language.unwrap_or_default(), file).await
+        }
+    }
+}
+
+impl DeliveryError {
+    fn new(target: &'static str, message: impl Into<String>) -> Self {
+        DeliveryError {
+            target,
+            message: message.into(),
+            status: StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn not_configured(target: &'static str) -> Self {
+        DeliveryError {
+            target,
+            message: "this target is not enabled on this server".to_string(),
+            status: StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    fn invalid(target: &'static str, message: impl Into<String>) -> Self {
+        DeliveryError {
+            target,
+            message: message.into(),
+            status: StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn too_large(target: &'static str, message: impl Into<String>) -> Self {
+        DeliveryError {
+            target,
+            message: message.into(),
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Describes a non-success upstream response without echoing request headers.
+    fn from_status(target: &'static str, status: reqwest::StatusCode) -> Self {
+        let message = match status.as_u16() {
+            401 | 403 => "the access token was rejected".to_string(),
+            413 | 507 => "the destination has insufficient space for this file".to_string(),
+            _ => format!("upstream responded with status {}", status),
+        };
+        DeliveryError::new(target, message)
+    }
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Delivery to {} failed: {}", self.target, self.message)
+    }
+}
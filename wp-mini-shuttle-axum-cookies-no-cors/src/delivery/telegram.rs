@@ -0,0 +1,112 @@
+use reqwest::multipart::{Form, Part};
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use super::{DeliveryError, DeliveryFile, DeliveryReceipt};
+
+pub use api_types::ChatId;
+
+const TARGET: &str = "telegram";
+const API_BASE: &str = "https://api.telegram.org";
+/// The Bot API rejects multipart document uploads above 50 MB.
+const MAX_UPLOAD_BYTES: usize = 50 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    message_id: i64,
+}
+
+pub async fn send_document(
+    client: &Client,
+    bot_token: &str,
+    chat_id: &ChatId,
+    file: DeliveryFile<'_>,
+    caption: Option<&str>,
+) -> Result<DeliveryReceipt, DeliveryError> {
+    if file.bytes.len() > MAX_UPLOAD_BYTES {
+        return Err(DeliveryError::too_large(
+            TARGET,
+            "the file exceeds Telegram's 50 MB bot upload limit",
+        ));
+    }
+
+    let document = Part::stream_with_length(file.bytes.clone(), file.bytes.len() as u64)
+        .file_name(file.file_name.to_string())
+        .mime_str(file.content_type)
+        .map_err(|_| DeliveryError::new(TARGET, "invalid content type"))?;
+
+    let mut form = Form::new()
+        .text("chat_id", chat_id.to_string())
+        .part("document", document);
+    if let Some(caption) = caption {
+        form = form.text("caption", caption.to_string());
+    }
+
+    let message: Message = call(client, bot_token, "sendDocument", form).await?;
+
+    info!(
+        message_id = message.message_id,
+        "Sent document via Telegram"
+    );
+    Ok(DeliveryReceipt {
+        target: TARGET.into(),
+        remote_id: message.message_id.to_string(),
+        remote_path: None,
+        web_url: None,
+    })
+}
+
+pub async fn send_message(
+    client: &Client,
+    bot_token: &str,
+    chat_id: &ChatId,
+    text: &str,
+) -> Result<(), DeliveryError> {
+    let form = Form::new()
+        .text("chat_id", chat_id.to_string())
+        .text("text", text.to_string());
+    call::<Message>(client, bot_token, "sendMessage", form)
+        .await
+        .map(|_| ())
+}
+
+async fn call<T: serde::de::DeserializeOwned>(
+    client: &Client,
+    bot_token: &str,
+    method: &str,
+    form: Form,
+) -> Result<T, DeliveryError> {
+    // The token is part of the URL, so request errors are logged without it.
+    let response = client
+        .post(format!("{}/bot{}/{}", API_BASE, bot_token, method))
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| {
+            warn!(error = %e.without_url(), method, "Telegram request failed");
+            DeliveryError::new(TARGET, "could not reach Telegram")
+        })?;
+
+    let status = response.status();
+    let body: ApiResponse<T> = response
+        .json()
+        .await
+        .map_err(|_| DeliveryError::from_status(TARGET, status))?;
+
+    match body.result {
+        Some(result) if body.ok => Ok(result),
+        _ => Err(DeliveryError::new(
+            TARGET,
+            body.description
+                .unwrap_or_else(|| format!("upstream responded with status {}", status)),
+        )),
+    }
+}
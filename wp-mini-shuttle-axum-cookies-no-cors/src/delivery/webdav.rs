@@ -0,0 +1,180 @@
+//! Uploads to a WebDAV folder the user names. The folder is the user's, so
+//! its host is resolved before anything is sent and the upload is refused
+//! unless every address is public, then made to the address checked, with
+//! redirects off, so neither DNS nor the server can point it back inside.
+//! `WEBDAV_ALLOWED_HOSTS` narrows the hosts further.
+
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tracing::{info, warn};
+
+use super::{DeliveryError, DeliveryFile, DeliveryReceipt, WebDavAuth};
+use crate::config::Config;
+use crate::APP_USER_AGENT;
+
+const TARGET: &str = "webdav";
+
+pub(super) async fn upload(
+    config: &Config,
+    folder_url: &str,
+    auth: Option<&WebDavAuth>,
+    file: DeliveryFile<'_>,
+) -> Result<DeliveryReceipt, DeliveryError> {
+    let mut url = Url::parse(folder_url)
+        .map_err(|_| DeliveryError::new(TARGET, "the WebDAV URL is not valid"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(DeliveryError::new(TARGET, "the WebDAV URL must be http(s)"));
+    }
+
+    // Credentials belong in `auth`; drop any embedded in the URL so they can't
+    // end up in the receipt we send back.
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+
+    url.path_segments_mut()
+        .map_err(|_| DeliveryError::new(TARGET, "the WebDAV URL is not valid"))?
+        .pop_if_empty()
+        .push(file.file_name);
+
+    let client = pinned_client(config, &url).await?;
+    let mut request = client
+        .put(url.clone())
+        .header(CONTENT_TYPE, file.content_type)
+        .body(file.bytes);
+    request = match auth {
+        Some(WebDavAuth::Basic { username, password }) => {
+            request.basic_auth(username, Some(password))
+        }
+        Some(WebDavAuth::Bearer { token }) => request.bearer_auth(token),
+        None => request,
+    };
+
+    let response = request.send().await.map_err(|e| {
+        warn!(error = %e.without_url(), "WebDAV upload request failed");
+        DeliveryError::new(TARGET, "could not reach the WebDAV server")
+    })?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(match status.as_u16() {
+            404 | 409 => DeliveryError::new(TARGET, "the destination folder does not exist"),
+            _ => DeliveryError::from_status(TARGET, status),
+        });
+    }
+
+    info!(host = ?url.host_str(), "Uploaded file to WebDAV server");
+    Ok(DeliveryReceipt {
+        target: TARGET.into(),
+        remote_id: url.path().to_string(),
+        remote_path: Some(url.path().to_string()),
+        web_url: Some(url.to_string()),
+    })
+}
+
+/// A client for `url` alone, pinned to the address its host was checked at.
+async fn pinned_client(config: &Config, url: &Url) -> Result<Client, DeliveryError> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| DeliveryError::new(TARGET, "the WebDAV URL is not valid"))?;
+    if !config.webdav_allowed_hosts.is_empty()
+        && !config
+            .webdav_allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Err(DeliveryError::new(TARGET, "the WebDAV host is not allowed"));
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = match literal.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| DeliveryError::new(TARGET, "could not resolve the WebDAV host"))?
+            .collect(),
+    };
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err(DeliveryError::new(
+            TARGET,
+            "the WebDAV host must be a public address",
+        ));
+    }
+    Client::builder()
+        .user_agent(APP_USER_AGENT)
+        .redirect(Policy::none())
+        .resolve_to_addrs(host, &addrs)
+        .build()
+        .map_err(|e| {
+            warn!(error = %e, "Could not build the WebDAV client");
+            DeliveryError::new(TARGET, "could not reach the WebDAV server")
+        })
+}
+
+/// Whether `ip` is on the public internet: not loopback, private, link-local
+/// (which holds the cloud metadata address, 169.254.169.254), shared,
+/// reserved or documentation space.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(v4),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || a >= 240
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 198 && (18..20).contains(&b))
+        || (a == 192 && b == 0 && ip.octets()[2] == 0))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let [first, second, ..] = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && second == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Advance notice for clients that still rely on something slated for
+//! removal.
+//!
+//! Handlers collect a [`Warning`] for each deprecated field or behavior a
+//! request uses and [`attach`] them to the response. The
+//! [`apply`] middleware then adds a `Deprecation` header (the time of the
+//! earliest deprecation, as in RFC 9745), a `Sunset` header when a removal
+//! date is set, a `Warning: 299` header per warning, and a `warnings` array
+//! to JSON object bodies.
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Warning {
+    /// Stable identifier clients can match on.
+    pub code: &'static str,
+    pub message: &'static str,
+    /// Unix seconds of when this was deprecated.
+    #[serde(skip)]
+    pub since: u64,
+    /// HTTP date after which it may be removed, when one is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<&'static str>,
+}
+
+/// The warnings for a response, carried to [`apply`] as an extension.
+#[derive(Clone)]
+struct Warnings(Vec<Warning>);
+
+pub fn attach(mut response: Response, warnings: Vec<Warning>) -> Response {
+    if !warnings.is_empty() {
+        info!(
+            codes = ?warnings.iter().map(|warning| warning.code).collect::<Vec<_>>(),
+            "Request relies on deprecated behavior"
+        );
+        response.extensions_mut().insert(Warnings(warnings));
+    }
+    response
+}
+
+pub async fn apply(response: Response) -> Response {
+    let Some(Warnings(warnings)) = response.extensions().get::<Warnings>().cloned() else {
+        return response;
+    };
+    let (mut parts, body) = response.into_parts();
+    let headers = &mut parts.headers;
+    if let Some(since) = warnings.iter().map(|warning| warning.since).min()
+        && let Ok(value) = HeaderValue::from_str(&format!("@{}", since))
+    {
+        headers.insert("deprecation", value);
+    }
+    if let Some(sunset) = warnings.iter().filter_map(|warning| warning.sunset).min()
+        && let Ok(value) = HeaderValue::from_str(sunset)
+    {
+        headers.insert("sunset", value);
+    }
+    for warning in &warnings {
+        let value = format!("299 - \"{}\"", warning.message.replace('"', "'"));
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.append(header::WARNING, value);
+        }
+    }
+
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return Response::from_parts(parts, body);
+    }
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::Object(mut object)) => {
+            object.insert("warnings".into(), serde_json::json!(warnings));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(Value::Object(object).to_string())
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::testing;
+
+    async fn start(app: &axum::Router, body: Value) -> (StatusCode, header::HeaderMap, Value) {
+        let request = Request::post("/generate-epub/async")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (
+            parts.status,
+            parts.headers,
+            serde_json::from_slice(&body).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn deprecated_fields_are_reported() {
+        let (app, state_dir) = testing::app(&[]).await;
+        let (status, headers, body) = start(
+            &app,
+            serde_json::json!({ "storyId": 1, "isEmbedImages": true }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(headers["deprecation"], "@1791936000");
+        assert_eq!(headers["sunset"], "Wed, 14 Apr 2027 00:00:00 GMT");
+        let warning = headers[header::WARNING].to_str().unwrap();
+        assert!(warning.starts_with("299 - \"isEmbedImages"), "{}", warning);
+        assert_eq!(body["warnings"][0]["code"], "isEmbedImages");
+        assert_eq!(
+            body["warnings"][0]["sunset"],
+            "Wed, 14 Apr 2027 00:00:00 GMT"
+        );
+
+        let (status, headers, body) = start(
+            &app,
+            serde_json::json!({ "storyId": 1, "embedImages": true }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(headers.get("deprecation").is_none());
+        assert!(headers.get(header::WARNING).is_none());
+        assert!(body.get("warnings").is_none());
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! Watches the shape of what Wattpad sends back, so an upstream change shows
+//! up as a metric and a warning instead of as a wave of failed downloads.
+//!
+//! Every book made by `wp_mini_epub`, or by the pipeline in its stead, is
+//! checked: the story metadata for the fields the book is built from, and the
+//! EPUB for a chapter per part, since both drop parts they cannot find or
+//! process without failing. Missing
+//! pieces, and the library failing to build a book at all, are counted as
+//! `wattdownload_upstream_drift_total` on `/metrics` and logged as warnings.
+//!
+//! Drift in the books the library makes, or the library failing to process
+//! the chapters or put the EPUB together, switches generation to a tolerant
+//! mode for `TOLERANT_FOR`: that book, and any other the library cannot build
+//! meanwhile, is then made with `crate::pipeline`, which fetches the parts
+//! one by one and leaves out only those it cannot read. Those books have no tags (see `crate::tags`), which
+//! the pipeline does not ask for. When the API will not serve the story at
+//! all, `crate::scrape` reads its pages, if the operator turned that on.
+//!
+//! Downloads that may be cut off and tried again (see `crate::job_budget`)
+//! or that report each part done (see `crate::sse`) are made with the
+//! pipeline from the start, which keeps each part it finishes for the next
+//! attempt, and with the library only if that fails. `download` says which
+//! `Engine` made the book, so `crate::shadow` compares it with the other one.
+
+use reqwest::Client;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+use wp_mini::field::StoryField;
+use wp_mini::types::StoryResponse;
+use wp_mini_epub::{download_story_to_memory, AppError, StoryDownload};
+use zip::ZipArchive;
+
+use crate::error::{map_anyhow_error, map_upstream_error, MyError};
+use crate::http_client::UpstreamRateLimited;
+use crate::pipeline::{ChapterCache, Hooks};
+use crate::progress::Progress;
+use crate::{authenticated, pipeline, scrape, unix_now, AppState, GenerateEpubRequest};
+use crate::{part_cache, story_cache};
+
+const TOLERANT_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// What made a book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    Library,
+    Pipeline,
+    /// `crate::scrape`, from the story's pages.
+    Pages,
+}
+
+#[derive(Default)]
+pub struct Drift {
+    /// Unix seconds of the last drift seen, 0 for never.
+    last_seen: AtomicU64,
+}
+
+impl Drift {
+    /// Records what `download` was expected to be made from but is not there.
+    fn check(&self, state: &AppState, download: &StoryDownload<Vec<u8>>) {
+        let missing = missing(&download.metadata, &download.epub_response);
+        if !missing.is_empty() {
+            self.record(state, &missing);
+        }
+    }
+
+    fn record(&self, state: &AppState, fields: &[&'static str]) {
+        warn!(?fields, "Wattpad's response is not shaped as expected");
+        for field in fields {
+            state.usage.drift(field);
+        }
+        self.last_seen.store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// Notes that the library could not build a book because of `error`;
+    /// returns whether that was drift, which turns the tolerant mode on.
+    fn failed(&self, error: &AppError) -> bool {
+        // Fetching failed in these, whatever Wattpad's format.
+        let drift = !matches!(
+            error,
+            AppError::MetadataFetchFailed | AppError::DownloadFailed | AppError::IoError(_)
+        );
+        if drift {
+            self.last_seen.store(unix_now(), Ordering::Relaxed);
+        }
+        drift
+    }
+
+    fn tolerant(&self) -> bool {
+        let last_seen = self.last_seen.load(Ordering::Relaxed);
+        last_seen > 0 && unix_now().saturating_sub(last_seen) < TOLERANT_FOR.as_secs()
+    }
+}
+
+/// `wp_mini_epub::download_story_to_memory` for `payload`, checked for drift
+/// and with the tolerant and page fallbacks, and what made it.
+pub async fn download(
+    state: &AppState,
+    client: &Client,
+    payload: &GenerateEpubRequest,
+    progress: &Progress,
+) -> Result<(StoryDownload<Vec<u8>>, Engine), MyError> {
+    let concurrency = state
+        .config
+        .chapter_concurrency(payload.concurrent_chapter_requests);
+    if progress.by_parts() {
+        if let Some(checkpoint) = progress.checkpoint()
+            && checkpoint.finished() > 0
+        {
+            info!(
+                parts = checkpoint.finished(),
+                "Resuming the download after the parts already done"
+            );
+        }
+        match by_parts(state, client, payload, concurrency, progress).await {
+            Ok(download) => {
+                state.drift.check(state, &download);
+                return Ok((download, Engine::Pipeline));
+            }
+            Err(e) if e.is::<UpstreamRateLimited>() => return Err(map_upstream_error(e)),
+            Err(e) => warn!(error = %e, "Could not make the book part by part; trying the library"),
+        }
+    }
+    let result = download_story_to_memory(
+        client,
+        payload.story_id,
+        payload.embed_images,
+        concurrency,
+        Some(&[StoryField::Tags]),
+    )
+    .await
+    .map_err(map_anyhow_error);
+    let error = match result {
+        Ok(download) => {
+            state.drift.check(state, &download);
+            return Ok((download, Engine::Library));
+        }
+        // Whatever Wattpad's format, these are not about it.
+        Err(
+            e @ (AppError::StoryNotFound(_)
+            | AppError::NotLoggedIn
+            | AppError::AuthenticationFailed
+            | AppError::LogoutFailed),
+        ) => return Err(e.into()),
+        Err(e) => e,
+    };
+    state.usage.drift("book");
+    if state.drift.failed(&error) {
+        warn!(error = %error, "Library could not process Wattpad's response");
+    }
+    let error = if state.drift.tolerant() {
+        warn!("Library could not build the book; trying the tolerant pipeline");
+        match by_parts(state, client, payload, concurrency, progress).await {
+            Ok(download) => return Ok((download, Engine::Pipeline)),
+            Err(e) if e.is::<UpstreamRateLimited>() => return Err(map_upstream_error(e)),
+            Err(e) => map_anyhow_error(e),
+        }
+    } else {
+        error
+    };
+    if !state.config.html_fallback
+        || !matches!(
+            error,
+            AppError::MetadataFetchFailed | AppError::DownloadFailed
+        )
+    {
+        return Err(error.into());
+    }
+    warn!("Wattpad's API did not serve the story; reading its pages instead");
+    scrape::download_story_to_memory(client, payload.story_id, payload.embed_images, concurrency)
+        .await
+        .map(|download| (download, Engine::Pages))
+        .map_err(map_upstream_error)
+}
+
+/// The book of `payload` made with `crate::pipeline`, reporting the parts
+/// it finishes to `progress` and keeping them in its checkpoint.
+async fn by_parts(
+    state: &AppState,
+    client: &Client,
+    payload: &GenerateEpubRequest,
+    concurrency: usize,
+    progress: &Progress,
+) -> anyhow::Result<StoryDownload<Vec<u8>>> {
+    let anonymous = !authenticated(payload);
+    let story = story_cache::story(state, client, payload.story_id, anonymous)
+        .await
+        .map_err(|_| AppError::MetadataFetchFailed)?;
+    pipeline::assemble(
+        client,
+        payload.story_id,
+        story,
+        payload.embed_images,
+        concurrency,
+        |part_id| part_cache::text(state, client, part_id, anonymous),
+        Hooks {
+            cache: ChapterCache::new(state),
+            checkpoint: progress.checkpoint(),
+            on_part: progress.parts(),
+        },
+    )
+    .await
+}
+
+/// What the book was expected to be made from but is not there.
+fn missing(story: &StoryResponse, epub: &[u8]) -> Vec<&'static str> {
+    let mut missing = Vec::new();
+    if story.title.is_none() {
+        missing.push("title");
+    }
+    if story
+        .user
+        .as_ref()
+        .and_then(|user| user.username.as_ref())
+        .is_none()
+    {
+        missing.push("author");
+    }
+    if story
+        .language
+        .as_ref()
+        .and_then(|language| language.id)
+        .is_none()
+    {
+        missing.push("language");
+    }
+    let parts = story.parts.as_deref().unwrap_or_default();
+    if parts.iter().any(|part| part.id.is_none()) {
+        missing.push("partId");
+    }
+    if parts.iter().any(|part| part.title.is_none()) {
+        missing.push("partTitle");
+    }
+    if chapters(epub).is_some_and(|chapters| chapters < parts.len()) {
+        missing.push("chapters");
+    }
+    missing
+}
+
+/// Chapters in a book of `wp_mini_epub` or the pipeline, which both name
+/// them `<n>.xhtml`.
+fn chapters(epub: &[u8]) -> Option<usize> {
+    let archive = ZipArchive::new(Cursor::new(epub)).ok()?;
+    let chapters = archive
+        .file_names()
+        .filter_map(|name| name.rsplit('/').next()?.strip_suffix(".xhtml"))
+        .filter(|stem| stem.parse::<usize>().is_ok())
+        .count();
+    Some(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn processing_failures_turn_the_tolerant_mode_on() {
+        let drift = Drift::default();
+        assert!(!drift.failed(&AppError::DownloadFailed));
+        assert!(!drift.failed(&AppError::MetadataFetchFailed));
+        assert!(!drift.tolerant());
+
+        assert!(drift.failed(&AppError::ChapterProcessingFailed));
+        assert!(drift.tolerant());
+    }
+}
@@ -0,0 +1,350 @@
+use api_types::ErrorBody;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::time::Duration;
+use tracing::warn;
+use wp_mini_epub::AppError;
+
+use crate::delivery::DeliveryError;
+use crate::http_client::UpstreamRateLimited;
+use crate::i18n::{self, Lang, Message};
+
+pub enum MyError {
+    App(AppError),
+    Delivery(DeliveryError),
+    /// The request was well-formed JSON but asked for something we won't do.
+    InvalidRequest(String),
+    NotFound(String),
+    Forbidden(String),
+    Unauthorized(String),
+    /// The request does not fit what it acts on as it is now, e.g. retrying a
+    /// job that is still running.
+    Conflict(String),
+    /// The client has to back off for this long before trying again.
+    Throttled(Duration),
+    /// The storage backend failed; the cause was logged where it happened.
+    Storage,
+    /// New work is refused for now, e.g. during maintenance.
+    Unavailable {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// Wattpad kept throttling this server (see `crate::http_client`), with
+    /// the wait it last asked for.
+    UpstreamRateLimited(Option<Duration>),
+    /// The request ran past its deadline (see `crate::deadline`).
+    TimedOut(Duration),
+}
+
+/// How an `AppError` is answered. The one place the library's errors are
+/// mapped: the match has no catch-all, so a variant added to `AppError`
+/// does not compile until it is given its own answer here.
+struct AppErrorMapping {
+    status: StatusCode,
+    code: &'static str,
+    /// Whether the same request may work later: Wattpad, the network or
+    /// this server failed, not the request.
+    retryable: bool,
+    message: String,
+}
+
+fn app_error_mapping(error: &AppError) -> AppErrorMapping {
+    let (status, code, retryable) = match error {
+        AppError::AuthenticationFailed => (StatusCode::UNAUTHORIZED, "authenticationFailed", false),
+        AppError::NotLoggedIn => (StatusCode::UNAUTHORIZED, "notLoggedIn", false),
+        AppError::LogoutFailed => (StatusCode::INTERNAL_SERVER_ERROR, "logoutFailed", true),
+        AppError::StoryNotFound(_) => (StatusCode::NOT_FOUND, "storyNotFound", false),
+        AppError::MetadataFetchFailed => (StatusCode::BAD_GATEWAY, "metadataFetchFailed", true),
+        AppError::DownloadFailed => (StatusCode::BAD_GATEWAY, "downloadFailed", true),
+        AppError::ChapterProcessingFailed => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "chapterProcessingFailed",
+            false,
+        ),
+        AppError::EpubGenerationFailed => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "epubGenerationFailed",
+            false,
+        ),
+        AppError::IoError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "ioError", true),
+    };
+    AppErrorMapping {
+        status,
+        code,
+        retryable,
+        message: i18n::format(Lang::En, &format!("error.{}", code), &app_error_args(error)),
+    }
+}
+
+/// What the catalog message for `error` is filled in with.
+fn app_error_args(error: &AppError) -> Vec<(&'static str, String)> {
+    match error {
+        AppError::StoryNotFound(id) => vec![("id", id.to_string())],
+        _ => Vec::new(),
+    }
+}
+
+/// The `AppError` behind `e`; anything else, I/O errors included, is a
+/// download that failed.
+pub fn map_anyhow_error(e: anyhow::Error) -> AppError {
+    match e.downcast::<AppError>() {
+        Ok(AppError::IoError(io)) => {
+            warn!(error = %io, "I/O error while downloading");
+            AppError::DownloadFailed
+        }
+        Ok(app_error) => app_error,
+        Err(e) => {
+            warn!("Unhandled error type: {:?}", e);
+            AppError::DownloadFailed
+        }
+    }
+}
+
+/// `map_anyhow_error`, keeping Wattpad throttling this server apart from
+/// downloads that failed.
+pub fn map_upstream_error(e: anyhow::Error) -> MyError {
+    match e.downcast_ref::<UpstreamRateLimited>() {
+        Some(limited) => MyError::UpstreamRateLimited(limited.retry_after),
+        None => map_anyhow_error(e).into(),
+    }
+}
+
+impl From<AppError> for MyError {
+    fn from(error: AppError) -> Self {
+        MyError::App(error)
+    }
+}
+
+impl From<DeliveryError> for MyError {
+    fn from(error: DeliveryError) -> Self {
+        MyError::Delivery(error)
+    }
+}
+
+impl MyError {
+    /// A stable name for the kind of error, for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MyError::App(error) => app_error_mapping(error).code,
+            MyError::Delivery(_) => "deliveryFailed",
+            MyError::InvalidRequest(_) => "invalidRequest",
+            MyError::NotFound(_) => "notFound",
+            MyError::Forbidden(_) => "forbidden",
+            MyError::Unauthorized(_) => "unauthorized",
+            MyError::Conflict(_) => "conflict",
+            MyError::Throttled(_) => "throttled",
+            MyError::Storage => "storageFailed",
+            MyError::Unavailable { .. } => "unavailable",
+            MyError::UpstreamRateLimited(_) => "upstreamRateLimited",
+            MyError::TimedOut(_) => "timedOut",
+        }
+    }
+
+    /// Whether the same request may work if it is tried again later.
+    pub fn retryable(&self) -> bool {
+        match self {
+            MyError::App(error) => app_error_mapping(error).retryable,
+            MyError::Delivery(error) => error.status().is_server_error(),
+            MyError::InvalidRequest(_)
+            | MyError::NotFound(_)
+            | MyError::Forbidden(_)
+            | MyError::Unauthorized(_)
+            | MyError::Conflict(_) => false,
+            MyError::Throttled(_)
+            | MyError::Storage
+            | MyError::Unavailable { .. }
+            | MyError::UpstreamRateLimited(_) => true,
+            // The same story takes as long again; the message points to the
+            // async endpoint instead.
+            MyError::TimedOut(_) => false,
+        }
+    }
+
+    /// The catalog message this error is answered with, for `crate::i18n` to
+    /// translate; `None` for messages made of what the request sent.
+    pub fn message(&self) -> Option<Message> {
+        let (key, args) = match self {
+            MyError::App(error) => (
+                format!("error.{}", app_error_mapping(error).code),
+                app_error_args(error),
+            ),
+            MyError::Throttled(wait) | MyError::TimedOut(wait) => (
+                format!("error.{}", self.code()),
+                vec![("seconds", wait.as_secs().to_string())],
+            ),
+            MyError::Storage | MyError::UpstreamRateLimited(_) => {
+                (format!("error.{}", self.code()), Vec::new())
+            }
+            MyError::Delivery(_)
+            | MyError::InvalidRequest(_)
+            | MyError::NotFound(_)
+            | MyError::Forbidden(_)
+            | MyError::Unauthorized(_)
+            | MyError::Conflict(_)
+            | MyError::Unavailable { .. } => return None,
+        };
+        Some(Message { key, args })
+    }
+
+    pub fn status_and_message(&self) -> (StatusCode, String) {
+        let catalog = || {
+            let message = self.message().expect("the error has a catalog message");
+            i18n::format(Lang::En, &message.key, &message.args)
+        };
+        match self {
+            MyError::App(error) => {
+                let mapping = app_error_mapping(error);
+                (mapping.status, mapping.message)
+            }
+            MyError::Delivery(error) => (error.status(), error.to_string()),
+            MyError::InvalidRequest(message) => (StatusCode::BAD_REQUEST, message.clone()),
+            MyError::NotFound(message) => (StatusCode::NOT_FOUND, message.clone()),
+            MyError::Forbidden(message) => (StatusCode::FORBIDDEN, message.clone()),
+            MyError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message.clone()),
+            MyError::Conflict(message) => (StatusCode::CONFLICT, message.clone()),
+            MyError::Throttled(_) => (StatusCode::TOO_MANY_REQUESTS, catalog()),
+            MyError::Storage => (StatusCode::INTERNAL_SERVER_ERROR, catalog()),
+            MyError::Unavailable { message, .. } => {
+                (StatusCode::SERVICE_UNAVAILABLE, message.clone())
+            }
+            MyError::UpstreamRateLimited(_) => (StatusCode::SERVICE_UNAVAILABLE, catalog()),
+            MyError::TimedOut(_) => (StatusCode::GATEWAY_TIMEOUT, catalog()),
+        }
+    }
+}
+
+impl IntoResponse for MyError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = self.status_and_message();
+
+        let body = Json(ErrorBody {
+            error: error_message,
+        });
+        let mut response = (status, body).into_response();
+        if let Some(message) = self.message() {
+            response.extensions_mut().insert(message);
+        }
+        if let MyError::Throttled(retry_after)
+        | MyError::Unavailable {
+            retry_after: Some(retry_after),
+            ..
+        }
+        | MyError::UpstreamRateLimited(Some(retry_after)) = self
+        {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.as_secs().into());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// One of each `AppError`. The match stops compiling when a variant is
+    /// added, so it cannot be left out here either.
+    fn every_app_error() -> Vec<AppError> {
+        let all = vec![
+            AppError::AuthenticationFailed,
+            AppError::NotLoggedIn,
+            AppError::LogoutFailed,
+            AppError::StoryNotFound(42),
+            AppError::MetadataFetchFailed,
+            AppError::DownloadFailed,
+            AppError::ChapterProcessingFailed,
+            AppError::EpubGenerationFailed,
+            AppError::IoError(std::io::Error::other("disk")),
+        ];
+        for error in &all {
+            match error {
+                AppError::AuthenticationFailed
+                | AppError::NotLoggedIn
+                | AppError::LogoutFailed
+                | AppError::StoryNotFound(_)
+                | AppError::MetadataFetchFailed
+                | AppError::DownloadFailed
+                | AppError::ChapterProcessingFailed
+                | AppError::EpubGenerationFailed
+                | AppError::IoError(_) => {}
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn every_app_error_has_its_own_code() {
+        let all = every_app_error();
+        let codes: HashSet<_> = all.iter().map(|e| app_error_mapping(e).code).collect();
+        assert_eq!(codes.len(), all.len());
+        for code in codes {
+            assert!(code.starts_with(|c: char| c.is_ascii_lowercase()), "{code}");
+            assert!(code.chars().all(|c| c.is_ascii_alphanumeric()), "{code}");
+        }
+    }
+
+    #[test]
+    fn app_errors_are_answered_as_their_mapping_says() {
+        for error in every_app_error() {
+            let mapping = app_error_mapping(&error);
+            assert!(
+                mapping.status.is_client_error() || mapping.status.is_server_error(),
+                "{}",
+                mapping.code
+            );
+            assert!(!mapping.message.is_empty(), "{}", mapping.code);
+            // Nothing the client sent wrong gets better by sending it again.
+            if mapping.status.is_client_error() {
+                assert!(!mapping.retryable, "{}", mapping.code);
+            }
+            let error = MyError::App(error);
+            assert_eq!(error.code(), mapping.code);
+            assert_eq!(error.retryable(), mapping.retryable);
+            assert_eq!(
+                error.status_and_message(),
+                (mapping.status, mapping.message)
+            );
+            assert_eq!(error.into_response().status(), mapping.status);
+        }
+    }
+
+    #[test]
+    fn story_not_found_names_the_story() {
+        let (status, message) = MyError::App(AppError::StoryNotFound(42)).status_and_message();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(message, "Story with ID 42 could not be found");
+    }
+
+    #[test]
+    fn anyhow_errors_keep_their_app_error() {
+        for error in every_app_error() {
+            let expected = match &error {
+                AppError::IoError(_) => "downloadFailed",
+                other => app_error_mapping(other).code,
+            };
+            let mapped = map_anyhow_error(anyhow::Error::new(error));
+            assert_eq!(app_error_mapping(&mapped).code, expected);
+        }
+        let mapped = map_anyhow_error(anyhow::anyhow!("something else"));
+        assert!(matches!(mapped, AppError::DownloadFailed));
+    }
+
+    #[test]
+    fn wattpad_throttling_is_kept_apart_from_failed_downloads() {
+        let limited = UpstreamRateLimited {
+            retry_after: Some(Duration::from_secs(7)),
+        };
+        let error = map_upstream_error(limited.into());
+        assert!(matches!(
+            error,
+            MyError::UpstreamRateLimited(Some(wait)) if wait == Duration::from_secs(7)
+        ));
+        assert!(error.retryable());
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "7");
+    }
+}
@@ -0,0 +1,291 @@
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderName, StatusCode};
+use axum::response::Response;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sanitize_filename::{sanitize_with_options, Options};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use wp_mini_epub::AppError;
+
+use crate::error::MyError;
+
+pub const CONTENT_SHA256: HeaderName = HeaderName::from_static("x-content-sha256");
+
+/// What most file systems allow in one file name.
+const MAX_FILE_NAME_BYTES: usize = 255;
+/// Bytes RFC 5987 lets `filename*` carry as they are (`attr-char`).
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Lowercase hex SHA-256, as in `X-Content-SHA256` and `.sha256` sidecars.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Builds a download response with an ASCII filename and the RFC 5987 encoded
+/// one, so browsers and the extension can recover non-ASCII titles, and the
+/// file's SHA-256 so clients behind flaky proxies can verify it.
+pub fn attachment(
+    utf8_name: &str,
+    content_type: &str,
+    bytes: impl Into<Bytes>,
+) -> Result<Response, MyError> {
+    let bytes = bytes.into();
+    match Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, content_disposition(utf8_name))
+        .header(header::CONTENT_LENGTH, bytes.len())
+        .header(CONTENT_SHA256, sha256_hex(&bytes))
+        .body(Body::from(bytes))
+    {
+        Ok(response) => Ok(response),
+        Err(_) => Err(MyError::App(AppError::EpubGenerationFailed)),
+    }
+}
+
+/// Like `attachment`, for a file sent as it is made, whose length and hash
+/// are not known up front.
+pub fn streamed_attachment(
+    utf8_name: &str,
+    content_type: &str,
+    body: Body,
+) -> Result<Response, MyError> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, content_disposition(utf8_name))
+        .body(body)
+        .map_err(|_| MyError::App(AppError::EpubGenerationFailed))
+}
+
+/// What a ZIP writer for a streamed attachment wrote since it was last taken.
+#[derive(Clone, Default)]
+pub struct Pending(Arc<Mutex<Vec<u8>>>);
+
+impl Pending {
+    pub fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.0.lock().unwrap()))
+    }
+}
+
+impl Write for Pending {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `name` made safe to save on any OS: no path separators or characters
+/// Windows refuses, no device names such as `CON`, no trailing dots or
+/// spaces, no invisible characters that reorder text (which can disguise the
+/// extension), and short enough for common file systems. The extension is
+/// kept whatever the title.
+fn safe_file_name(name: &str) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension))
+            if !stem.is_empty()
+                && (1..=8).contains(&extension.len())
+                && extension.bytes().all(|b| b.is_ascii_alphanumeric()) =>
+        {
+            (stem, Some(extension))
+        }
+        _ => (name, None),
+    };
+    let visible: String = stem
+        .chars()
+        .filter(|c| !is_bidi_control(*c))
+        .map(|c| match c.is_control() {
+            true => '_',
+            false => c,
+        })
+        .collect();
+    let mut stem = sanitize_with_options(
+        visible,
+        Options {
+            windows: true,
+            truncate: false,
+            replacement: "_",
+        },
+    );
+    let mut room = MAX_FILE_NAME_BYTES - extension.map_or(0, |extension| extension.len() + 1);
+    if stem.len() > room {
+        while !stem.is_char_boundary(room) {
+            room -= 1;
+        }
+        stem.truncate(room);
+    }
+    // Cutting it short can leave a dot or space at the end again.
+    let stem = match stem.trim_end_matches(['.', ' ']) {
+        "" => "download",
+        stem => stem,
+    };
+    match extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem.to_string(),
+    }
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+/// RFC 6266: `filename` for clients that only read that, reduced to ASCII a
+/// quoted string can hold (and without `%`, which some decode), and
+/// `filename*` with the whole name.
+fn content_disposition(utf8_name: &str) -> String {
+    let name = safe_file_name(utf8_name);
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | '%' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(&name, ATTR_CHAR)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use percent_encoding::percent_decode_str;
+    use proptest::prelude::*;
+
+    /// Pieces titles are made of, weighted towards the ones that go wrong.
+    const PIECES: [&str; 24] = [
+        "CON", "nul", "Com1", "lpt9", ".", "..", " ", "/", "\\", ":", "*", "?", "\"", "<", ">",
+        "|", "%", ";", "\n", "\u{7f}", "\u{202e}", "\u{2066}", "\u{200f}", "\u{feff}",
+    ];
+
+    /// Titles made of ASCII, control characters, any other scalar value and
+    /// `PIECES`, some long enough to need shortening, some ending in `.epub`.
+    fn title() -> impl Strategy<Value = String> {
+        let piece = prop_oneof![
+            (0x20..0x7f_u8).prop_map(|c| char::from(c).to_string()),
+            (0..0x20_u8).prop_map(|c| char::from(c).to_string()),
+            any::<char>().prop_map(String::from),
+            prop::sample::select(&PIECES[..]).prop_map(str::to_string),
+        ];
+        let len = prop_oneof![9 => 0..=24_usize, 1 => 0..=400_usize];
+        (len, any::<bool>())
+            .prop_flat_map(move |(len, epub)| {
+                (prop::collection::vec(piece.clone(), len), Just(epub))
+            })
+            .prop_map(|(pieces, epub)| {
+                let title = pieces.concat();
+                match epub {
+                    true => format!("{}.epub", title),
+                    false => title,
+                }
+            })
+    }
+
+    /// The `filename` and the decoded `filename*` of a header this module
+    /// wrote, or why it does not follow RFC 6266.
+    fn parse(header: &str) -> Result<(String, String), String> {
+        let rest = header
+            .strip_prefix("attachment; filename=\"")
+            .ok_or("no quoted filename")?;
+        let (fallback, rest) = rest.split_once('"').ok_or("unterminated filename")?;
+        if let Some(c) = fallback
+            .chars()
+            .find(|c| !(*c == ' ' || c.is_ascii_graphic()) || matches!(c, '\\' | '%'))
+        {
+            return Err(format!("filename holds {:?}", c));
+        }
+        let encoded = rest
+            .strip_prefix("; filename*=UTF-8''")
+            .ok_or("no filename*")?;
+        if let Some(c) = encoded
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || "!#$&+-.^_`|~%".contains(*c)))
+        {
+            return Err(format!("filename* holds {:?}", c));
+        }
+        let decoded = percent_decode_str(encoded)
+            .decode_utf8()
+            .map_err(|_| "filename* is not UTF-8")?;
+        Ok((fallback.to_string(), decoded.into_owned()))
+    }
+
+    fn is_reserved(name: &str) -> bool {
+        let stem = name
+            .split('.')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        matches!(stem.as_str(), "con" | "prn" | "aux" | "nul")
+            || (stem.len() == 4
+                && (stem.starts_with("com") || stem.starts_with("lpt"))
+                && stem.as_bytes()[3].is_ascii_digit())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(5_000))]
+
+        #[test]
+        fn any_title_gives_a_valid_content_disposition(title in title()) {
+            let header = content_disposition(&title);
+            prop_assert!(
+                HeaderValue::from_str(&header).is_ok(),
+                "{:?} gave an invalid header {:?}",
+                title,
+                header
+            );
+            let (fallback, decoded) = parse(&header)
+                .map_err(|e| TestCaseError::fail(format!("{:?} gave {:?}: {}", title, header, e)))?;
+            prop_assert_eq!(&decoded, &safe_file_name(&title));
+            prop_assert_eq!(fallback.chars().count(), decoded.chars().count());
+        }
+
+        #[test]
+        fn any_title_gives_a_file_name_every_os_accepts(title in title()) {
+            let name = safe_file_name(&title);
+            prop_assert!(!name.is_empty());
+            prop_assert!(name.len() <= MAX_FILE_NAME_BYTES, "{:?} gave {:?}", title, name);
+            prop_assert!(!name.ends_with(['.', ' ']), "{:?} gave {:?}", title, name);
+            prop_assert!(!is_reserved(&name), "{:?} gave {:?}", title, name);
+            let bad = name
+                .chars()
+                .find(|c| c.is_control() || is_bidi_control(*c) || "/\\:*?\"<>|".contains(*c));
+            prop_assert!(bad.is_none(), "{:?} gave {:?}, holding {:?}", title, name, bad);
+            if title.ends_with(".epub") {
+                prop_assert!(name.ends_with(".epub"), "{:?} gave {:?}", title, name);
+            }
+        }
+    }
+
+    #[test]
+    fn plain_titles_are_kept() {
+        assert_eq!(
+            content_disposition("123-My_Story.epub"),
+            "attachment; filename=\"123-My_Story.epub\"; filename*=UTF-8''123-My_Story.epub"
+        );
+        assert_eq!(
+            content_disposition("123-Café.epub"),
+            "attachment; filename=\"123-Caf_.epub\"; filename*=UTF-8''123-Caf%C3%A9.epub"
+        );
+    }
+}
@@ -0,0 +1,35 @@
+//! Entry points for the `cargo fuzz` targets in `fuzz/`, for the code that
+//! reads what clients and Wattpad send: request bodies, cookies and chapter
+//! HTML. Only built under `cargo fuzz`, which sets `cfg(fuzzing)`.
+
+use std::collections::HashMap;
+
+use crate::pipeline::html;
+use crate::{chapters, client_pool, cookie_jar, upgrade, Cookie, GenerateEpubRequest};
+
+/// A `/generate-epub` body, read and checked as the handler would.
+pub fn generate_epub_request(data: &[u8]) {
+    let Ok(mut request) = serde_json::from_slice::<GenerateEpubRequest>(data) else {
+        return;
+    };
+    upgrade(&mut request);
+    let _ = chapters::validate(&request.chapters, true);
+}
+
+/// The `cookies` of a request, put into a jar and fingerprinted.
+pub fn cookies(data: &[u8]) {
+    let Ok(cookies) = serde_json::from_slice::<Vec<Cookie>>(data) else {
+        return;
+    };
+    cookie_jar(&cookies);
+    client_pool::fingerprint(&cookies);
+}
+
+/// Chapter HTML as Wattpad sends it, cleaned into XHTML.
+pub fn chapter_html(data: &[u8]) {
+    let Ok(html) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = html::image_urls(html);
+    let _ = html::clean(html, &HashMap::new());
+}
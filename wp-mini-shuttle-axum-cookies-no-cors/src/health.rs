@@ -0,0 +1,104 @@
+//! `GET /healthz` and `GET /readyz`, for load balancers and for clients that
+//! want to tell "this server is down" from "Wattpad is down".
+//!
+//! `/healthz` only says the process answers. `/readyz` also sends a `HEAD` to
+//! Wattpad with the shared client and says whether new downloads can be
+//! started; it is `503` during maintenance (see `crate::maintenance`) and
+//! while Wattpad does not answer, answers with a server error or throttles
+//! this server. The probe's result is reused for `PROBE_TTL`, so polling
+//! `/readyz` does not turn into traffic against Wattpad.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::AppState;
+
+const PROBE_URL: &str = "https://www.wattpad.com/";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_TTL: Duration = Duration::from_secs(15);
+
+/// The last probe of Wattpad and when it was made.
+#[derive(Default)]
+pub struct Readiness {
+    last: Mutex<Option<(Instant, Probe)>>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Probe {
+    reachable: bool,
+    /// The status Wattpad answered with, if it answered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    latency_ms: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadyResponse {
+    ready: bool,
+    maintenance: bool,
+    wattpad: Probe,
+}
+
+pub async fn healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let maintenance = state.maintenance.check().await.is_err();
+    let wattpad = probe(&state).await;
+    let ready = !maintenance && wattpad.reachable;
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    let body = ReadyResponse {
+        ready,
+        maintenance,
+        wattpad,
+    };
+    (status, Json(body)).into_response()
+}
+
+async fn probe(state: &AppState) -> Probe {
+    if let Some((at, probe)) = state.readiness.last.lock().unwrap().as_ref()
+        && at.elapsed() < PROBE_TTL
+    {
+        return probe.clone();
+    }
+    let started = Instant::now();
+    let response = state
+        .anon_client
+        .head(PROBE_URL)
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let probe = match response {
+        Ok(response) => {
+            let status = response.status();
+            Probe {
+                reachable: !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS,
+                status: Some(status.as_u16()),
+                latency_ms,
+            }
+        }
+        Err(e) => {
+            warn!(error = %e, "Wattpad did not answer the readiness probe");
+            Probe {
+                reachable: false,
+                status: None,
+                latency_ms,
+            }
+        }
+    };
+    *state.readiness.last.lock().unwrap() = Some((Instant::now(), probe.clone()));
+    probe
+}
@@ -0,0 +1,101 @@
+//! Requests to Wattpad that this server sends itself, retried when Wattpad
+//! answers `429`, `502` or `503`: those mostly clear up within seconds, and
+//! failing the whole book for one of them wastes every request made for it
+//! so far.
+//!
+//! Retries back off exponentially with full jitter, so the clients that
+//! were throttled together do not come back together, and wait at least as
+//! long as a `Retry-After` asks. Once the retries are used up the request
+//! fails with `UpstreamRateLimited`, answered as `503` (see
+//! `crate::error::map_upstream_error`) rather than the `502` of a download
+//! that failed.
+//!
+//! These are the pipeline's chapters and images (see `crate::pipeline`),
+//! story pages (see `crate::scrape`), covers (see `crate::cover_image`), WARC
+//! captures (see `crate::warc`) and session checks (see `crate::session`).
+//! `wp_mini` and `wp_mini_epub` send their requests from inside the
+//! libraries and drop the status of failed ones, so they are not retried.
+
+use anyhow::Result;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::fmt;
+use std::time::Duration;
+use tracing::warn;
+
+/// Tries per request, the first one included.
+const ATTEMPTS: u32 = 4;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// Wattpad kept throttling or failing a request through all of its retries.
+#[derive(Debug)]
+pub struct UpstreamRateLimited {
+    /// The last `Retry-After` Wattpad sent, if any.
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for UpstreamRateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Wattpad is rate limiting this server")
+    }
+}
+
+impl std::error::Error for UpstreamRateLimited {}
+
+/// Sends `request`, retrying it as described above. Responses with any
+/// other status are returned as they are, for the caller to judge.
+pub async fn send(request: RequestBuilder) -> Result<Response> {
+    let mut attempt: u32 = 0;
+    loop {
+        attempt += 1;
+        // Requests with streamed bodies cannot be sent twice.
+        let Some(retry) = request.try_clone().filter(|_| attempt < ATTEMPTS) else {
+            let response = request.send().await?;
+            return match retryable(response.status()) {
+                true => Err(UpstreamRateLimited {
+                    retry_after: retry_after(&response),
+                }
+                .into()),
+                false => Ok(response),
+            };
+        };
+        let response = retry.send().await?;
+        if !retryable(response.status()) {
+            return Ok(response);
+        }
+        // One answer should not hold a request for long.
+        let asked = retry_after(&response).unwrap_or_default().min(MAX_DELAY);
+        let delay = backoff(attempt).max(asked);
+        warn!(status = %response.status(), url = %response.url(), attempt, ?delay, "Wattpad is throttling; retrying");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE
+    )
+}
+
+/// A random delay up to `BASE_DELAY` doubled for each attempt made.
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_DELAY);
+    Duration::from_millis(rand::random_range(0..=ceiling.as_millis() as u64))
+}
+
+/// The response's `Retry-After` in seconds; dates are not worth parsing here.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
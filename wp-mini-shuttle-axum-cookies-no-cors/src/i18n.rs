@@ -0,0 +1,224 @@
+//! Messages in the reader's language: the web UI, the default email
+//! templates, the pages added to books (such as `crate::stats`) and the fixed
+//! error messages are looked up by key in a catalog, `i18n/<language>.json`,
+//! that falls back to English for any key it lacks.
+//! Messages made of what the request sent (which field is wrong, ...) stay
+//! in English.
+//!
+//! The language is the `lang` query parameter when it names one here, else
+//! the best one in `Accept-Language`, else English. Error responses are
+//! translated on their way out by `localize`: `MyError` marks the ones it has
+//! a catalog message for with a `Message`, and the layer rewrites their body.
+//! Emails and the pages added to books use the language of the request that
+//! asked for them, kept on the email delivery and the request's `language`
+//! for jobs that make them later.
+
+use api_types::ErrorBody;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+pub use api_types::Lang;
+
+type Catalog = HashMap<String, String>;
+
+fn parse_catalog(source: &str) -> Catalog {
+    serde_json::from_str(source).expect("i18n catalogs are valid JSON objects of strings")
+}
+
+static EN: LazyLock<Catalog> = LazyLock::new(|| parse_catalog(include_str!("i18n/en.json")));
+static ES: LazyLock<Catalog> = LazyLock::new(|| parse_catalog(include_str!("i18n/es.json")));
+static PT: LazyLock<Catalog> = LazyLock::new(|| parse_catalog(include_str!("i18n/pt.json")));
+
+fn catalog(lang: Lang) -> &'static Catalog {
+    match lang {
+        Lang::En => &EN,
+        Lang::Es => &ES,
+        Lang::Pt => &PT,
+    }
+}
+
+/// The language asked for by `query`'s `lang`, else the best one in
+/// `Accept-Language`.
+pub fn negotiate(headers: &HeaderMap, query: Option<&str>) -> Lang {
+    query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("lang=").and_then(Lang::parse))
+        .unwrap_or_else(|| accepted(headers))
+}
+
+/// The best language in `Accept-Language`; English when none is known.
+pub fn accepted(headers: &HeaderMap) -> Lang {
+    let Some(accepted) = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Lang::En;
+    };
+    let mut ranges: Vec<(f32, &str)> = accepted
+        .split(',')
+        .map(|range| {
+            let mut params = range.split(';');
+            let tag = params.next().unwrap_or_default().trim();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (quality, tag)
+        })
+        .filter(|(quality, _)| *quality > 0.0)
+        .collect();
+    // Stable, so equally weighted languages keep the client's order.
+    ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranges
+        .into_iter()
+        .find_map(|(_, tag)| Lang::parse(tag))
+        .unwrap_or_default()
+}
+
+/// `key`'s message; the key itself if no catalog has it.
+pub fn message(lang: Lang, key: &str) -> &str {
+    catalog(lang)
+        .get(key)
+        .or_else(|| EN.get(key))
+        .map_or(key, String::as_str)
+}
+
+/// `key`'s message with each `{name}` in it replaced by its value.
+pub fn format(lang: Lang, key: &str, args: &[(&str, String)]) -> String {
+    args.iter()
+        .fold(message(lang, key).to_string(), |message, (name, value)| {
+            message.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// The messages whose keys start with `prefix`.
+pub fn messages(lang: Lang, prefix: &str) -> impl Iterator<Item = (&'static str, &'static str)> {
+    let prefix = prefix.to_string();
+    EN.keys()
+        .filter(move |key| key.starts_with(&prefix))
+        .map(move |key| (key.as_str(), message(lang, key)))
+}
+
+/// An error response's message as a catalog key and its arguments.
+#[derive(Clone)]
+pub struct Message {
+    pub key: String,
+    pub args: Vec<(&'static str, String)>,
+}
+
+/// Rewrites the body of error responses marked with a `Message` in the
+/// request's language.
+pub async fn localize(request: Request, next: Next) -> Response {
+    let lang = negotiate(request.headers(), request.uri().query());
+    let mut response = next.run(request).await;
+    let Some(message) = response.extensions_mut().remove::<Message>() else {
+        return response;
+    };
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static("accept-language"));
+    headers.insert(
+        header::CONTENT_LANGUAGE,
+        HeaderValue::from_static(lang.code()),
+    );
+    if lang == Lang::En {
+        return response;
+    }
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = ErrorBody {
+        error: format(lang, &message.key, &message.args),
+    };
+    let body = serde_json::to_vec(&body).expect("error bodies serialize");
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepting(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn the_best_known_language_is_picked() {
+        assert_eq!(accepted(&HeaderMap::new()), Lang::En);
+        assert_eq!(accepted(&accepting("pt-BR,pt;q=0.9,en;q=0.8")), Lang::Pt);
+        assert_eq!(accepted(&accepting("de, es;q=0.5, en;q=0.4")), Lang::Es);
+        assert_eq!(accepted(&accepting("en;q=0.2, ES;q=0.7")), Lang::Es);
+        assert_eq!(accepted(&accepting("es;q=0, fr")), Lang::En);
+        assert_eq!(
+            negotiate(&accepting("es"), Some("storyId=1&lang=pt")),
+            Lang::Pt
+        );
+        assert_eq!(negotiate(&accepting("es"), Some("lang=xx")), Lang::Es);
+    }
+
+    #[tokio::test]
+    async fn errors_are_answered_in_the_negotiated_language() {
+        use axum::middleware::from_fn;
+        use axum::response::IntoResponse;
+        use axum::routing::get;
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::error::MyError;
+
+        let app = Router::new()
+            .route("/", get(|| async { MyError::Storage.into_response() }))
+            .layer(from_fn(localize));
+        for (accept, expected) in [
+            ("es-MX", message(Lang::Es, "error.storageFailed")),
+            ("de", message(Lang::En, "error.storageFailed")),
+        ] {
+            let request = Request::builder()
+                .uri("/")
+                .header(header::ACCEPT_LANGUAGE, accept)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.headers()[header::VARY], "accept-language");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], expected, "{}", accept);
+        }
+    }
+
+    #[test]
+    fn every_catalog_has_the_english_keys_and_placeholders() {
+        for lang in Lang::ALL {
+            for (key, english) in EN.iter() {
+                let message = catalog(lang)
+                    .get(key)
+                    .unwrap_or_else(|| panic!("{} has no {}", lang.code(), key));
+                let placeholders = |text: &str| {
+                    let mut names: Vec<String> = text
+                        .split('{')
+                        .skip(1)
+                        .filter_map(|rest| Some(rest.split_once('}')?.0.to_string()))
+                        .collect();
+                    names.sort();
+                    names
+                };
+                assert_eq!(
+                    placeholders(message),
+                    placeholders(english),
+                    "{} {}",
+                    lang.code(),
+                    key
+                );
+            }
+            assert_eq!(catalog(lang).len(), EN.len(), "{}", lang.code());
+        }
+    }
+}
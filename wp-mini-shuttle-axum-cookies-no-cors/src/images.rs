@@ -0,0 +1,368 @@
+//! Embedded images are made safe and readable before they go into the book:
+//!
+//! - Rasters in formats e-readers cannot show, mostly the WebP Wattpad's CDN
+//!   serves more and more, are transcoded to JPEG, or to PNG when they have
+//!   transparency. Which formats are kept is configured with
+//!   `EPUB_IMAGE_FORMATS`. AVIF is only decoded with the `avif` feature.
+//! - SVGs are sanitized (scripts, event handlers and external references
+//!   removed) or, with `svgImages: "rasterize"`, turned into PNGs. Text in
+//!   rasterized SVGs is dropped, as no fonts are loaded.
+//! - Animated GIFs are kept, or with `gifImages: "firstFrame"` replaced by a
+//!   PNG of their first frame.
+//! - Rasters wider than `EPUB_IMAGE_MAX_WIDTH` are scaled down to it, which
+//!   is what keeps books of image-heavy stories from growing past what
+//!   e-readers accept. Transcoded and scaled JPEGs are written at
+//!   `EPUB_IMAGE_QUALITY`. Animated GIFs are not scaled, as that would keep
+//!   only their first frame.
+//! - Exif, XMP, IPTC and text metadata is dropped from JPEGs and PNGs kept as
+//!   they are, without decoding them, unless `EPUB_IMAGE_STRIP_METADATA` is
+//!   off; Wattpad leaves camera details and locations in uploads. Images the
+//!   Exif rotates are turned upright first, as they would show sideways
+//!   without it. Images that are written again carry no metadata anyway.
+//!
+//! Only JPEG is written lossily: `image` encodes WebP losslessly, which
+//! would make photos larger rather than smaller.
+
+use anyhow::{anyhow, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use resvg::{tiny_skia, usvg};
+use std::io::Cursor;
+use tracing::{info, warn};
+
+use crate::book::Book;
+use crate::config::Config;
+
+pub use api_types::{GifImages, SvgImages};
+
+/// Formats every EPUB reader supports.
+pub const DEFAULT_FORMATS: [&str; 4] = ["jpeg", "png", "gif", "svg"];
+pub const JPEG_QUALITY: u8 = 85;
+/// JPEG segments dropped: APP1 (Exif, XMP), APP13 (IPTC) and comments.
+const JPEG_METADATA: [u8; 3] = [0xe1, 0xed, 0xfe];
+/// PNG chunks dropped.
+const PNG_METADATA: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+/// Longest side of a rasterized SVG.
+const MAX_RASTER_SIDE: f32 = 2_000.0;
+/// SVG elements dropped with everything in them.
+const UNSAFE_ELEMENTS: [&[u8]; 4] = [b"script", b"foreignObject", b"iframe", b"embed"];
+
+/// How `process` treats each kind of image.
+pub struct Policy<'a> {
+    /// Raster formats to keep, as lowercase extensions such as `jpeg` or
+    /// `webp`; `None` keeps all of them.
+    pub keep: Option<&'a [String]>,
+    pub svg: SvgImages,
+    pub gif: GifImages,
+    /// Widest a raster may be, in pixels.
+    pub max_width: Option<u32>,
+    /// Quality of the JPEGs written.
+    pub quality: u8,
+    pub strip_metadata: bool,
+}
+
+impl<'a> Policy<'a> {
+    /// The server's policy, with a request's `svg` and `gif` options.
+    pub fn new(config: &'a Config, svg: SvgImages, gif: GifImages) -> Self {
+        Policy {
+            keep: config.image_formats.as_deref(),
+            svg,
+            gif,
+            max_width: config.image_max_width,
+            quality: config.image_quality,
+            strip_metadata: config.image_strip_metadata,
+        }
+    }
+
+    /// Whether `process` leaves every image as it is.
+    pub fn keeps_all(&self) -> bool {
+        self.keep.is_none()
+            && self.svg == SvgImages::Keep
+            && self.gif == GifImages::Keep
+            && self.max_width.is_none()
+            && !self.strip_metadata
+    }
+
+    fn keeps(&self, format: ImageFormat) -> bool {
+        if format == ImageFormat::Gif && self.gif == GifImages::FirstFrame {
+            return false;
+        }
+        self.keep.is_none_or(|keep| {
+            format
+                .extensions_str()
+                .iter()
+                .any(|extension| keep.iter().any(|kept| kept == extension))
+        })
+    }
+}
+
+/// The image `name` converted as `policy` asks, under its new name if its
+/// format changed; `None` if it is kept as it is.
+pub fn convert(name: &str, data: &[u8], policy: &Policy) -> Option<(String, Vec<u8>)> {
+    let converted = if is_svg(data) {
+        match policy.svg {
+            SvgImages::Keep => return None,
+            SvgImages::Sanitize => sanitize_svg(data).map(|svg| (None, svg)),
+            SvgImages::Rasterize => rasterize_svg(data).map(|png| (Some("png"), png)),
+        }
+    } else {
+        match image::guess_format(data) {
+            Ok(format) => match raster(data, format, policy) {
+                Ok(Some(converted)) => Ok(converted),
+                Ok(None) => return None,
+                Err(e) => Err(e),
+            },
+            Err(_) => return None,
+        }
+    };
+    let (extension, converted) = match converted {
+        Ok(converted) => converted,
+        Err(e) => {
+            warn!(error = %e, image = %name, "Could not convert image");
+            return None;
+        }
+    };
+    let name = match extension {
+        Some(extension) => {
+            let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+            format!("{}.{}", stem, extension)
+        }
+        None => name.to_string(),
+    };
+    Some((name, converted))
+}
+
+/// Applies `policy` to the images of `book` and points the chapters at any
+/// renamed files. Returns whether any image changed.
+pub fn process(book: &mut Book, policy: &Policy) -> bool {
+    let mut changed = 0;
+    let mut renamed = Vec::new();
+    for (name, data) in &mut book.assets {
+        let Some((new_name, converted)) = convert(name, data, policy) else {
+            continue;
+        };
+        changed += 1;
+        *data = converted;
+        if new_name != *name {
+            renamed.push((std::mem::replace(name, new_name.clone()), new_name));
+        }
+    }
+    for chapter in &mut book.chapters {
+        for (old, new) in &renamed {
+            if chapter.body.contains(old.as_str()) {
+                chapter.body = chapter.body.replace(old.as_str(), new);
+            }
+        }
+    }
+    if changed > 0 {
+        info!(images = changed, "Converted images");
+    }
+    changed > 0
+}
+
+/// The raster `data` as `policy` wants it, with the extension for it when
+/// that changed; `None` if it is kept as it is.
+fn raster(
+    data: &[u8],
+    format: ImageFormat,
+    policy: &Policy,
+) -> Result<Option<(Option<&'static str>, Vec<u8>)>> {
+    let transcode = !policy.keeps(format);
+    // Scaling decodes every frame into one.
+    let animated = format == ImageFormat::Gif && policy.gif == GifImages::Keep;
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format).into_decoder()?;
+    let (width, _) = decoder.dimensions();
+    let scale = !animated && policy.max_width.is_some_and(|max_width| width > max_width);
+    let orientation = match policy.strip_metadata {
+        true => decoder.orientation().unwrap_or(Orientation::NoTransforms),
+        false => Orientation::NoTransforms,
+    };
+    if !transcode && !scale && orientation == Orientation::NoTransforms {
+        return Ok(match policy.strip_metadata {
+            true => strip_metadata(data, format).map(|data| (None, data)),
+            false => None,
+        });
+    }
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    if scale && let Some(max_width) = policy.max_width {
+        image = image.resize(max_width, u32::MAX, FilterType::Lanczos3);
+    }
+    let (extension, data) = match transcode {
+        true => encode(&image, policy.quality)?,
+        // Kept as their own format where it can be written again.
+        false => match format {
+            ImageFormat::Png | ImageFormat::Gif => {
+                let mut out = Cursor::new(Vec::new());
+                image.write_to(&mut out, format)?;
+                (format.extensions_str()[0], out.into_inner())
+            }
+            _ => encode(&image, policy.quality)?,
+        },
+    };
+    Ok(Some((Some(extension), data)))
+}
+
+/// `image` as a JPEG at `quality`, or as a PNG when it has transparency,
+/// and the extension for it.
+fn encode(image: &DynamicImage, quality: u8) -> Result<(&'static str, Vec<u8>)> {
+    let mut out = Cursor::new(Vec::new());
+    if image.color().has_alpha() {
+        image.write_to(&mut out, ImageFormat::Png)?;
+        return Ok(("png", out.into_inner()));
+    }
+    JpegEncoder::new_with_quality(&mut out, quality)
+        .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))?;
+    Ok(("jpg", out.into_inner()))
+}
+
+/// `data` without its metadata, or `None` if it had none or is in another
+/// format than JPEG or PNG.
+fn strip_metadata(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let stripped = match format {
+        ImageFormat::Jpeg => strip_jpeg(data)?,
+        ImageFormat::Png => strip_png(data)?,
+        _ => return None,
+    };
+    (stripped.len() < data.len()).then_some(stripped)
+}
+
+/// The JPEG without its `JPEG_METADATA` segments. Everything from the start
+/// of the scan on is copied as it is.
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = data.get(..2)?.to_vec();
+    let mut at = 2;
+    loop {
+        let &[0xff, marker] = data.get(at..at + 2)? else {
+            return None;
+        };
+        // Start of scan: the compressed image follows.
+        if marker == 0xda {
+            out.extend_from_slice(&data[at..]);
+            return Some(out);
+        }
+        let length = u16::from_be_bytes([*data.get(at + 2)?, *data.get(at + 3)?]) as usize;
+        let end = at + 2 + length;
+        if !JPEG_METADATA.contains(&marker) {
+            out.extend_from_slice(data.get(at..end)?);
+        }
+        at = end;
+    }
+}
+
+/// The PNG without its `PNG_METADATA` chunks.
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = data.get(..PNG_SIGNATURE.len())?.to_vec();
+    let mut at = PNG_SIGNATURE.len();
+    while at < data.len() {
+        let length = u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize;
+        // Length, type, data and CRC.
+        let end = at + 12 + length;
+        let kind = data.get(at + 4..at + 8)?;
+        if !PNG_METADATA
+            .iter()
+            .any(|dropped| dropped.as_slice() == kind)
+        {
+            out.extend_from_slice(data.get(at..end)?);
+        }
+        at = end;
+    }
+    Some(out)
+}
+
+/// A raster image as an RGB JPEG, with its width and height.
+pub fn to_jpeg(data: &[u8]) -> Result<(Vec<u8>, u32, u32)> {
+    let image = image::load_from_memory(data)?;
+    let mut out = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)
+        .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))?;
+    Ok((out.into_inner(), image.width(), image.height()))
+}
+
+fn is_svg(data: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&data[..data.len().min(1024)]);
+    head.trim_start().starts_with('<') && head.contains("<svg")
+}
+
+/// `data` without the parts of SVG that can run code or load other files.
+fn sanitize_svg(data: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = Reader::from_reader(data);
+    reader.config_mut().trim_text(false);
+    let mut writer = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    // Nesting depth inside a dropped element.
+    let mut dropped = 0usize;
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| anyhow!("Invalid SVG at {}: {}", reader.buffer_position(), e))?;
+        match event {
+            Event::Eof => break,
+            Event::Start(_) if dropped > 0 => dropped += 1,
+            Event::End(_) if dropped > 0 => dropped -= 1,
+            _ if dropped > 0 => {}
+            Event::Start(e) if is_unsafe(&e) => dropped = 1,
+            Event::Empty(e) if is_unsafe(&e) => {}
+            Event::Start(e) => writer.write_event(Event::Start(safe_attributes(&e)?))?,
+            Event::Empty(e) => writer.write_event(Event::Empty(safe_attributes(&e)?))?,
+            // Entity declarations can expand without bound.
+            Event::DocType(_) | Event::PI(_) => {}
+            event => writer.write_event(event)?,
+        }
+        buf.clear();
+    }
+    Ok(writer.into_inner())
+}
+
+fn is_unsafe(element: &BytesStart) -> bool {
+    let name = element.local_name();
+    UNSAFE_ELEMENTS
+        .iter()
+        .any(|unsafe_name| name.as_ref().eq_ignore_ascii_case(unsafe_name))
+}
+
+/// `element` without event handlers and links outside the image.
+fn safe_attributes(element: &BytesStart) -> Result<BytesStart<'static>> {
+    let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+    let mut safe = BytesStart::new(name);
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        let key = attribute.key.local_name();
+        let key = key.as_ref();
+        if key.len() > 2 && key[..2].eq_ignore_ascii_case(b"on") {
+            continue;
+        }
+        if key == b"href" {
+            let value = attribute.unescape_value()?;
+            let value = value.trim_start();
+            if !(value.starts_with('#') || value.starts_with("data:image/")) {
+                continue;
+            }
+        }
+        safe.push_attribute(attribute);
+    }
+    Ok(safe.into_owned())
+}
+
+/// `data` drawn as a PNG at its own size, scaled down to fit `MAX_RASTER_SIDE`.
+fn rasterize_svg(data: &[u8]) -> Result<Vec<u8>> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default())?;
+    let size = tree.size();
+    let scale = (MAX_RASTER_SIDE / size.width().max(size.height())).min(1.0);
+    let width = (size.width() * scale).ceil() as u32;
+    let height = (size.height() * scale).ceil() as u32;
+    let mut pixmap =
+        tiny_skia::Pixmap::new(width, height).ok_or_else(|| anyhow!("SVG has no size"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    Ok(pixmap.encode_png()?)
+}
@@ -0,0 +1,136 @@
+//! Soft time budget for asynchronous jobs (`JOB_BUDGET_SECS`).
+//!
+//! A job that runs past its budget is paused at the next checkpoint and put
+//! in a low-priority lane: it resumes, one at a time, only once no job within
+//! its budget is running on the instance. Checkpoints sit between stages, so a
+//! finished download is kept while the job waits. A download that overruns is
+//! cut off and resumed in the low-priority lane, where it runs without a
+//! budget, after the last part it finished: jobs download part by part and
+//! keep each part done (see `crate::progress::Progress::resumable`).
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Semaphore, SemaphorePermit};
+use tracing::info;
+
+use crate::jobs::{record, update, Job, JobState};
+
+pub struct Lanes {
+    budget: Option<Duration>,
+    /// Jobs running within their budget.
+    busy: watch::Sender<usize>,
+    low_priority: Semaphore,
+}
+
+impl Lanes {
+    pub fn new(budget: Option<Duration>) -> Self {
+        Lanes {
+            budget,
+            busy: watch::Sender::new(0),
+            low_priority: Semaphore::new(1),
+        }
+    }
+
+    /// Starts timing a job; it counts as busy until paused or dropped.
+    pub fn start<'a>(&'a self, job: &'a watch::Sender<Job>) -> Budget<'a> {
+        self.busy.send_modify(|busy| *busy += 1);
+        Budget {
+            lanes: self,
+            job,
+            started: Instant::now(),
+            busy: true,
+            _low_priority: None,
+        }
+    }
+}
+
+pub struct Budget<'a> {
+    lanes: &'a Lanes,
+    job: &'a watch::Sender<Job>,
+    started: Instant,
+    /// Whether the job is counted in `Lanes::busy`.
+    busy: bool,
+    /// Held once the job runs in the low-priority lane.
+    _low_priority: Option<SemaphorePermit<'a>>,
+}
+
+impl<'a> Budget<'a> {
+    fn remaining(&self) -> Option<Duration> {
+        match self.busy {
+            false => None,
+            true => self
+                .lanes
+                .budget
+                .map(|budget| budget.saturating_sub(self.started.elapsed())),
+        }
+    }
+
+    /// Runs a stage, running it again in the low-priority lane if it
+    /// overruns; the stage picks up what it kept of its first run.
+    pub async fn limit<T, F, Fut>(&mut self, stage: &str, mut run: F) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let Some(remaining) = self.remaining() else {
+            return run().await;
+        };
+        match tokio::time::timeout(remaining, run()).await {
+            Ok(output) => output,
+            Err(_) => {
+                self.pause(format!("over budget while {}; resuming it later", stage))
+                    .await;
+                run().await
+            }
+        }
+    }
+
+    /// Pauses the job here if it has used up its budget.
+    pub async fn checkpoint(&mut self, done: &str) {
+        if self
+            .remaining()
+            .is_some_and(|remaining| remaining.is_zero())
+        {
+            self.pause(format!("over budget after {}", done)).await;
+        }
+    }
+
+    async fn pause(&mut self, detail: String) {
+        info!(%detail, "Job paused for running over its budget");
+        update(self.job, |status| {
+            status.state = JobState::Queued;
+            status.stage = None;
+            status.deprioritized = true;
+            record(status, "paused", Some(detail));
+        });
+        self.lanes.busy.send_modify(|busy| *busy -= 1);
+        self.busy = false;
+
+        let permit = self
+            .lanes
+            .low_priority
+            .acquire()
+            .await
+            .expect("the low-priority lane is never closed");
+        let _ = self
+            .lanes
+            .busy
+            .subscribe()
+            .wait_for(|busy| *busy == 0)
+            .await;
+        self._low_priority = Some(permit);
+
+        update(self.job, |status| {
+            status.state = JobState::Running;
+            record(status, "resumed", None);
+        });
+    }
+}
+
+impl Drop for Budget<'_> {
+    fn drop(&mut self) {
+        if self.busy {
+            self.lanes.busy.send_modify(|busy| *busy -= 1);
+        }
+    }
+}
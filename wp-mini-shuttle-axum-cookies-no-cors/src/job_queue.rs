@@ -0,0 +1,368 @@
+//! A job queue shared by every instance, so asynchronous jobs are not tied to
+//! the instance that accepted them.
+//!
+//! Enabled when `REDIS_URL` is set and `STORAGE_BACKEND` is `postgres` or
+//! `s3`. Queued jobs and their status live in storage; an instance claims a
+//! job by taking its lease in Redis and keeps it with heartbeats while the job
+//! runs. A job whose instance stops heartbeating is picked up again by another
+//! instance once the lease expires, and two instances never run it at once.
+//!
+//! What lets a job act as its user (cookies, delivery tokens and passwords,
+//! notification webhooks) is not stored with it: it waits in Redis for up to
+//! `CREDENTIALS_TTL` and is deleted once the job has run. A job claimed after
+//! that fails instead of running without it.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{info, info_span, warn, Instrument};
+use uuid::Uuid;
+
+use crate::delivery::Delivery;
+use crate::error::MyError;
+use crate::jobs::{self, Job, JobError, JobEvent, JobState, JobStatus};
+use crate::notify::Notification;
+use crate::shared::Shared;
+use crate::storage::Storage;
+use crate::{unix_now, upgrade, AppState, Cookie, GenerateEpubRequest};
+
+const STATUS: &str = "jobs/status/";
+const QUEUE: &str = "jobs/queue/";
+/// How long a claim lasts without a heartbeat.
+const LEASE: Duration = Duration::from_secs(30);
+const HEARTBEAT: Duration = Duration::from_secs(10);
+/// How often each instance looks for unclaimed jobs.
+const POLL: Duration = Duration::from_secs(2);
+/// How often waiting requests re-read the status of a job run elsewhere.
+const STATUS_POLL: Duration = Duration::from_secs(1);
+/// Jobs one instance runs at a time.
+const MAX_RUNNING: usize = 4;
+/// How often finished statuses past retention are deleted (by one instance).
+const SWEEP: Duration = Duration::from_secs(10 * 60);
+/// How long a queued job's credentials wait for it to be claimed.
+const CREDENTIALS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// What a worker needs to run a job it claims, but its `Credentials`.
+#[derive(Serialize, Deserialize)]
+struct QueuedJob {
+    request: GenerateEpubRequest,
+    /// Whether the job has `Credentials` to run with.
+    #[serde(default)]
+    credentials: bool,
+}
+
+/// The parts of a job that are its user's secrets.
+#[derive(Serialize, Deserialize)]
+struct Credentials {
+    cookies: Option<Vec<Cookie>>,
+    delivery: Option<Delivery>,
+    notifications: Vec<Notification>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredStatus {
+    status: JobStatus,
+    events: Vec<JobEvent>,
+}
+
+pub struct JobQueue {
+    storage: Arc<dyn Storage>,
+    shared: Arc<dyn Shared>,
+    /// Identifies this instance's leases.
+    instance: Bytes,
+    /// How long finished jobs stay queryable.
+    retention: Duration,
+    running: AtomicUsize,
+}
+
+fn lease(id: &str) -> String {
+    format!("job-lease:{}", id)
+}
+
+fn credentials(id: &str) -> String {
+    format!("job-credentials:{}", id)
+}
+
+impl JobQueue {
+    pub fn new(storage: Arc<dyn Storage>, shared: Arc<dyn Shared>, retention: Duration) -> Self {
+        JobQueue {
+            storage,
+            shared,
+            instance: Bytes::from(Uuid::new_v4().simple().to_string()),
+            retention,
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    pub async fn enqueue(
+        &self,
+        status: &Job,
+        mut request: GenerateEpubRequest,
+        notifications: Vec<Notification>,
+    ) -> Result<()> {
+        let secrets = Credentials {
+            cookies: request.cookies.take(),
+            delivery: request.delivery.take(),
+            notifications,
+        };
+        let job = QueuedJob {
+            request,
+            credentials: secrets.cookies.is_some()
+                || secrets.delivery.is_some()
+                || !secrets.notifications.is_empty(),
+        };
+        if job.credentials {
+            self.shared
+                .set(
+                    &credentials(&status.id),
+                    Bytes::from(serde_json::to_vec(&secrets)?),
+                    CREDENTIALS_TTL,
+                )
+                .await?;
+        }
+        // The status goes in first so a worker that finds the job can load it.
+        self.save(status).await?;
+        self.storage
+            .put(
+                &format!("{}{}", QUEUE, status.id),
+                Bytes::from(serde_json::to_vec(&job)?),
+            )
+            .await
+    }
+
+    /// The credentials `job` was queued with, `None` once they expired.
+    async fn credentials(&self, id: &str, job: &QueuedJob) -> Result<Option<(Bytes, Credentials)>> {
+        if !job.credentials {
+            let none = Credentials {
+                cookies: None,
+                delivery: None,
+                notifications: Vec::new(),
+            };
+            return Ok(Some((Bytes::new(), none)));
+        }
+        let Some(bytes) = self.shared.get(&credentials(id)).await? else {
+            return Ok(None);
+        };
+        let secrets = serde_json::from_slice(&bytes)?;
+        Ok(Some((bytes, secrets)))
+    }
+
+    async fn save(&self, job: &Job) -> Result<()> {
+        let stored = StoredStatus {
+            status: job.status.clone(),
+            events: job.events.clone(),
+        };
+        self.storage
+            .put(
+                &format!("{}{}", STATUS, job.id),
+                Bytes::from(serde_json::to_vec(&stored)?),
+            )
+            .await
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<Job>> {
+        let Some(bytes) = self.storage.get(&format!("{}{}", STATUS, id)).await? else {
+            return Ok(None);
+        };
+        let stored: StoredStatus = serde_json::from_slice(&bytes)?;
+        Ok(Some(Job::from_status(stored.status, stored.events)))
+    }
+
+    /// The stored status of a job, waiting up to `wait` for a version newer
+    /// than `since` (or than the current one) while it is unfinished.
+    pub async fn wait(
+        &self,
+        id: &str,
+        wait: Option<Duration>,
+        since: Option<u64>,
+    ) -> Result<Option<Job>> {
+        let deadline = Instant::now() + wait.unwrap_or_default();
+        let mut since = since;
+        loop {
+            let Some(status) = self.load(id).await? else {
+                return Ok(None);
+            };
+            let since = *since.get_or_insert(status.version);
+            if wait.is_none()
+                || status.version > since
+                || status.state.is_finished()
+                || Instant::now() >= deadline
+            {
+                return Ok(Some(status));
+            }
+            tokio::time::sleep(STATUS_POLL.min(deadline - Instant::now())).await;
+        }
+    }
+
+    async fn sweep(&self) -> Result<()> {
+        let now = unix_now();
+        let retention = self.retention.as_secs();
+        for key in self.storage.list(STATUS).await? {
+            let Some(id) = key.strip_prefix(STATUS) else {
+                continue;
+            };
+            if let Some(status) = self.load(id).await?
+                && status.state.is_finished()
+                && now.saturating_sub(status.updated_at) >= retention
+            {
+                self.storage.delete(&key).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Starts claiming queued jobs on this instance.
+pub fn spawn_worker(state: AppState, queue: Arc<JobQueue>) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(POLL);
+        let mut last_sweep: Option<Instant> = None;
+        loop {
+            tick.tick().await;
+            if let Err(e) = poll(&state, &queue).await {
+                warn!(error = %e, "Could not poll the job queue");
+            }
+            if last_sweep.is_none_or(|at| at.elapsed() >= SWEEP) {
+                last_sweep = Some(Instant::now());
+                let claimed = queue
+                    .shared
+                    .set_if_absent("job-sweep", queue.instance.clone(), SWEEP)
+                    .await
+                    .unwrap_or(false);
+                if claimed && let Err(e) = queue.sweep().await {
+                    warn!(error = %e, "Could not sweep finished jobs");
+                }
+            }
+        }
+    });
+}
+
+async fn poll(state: &AppState, queue: &Arc<JobQueue>) -> Result<()> {
+    for key in queue.storage.list(QUEUE).await? {
+        if queue.running.load(Ordering::Relaxed) >= MAX_RUNNING {
+            break;
+        }
+        let Some(id) = key.strip_prefix(QUEUE) else {
+            continue;
+        };
+        if state.jobs.get(id).is_some() {
+            continue;
+        }
+        if !queue
+            .shared
+            .set_if_absent(&lease(id), queue.instance.clone(), LEASE)
+            .await?
+        {
+            continue;
+        }
+        queue.running.fetch_add(1, Ordering::Relaxed);
+        let (state, queue, id) = (state.clone(), queue.clone(), id.to_string());
+        let span = info_span!("queued_job", job_id = %id);
+        tokio::spawn(
+            async move {
+                if let Err(e) = work(&state, &queue, &id).await {
+                    warn!(error = %e, "Could not run queued job");
+                    state.jobs.forget(&id);
+                }
+                queue.running.fetch_sub(1, Ordering::Relaxed);
+                if let Err(e) = queue
+                    .shared
+                    .delete_if(&lease(&id), queue.instance.clone())
+                    .await
+                {
+                    warn!(error = %e, "Could not release job lease");
+                }
+            }
+            .instrument(span),
+        );
+    }
+    Ok(())
+}
+
+/// Runs a claimed job while holding its lease.
+async fn work(state: &AppState, queue: &Arc<JobQueue>, id: &str) -> Result<()> {
+    let job_key = format!("{}{}", QUEUE, id);
+    // Another instance may have finished it since the listing.
+    let Some(job) = queue.storage.get(&job_key).await? else {
+        return Ok(());
+    };
+    let mut job: QueuedJob = serde_json::from_slice(&job)?;
+    // Queued by an older version.
+    upgrade(&mut job.request);
+    let status = match queue.load(id).await? {
+        Some(status) if !status.state.is_finished() => status,
+        _ => return queue.storage.delete(&job_key).await,
+    };
+
+    let sender = state.jobs.adopt(status);
+    jobs::update(&sender, |status| jobs::record(status, "claimed", None));
+    info!("Claimed queued job");
+    let mirror = tokio::spawn(mirror(queue.clone(), sender.subscribe()));
+
+    let Some((stored, secrets)) = queue.credentials(id, &job).await? else {
+        warn!("Queued job's credentials expired before it was claimed");
+        let error = JobError::from(&MyError::Unavailable {
+            message: "The job waited too long to run; submit it again".into(),
+            retry_after: None,
+        });
+        jobs::update(&sender, |status| {
+            status.state = JobState::Failed;
+            status.stage = None;
+            jobs::record(status, "failed", Some("credentials expired".into()));
+            status.error = Some(error);
+        });
+        drop(sender);
+        let _ = mirror.await;
+        return queue.storage.delete(&job_key).await;
+    };
+    job.request.cookies = secrets.cookies;
+    job.request.delivery = secrets.delivery;
+
+    let heartbeat = async {
+        loop {
+            tokio::time::sleep(HEARTBEAT).await;
+            match queue
+                .shared
+                .renew_if(&lease(id), queue.instance.clone(), LEASE)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => return,
+                // The lease outlives a few failed heartbeats.
+                Err(e) => warn!(error = %e, "Could not renew job lease"),
+            }
+        }
+    };
+    tokio::select! {
+        _ = jobs::run(state.clone(), sender, job.request, secrets.notifications) => {
+            let _ = mirror.await;
+            queue.storage.delete(&job_key).await?;
+            if job.credentials {
+                queue.shared.delete_if(&credentials(id), stored).await?;
+            }
+        }
+        _ = heartbeat => {
+            warn!("Lost the job lease; leaving the job to another instance");
+            mirror.abort();
+            state.jobs.forget(id);
+        }
+    }
+    Ok(())
+}
+
+/// Writes every change of a job run here to storage, for the other instances.
+async fn mirror(queue: Arc<JobQueue>, mut updates: watch::Receiver<Job>) {
+    loop {
+        let status = updates.borrow_and_update().clone();
+        if let Err(e) = queue.save(&status).await {
+            warn!(error = %e, "Could not store job status");
+        }
+        if status.state.is_finished() || updates.changed().await.is_err() {
+            return;
+        }
+    }
+}
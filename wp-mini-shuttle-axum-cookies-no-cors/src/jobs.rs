@@ -0,0 +1,465 @@
+//! Background generation jobs for clients that cannot hold a request open.
+//!
+//! `POST /generate-epub/async` takes the same body as `/generate-epub` and
+//! answers `202` with a job; `GET /jobs/{id}` reports on it. Passing
+//! `?wait=30s` turns the status call into a long poll that returns as soon as
+//! the job changes (or its `version` passes `since`), or once the wait is over.
+//! `GET /jobs/{id}/events-history` lists what happened to the job, so users can
+//! see why theirs failed without access to the server logs.
+//! `GET /jobs/{id}/download` redirects to the finished file, and answers like
+//! the status call with `202` while the job is not done yet.
+//!
+//! Jobs run on the instance that accepted them, unless `crate::job_queue` is
+//! set up; then any instance may claim them and the status is read from storage.
+//! Jobs that run past `JOB_BUDGET_SECS` make way for others (see
+//! `crate::job_budget`).
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, instrument, Instrument};
+use uuid::Uuid;
+
+use crate::artifacts::Artifact;
+use crate::batch::ItemStatus;
+use crate::deprecation;
+use crate::error::MyError;
+use crate::job_budget::Lanes;
+use crate::notify::{self, Notification};
+use crate::profiles::Profiled;
+use crate::progress::Progress;
+use crate::{admit, deliver, download, failed_event, unix_now, AppState, GenerateEpubRequest};
+
+pub use api_types::{EventHistory, JobError, JobEvent, JobResult, JobState, JobStatus};
+
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// Events kept per job; older ones are dropped first.
+const MAX_EVENTS: usize = 100;
+
+/// A job as the server keeps it: the status clients are sent, and what only
+/// the server needs of it.
+#[derive(Clone)]
+pub struct Job {
+    pub status: JobStatus,
+    /// Sent by `events-history` rather than with the status.
+    pub events: Vec<JobEvent>,
+    /// The options of a batch job, to generate its failed stories again with.
+    pub batch_options: Option<Arc<Map<String, Value>>>,
+    /// The `crate::artifacts` token of each book of a batch, in the order of
+    /// `items`, for retries to take them from.
+    pub artifacts: Vec<Option<String>>,
+}
+
+impl Job {
+    /// A job last seen as `status`, with what happened to it so far.
+    pub fn from_status(status: JobStatus, events: Vec<JobEvent>) -> Self {
+        Job {
+            status,
+            events,
+            batch_options: None,
+            artifacts: Vec::new(),
+        }
+    }
+}
+
+impl Deref for Job {
+    type Target = JobStatus;
+
+    fn deref(&self) -> &JobStatus {
+        &self.status
+    }
+}
+
+impl DerefMut for Job {
+    fn deref_mut(&mut self) -> &mut JobStatus {
+        &mut self.status
+    }
+}
+
+/// A job for the story `story_id`.
+fn queued_story(story_id: u64) -> Job {
+    let mut job = queued();
+    job.story_id = Some(story_id);
+    job
+}
+
+/// A job for the stories `ids` together, each generated with `options`.
+pub fn batch(ids: &[u64], options: Map<String, Value>) -> Job {
+    let mut job = queued();
+    job.items = ids.iter().map(|id| ItemStatus::pending(*id)).collect();
+    job.batch_options = Some(Arc::new(options));
+    job.artifacts = vec![None; ids.len()];
+    job
+}
+
+fn queued() -> Job {
+    let now = unix_now();
+    let status = JobStatus {
+        id: Uuid::new_v4().simple().to_string(),
+        story_id: None,
+        state: JobState::Queued,
+        stage: None,
+        deprioritized: false,
+        version: 0,
+        created_at: now,
+        updated_at: now,
+        result: None,
+        error: None,
+        items: Vec::new(),
+    };
+    let mut job = Job::from_status(status, Vec::new());
+    record(&mut job, "queued", None);
+    job
+}
+
+/// Adds `event` to what `events-history` lists for the job.
+pub fn record(job: &mut Job, event: &'static str, detail: Option<String>) {
+    if job.events.len() == MAX_EVENTS {
+        job.events.remove(0);
+    }
+    job.events.push(JobEvent {
+        at: unix_now(),
+        event: event.into(),
+        detail,
+    });
+}
+
+impl From<&MyError> for JobError {
+    fn from(error: &MyError) -> Self {
+        let (status, message) = error.status_and_message();
+        JobError {
+            status: status.as_u16(),
+            code: error.code().to_string(),
+            retryable: error.retryable(),
+            error: message,
+        }
+    }
+}
+
+pub struct JobStore {
+    jobs: Mutex<HashMap<String, watch::Sender<Job>>>,
+    /// How long finished jobs stay queryable.
+    retention: Duration,
+    lanes: Lanes,
+}
+
+impl JobStore {
+    pub fn new(retention: Duration, budget: Option<Duration>) -> Self {
+        JobStore {
+            jobs: Mutex::new(HashMap::new()),
+            retention,
+            lanes: Lanes::new(budget),
+        }
+    }
+
+    fn create(&self, story_id: u64) -> watch::Sender<Job> {
+        self.adopt(queued_story(story_id))
+    }
+
+    /// Tracks a job created elsewhere (e.g. claimed from the queue) here.
+    pub fn adopt(&self, job: Job) -> watch::Sender<Job> {
+        let now = unix_now();
+        let id = job.id.clone();
+        let (job, _) = watch::channel(job);
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let retention = self.retention.as_secs();
+        jobs.retain(|_, job| {
+            let status = job.borrow();
+            !status.state.is_finished() || now.saturating_sub(status.updated_at) < retention
+        });
+        jobs.insert(id, job.clone());
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Option<watch::Sender<Job>> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn forget(&self, id: &str) {
+        self.jobs.lock().unwrap().remove(id);
+    }
+}
+
+pub fn update(job: &watch::Sender<Job>, change: impl FnOnce(&mut Job)) {
+    job.send_modify(|status| {
+        change(status);
+        status.version += 1;
+        status.updated_at = unix_now();
+    });
+}
+
+#[instrument(skip(state, payload), fields(story_id = payload.story_id))]
+pub async fn create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Profiled(mut payload): Profiled,
+) -> Result<Response, MyError> {
+    let (notifications, warnings) = admit(&state, &headers, &mut payload, "async").await?;
+    let status = start(state, payload, notifications).await?;
+    Ok(deprecation::attach(
+        (StatusCode::ACCEPTED, Json(status)).into_response(),
+        warnings,
+    ))
+}
+
+/// Queues an admitted request as a job.
+pub async fn start(
+    state: AppState,
+    payload: GenerateEpubRequest,
+    notifications: Vec<Notification>,
+) -> Result<JobStatus, MyError> {
+    if let Some(queue) = &state.job_queue {
+        let job = queued_story(payload.story_id);
+        queue
+            .enqueue(&job, payload, notifications)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not queue generation job");
+                MyError::Storage
+            })?;
+        info!(job_id = %job.id, "Queued generation job for any instance");
+        return Ok(job.status);
+    }
+    let job = state.jobs.create(payload.story_id);
+    let status = job.borrow().status.clone();
+    info!(job_id = %status.id, "Queued generation job");
+
+    tokio::spawn(run(state, job, payload, notifications).in_current_span());
+    Ok(status)
+}
+
+pub async fn run(
+    state: AppState,
+    job: watch::Sender<Job>,
+    payload: GenerateEpubRequest,
+    notifications: Vec<Notification>,
+) {
+    update(&job, |status| {
+        status.state = JobState::Running;
+        status.stage = Some("downloading".into());
+        record(status, "running", None);
+    });
+    let progress = {
+        let job = job.clone();
+        Progress::new(move |stage| {
+            update(&job, |status| {
+                status.stage = Some(stage.into());
+                record(status, "stage", Some(stage.to_string()));
+            })
+        })
+        .resumable()
+    };
+
+    let mut budget = state.jobs.lanes.start(&job);
+    let outcome = async {
+        let epub = budget
+            .limit("downloading", || download(&state, &payload, &progress))
+            .await?;
+        budget.checkpoint("downloading").await;
+        let (download_url, delivery) = match payload.delivery.as_ref() {
+            Some(delivery) => {
+                let receipt =
+                    deliver(&state, delivery, &payload.download_link, &epub, &progress).await?;
+                (receipt.web_url.clone(), Some(receipt))
+            }
+            None => {
+                let path = state
+                    .artifacts
+                    .publish(
+                        Artifact {
+                            file_name: epub.file_name.clone(),
+                            content_type: epub.content_type.to_string(),
+                            bytes: epub.bytes.clone(),
+                        },
+                        &payload.download_link,
+                    )
+                    .await?;
+                let url = state.config.public_url(&path);
+                (Some(url), None)
+            }
+        };
+        let event = epub.completed_event(payload.story_id, download_url.clone());
+        let result = JobResult {
+            size: epub.bytes.len(),
+            file_name: epub.file_name,
+            download_url,
+            delivery,
+            skipped_chapters: epub.skipped,
+        };
+        Ok::<_, MyError>((result, event))
+    }
+    .await;
+    drop(budget);
+
+    let event = match outcome {
+        Ok((result, event)) => {
+            info!("Generation job completed");
+            update(&job, |status| {
+                status.state = JobState::Completed;
+                status.stage = None;
+                record(
+                    status,
+                    "completed",
+                    Some(format!("{} ({} bytes)", result.file_name, result.size)),
+                );
+                status.result = Some(result);
+            });
+            event
+        }
+        Err(e) => {
+            let event = failed_event(payload.story_id, &e);
+            let error = JobError::from(&e);
+            info!(status = error.status, "Generation job failed");
+            update(&job, |status| {
+                status.state = JobState::Failed;
+                status.stage = None;
+                record(
+                    status,
+                    "failed",
+                    Some(format!("{}: {}", error.status, error.error)),
+                );
+                status.error = Some(error);
+            });
+            event
+        }
+    };
+    notify::dispatch(
+        state.delivery_client.clone(),
+        state.config.clone(),
+        notifications,
+        event,
+    );
+}
+
+#[derive(Deserialize)]
+pub struct StatusQuery {
+    /// `30s`, `30` or `500ms`; capped at a minute.
+    wait: Option<String>,
+    since: Option<u64>,
+}
+
+pub async fn status(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<StatusQuery>,
+) -> Result<Json<JobStatus>, MyError> {
+    let wait = query.wait.as_deref().map(parse_wait).transpose()?;
+    let job = match state.jobs.get(&id) {
+        Some(job) => job,
+        None => {
+            let status = remote(
+                &state,
+                &id,
+                wait.map(|wait| wait.min(MAX_WAIT)),
+                query.since,
+            )
+            .await?;
+            return Ok(Json(status.status));
+        }
+    };
+
+    let mut updates = job.subscribe();
+    if let Some(wait) = wait {
+        let since = query.since.unwrap_or(updates.borrow().version);
+        let _ = tokio::time::timeout(
+            wait.min(MAX_WAIT),
+            updates.wait_for(|status| status.version > since || status.state.is_finished()),
+        )
+        .await;
+    }
+    let status = updates.borrow().status.clone();
+    Ok(Json(status))
+}
+
+pub async fn events_history(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<EventHistory>, MyError> {
+    let job = match state.jobs.get(&id) {
+        Some(job) => job.borrow().clone(),
+        None => remote(&state, &id, None, None).await?,
+    };
+    Ok(Json(EventHistory {
+        id: job.status.id,
+        state: job.status.state,
+        events: job.events,
+    }))
+}
+
+pub async fn file(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, MyError> {
+    let status = match state.jobs.get(&id) {
+        Some(job) => job.borrow().status.clone(),
+        None => remote(&state, &id, None, None).await?.status,
+    };
+    match status.state {
+        JobState::Completed => {}
+        JobState::Failed => {
+            return Err(MyError::NotFound(format!(
+                "Job {} failed; see /jobs/{} for why",
+                id, id
+            )));
+        }
+        JobState::Queued | JobState::Running => {
+            let location = format!("/jobs/{}", id);
+            return Ok((
+                StatusCode::ACCEPTED,
+                [(header::LOCATION, location)],
+                Json(status),
+            )
+                .into_response());
+        }
+    }
+    let url = status
+        .result
+        .and_then(|result| result.download_url)
+        .ok_or_else(|| MyError::NotFound(format!("Job {} delivered its file elsewhere", id)))?;
+    Ok((StatusCode::SEE_OTHER, [(header::LOCATION, url)]).into_response())
+}
+
+/// A job this instance does not track, as last written to the queue.
+async fn remote(
+    state: &AppState,
+    id: &str,
+    wait: Option<Duration>,
+    since: Option<u64>,
+) -> Result<Job, MyError> {
+    let not_found = || MyError::NotFound(format!("Job {} does not exist or has expired", id));
+    let Some(queue) = &state.job_queue else {
+        return Err(not_found());
+    };
+    queue
+        .wait(id, wait, since)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not read queued job");
+            MyError::Storage
+        })?
+        .ok_or_else(not_found)
+}
+
+fn parse_wait(wait: &str) -> Result<Duration, MyError> {
+    let invalid = || MyError::InvalidRequest(format!("Invalid wait duration: {}", wait));
+    if let Some(millis) = wait.strip_suffix("ms") {
+        return millis
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| invalid());
+    }
+    wait.strip_suffix('s')
+        .unwrap_or(wait)
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| invalid())
+}
@@ -0,0 +1,907 @@
+mod abuse;
+mod admin;
+mod alt_text;
+mod analysis;
+mod api_key;
+mod artifacts;
+mod batch;
+mod book;
+mod bookmarklet;
+mod chapters;
+mod client_pool;
+mod conditional;
+mod config;
+#[cfg(test)]
+mod contract;
+mod convert;
+mod cors;
+mod cover;
+mod cover_image;
+mod cover_upload;
+mod deadline;
+mod delivery;
+mod deprecation;
+mod drift;
+mod error;
+mod file_response;
+#[cfg(fuzzing)]
+pub mod fuzzing;
+mod health;
+mod http_client;
+mod i18n;
+mod images;
+mod job_budget;
+mod job_queue;
+mod jobs;
+mod maintenance;
+mod metadata;
+mod notify;
+mod opf;
+mod part_cache;
+mod pipeline;
+mod plugins;
+mod politeness;
+mod postprocess;
+mod prefs;
+mod profiles;
+mod progress;
+mod rate_limit;
+mod render;
+mod replace;
+mod response_cache;
+mod scrape;
+mod security_headers;
+mod session;
+mod shadow;
+mod shared;
+mod signing;
+mod sse;
+mod stats;
+mod storage;
+mod story_cache;
+mod story_url;
+mod subscriptions;
+mod tags;
+mod telegram;
+#[cfg(test)]
+mod testing;
+mod themes;
+mod tts;
+mod uploads;
+mod usage;
+mod warc;
+mod web_ui;
+
+use abuse::ScrapeDetector;
+use admin::AuditLog;
+use api_types::{Cookie, DeliveryResponse, GenerateEpubRequest};
+use artifacts::{ArtifactStore, LinkOptions};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::middleware::{from_fn, from_fn_with_state, map_response, map_response_with_state};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, head, post};
+use axum::{Json, Router};
+use client_pool::ClientPool;
+use config::{Config, StorageConfig};
+use delivery::{Delivery, DeliveryContext, DeliveryFile, DeliveryReceipt};
+use deprecation::Warning;
+use drift::Drift;
+use error::MyError;
+use file_response::{attachment, streamed_attachment};
+use health::Readiness;
+use job_queue::JobQueue;
+use jobs::JobStore;
+use maintenance::Maintenance;
+use notify::{Event, Notification};
+use opf::MetadataOverrides;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use plugins::Plugins;
+use politeness::RateLimit;
+use profiles::{Profiled, Profiles};
+use progress::Progress;
+use rate_limit::ClientLimits;
+use render::Format;
+use reqwest::cookie::Jar;
+use reqwest::{Client, Url};
+use response_cache::CacheStatus;
+use security_headers::Policy;
+use shadow::Shadow;
+use shared::Shared;
+use shuttle_runtime::SecretStore;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use storage::Storage;
+use tags::Tags;
+use tracing::{error, info, instrument, warn};
+use uploads::UploadStore;
+use usage::Usage;
+use wp_mini_epub::AppError;
+
+/// Parts fetched at a time for requests that do not ask for another number.
+pub(crate) const CONCURRENT_CHAPTER_REQUESTS: usize = 10;
+const APP_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/139.0.0.0 Safari/537.36";
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Clone)]
+struct AppState {
+    anon_client: Arc<Client>,
+    /// Cookie-less client for uploads to delivery targets.
+    delivery_client: Client,
+    config: Arc<Config>,
+    artifacts: Arc<ArtifactStore>,
+    jobs: Arc<JobStore>,
+    uploads: Arc<UploadStore>,
+    scraping: Arc<ScrapeDetector>,
+    audit: Arc<AuditLog>,
+    maintenance: Arc<Maintenance>,
+    shadow: Arc<Shadow>,
+    drift: Arc<Drift>,
+    /// Stories started against Wattpad, for `WATTPAD_MAX_STORIES_PER_MINUTE`.
+    wattpad_rate: Arc<RateLimit>,
+    plugins: Arc<Plugins>,
+    /// Stories started per client, for `RATE_LIMIT_PER_*`.
+    client_limits: Arc<ClientLimits>,
+    readiness: Arc<Readiness>,
+    session_clients: Arc<ClientPool>,
+    /// Cache, locks and throttling shared with other instances.
+    shared: Arc<dyn Shared>,
+    /// Set when jobs are shared with other instances.
+    job_queue: Option<Arc<JobQueue>>,
+    usage: Arc<Usage>,
+    profiles: Arc<Profiles>,
+    storage: Arc<dyn Storage>,
+}
+
+/// `embedImages` under the name extensions up to 0.2.8 send it as.
+const IS_EMBED_IMAGES: Warning = Warning {
+    code: "isEmbedImages",
+    message: "isEmbedImages is deprecated; send embedImages instead",
+    // 2026-10-14, when 0.2.9 started sending `embedImages`.
+    since: 1_791_936_000,
+    sunset: Some("Wed, 14 Apr 2027 00:00:00 GMT"),
+};
+
+/// Moves fields sent under another name to the one they are read from,
+/// with a warning for each that is deprecated.
+fn upgrade(payload: &mut GenerateEpubRequest) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    if let Some(embed_images) = payload.is_embed_images.take() {
+        payload.embed_images = embed_images;
+        warnings.push(IS_EMBED_IMAGES);
+    }
+    warnings
+}
+
+/// The service `main` runs, configured from `secrets`.
+pub async fn serve(secrets: SecretStore) -> shuttle_axum::ShuttleAxum {
+    let config = Config::from_secrets(&secrets).map_err(|e| {
+        error!("{}", e);
+        shuttle_runtime::Error::Custom(anyhow::anyhow!("{}", e))
+    })?;
+    Ok(app(config).await.into())
+}
+
+/// Every route, with the state they share; what `serve` serves.
+async fn app(config: Config) -> Router {
+    let app_state = state(config).await;
+    if let Some(queue) = app_state.job_queue.clone() {
+        job_queue::spawn_worker(app_state.clone(), queue);
+    }
+    subscriptions::spawn_worker(app_state.clone());
+    routes(app_state)
+}
+
+/// The state `config` makes, with the stores and backends it names opened.
+async fn state(config: Config) -> AppState {
+    let shared_client = Arc::new(
+        politeness::identify(Client::builder(), &config.wattpad)
+            .cookie_store(true)
+            .build()
+            .expect("Failed to create reqwest client"),
+    );
+
+    let delivery_client = Client::builder()
+        .user_agent(APP_USER_AGENT)
+        .build()
+        .expect("Failed to create reqwest client");
+
+    let config = Arc::new(config);
+    let storage = storage::open(&config.storage, &config.state_dir)
+        .await
+        .expect("Failed to open storage");
+    let shared = shared::open(config.redis_url.as_deref(), config.response_cache_max_bytes)
+        .await
+        .expect("Failed to connect to Redis");
+    let artifacts = ArtifactStore::open(
+        storage.clone(),
+        shared.clone(),
+        config.artifact_ttl,
+        config.artifact_store_max_bytes,
+        &config.keys(),
+    )
+    .await
+    .expect("Failed to load stored artifacts");
+    let jobs = JobStore::new(config.artifact_ttl, config.job_budget);
+    let maintenance = Maintenance::load(storage.clone(), shared.clone()).await;
+    let profiles = Profiles::new(storage.clone(), config.clone());
+    let job_queue = match (&config.redis_url, &config.storage) {
+        (Some(_), StorageConfig::Postgres { .. } | StorageConfig::S3(_)) => {
+            info!("Sharing the job queue with other instances");
+            Some(Arc::new(JobQueue::new(
+                storage.clone(),
+                shared.clone(),
+                config.artifact_ttl,
+            )))
+        }
+        (Some(_), _) => {
+            warn!("REDIS_URL is set but STORAGE_BACKEND is not postgres or s3; jobs run on the instance that accepted them");
+            None
+        }
+        (None, _) => None,
+    };
+    let uploads = UploadStore::new(config.upload_max_bytes, config.artifact_ttl);
+    let usage = Arc::new(Usage::new(config.usage_metrics));
+    let plugins = Plugins::load(&config.plugins);
+    let client_limits = ClientLimits::new(&config.rate_limits);
+
+    AppState {
+        anon_client: shared_client,
+        delivery_client,
+        config,
+        artifacts: Arc::new(artifacts),
+        jobs: Arc::new(jobs),
+        uploads: Arc::new(uploads),
+        scraping: Arc::default(),
+        audit: Arc::default(),
+        maintenance: Arc::new(maintenance),
+        shadow: Arc::default(),
+        drift: Arc::default(),
+        wattpad_rate: Arc::default(),
+        plugins: Arc::new(plugins),
+        client_limits: Arc::new(client_limits),
+        readiness: Arc::default(),
+        session_clients: Arc::default(),
+        shared,
+        job_queue,
+        usage,
+        profiles: Arc::new(profiles),
+        storage,
+    }
+}
+
+fn routes(app_state: AppState) -> Router {
+    let generation_cors = cors::generation(&app_state.config.cors);
+    let api_headers = Policy::api(&app_state.config.security_headers);
+    let download_headers = Policy::download(&app_state.config.security_headers);
+    let upload_body_limit = DefaultBodyLimit::max(app_state.config.upload_max_bytes);
+
+    let mut generation = Router::new()
+        .route("/generate-epub", post(generate_epub))
+        .route("/generate-epub/async", post(jobs::create))
+        .route("/generate-epub/stream", get(sse::generate))
+        .route("/go", get(bookmarklet::go))
+        .route("/generate-epub/batch", post(batch::generate))
+        .route("/generate-epub/batch/async", post(batch::create))
+        .route("/jobs/{id}/retry-failed", post(batch::retry_failed))
+        .route("/estimate", post(analysis::estimate))
+        .route("/stories/metadata", post(metadata::bulk))
+        .route("/story/{id}/info", get(metadata::info))
+        .route("/story/{id}/cover", get(cover_image::cover))
+        .route("/prefs", get(prefs::get).put(prefs::put))
+        .route("/validate-session", post(session::validate))
+        .route(
+            "/profile",
+            get(profiles::get)
+                .put(profiles::put)
+                .delete(profiles::delete),
+        )
+        .route(
+            "/subscriptions",
+            get(subscriptions::list).post(subscriptions::create),
+        )
+        .route(
+            "/subscriptions/{id}",
+            get(subscriptions::get)
+                .put(subscriptions::put)
+                .delete(subscriptions::delete),
+        )
+        .route("/subscriptions/{id}/pause", post(subscriptions::pause))
+        .route("/subscriptions/{id}/resume", post(subscriptions::resume))
+        .route("/subscriptions/{id}/restore", post(subscriptions::restore))
+        .route_layer(from_fn_with_state(
+            app_state.config.generation_timeout,
+            deadline::apply,
+        ))
+        .route("/uploads", post(uploads::create).options(uploads::options))
+        .route(
+            "/uploads/{id}",
+            head(uploads::head)
+                .patch(uploads::patch)
+                .delete(uploads::delete)
+                .layer(upload_body_limit),
+        )
+        .layer(map_response(deprecation::apply));
+    if app_state.config.require_api_key {
+        generation = generation.layer(from_fn_with_state(
+            app_state.config.clone(),
+            api_key::require,
+        ));
+    }
+    let generation = generation.layer(generation_cors);
+
+    let downloads = Router::new()
+        .route("/downloads/{token}", get(artifacts::download))
+        .layer(map_response_with_state(
+            download_headers,
+            security_headers::apply,
+        ));
+
+    let public = Router::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/jobs/{id}", get(jobs::status))
+        .route("/jobs/{id}/events-history", get(jobs::events_history))
+        .route("/jobs/{id}/download", get(jobs::file))
+        .route("/story/{id}/chapters/{part_id}/text", get(tts::text))
+        .route("/downloads/{token}/qr", get(artifacts::qr))
+        .merge(downloads)
+        .layer(cors::public());
+
+    let mut app = Router::new()
+        .route("/metrics", get(usage::metrics))
+        .route("/signing-key", get(signing::public_key))
+        .route("/telegram/webhook", post(telegram::webhook))
+        .route("/hooks/story-updated", post(subscriptions::story_updated))
+        .route("/admin/token", post(admin::issue_token))
+        .route("/admin/stats", get(admin::stats))
+        .route("/admin/audit", get(admin::audit))
+        .route("/admin/reload-secrets", post(admin::reload_secrets))
+        .route(
+            "/admin/maintenance",
+            get(maintenance::status)
+                .put(maintenance::enable)
+                .delete(maintenance::disable),
+        )
+        .route(
+            "/admin/shadow",
+            get(shadow::status)
+                .put(shadow::enable)
+                .delete(shadow::disable),
+        )
+        .merge(generation)
+        .merge(public);
+    if app_state.config.web_ui {
+        app = app.merge(web_ui::routes(&app_state.config.branding));
+    }
+    app.layer(map_response_with_state(
+        app_state.config.clone(),
+        signing::apply,
+    ))
+    .layer(map_response_with_state(
+        api_headers,
+        security_headers::apply,
+    ))
+    .layer(from_fn(i18n::localize))
+    .with_state(app_state)
+}
+
+#[instrument(skip(state, payload), fields(story_id = payload.story_id))]
+async fn generate_epub(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Profiled(mut payload): Profiled,
+) -> Result<Response, MyError> {
+    let (notifications, warnings) = admit(&state, &headers, &mut payload, "sync").await?;
+    let response = respond(state, &headers, payload, notifications)
+        .await
+        .into_response();
+    Ok(deprecation::attach(response, warnings))
+}
+
+async fn respond(
+    state: AppState,
+    headers: &HeaderMap,
+    payload: GenerateEpubRequest,
+    notifications: Vec<Notification>,
+) -> Result<Response, MyError> {
+    let etag = conditional::etag(&state, &*client_for(&state, &payload)?, &payload).await;
+    if let Some(etag) = &etag
+        && conditional::matches(headers, etag)
+    {
+        info!("Story is unchanged since the client's copy");
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag.clone())]).into_response());
+    }
+
+    if payload.stream && streamable(&state, headers, &payload, &notifications) {
+        let mut response = stream_epub(&state, &payload).await?;
+        if let Some(etag) = etag {
+            response.headers_mut().insert(header::ETAG, etag);
+        }
+        return Ok(response);
+    }
+
+    if let Some(reason) = too_big(&state, &payload).await? {
+        info!(reason, "Story is too big to generate synchronously");
+        let status = jobs::start(state, payload, notifications).await?;
+        let location = format!("/jobs/{}", status.id);
+        return Ok((
+            StatusCode::ACCEPTED,
+            [(header::LOCATION, location)],
+            Json(status),
+        )
+            .into_response());
+    }
+
+    if progress::requested(headers) {
+        return Ok(progress::respond(|progress| async move {
+            run(&state, &payload, notifications, &progress)
+                .await
+                .into_response()
+        }));
+    }
+    let mut response = run(&state, &payload, notifications, &Progress::default()).await?;
+    if let Some(etag) = etag
+        && response.status() == StatusCode::OK
+    {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+    Ok(response)
+}
+
+/// Whether the book can be sent as it is made: nothing may need the whole
+/// file once it is done.
+fn streamable(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: &GenerateEpubRequest,
+    notifications: &[Notification],
+) -> bool {
+    payload.format == Format::Epub
+        && payload.chapters.is_default()
+        && !payload.personalized()
+        && payload.delivery.is_none()
+        && notifications.is_empty()
+        && state.config.postprocess.is_none()
+        && !progress::requested(headers)
+}
+
+async fn stream_epub(state: &AppState, payload: &GenerateEpubRequest) -> Result<Response, MyError> {
+    info!("Streaming EPUB");
+    let client = client_for(state, payload)?;
+    let streamed = pipeline::streamed::start(
+        state,
+        (*client).clone(),
+        payload.story_id,
+        payload.embed_images,
+        opf::identifier(payload.story_id, payload.embed_images, &payload.chapters),
+        state
+            .config
+            .chapter_concurrency(payload.concurrent_chapter_requests),
+        !authenticated(payload),
+    )
+    .await?;
+    let mut response =
+        streamed_attachment(&streamed.file_name, "application/epub+zip", streamed.body)?;
+    response
+        .headers_mut()
+        .insert(CACHE_STATUS, CacheStatus::Bypass.header());
+    Ok(response)
+}
+
+/// Checks a generation request before any work starts: refuses it during
+/// maintenance, throttles ID enumerators, refuses stories known to be
+/// missing, validates notification targets and moves them out of it.
+async fn admit(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: &mut GenerateEpubRequest,
+    endpoint: &'static str,
+) -> Result<(Vec<Notification>, Vec<Warning>), MyError> {
+    admit_as(
+        state,
+        &abuse::client_key(headers),
+        headers,
+        payload,
+        endpoint,
+    )
+    .await
+}
+
+/// `admit` for a request from `client`, for requests that do not come with
+/// the client's address, such as the Telegram bot's.
+async fn admit_as(
+    state: &AppState,
+    client: &str,
+    headers: &HeaderMap,
+    payload: &mut GenerateEpubRequest,
+    endpoint: &'static str,
+) -> Result<(Vec<Notification>, Vec<Warning>), MyError> {
+    state.maintenance.check().await?;
+    state
+        .scraping
+        .check(&*state.shared, client, payload.story_id)
+        .await
+        .map_err(MyError::Throttled)?;
+    state
+        .client_limits
+        .check(client, payload.cookies.as_deref())
+        .map_err(MyError::Throttled)?;
+    state
+        .wattpad_rate
+        .check(state.config.wattpad.max_stories_per_minute)
+        .map_err(MyError::Throttled)?;
+    chapters::validate(&payload.chapters, state.config.allow_regex_replacements)
+        .map_err(MyError::InvalidRequest)?;
+    state
+        .plugins
+        .validate(&payload.chapters.plugins)
+        .map_err(MyError::InvalidRequest)?;
+    render::validate(payload.format, &payload.chapters, &state.config)
+        .map_err(MyError::InvalidRequest)?;
+    if let Some(metadata) = &payload.metadata {
+        opf::validate(metadata).map_err(MyError::InvalidRequest)?;
+    }
+    if let Some(cover) = &payload.cover_image {
+        cover_upload::decode(cover).map_err(MyError::InvalidRequest)?;
+    }
+    if let Some(style) = &payload.style {
+        themes::validate(style).map_err(MyError::InvalidRequest)?;
+    }
+    for notification in &payload.notifications {
+        notify::validate(notification, &state.config).map_err(MyError::InvalidRequest)?;
+    }
+    if !authenticated(payload) && story_cache::known_missing(state, payload.story_id).await {
+        info!("Story is known to be missing");
+        return Err(AppError::StoryNotFound(payload.story_id as i32).into());
+    }
+    if let Some(Delivery::Email { language, .. }) = &mut payload.delivery {
+        language.get_or_insert_with(|| i18n::accepted(headers));
+    }
+    payload
+        .language
+        .get_or_insert_with(|| i18n::accepted(headers));
+    let warnings = upgrade(payload);
+    state.usage.request(endpoint, payload);
+    Ok((std::mem::take(&mut payload.notifications), warnings))
+}
+
+/// Which of the configured synchronous limits the story is over. Stories that
+/// cannot be analysed are generated synchronously as before.
+async fn too_big(
+    state: &AppState,
+    payload: &GenerateEpubRequest,
+) -> Result<Option<&'static str>, MyError> {
+    let limits = &state.config.sync_limits;
+    if !limits.any() {
+        return Ok(None);
+    }
+    let client = client_for(state, payload)?;
+    let analysis = analysis::analyze(
+        state,
+        &client,
+        payload.story_id,
+        payload.embed_images,
+        !authenticated(payload),
+    );
+    match analysis.await {
+        Ok(analysis) => Ok(analysis.exceeds(limits)),
+        Err(e) => {
+            warn!(error = %e, "Could not analyse story; generating it synchronously");
+            Ok(None)
+        }
+    }
+}
+
+/// Generates the EPUB and notifies about the outcome either way.
+async fn run(
+    state: &AppState,
+    payload: &GenerateEpubRequest,
+    notifications: Vec<Notification>,
+    progress: &Progress,
+) -> Result<Response, MyError> {
+    let (result, event) = match generate(state, payload, progress).await {
+        Ok((response, event)) => (Ok(response), event),
+        Err(e) => {
+            let event = failed_event(payload.story_id, &e);
+            (Err(e), event)
+        }
+    };
+    notify::dispatch(
+        state.delivery_client.clone(),
+        state.config.clone(),
+        notifications,
+        event,
+    );
+    result
+}
+
+/// Downloads and returns or delivers the EPUB, along with the event to notify about.
+async fn generate(
+    state: &AppState,
+    payload: &GenerateEpubRequest,
+    progress: &Progress,
+) -> Result<(Response, Event), MyError> {
+    let epub = download(state, payload, progress).await?;
+
+    if let Some(delivery) = payload.delivery.as_ref() {
+        let receipt = deliver(state, delivery, &payload.download_link, &epub, progress).await?;
+        let event = epub.completed_event(payload.story_id, receipt.web_url.clone());
+        let response = Json(DeliveryResponse {
+            story_id: payload.story_id,
+            size: epub.bytes.len(),
+            file_name: epub.file_name,
+            delivery: receipt,
+            skipped_chapters: epub.skipped,
+        })
+        .into_response();
+        return Ok((response, event));
+    }
+
+    let event = epub.completed_event(payload.story_id, None);
+    let mut response = attachment(&epub.file_name, epub.content_type, epub.bytes)?;
+    response
+        .headers_mut()
+        .insert(CACHE_STATUS, epub.cache.header());
+    if !epub.skipped.is_empty() {
+        response
+            .headers_mut()
+            .insert(SKIPPED_CHAPTERS, skipped_header(&epub.skipped));
+    }
+    Ok((response, event))
+}
+
+const CACHE_STATUS: HeaderName = HeaderName::from_static("cache-status");
+
+/// Titles of the parts left out, as a percent-encoded JSON array.
+const SKIPPED_CHAPTERS: HeaderName = HeaderName::from_static("x-skipped-chapters");
+
+fn skipped_header(titles: &[String]) -> HeaderValue {
+    let json = serde_json::to_string(titles).expect("titles serialize");
+    HeaderValue::from_str(&utf8_percent_encode(&json, NON_ALPHANUMERIC).to_string())
+        .expect("percent-encoded text is a valid header value")
+}
+
+/// A generated EPUB and what notifications and deliveries need to know about it.
+struct Epub {
+    title: String,
+    file_name: String,
+    /// `application/zip` when the story was split into volumes.
+    content_type: &'static str,
+    /// Titles of the parts left out by `excludeTitlePatterns`.
+    skipped: Vec<String>,
+    cover_url: Option<String>,
+    bytes: Bytes,
+    cache: CacheStatus,
+}
+
+impl Epub {
+    fn completed_event(&self, story_id: u64, download_url: Option<String>) -> Event {
+        Event::Completed {
+            story_id,
+            title: self.title.clone(),
+            cover_url: self.cover_url.clone(),
+            size: self.bytes.len(),
+            download_url,
+        }
+    }
+}
+
+fn failed_event(story_id: u64, error: &MyError) -> Event {
+    Event::Failed {
+        story_id,
+        error: error.status_and_message().1,
+    }
+}
+
+async fn download(
+    state: &AppState,
+    payload: &GenerateEpubRequest,
+    progress: &Progress,
+) -> Result<Epub, MyError> {
+    let client = client_for(state, payload)?;
+    let epub =
+        if !authenticated(payload) && payload.chapters.is_default() && !payload.personalized() {
+            info!("Handling anonymous request");
+            response_cache::get_or_generate(state, payload.story_id, payload.embed_images, || {
+                fetch(state, client.clone(), payload, progress)
+            })
+            .await?
+        } else {
+            info!("Handling authenticated request with cookies");
+            fetch(state, client.clone(), payload, progress).await?
+        };
+    let epub = match payload.format {
+        Format::Warc => {
+            let concurrency = state
+                .config
+                .chapter_concurrency(payload.concurrent_chapter_requests);
+            warc::archive(&client, payload, epub, concurrency).await
+        }
+        Format::Azw3 => match &state.config.ebook_convert {
+            Some(program) => convert::azw3(program, epub).await,
+            None => Err(MyError::InvalidRequest(
+                "This server cannot make AZW3 files".into(),
+            )),
+        },
+        format => render::render(epub, format),
+    }?;
+    match &state.config.postprocess {
+        Some(config) => {
+            postprocess::apply(&state.delivery_client, config, payload.story_id, epub).await
+        }
+        None => Ok(epub),
+    }
+}
+
+fn authenticated(payload: &GenerateEpubRequest) -> bool {
+    payload.cookies.as_ref().is_some_and(|c| !c.is_empty())
+}
+
+/// The shared anonymous client, or one carrying the request's Wattpad cookies.
+fn client_for(state: &AppState, payload: &GenerateEpubRequest) -> Result<Arc<Client>, MyError> {
+    // Determine if we have cookies to create an authenticated session
+    match payload.cookies.as_ref().filter(|c| !c.is_empty()) {
+        Some(cookies) => session_client(state, cookies),
+        None => Ok(state.anon_client.clone()),
+    }
+}
+
+/// A client with the Wattpad cookies out of `cookies`, reused for the same
+/// session (see `crate::client_pool`).
+fn session_client(state: &AppState, cookies: &[Cookie]) -> Result<Arc<Client>, MyError> {
+    state.session_clients.get_or_build(cookies, || {
+        politeness::identify(Client::builder(), &state.config.wattpad)
+            .cookie_provider(Arc::new(cookie_jar(cookies)))
+            .build()
+            .map_err(|_| MyError::App(AppError::DownloadFailed))
+    })
+}
+
+/// A cookie jar holding the Wattpad cookies from the extension.
+fn cookie_jar(cookies: &[Cookie]) -> Jar {
+    let jar = Jar::default();
+    let wattpad_url = Url::parse("https://www.wattpad.com").unwrap();
+    for cookie in cookies {
+        if cookie.domain.contains("wattpad.com") {
+            jar.add_cookie_str(&format!("{}={}", cookie.name, cookie.value), &wattpad_url);
+        }
+    }
+    jar
+}
+
+/// Generates the EPUB from Wattpad with `client`.
+async fn fetch(
+    state: &AppState,
+    client: Arc<Client>,
+    payload: &GenerateEpubRequest,
+    progress: &Progress,
+) -> Result<Epub, MyError> {
+    let (epub_result, made_by) = drift::download(state, &client, payload, progress).await?;
+
+    let bytes = Bytes::from(epub_result.epub_response);
+    state.shadow.maybe_run(
+        client.clone(),
+        payload.story_id,
+        payload.embed_images,
+        bytes.clone(),
+        made_by,
+    );
+
+    let story = match payload.chapters.stats_page {
+        true => {
+            stats::with_part_dates(
+                state,
+                &client,
+                epub_result.metadata,
+                payload.story_id,
+                !authenticated(payload),
+            )
+            .await
+        }
+        false => epub_result.metadata,
+    };
+    let overrides = payload.metadata.as_ref();
+    let file_name = match overrides.and_then(MetadataOverrides::title) {
+        Some(title) => format!(
+            "{}.epub",
+            pipeline::sanitized_title(payload.story_id, title)
+        ),
+        None => format!("{}.epub", epub_result.sanitized_title),
+    };
+    let title = overrides
+        .and_then(MetadataOverrides::title)
+        .map(str::to_string)
+        .or_else(|| story.title.clone());
+    let epub = Epub {
+        title: title.unwrap_or_else(|| file_name.clone()),
+        file_name,
+        content_type: "application/epub+zip",
+        skipped: Vec::new(),
+        cover_url: story.cover.clone(),
+        bytes,
+        cache: CacheStatus::Bypass,
+    };
+    // Validated on admission.
+    let cover = payload
+        .cover_image
+        .as_deref()
+        .map(cover_upload::decode)
+        .transpose()
+        .map_err(MyError::InvalidRequest)?;
+    let lang = payload.language.unwrap_or_default();
+    let epub = chapters::apply(state, epub, &payload.chapters, &story, cover, lang).await?;
+    let identifier = opf::identifier(payload.story_id, payload.embed_images, &payload.chapters);
+    let subjects = Tags::new(story.tags.as_deref().unwrap_or_default()).subjects();
+    let bytes = opf::label(
+        &epub.bytes,
+        epub.content_type,
+        &identifier,
+        &subjects,
+        &state.config.branding.name,
+        overrides,
+    )
+    .map_err(|e| {
+        error!(error = %e, "Could not label EPUB");
+        MyError::App(AppError::EpubGenerationFailed)
+    })?;
+    let bytes = match &payload.style {
+        Some(style) => themes::apply(&bytes, epub.content_type, style).map_err(|e| {
+            error!(error = %e, "Could not style EPUB");
+            MyError::App(AppError::EpubGenerationFailed)
+        })?,
+        None => bytes,
+    };
+    let epub = Epub { bytes, ..epub };
+    state.usage.book(&epub);
+    Ok(epub)
+}
+
+async fn deliver(
+    state: &AppState,
+    delivery: &Delivery,
+    link: &LinkOptions,
+    epub: &Epub,
+    progress: &Progress,
+) -> Result<DeliveryReceipt, MyError> {
+    info!(target = delivery.target(), "Delivering EPUB server-side");
+    progress.stage("delivering");
+    let receipt = delivery::deliver(
+        delivery,
+        &DeliveryContext {
+            client: &state.delivery_client,
+            config: &state.config,
+            artifacts: &state.artifacts,
+            link,
+        },
+        DeliveryFile {
+            title: &epub.title,
+            file_name: &epub.file_name,
+            content_type: epub.content_type,
+            bytes: epub.bytes.clone(),
+        },
+    )
+    .await?;
+    Ok(receipt)
+}
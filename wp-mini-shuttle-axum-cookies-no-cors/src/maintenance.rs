@@ -0,0 +1,236 @@
+//! Read-only maintenance mode.
+//!
+//! While it is on, everything that only reads (job status, stored downloads,
+//! admin routes) keeps working, but new generations and uploads are refused
+//! with `503`, the operator's message and, if given, an ETA. The switch is
+//! kept in `crate::storage` so it survives restarts, and read through
+//! `crate::shared` for `CACHE_TTL`, so every instance sharing them follows
+//! it: at once with `REDIS_URL`, within `CACHE_TTL` without.
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::admin::Admin;
+use crate::error::MyError;
+use crate::shared::Shared;
+use crate::storage::Storage;
+use crate::{unix_now, AppState};
+
+const KEY: &str = "state/maintenance.json";
+const CACHE_KEY: &str = "maintenance";
+/// How long an instance goes on with what it last read from storage.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceInfo {
+    message: String,
+    /// Unix seconds when the service is expected back, if known.
+    eta: Option<u64>,
+    /// Unix seconds.
+    since: u64,
+    by: String,
+}
+
+pub struct Maintenance {
+    storage: Arc<dyn Storage>,
+    shared: Arc<dyn Shared>,
+}
+
+impl Maintenance {
+    pub async fn load(storage: Arc<dyn Storage>, shared: Arc<dyn Shared>) -> Self {
+        let maintenance = Maintenance { storage, shared };
+        if let Some(info) = maintenance.current().await {
+            warn!(message = %info.message, "Starting in maintenance mode");
+        }
+        maintenance
+    }
+
+    /// The switch as `crate::shared` has it, read from storage when it has
+    /// not for `CACHE_TTL`.
+    async fn current(&self) -> Option<MaintenanceInfo> {
+        match self.shared.get(CACHE_KEY).await {
+            Ok(Some(cached)) => match serde_json::from_slice(&cached) {
+                Ok(info) => return info,
+                Err(e) => warn!(error = %e, "Ignoring unreadable cached maintenance state"),
+            },
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "Could not look up cached maintenance state"),
+        }
+        let info = match self.storage.get(KEY).await {
+            Ok(Some(bytes)) => match serde_json::from_slice::<MaintenanceInfo>(&bytes) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    warn!(error = %e, "Ignoring unreadable maintenance state");
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                warn!(error = %e, "Could not read maintenance state");
+                None
+            }
+        };
+        self.cache(&info).await;
+        info
+    }
+
+    async fn cache(&self, info: &Option<MaintenanceInfo>) {
+        let value = Bytes::from(serde_json::to_vec(info).unwrap());
+        if let Err(e) = self.shared.set(CACHE_KEY, value, CACHE_TTL).await {
+            warn!(error = %e, "Could not cache maintenance state");
+        }
+    }
+
+    /// Refuses new work while maintenance mode is on.
+    pub async fn check(&self) -> Result<(), MyError> {
+        let Some(info) = self.current().await else {
+            return Ok(());
+        };
+        let retry_after = info
+            .eta
+            .map(|eta| Duration::from_secs(eta.saturating_sub(unix_now())));
+        Err(MyError::Unavailable {
+            message: info.message,
+            retry_after,
+        })
+    }
+
+    /// Turns maintenance mode on with `info`, or off. Fails, leaving it as it
+    /// was, if storage cannot keep the change.
+    async fn set(&self, info: Option<MaintenanceInfo>) -> Result<(), MyError> {
+        let result = match &info {
+            Some(info) => {
+                self.storage
+                    .put(KEY, serde_json::to_vec(info).unwrap().into())
+                    .await
+            }
+            None => self.storage.delete(KEY).await,
+        };
+        if let Err(e) = result {
+            error!(error = %e, "Could not persist maintenance state");
+            return Err(MyError::Storage);
+        }
+        self.cache(&info).await;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnableRequest {
+    message: Option<String>,
+    /// Minutes from now until the service is expected back.
+    eta_minutes: Option<u64>,
+}
+
+pub async fn status(admin: Admin, State(state): State<AppState>) -> Json<Option<MaintenanceInfo>> {
+    state.audit.record(&admin.subject, "view_maintenance", None);
+    Json(state.maintenance.current().await)
+}
+
+pub async fn enable(
+    admin: Admin,
+    State(state): State<AppState>,
+    Json(request): Json<EnableRequest>,
+) -> Result<Json<Option<MaintenanceInfo>>, MyError> {
+    let now = unix_now();
+    let info = MaintenanceInfo {
+        message: request.message.unwrap_or_else(|| {
+            "WattDownload is down for maintenance; please try again later".to_string()
+        }),
+        eta: request.eta_minutes.map(|minutes| now + minutes * 60),
+        since: now,
+        by: admin.subject.clone(),
+    };
+    state.maintenance.set(Some(info.clone())).await?;
+    info!(message = %info.message, "Entering maintenance mode");
+    state.audit.record(
+        &admin.subject,
+        "enable_maintenance",
+        Some(info.message.clone()),
+    );
+    Ok(Json(Some(info)))
+}
+
+pub async fn disable(
+    admin: Admin,
+    State(state): State<AppState>,
+) -> Result<Json<Option<MaintenanceInfo>>, MyError> {
+    state.maintenance.set(None).await?;
+    info!("Leaving maintenance mode");
+    state
+        .audit
+        .record(&admin.subject, "disable_maintenance", None);
+    Ok(Json(None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StorageConfig;
+    use crate::{shared, storage, testing};
+
+    #[tokio::test]
+    async fn every_instance_follows_the_switch() {
+        let storage = storage::open(&StorageConfig::Memory, "").await.unwrap();
+        let redis = shared::open(None, 1024 * 1024).await.unwrap();
+        let first = Maintenance::load(storage.clone(), redis.clone()).await;
+        let second = Maintenance::load(storage.clone(), redis).await;
+        // An instance without Redis, which only shares the storage.
+        let alone =
+            Maintenance::load(storage, shared::open(None, 1024 * 1024).await.unwrap()).await;
+        assert!(alone.check().await.is_ok());
+
+        let enabled = first
+            .set(Some(MaintenanceInfo {
+                message: "Back soon".to_string(),
+                eta: None,
+                since: unix_now(),
+                by: "test".to_string(),
+            }))
+            .await;
+        assert!(enabled.is_ok());
+        assert!(second.check().await.is_err());
+        // What it read at startup expiring, `CACHE_TTL` later.
+        alone
+            .shared
+            .delete_if(CACHE_KEY, Bytes::from("null"))
+            .await
+            .unwrap();
+        assert!(alone.check().await.is_err());
+
+        assert!(second.set(None).await.is_ok());
+        assert!(first.check().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_switch_storage_cannot_keep_is_refused() {
+        let state_dir = testing::state_dir();
+        // Where the switch would be kept is a file, so it cannot be written.
+        std::fs::remove_dir(state_dir.join("state")).unwrap();
+        std::fs::write(state_dir.join("state"), "").unwrap();
+        let storage = storage::open(&StorageConfig::Disk, state_dir.to_str().unwrap())
+            .await
+            .unwrap();
+        let maintenance =
+            Maintenance::load(storage, shared::open(None, 1024 * 1024).await.unwrap()).await;
+
+        let enabled = maintenance
+            .set(Some(MaintenanceInfo {
+                message: "Back soon".to_string(),
+                eta: None,
+                since: unix_now(),
+                by: "test".to_string(),
+            }))
+            .await;
+        assert!(matches!(enabled, Err(MyError::Storage)));
+        assert!(maintenance.check().await.is_ok());
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! `POST /stories/metadata`: the metadata of many stories in one round trip,
+//! for library views that would otherwise ask story by story.
+//!
+//! Stories are looked up concurrently; each ID gets either its metadata or an
+//! error entry, so one missing story does not fail the rest.
+//!
+//! `GET /story/{id}/info` is the same for a single story, plus an estimate of
+//! its words from `crate::analysis`, for a preview before a download. It
+//! costs one more Wattpad request, as the metadata is shared through
+//! `crate::story_cache`; `words` is left out when that fails.
+
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::warn;
+use wp_mini::types::StoryResponse;
+use wp_mini::WattpadError;
+use wp_mini_epub::AppError;
+
+use crate::abuse;
+use crate::analysis;
+use crate::error::MyError;
+use crate::story_cache;
+use crate::tags::Tags;
+use crate::AppState;
+
+const MAX_STORIES: usize = 50;
+const CONCURRENT_LOOKUPS: usize = 8;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataRequest {
+    story_ids: Vec<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataResponse {
+    /// One entry per distinct requested ID, in request order.
+    stories: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Entry {
+    Found(Box<Metadata>),
+    Failed { id: u64, error: String },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    id: u64,
+    title: Option<String>,
+    author: Option<String>,
+    description: Option<String>,
+    cover: Option<String>,
+    url: Option<String>,
+    parts: Option<i64>,
+    completed: Option<bool>,
+    mature: Option<bool>,
+    /// Normalized, see `crate::tags`.
+    tags: Vec<String>,
+    genres: Vec<&'static str>,
+    read_count: Option<i64>,
+    vote_count: Option<i64>,
+    /// ISO 8601.
+    modified: Option<String>,
+}
+
+impl Metadata {
+    fn new(id: u64, story: StoryResponse) -> Self {
+        let tags = Tags::new(story.tags.as_deref().unwrap_or_default());
+        Metadata {
+            id,
+            title: story.title,
+            author: story.user.and_then(|user| user.username),
+            description: story.description,
+            cover: story.cover,
+            url: story.url,
+            parts: story.num_parts,
+            completed: story.completed,
+            mature: story.mature,
+            tags: tags.tags,
+            genres: tags.genres,
+            read_count: story.read_count,
+            vote_count: story.vote_count,
+            modified: story.modify_date,
+        }
+    }
+}
+
+pub async fn bulk(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<MetadataRequest>,
+) -> Result<Json<MetadataResponse>, MyError> {
+    let mut ids = request.story_ids;
+    if ids.is_empty() || ids.len() > MAX_STORIES {
+        return Err(MyError::InvalidRequest(format!(
+            "storyIds must list 1 to {} stories",
+            MAX_STORIES
+        )));
+    }
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    let client_key = abuse::client_key(&headers);
+    for id in &ids {
+        state
+            .scraping
+            .check(&*state.shared, &client_key, *id)
+            .await
+            .map_err(MyError::Throttled)?;
+    }
+
+    let stories = stream::iter(ids)
+        .map(|id| {
+            let state = &state;
+            async move {
+                match story_cache::story(state, &state.anon_client, id, true).await {
+                    Ok(story) => Entry::Found(Box::new(Metadata::new(id, story))),
+                    Err(e) => Entry::Failed {
+                        id,
+                        error: describe(&e),
+                    },
+                }
+            }
+        })
+        .buffered(CONCURRENT_LOOKUPS)
+        .collect()
+        .await;
+    Ok(Json(MetadataResponse { stories }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoryInfo {
+    #[serde(flatten)]
+    metadata: Metadata,
+    /// Estimated from the first part.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    words: Option<u64>,
+}
+
+pub async fn info(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<Json<StoryInfo>, MyError> {
+    state
+        .scraping
+        .check(&*state.shared, &abuse::client_key(&headers), id)
+        .await
+        .map_err(MyError::Throttled)?;
+
+    let story = story_cache::story(&state, &state.anon_client, id, true)
+        .await
+        .map_err(|e| match e {
+            WattpadError::StoryNotFound => {
+                MyError::NotFound(format!("Story {} could not be found", id))
+            }
+            _ => MyError::App(AppError::MetadataFetchFailed),
+        })?;
+    let words = match analysis::analyze(&state, &state.anon_client, id, false, true).await {
+        Ok(analysis) => Some(analysis.words),
+        Err(e) => {
+            warn!(error = %e, "Could not estimate the words of the story");
+            None
+        }
+    };
+    Ok(Json(StoryInfo {
+        metadata: Metadata::new(id, story),
+        words,
+    }))
+}
+
+fn describe(error: &WattpadError) -> String {
+    match error {
+        WattpadError::StoryNotFound => "Story not found".to_string(),
+        WattpadError::PermissionDeniedNotLoggedIn | WattpadError::AccessDenied => {
+            "Story is not accessible".to_string()
+        }
+        _ => "Failed to fetch story metadata".to_string(),
+    }
+}
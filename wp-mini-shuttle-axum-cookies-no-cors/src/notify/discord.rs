@@ -0,0 +1,77 @@
+use reqwest::{Client, Url};
+use serde_json::json;
+
+use super::{human_size, Event};
+
+const WEBHOOK_HOSTS: [&str; 4] = [
+    "discord.com",
+    "discordapp.com",
+    "canary.discord.com",
+    "ptb.discord.com",
+];
+/// Wattpad orange, so the embeds are recognizable in busy channels.
+const EMBED_COLOR: u32 = 0xFF500A;
+const FAILED_COLOR: u32 = 0xD32F2F;
+
+pub(super) fn validate_webhook_url(webhook_url: &str) -> Result<(), String> {
+    let url = Url::parse(webhook_url).map_err(|_| "Discord webhook URL is not valid")?;
+    let host_ok = url
+        .host_str()
+        .is_some_and(|host| WEBHOOK_HOSTS.contains(&host));
+    if url.scheme() != "https" || !host_ok || !url.path().starts_with("/api/webhooks/") {
+        return Err("Discord webhook URL must be a https://discord.com/api/webhooks/… URL".into());
+    }
+    Ok(())
+}
+
+pub(super) async fn send(client: &Client, webhook_url: &str, event: &Event) -> Result<(), String> {
+    let embed = match event {
+        Event::Completed {
+            story_id,
+            title,
+            cover_url,
+            size,
+            download_url,
+        } => {
+            let mut embed = json!({
+                "title": title,
+                "description": "Download finished",
+                "color": EMBED_COLOR,
+                "fields": [
+                    { "name": "Story ID", "value": story_id.to_string(), "inline": true },
+                    { "name": "Size", "value": human_size(*size), "inline": true },
+                ],
+            });
+            if let Some(url) = download_url {
+                embed["url"] = json!(url);
+            }
+            if let Some(cover_url) = cover_url {
+                embed["thumbnail"] = json!({ "url": cover_url });
+            }
+            embed
+        }
+        Event::Failed { story_id, error } => json!({
+            "title": "Download failed",
+            "description": error,
+            "color": FAILED_COLOR,
+            "fields": [
+                { "name": "Story ID", "value": story_id.to_string(), "inline": true },
+            ],
+        }),
+    };
+
+    let response = client
+        .post(webhook_url)
+        .json(&json!({ "embeds": [embed] }))
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Discord responded with status {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
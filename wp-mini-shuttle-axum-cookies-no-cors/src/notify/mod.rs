@@ -0,0 +1,102 @@
+//! Fire-and-forget notifications about finished or failed downloads.
+//!
+//! A request may list any number of `notifications`; they are validated before
+//! work starts and sent in the background once it ends, so a slow or broken
+//! webhook never delays or fails the download itself.
+
+mod discord;
+mod ntfy;
+mod pushover;
+
+use reqwest::Client;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::config::Config;
+
+pub use api_types::Notification;
+
+/// What happened to a download, as reported to notification targets.
+pub enum Event {
+    Completed {
+        story_id: u64,
+        title: String,
+        cover_url: Option<String>,
+        size: usize,
+        download_url: Option<String>,
+    },
+    Failed {
+        story_id: u64,
+        error: String,
+    },
+}
+
+/// Rejects malformed targets, targets this server is not configured for, and
+/// targets that would make the server call arbitrary URLs.
+pub fn validate(notification: &Notification, config: &Config) -> Result<(), String> {
+    match notification {
+        Notification::Discord { webhook_url } => discord::validate_webhook_url(webhook_url),
+        Notification::Ntfy { topic } => ntfy::validate_topic(topic),
+        Notification::Pushover { user_key, .. } => {
+            if config.keys().pushover_app_token.is_none() {
+                return Err("Pushover notifications are not configured on this server".into());
+            }
+            pushover::validate_user_key(user_key)
+        }
+    }
+}
+
+async fn send(
+    notification: &Notification,
+    client: &Client,
+    config: &Config,
+    event: &Event,
+) -> Result<(), String> {
+    match notification {
+        Notification::Discord { webhook_url } => discord::send(client, webhook_url, event).await,
+        Notification::Ntfy { topic } => ntfy::send(client, topic, event).await,
+        Notification::Pushover { user_key, device } => {
+            let keys = config.keys();
+            let app_token = keys
+                .pushover_app_token
+                .as_deref()
+                .ok_or("Pushover is not configured")?;
+            pushover::send(client, app_token, user_key, device.as_deref(), event).await
+        }
+    }
+}
+
+/// Sends `event` to every target in the background.
+pub fn dispatch(
+    client: Client,
+    config: Arc<Config>,
+    notifications: Vec<Notification>,
+    event: Event,
+) {
+    if notifications.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        for notification in &notifications {
+            if let Err(e) = send(notification, &client, &config, &event).await {
+                warn!(target = notification.target(), error = %e, "Failed to send notification");
+            }
+        }
+    });
+}
+
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,61 @@
+use reqwest::Client;
+use serde_json::json;
+
+use super::{human_size, Event};
+
+const SERVER: &str = "https://ntfy.sh";
+
+/// ntfy topics are public names; anyone who knows one can subscribe, so we only
+/// insist on the characters ntfy itself accepts.
+pub(super) fn validate_topic(topic: &str) -> Result<(), String> {
+    let valid = !topic.is_empty()
+        && topic.len() <= 64
+        && topic
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err("ntfy topic must be 1-64 letters, digits, '-' or '_'".into());
+    }
+    Ok(())
+}
+
+pub(super) async fn send(client: &Client, topic: &str, event: &Event) -> Result<(), String> {
+    let message = match event {
+        Event::Completed {
+            title,
+            size,
+            download_url,
+            ..
+        } => {
+            let mut message = json!({
+                "topic": topic,
+                "title": "Download finished",
+                "message": format!("{} ({})", title, human_size(*size)),
+                "tags": ["books"],
+            });
+            if let Some(url) = download_url {
+                message["click"] = json!(url);
+            }
+            message
+        }
+        Event::Failed { story_id, error } => json!({
+            "topic": topic,
+            "title": "Download failed",
+            "message": format!("Story {}: {}", story_id, error),
+            "tags": ["warning"],
+            "priority": 4,
+        }),
+    };
+
+    let response = client
+        .post(SERVER)
+        .json(&message)
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!("ntfy responded with status {}", response.status()));
+    }
+    Ok(())
+}
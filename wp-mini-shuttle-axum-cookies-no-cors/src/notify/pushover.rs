@@ -0,0 +1,64 @@
+use reqwest::Client;
+use serde_json::json;
+
+use super::{human_size, Event};
+
+const MESSAGES_URL: &str = "https://api.pushover.net/1/messages.json";
+
+pub(super) fn validate_user_key(user_key: &str) -> Result<(), String> {
+    if user_key.len() != 30 || !user_key.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err("Pushover user key must be 30 letters or digits".into());
+    }
+    Ok(())
+}
+
+pub(super) async fn send(
+    client: &Client,
+    app_token: &str,
+    user_key: &str,
+    device: Option<&str>,
+    event: &Event,
+) -> Result<(), String> {
+    let mut message = match event {
+        Event::Completed {
+            title,
+            size,
+            download_url,
+            ..
+        } => {
+            let mut message = json!({
+                "title": "Download finished",
+                "message": format!("{} ({})", title, human_size(*size)),
+            });
+            if let Some(url) = download_url {
+                message["url"] = json!(url);
+            }
+            message
+        }
+        Event::Failed { story_id, error } => json!({
+            "title": "Download failed",
+            "message": format!("Story {}: {}", story_id, error),
+            "priority": 1,
+        }),
+    };
+    message["token"] = json!(app_token);
+    message["user"] = json!(user_key);
+    if let Some(device) = device {
+        message["device"] = json!(device);
+    }
+
+    let response = client
+        .post(MESSAGES_URL)
+        .json(&message)
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Pushover responded with status {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
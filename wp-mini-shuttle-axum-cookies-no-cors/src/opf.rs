@@ -0,0 +1,284 @@
+//! Edits to the OPF package document of a finished book, for metadata the
+//! EPUB writers cannot set themselves.
+//!
+//! Only the `.opf` entry is rewritten; every other entry is copied over as
+//! it is, so this is cheap next to taking the book apart with
+//! `crate::book`. Volume ZIPs from `crate::chapters` have each volume edited.
+//!
+//! The request's `metadata` replaces what the book was made with from the
+//! story: `title`, `author`, `language` and `description`, and puts it in
+//! a series (`series`, `seriesIndex`), written both as the EPUB 3 collection
+//! and as the `calibre:series` Calibre reads from older books. Volumes keep
+//! their " — Vol. N" after an overridden title.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Read, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::book::escape;
+use crate::chapters::ChapterOptions;
+use crate::encode_hex;
+
+pub use api_types::MetadataOverrides;
+
+/// Where the books come from, as the first part of their identifiers.
+const SOURCE: &str = "wattpad";
+const MAX_FIELD_LENGTH: usize = 500;
+const MAX_DESCRIPTION_LENGTH: usize = 10_000;
+
+pub fn validate(overrides: &MetadataOverrides) -> Result<(), String> {
+    for (name, value) in [
+        ("title", &overrides.title),
+        ("author", &overrides.author),
+        ("series", &overrides.series),
+    ] {
+        match value.as_deref().map(str::trim) {
+            Some("") => return Err(format!("metadata.{} must not be empty", name)),
+            Some(value) if value.chars().count() > MAX_FIELD_LENGTH => {
+                return Err(format!(
+                    "metadata.{} can be at most {} characters",
+                    name, MAX_FIELD_LENGTH
+                ));
+            }
+            _ => {}
+        }
+    }
+    if let Some(index) = overrides.series_index {
+        if overrides.series.is_none() {
+            return Err("metadata.seriesIndex needs metadata.series".into());
+        }
+        if !index.is_finite() || index < 0.0 {
+            return Err("metadata.seriesIndex must be a number of at least 0".into());
+        }
+    }
+    if let Some(language) = &overrides.language
+        && !language_tag(language)
+    {
+        return Err("metadata.language must be a language tag such as en or pt-BR".into());
+    }
+    if overrides
+        .description
+        .as_ref()
+        .is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LENGTH)
+    {
+        return Err(format!(
+            "metadata.description can be at most {} characters",
+            MAX_DESCRIPTION_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+fn apply(overrides: &MetadataOverrides, opf: &mut String, volume: Option<usize>) {
+    if let Some(title) = overrides.title() {
+        let title = match volume {
+            Some(volume) => format!("{} \u{2014} Vol. {}", title, volume),
+            None => title.to_string(),
+        };
+        set_text(opf, "dc:title", &title, "<dc:title>");
+    }
+    if let Some(author) = &overrides.author {
+        set_text(
+            opf,
+            "dc:creator",
+            author.trim(),
+            r#"<dc:creator id="creator">"#,
+        );
+    }
+    if let Some(language) = &overrides.language {
+        set_text(opf, "dc:language", language, "<dc:language>");
+    }
+    if let Some(description) = &overrides.description {
+        set_text(opf, "dc:description", description, "<dc:description>");
+    }
+    if let Some(series) = &overrides.series {
+        add_metadata(opf, &series_metadata(series.trim(), overrides.series_index));
+    }
+}
+
+/// Letters, digits and hyphens in the shape of a BCP 47 tag; whether the
+/// subtags are registered is left to the reader.
+fn language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    (2..=8).contains(&primary.len())
+        && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
+}
+
+/// A stable `dc:identifier` for `story_id` made with these options, so
+/// library tools such as Calibre take a new download of the same story for
+/// the same book and offer to update it instead of adding a duplicate.
+pub fn identifier(story_id: u64, embed_images: bool, options: &ChapterOptions) -> String {
+    let options = serde_json::to_vec(&(embed_images, options)).unwrap_or_default();
+    format!(
+        "urn:{}:story:{}:{}",
+        SOURCE,
+        story_id,
+        encode_hex(&Sha256::digest(&options)[..8])
+    )
+}
+
+/// Writes `identifier`, `subjects`, this service as the book's producer and
+/// the request's `overrides` into the book, or each volume of it; volumes
+/// get `:volN` appended to the identifier.
+pub fn label(
+    bytes: &Bytes,
+    content_type: &str,
+    identifier: &str,
+    subjects: &[String],
+    producer: &str,
+    overrides: Option<&MetadataOverrides>,
+) -> Result<Bytes> {
+    edit(bytes, content_type, |opf, volume| {
+        match volume {
+            Some(volume) => set_identifier(opf, &format!("{}:vol{}", identifier, volume)),
+            None => set_identifier(opf, identifier),
+        }
+        add_subjects(opf, subjects);
+        add_metadata(opf, &producer_metadata(producer));
+        if let Some(overrides) = overrides {
+            apply(overrides, opf, volume);
+        }
+    })
+}
+
+/// `bytes` with `edit` applied to the package document of each book in it,
+/// along with the 1-based volume number in volume ZIPs.
+fn edit(
+    bytes: &Bytes,
+    content_type: &str,
+    edit: impl Fn(&mut String, Option<usize>),
+) -> Result<Bytes> {
+    each_book(bytes, content_type, |book, volume| {
+        rewrite(
+            &book,
+            |name| name.ends_with(".opf"),
+            |opf| {
+                let mut opf = String::from_utf8(opf)?;
+                edit(&mut opf, volume);
+                Ok(opf.into_bytes())
+            },
+        )
+    })
+}
+
+/// `bytes`, a book or volume ZIP of `content_type`, with each book in it
+/// replaced by what `change` makes of it, along with the 1-based volume
+/// number in volume ZIPs.
+pub fn each_book(
+    bytes: &Bytes,
+    content_type: &str,
+    mut change: impl FnMut(Vec<u8>, Option<usize>) -> Result<Vec<u8>>,
+) -> Result<Bytes> {
+    let bytes = match content_type {
+        "application/epub+zip" => change(bytes.to_vec(), None)?,
+        _ => {
+            let mut volume = 0;
+            rewrite(
+                bytes,
+                |name| name.ends_with(".epub"),
+                |book| {
+                    volume += 1;
+                    change(book, Some(volume))
+                },
+            )?
+        }
+    };
+    Ok(Bytes::from(bytes))
+}
+
+fn set_identifier(opf: &mut String, identifier: &str) {
+    set_text(
+        opf,
+        "dc:identifier",
+        identifier,
+        r#"<dc:identifier id="id">"#,
+    );
+}
+
+/// Replaces the text of the first `element`, adding `open` with `text` and
+/// the closing tag if there is none.
+fn set_text(opf: &mut String, element: &str, text: &str, open: &str) {
+    let text = escape(text);
+    let existing = opf.find(&format!("<{}", element)).and_then(|start| {
+        let content = start + opf[start..].find('>')? + 1;
+        let end = content + opf[content..].find(&format!("</{}>", element))?;
+        Some(content..end)
+    });
+    match existing {
+        Some(range) => opf.replace_range(range, &text),
+        None => add_metadata(opf, &format!("{}{}</{}>", open, text, element)),
+    }
+}
+
+/// `series` as an EPUB 3 collection and as Calibre's own metadata.
+fn series_metadata(series: &str, index: Option<f64>) -> String {
+    let series = escape(series);
+    let mut elements = format!(
+        r##"<meta property="belongs-to-collection" id="series">{}</meta><meta refines="#series" property="collection-type">series</meta><meta name="calibre:series" content="{}"/>"##,
+        series,
+        series.replace('"', "&quot;")
+    );
+    if let Some(index) = index {
+        elements.push_str(&format!(
+            r##"<meta refines="#series" property="group-position">{}</meta><meta name="calibre:series_index" content="{}"/>"##,
+            index, index
+        ));
+    }
+    elements
+}
+
+/// Adds a `dc:subject` for each of `subjects`.
+fn add_subjects(opf: &mut String, subjects: &[String]) {
+    let elements: String = subjects
+        .iter()
+        .map(|subject| format!("<dc:subject>{}</dc:subject>", escape(subject)))
+        .collect();
+    add_metadata(opf, &elements);
+}
+
+/// A `dc:contributor` for `producer` in the MARC role of book producer, as
+/// EPUB tools credit themselves.
+pub fn producer_metadata(producer: &str) -> String {
+    format!(
+        r##"<dc:contributor id="producer">{}</dc:contributor><meta refines="#producer" property="role" scheme="marc:relators">bkp</meta>"##,
+        escape(producer)
+    )
+}
+
+fn add_metadata(opf: &mut String, elements: &str) {
+    if let Some(end) = opf.find("</metadata>") {
+        opf.insert_str(end, elements);
+    }
+}
+
+/// `zip` with the entries `wanted` picks replaced by what `change` makes of
+/// them.
+fn rewrite(
+    zip: &[u8],
+    wanted: impl Fn(&str) -> bool,
+    mut change: impl FnMut(Vec<u8>) -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip))?;
+    let mut out = ZipWriter::new(Cursor::new(Vec::new()));
+    // Books are stored uncompressed, and so are books in volume ZIPs.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for i in 0..archive.len() {
+        let name = archive.by_index_raw(i)?.name().to_string();
+        if !wanted(&name) {
+            out.raw_copy_file(archive.by_index_raw(i)?)?;
+            continue;
+        }
+        let mut data = Vec::new();
+        archive.by_index(i)?.read_to_end(&mut data)?;
+        out.start_file(name, stored)?;
+        out.write_all(&change(data)?)?;
+    }
+    Ok(out.finish()?.into_inner())
+}
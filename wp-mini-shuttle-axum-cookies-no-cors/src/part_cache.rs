@@ -0,0 +1,106 @@
+//! Chapter text kept with the `ETag` and `Last-Modified` Wattpad sent for it,
+//! so the next time a part is fetched it is asked for conditionally and only
+//! sent again if it changed. Regenerating a story that gained a chapter then
+//! costs the new chapter and a `304 Not Modified` for each of the others.
+//!
+//! Used wherever chapters are fetched here rather than inside `wp_mini_epub`:
+//! `crate::pipeline` (tolerant and streamed books), the sample part of
+//! `crate::analysis` and `crate::tts`. Parts are kept in `crate::shared` for
+//! `PART_CACHE_SECS`, and only when Wattpad sent a validator with them and
+//! the request has no cookies, as signed-in readers may be sent text others
+//! are not.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use wp_mini_epub::AppError;
+
+use crate::{http_client, pipeline, AppState};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Cached {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    text: String,
+}
+
+/// The HTML of `part_id` as `client` sees it; `anonymous` as for
+/// `story_cache::story`.
+pub async fn text(
+    state: &AppState,
+    client: &Client,
+    part_id: u64,
+    anonymous: bool,
+) -> Result<String> {
+    let Some(ttl) = state.config.part_cache_ttl.filter(|_| anonymous) else {
+        return fetch(client, part_id, None).await.map(|(text, _)| text);
+    };
+    let key = format!("part:{}", part_id);
+    let cached = match state.shared.get(&key).await {
+        Ok(cached) => cached.and_then(|raw| serde_json::from_slice::<Cached>(&raw).ok()),
+        Err(e) => {
+            warn!(error = %e, "Could not look up a cached part");
+            None
+        }
+    };
+    let (text, fresh) = fetch(client, part_id, cached).await?;
+    if let Some(fresh) = fresh
+        && let Ok(value) = serde_json::to_vec(&fresh)
+        && let Err(e) = state.shared.set(&key, Bytes::from(value), ttl).await
+    {
+        warn!(error = %e, "Could not cache a part");
+    }
+    Ok(text)
+}
+
+/// The part's text, asked for on the condition that it changed since
+/// `cached`, and what to cache for it when Wattpad sent something new
+/// with validators.
+async fn fetch(
+    client: &Client,
+    part_id: u64,
+    cached: Option<Cached>,
+) -> Result<(String, Option<Cached>)> {
+    let mut request = pipeline::part_request(client, part_id);
+    if let Some(cached) = &cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = http_client::send(request).await?;
+    if response.status() == StatusCode::NOT_MODIFIED
+        && let Some(cached) = cached
+    {
+        info!(part_id, "Part is unchanged since it was cached");
+        return Ok((cached.text, None));
+    }
+    if !response.status().is_success() {
+        return Err(AppError::DownloadFailed.into());
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let text = response.text().await?;
+    let fresh = match etag.is_some() || last_modified.is_some() {
+        true => Some(Cached {
+            etag,
+            last_modified,
+            text: text.clone(),
+        }),
+        false => None,
+    };
+    Ok((text, fresh))
+}
@@ -0,0 +1,133 @@
+//! Chapters as the pipeline made them, with their embedded images, kept in
+//! `crate::shared` for `CHAPTER_CACHE_SECS` under the part's ID and a hash of
+//! the HTML Wattpad sent for it. Every format is rendered from the EPUB, so a
+//! story the pipeline makes again (streamed, or when `crate::drift` falls
+//! back to it), as an EPUB, then a PDF, then a MOBI, reuses its cleaned
+//! chapters and images instead of downloading every image again; the text
+//! itself is only asked for conditionally (see `crate::part_cache`).
+//!
+//! Keyed by content, a chapter is the same for everyone who got the same
+//! HTML, so this holds for requests with cookies too. Chapters with more
+//! than `MAX_CACHED_BYTES` of images are not kept.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::Chapter;
+use crate::shared::Shared;
+use crate::{encode_hex, AppState};
+
+const MAX_CACHED_BYTES: usize = 8 * 1024 * 1024;
+
+/// Where chapters are cached, when they are.
+pub struct ChapterCache<'a> {
+    shared: &'a dyn Shared,
+    ttl: Duration,
+}
+
+impl<'a> ChapterCache<'a> {
+    /// `None` when `CHAPTER_CACHE_SECS` is 0.
+    pub fn new(state: &'a AppState) -> Option<Self> {
+        Some(ChapterCache {
+            shared: &*state.shared,
+            ttl: state.config.chapter_cache_ttl?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Cached {
+    /// Position of the part when it was cached, which its image paths name.
+    index: usize,
+    html: String,
+    /// EPUB path and base64 content of each embedded image.
+    images: Vec<(String, String)>,
+}
+
+fn key(part_id: u64, embed_images: bool, raw: &str) -> String {
+    format!(
+        "chapter:{}:{}:{}",
+        part_id,
+        embed_images,
+        encode_hex(&Sha256::digest(raw.as_bytes())[..16])
+    )
+}
+
+/// The chapter `title` made from `raw`, the HTML of the part at `index`,
+/// from `cache` or from `process`, whose result is cached.
+pub async fn chapter(
+    cache: Option<&ChapterCache<'_>>,
+    part_id: u64,
+    index: usize,
+    title: String,
+    embed_images: bool,
+    raw: &str,
+    process: impl Future<Output = Result<Chapter>>,
+) -> Result<Chapter> {
+    let Some(cache) = cache else {
+        return process.await;
+    };
+    let key = key(part_id, embed_images, raw);
+    match cache.shared.get(&key).await {
+        Ok(Some(cached)) => match serde_json::from_slice::<Cached>(&cached)
+            .map_err(anyhow::Error::from)
+            .and_then(|cached| restore(cached, index, title))
+        {
+            Ok(chapter) => {
+                info!(part_id, "Serving chapter from the cache");
+                return Ok(chapter);
+            }
+            Err(e) => warn!(error = %e, "Could not read a cached chapter"),
+        },
+        Ok(None) => {}
+        Err(e) => warn!(error = %e, "Could not look up a cached chapter"),
+    }
+
+    let chapter = process.await?;
+    let size: usize = chapter.images.iter().map(|(_, data)| data.len()).sum();
+    if size <= MAX_CACHED_BYTES {
+        let cached = Cached {
+            index,
+            html: chapter.html.clone(),
+            images: chapter
+                .images
+                .iter()
+                .map(|(path, data)| (path.clone(), STANDARD.encode(data)))
+                .collect(),
+        };
+        if let Ok(value) = serde_json::to_vec(&cached)
+            && let Err(e) = cache.shared.set(&key, Bytes::from(value), cache.ttl).await
+        {
+            warn!(error = %e, "Could not cache a chapter");
+        }
+    }
+    Ok(chapter)
+}
+
+/// The cached chapter, with its image paths moved to the part's position now
+/// if that changed.
+fn restore(cached: Cached, index: usize, title: String) -> Result<Chapter> {
+    let from = super::image_dir(cached.index);
+    let to = super::image_dir(index);
+    let mut images = Vec::with_capacity(cached.images.len());
+    for (path, data) in cached.images {
+        images.push((path.replacen(&from, &to, 1), STANDARD.decode(data)?));
+    }
+    let html = match from == to {
+        true => cached.html,
+        false => cached.html.replace(&from, &to),
+    };
+    Ok(Chapter {
+        title,
+        html,
+        images,
+    })
+}
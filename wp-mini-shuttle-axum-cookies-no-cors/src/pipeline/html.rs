@@ -0,0 +1,102 @@
+//! Turns Wattpad's chapter HTML into XHTML an EPUB reader accepts.
+
+use anyhow::{anyhow, Result};
+use lol_html::html_content::ContentType;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use quick_xml::events::Event;
+use quick_xml::{Reader, Writer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Strips Wattpad's markup, closes void elements and points images found in
+/// `image_paths` at their embedded copies.
+pub fn clean(html: &str, image_paths: &HashMap<String, String>) -> Result<String> {
+    let cleaned = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("p[data-media-type='image']", |el| {
+                    el.remove_and_keep_content();
+                    Ok(())
+                }),
+                element!("*[data-p-id]", |el| {
+                    el.remove_attribute("data-p-id");
+                    Ok(())
+                }),
+                element!("br, hr, wbr", |el| {
+                    el.replace(&format!("<{} />", el.tag_name()), ContentType::Html);
+                    Ok(())
+                }),
+                element!("img", |el| {
+                    if let Some(path) = el
+                        .get_attribute("src")
+                        .and_then(|src| image_paths.get(&src))
+                    {
+                        el.set_attribute("src", path)?;
+                    }
+                    el.remove_attribute("data-original-width");
+                    el.remove_attribute("data-original-height");
+                    let mut tag = String::from("<img");
+                    for attribute in el.attributes() {
+                        tag.push_str(&format!(" {}=\"{}\"", attribute.name(), attribute.value()));
+                    }
+                    tag.push_str(" />");
+                    el.replace(&tag, ContentType::Html);
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+    re_encode(&cleaned)
+}
+
+/// Round-trips the fragment through an XML parser so anything that is not
+/// well-formed fails here rather than in the reader.
+fn re_encode(fragment: &str) -> Result<String> {
+    let wrapped = format!("<root>{}</root>", fragment);
+    let mut reader = Reader::from_str(&wrapped);
+    reader.config_mut().trim_text(false);
+    let mut writer = Writer::new(Vec::new());
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.name().as_ref() == b"root" => {}
+            Ok(Event::End(e)) if e.name().as_ref() == b"root" => {}
+            Ok(Event::Eof) => break,
+            Ok(event) => writer.write_event(event)?,
+            Err(e) => {
+                return Err(anyhow!(
+                    "XML parsing error at position {}: {:?}",
+                    reader.buffer_position(),
+                    e
+                ));
+            }
+        }
+    }
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+pub fn image_urls(html: &str) -> Result<Vec<String>> {
+    let urls = RefCell::new(Vec::new());
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("img[src]", |el| {
+                if let Some(src) = el.get_attribute("src") {
+                    urls.borrow_mut().push(src);
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+    Ok(urls.into_inner())
+}
+
+pub fn image_extension(data: &[u8]) -> &'static str {
+    match data {
+        [0x89, b'P', b'N', b'G', ..] => "png",
+        [b'G', b'I', b'F', b'8', ..] => "gif",
+        _ => "jpg",
+    }
+}
@@ -0,0 +1,26 @@
+//! Wattpad's numeric language IDs.
+
+use iepub::prelude::Direction;
+
+/// IETF codes indexed by Wattpad language ID minus one.
+const CODES: [&str; 57] = [
+    "en", "fr", "it", "de", "es", "pt-PT", "ru", "zh-Hant", "ja", "ko", "en", "zh-Hans", "nl",
+    "pl", "ro", "ar", "he", "fil", "vi", "id", "hi", "ms", "tr", "cs", "ml", "sv", "no", "hu",
+    "da", "el", "fa", "th", "is", "fi", "et", "lv", "lt", "ca", "bs", "sr", "hr", "sl", "bg", "sk",
+    "be", "uk", "bn", "ur", "ta", "sw", "af", "pt-BR", "gu", "or", "pa", "as", "mr",
+];
+
+/// Unknown IDs (and 11, "Other") fall back to English.
+pub fn code(id: u64) -> &'static str {
+    id.checked_sub(1)
+        .and_then(|index| CODES.get(index as usize))
+        .copied()
+        .unwrap_or("en")
+}
+
+pub fn direction(id: u64) -> Direction {
+    match code(id) {
+        "ar" | "he" | "fa" | "ur" => Direction::RTL,
+        _ => Direction::LTR,
+    }
+}
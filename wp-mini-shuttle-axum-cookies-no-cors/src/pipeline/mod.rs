@@ -0,0 +1,505 @@
+//! Experimental EPUB pipeline with streaming assembly.
+//!
+//! `wp_mini_epub` downloads the whole story as one ZIP, cleans every chapter,
+//! sorts them and only then builds the book. This pipeline fetches parts one by
+//! one (a bounded number in flight, yielded in story order) and adds each
+//! chapter to the book as soon as it is ready, so a story never has to be held
+//! twice in memory. It makes the books of downloads that report each part or
+//! may be cut off and resumed, and books the library fails to make (see
+//! `crate::drift`), as well as books streamed as they are made (see
+//! `streamed`); its books are checked for drift like the library's, and
+//! shadow mode (see `crate::shadow`) compares the two either way round.
+
+mod cache;
+pub mod html;
+pub mod lang;
+pub mod streamed;
+
+pub use cache::ChapterCache;
+
+use anyhow::{anyhow, Result};
+use futures_util::stream::{self, Stream, StreamExt};
+use iepub::prelude::{EpubBuilder, EpubHtml};
+use reqwest::{Client, RequestBuilder};
+use sanitize_filename::{sanitize_with_options, Options};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tracing::{info, warn};
+use wp_mini::field::{LanguageField, PartStubField, StoryField, UserStubField};
+use wp_mini::types::StoryResponse;
+use wp_mini::WattpadClient;
+use wp_mini_epub::{AppError, StoryDownload};
+
+use crate::http_client::{self, UpstreamRateLimited};
+
+const PART_TEXT_URL: &str = "https://www.wattpad.com/apiv2/";
+
+#[derive(Clone)]
+struct Chapter {
+    title: String,
+    html: String,
+    /// EPUB path and content of each embedded image.
+    images: Vec<(String, Vec<u8>)>,
+}
+
+/// Same contract as `wp_mini_epub::download_story_to_memory`.
+pub async fn download_story_to_memory(
+    client: &Client,
+    story_id: u64,
+    embed_images: bool,
+    concurrent_requests: usize,
+) -> Result<StoryDownload<Vec<u8>>> {
+    let wattpad = WattpadClient::builder()
+        .reqwest_client(client.clone())
+        .build();
+    let story = story_info(&wattpad, story_id).await?;
+    assemble(
+        client,
+        story_id,
+        story,
+        embed_images,
+        concurrent_requests,
+        |part_id| part_text(client, part_id),
+        Hooks::default(),
+    )
+    .await
+}
+
+/// What `assemble` reuses besides Wattpad.
+#[derive(Default)]
+pub struct Hooks<'a> {
+    /// Chapters made before from the same HTML.
+    pub cache: Option<ChapterCache<'a>>,
+    /// Chapters an earlier attempt at the book finished, which this one
+    /// keeps its own in too.
+    pub checkpoint: Option<&'a Checkpoint>,
+    /// Told the position of each part once it is done, added or left out,
+    /// and how many parts there are.
+    pub on_part: Option<&'a (dyn Fn(usize, usize) + Send + Sync)>,
+}
+
+/// The chapters finished while making a book, by part ID, so an attempt
+/// that is cut off can be made again from where it stopped (see
+/// `crate::job_budget`).
+#[derive(Default)]
+pub struct Checkpoint(Mutex<HashMap<u64, (usize, Chapter)>>);
+
+impl Checkpoint {
+    /// The chapter of `part_id` if it was finished at the same `index`.
+    fn get(&self, part_id: u64, index: usize) -> Option<Chapter> {
+        let done = self.0.lock().unwrap();
+        done.get(&part_id)
+            .filter(|(at, _)| *at == index)
+            .map(|(_, chapter)| chapter.clone())
+    }
+
+    fn keep(&self, part_id: u64, index: usize, chapter: &Chapter) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(part_id, (index, chapter.clone()));
+    }
+
+    /// Parts finished so far.
+    pub fn finished(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+/// The HTML of a part, from the endpoint `wp_mini` reads it from, sent here
+/// to be retried (see `crate::http_client`).
+async fn part_text(client: &Client, part_id: u64) -> Result<String> {
+    let response = http_client::send(part_request(client, part_id)).await?;
+    if !response.status().is_success() {
+        return Err(AppError::DownloadFailed.into());
+    }
+    Ok(response.text().await?)
+}
+
+/// The request for the HTML of a part; see `part_text`.
+pub fn part_request(client: &Client, part_id: u64) -> RequestBuilder {
+    client
+        .get(PART_TEXT_URL)
+        .query(&[("m", "storytext"), ("id", &part_id.to_string())])
+}
+
+/// What the book is made from.
+async fn story_info(wattpad: &WattpadClient, story_id: u64) -> Result<StoryResponse> {
+    let fields = [
+        StoryField::Title,
+        StoryField::ModifyDate,
+        StoryField::Description,
+        StoryField::Cover,
+        StoryField::Language(vec![LanguageField::Id]),
+        StoryField::User(vec![UserStubField::Username]),
+        StoryField::Tags,
+        StoryField::Parts(vec![PartStubField::Id, PartStubField::Title]),
+    ];
+    Ok(wattpad
+        .story
+        .get_story_info(story_id, Some(&fields))
+        .await
+        .map_err(|_| AppError::MetadataFetchFailed)?)
+}
+
+/// Builds the book of `story` from the HTML `part_text` gives for each of its
+/// parts, reusing chapters as `hooks` say. Shared with `crate::scrape`,
+/// which gets both from the story's pages.
+pub async fn assemble<F, Fut>(
+    client: &Client,
+    story_id: u64,
+    story: StoryResponse,
+    embed_images: bool,
+    concurrent_requests: usize,
+    part_text: F,
+    hooks: Hooks<'_>,
+) -> Result<StoryDownload<Vec<u8>>>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let parts = parts_of(&story)?;
+    let total = parts.len();
+
+    let title = story.title.as_deref().unwrap_or("Untitled Story");
+    let language_id = story
+        .language
+        .as_ref()
+        .and_then(|language| language.id)
+        .unwrap_or(1);
+    let language = lang::code(language_id);
+    let mut builder = EpubBuilder::default()
+        .with_title(title)
+        .with_creator(
+            story
+                .user
+                .as_ref()
+                .and_then(|user| user.username.as_deref())
+                .unwrap_or("Unknown Author"),
+        )
+        .with_description(story.description.as_deref().unwrap_or(""))
+        .with_direction(lang::direction(language_id));
+    // Dated by the story rather than the clock, so the same version of a
+    // story always makes the same book.
+    if let Some(modified) = story.modify_date.as_deref() {
+        builder = builder.with_last_modify(modified);
+    }
+    if let Some(cover_url) = story.cover.as_deref()
+        && let Some(cover) = download_image(client, cover_url).await
+    {
+        builder = builder.cover("cover.jpg", cover);
+    }
+
+    let mut chapters = chapters(
+        client,
+        parts,
+        embed_images,
+        concurrent_requests,
+        &part_text,
+        &hooks,
+    );
+    let mut added = 0;
+    while let Some((index, part_id, chapter)) = chapters.next().await {
+        let chapter = match chapter {
+            Ok(chapter) => chapter,
+            // Every other part would be throttled just the same.
+            Err(e) if e.is::<UpstreamRateLimited>() => return Err(e),
+            Err(e) => {
+                warn!(part_id, error = %e, "Failed to process a chapter");
+                if let Some(on_part) = hooks.on_part {
+                    on_part(index, total);
+                }
+                continue;
+            }
+        };
+        for (path, data) in chapter.images {
+            builder = builder.add_assets(path, data);
+        }
+        builder = builder.add_chapter(
+            EpubHtml::default()
+                .with_title(&chapter.title)
+                .with_file_name(format!("{}.xhtml", index))
+                .with_language(language)
+                .with_data(chapter.html.into_bytes()),
+        );
+        added += 1;
+        if let Some(on_part) = hooks.on_part {
+            on_part(index, total);
+        }
+    }
+    info!(chapters = added, "Assembled EPUB");
+
+    let epub = builder
+        .mem()
+        .map_err(|e| anyhow!("Failed to generate EPUB in memory: {:?}", e))?;
+    Ok(StoryDownload {
+        sanitized_title: sanitized_title(story_id, title),
+        epub_response: epub,
+        metadata: story,
+    })
+}
+
+/// Position (1-based), ID and title of each part of `story`.
+fn parts_of(story: &StoryResponse) -> Result<Vec<(usize, u64, Option<String>)>> {
+    let parts = story.parts.clone().ok_or(AppError::MetadataFetchFailed)?;
+    Ok(parts
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, part)| part.id.map(|id| (i + 1, id, part.title)))
+        .collect())
+}
+
+/// The chapters of `parts` in story order, a bounded number fetched at a
+/// time, along with the position and ID of their parts.
+fn chapters<'a, F, Fut>(
+    client: &'a Client,
+    parts: Vec<(usize, u64, Option<String>)>,
+    embed_images: bool,
+    concurrent_requests: usize,
+    part_text: &'a F,
+    hooks: &'a Hooks<'a>,
+) -> impl Stream<Item = (usize, u64, Result<Chapter>)> + 'a
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<String>> + 'a,
+{
+    stream::iter(parts)
+        .map(move |(index, part_id, title)| async move {
+            let chapter = async {
+                let checkpoint = hooks.checkpoint;
+                if let Some(chapter) = checkpoint.and_then(|done| done.get(part_id, index)) {
+                    return Ok(chapter);
+                }
+                let raw = part_text(part_id).await?;
+                let title = title.unwrap_or_else(|| "Untitled Chapter".to_string());
+                let process = fetch_chapter(
+                    client,
+                    index,
+                    title.clone(),
+                    &raw,
+                    embed_images,
+                    concurrent_requests,
+                );
+                let cache = hooks.cache.as_ref();
+                let chapter =
+                    cache::chapter(cache, part_id, index, title, embed_images, &raw, process)
+                        .await?;
+                if let Some(done) = checkpoint {
+                    done.keep(part_id, index, &chapter);
+                }
+                Ok(chapter)
+            };
+            (index, part_id, chapter.await)
+        })
+        .buffered(concurrent_requests.max(1))
+}
+
+/// The file name, without extension, of the book of `story_id`, as
+/// `wp_mini_epub` makes it.
+/// The file name stem Wattpad's library gives the book of `title`.
+pub fn sanitized_title(story_id: u64, title: &str) -> String {
+    format!(
+        "{}-{}",
+        story_id,
+        sanitize_with_options(
+            title,
+            Options {
+                replacement: "_",
+                ..Default::default()
+            }
+        )
+    )
+}
+
+/// The chapter made from `raw`, the HTML of the part at `index`, with its
+/// images downloaded when `embed_images` asks for them.
+async fn fetch_chapter(
+    client: &Client,
+    index: usize,
+    title: String,
+    raw: &str,
+    embed_images: bool,
+    concurrent_requests: usize,
+) -> Result<Chapter> {
+    let mut images = Vec::new();
+    let mut image_paths = HashMap::new();
+    if embed_images {
+        let downloads: Vec<(String, Option<Vec<u8>>)> = stream::iter(html::image_urls(raw)?)
+            .map(|url| async move {
+                let data = download_image(client, &url).await;
+                (url, data)
+            })
+            .buffered(concurrent_requests.max(1))
+            .collect()
+            .await;
+        for (url, data) in downloads {
+            // Images that could not be fetched keep pointing at Wattpad.
+            let Some(data) = data else { continue };
+            let path = format!(
+                "{}image_{}.{}",
+                image_dir(index),
+                images.len(),
+                html::image_extension(&data)
+            );
+            image_paths.insert(url, path.clone());
+            images.push((path, data));
+        }
+    }
+
+    Ok(Chapter {
+        title,
+        html: html::clean(raw, &image_paths)?,
+        images,
+    })
+}
+
+/// Where the images of the part at `index` go in the book.
+fn image_dir(index: usize) -> String {
+    format!("images/chapter_{}/", index)
+}
+
+async fn download_image(client: &Client, url: &str) -> Option<Vec<u8>> {
+    reqwest::Url::parse(url).ok()?;
+    match http_client::send(client.get(url)).await {
+        Ok(response) if response.status().is_success() => {
+            response.bytes().await.ok().map(|bytes| bytes.to_vec())
+        }
+        Ok(response) => {
+            warn!(status = %response.status(), url, "Failed to download image");
+            None
+        }
+        Err(e) => {
+            warn!(error = %e, url, "Failed to download image");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+    use std::path::{Path, PathBuf};
+    use zip::ZipArchive;
+
+    const STORY_ID: u64 = 1_000_001;
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/golden")
+    }
+
+    /// The book of the fixture story, its parts read from `fixtures/golden`
+    /// instead of Wattpad.
+    async fn book() -> StoryDownload<Vec<u8>> {
+        let story = std::fs::read_to_string(fixtures().join("story.json")).unwrap();
+        let story: StoryResponse = serde_json::from_str(&story).unwrap();
+        let part_text = |part_id: u64| async move {
+            let path = fixtures().join(format!("parts/{}.html", part_id));
+            Ok(std::fs::read_to_string(path)?)
+        };
+        assemble(
+            &Client::new(),
+            STORY_ID,
+            story,
+            false,
+            2,
+            part_text,
+            Hooks::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// The name and content of every entry of `epub`.
+    fn contents(epub: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let mut zip = ZipArchive::new(Cursor::new(epub)).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let mut entry = zip.by_index(i).unwrap();
+                let mut bytes = Vec::new();
+                entry.read_to_end(&mut bytes).unwrap();
+                (entry.name().to_string(), bytes)
+            })
+            .collect()
+    }
+
+    /// Compares `actual` with the golden file `name`, or rewrites it when
+    /// `UPDATE_GOLDEN` is set.
+    fn check(name: &str, actual: &str) {
+        let path = fixtures().join("epub").join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("No golden {}; run with UPDATE_GOLDEN=1", name));
+        assert_eq!(
+            actual, expected,
+            "{} no longer matches its golden file; if the change is intended, run with UPDATE_GOLDEN=1",
+            name
+        );
+    }
+
+    #[tokio::test]
+    async fn the_fixture_story_makes_the_golden_book() {
+        let book = book().await;
+        assert_eq!(
+            book.sanitized_title,
+            "1000001-The Lighthouse Keeper's _Daughter_ & Other Tales"
+        );
+
+        let mut zip = ZipArchive::new(Cursor::new(book.epub_response)).unwrap();
+        let mut entries = String::new();
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i).unwrap();
+            entries.push_str(&format!("{} {:?}\n", entry.name(), entry.compression()));
+            let name = entry.name().to_string();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            check(&name, &contents);
+        }
+        check("entries.txt", &entries);
+    }
+
+    #[tokio::test]
+    async fn a_checkpointed_book_resumes_without_the_parts_done() {
+        let checkpoint = Checkpoint::default();
+        let story = std::fs::read_to_string(fixtures().join("story.json")).unwrap();
+        let story: StoryResponse = serde_json::from_str(&story).unwrap();
+        let parts = parts_of(&story).unwrap().len();
+        let part_text = |part_id: u64| async move {
+            let path = fixtures().join(format!("parts/{}.html", part_id));
+            Ok(std::fs::read_to_string(path)?)
+        };
+        let hooks = || Hooks {
+            checkpoint: Some(&checkpoint),
+            ..Hooks::default()
+        };
+        let first = assemble(
+            &Client::new(),
+            STORY_ID,
+            story.clone(),
+            false,
+            2,
+            part_text,
+            hooks(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(checkpoint.finished(), parts);
+
+        let offline = |_: u64| async { Err::<String, _>(anyhow!("offline")) };
+        let resumed = assemble(&Client::new(), STORY_ID, story, false, 2, offline, hooks())
+            .await
+            .unwrap();
+        assert!(contents(first.epub_response) == contents(resumed.epub_response));
+    }
+
+    #[tokio::test]
+    async fn the_same_story_makes_the_same_book() {
+        let (first, second) = (book().await, book().await);
+        assert!(contents(first.epub_response) == contents(second.epub_response));
+    }
+}
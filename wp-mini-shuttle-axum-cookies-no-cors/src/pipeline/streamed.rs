@@ -0,0 +1,385 @@
+//! `"stream": true` on `/generate-epub`: the book is written into the
+//! response while its parts are still being fetched, so a story of a thousand
+//! chapters never has to be held in memory as a whole.
+//!
+//! `iepub` only writes finished books, so the EPUB is put together here, in
+//! the same layout it uses, with the ZIP written as it goes (see
+//! `crate::file_response::Pending`). Each chapter is sent as soon as it and
+//! the ones before it are ready; the package document, navigation and table
+//! of contents, which list every chapter, come last. The response is chunked,
+//! as neither its length nor its hash is known up front, and a failure after
+//! the first chapter can only cut it off.
+//!
+//! Only books that need nothing done to them once made are streamed: EPUBs
+//! with default chapter options, neither delivered nor post-processed. Their
+//! images are converted and a cover generated as `crate::chapters` would.
+//! Other requests with `stream` set are answered as usual.
+
+use anyhow::Result;
+use axum::body::{Body, Bytes};
+use chrono::{SecondsFormat, Utc};
+use futures_util::stream::{self, StreamExt};
+use iepub::prelude::Direction;
+use reqwest::Client;
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn, Instrument};
+use wp_mini::types::StoryResponse;
+use wp_mini_epub::AppError;
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::{CompressionMethod, ZipWriter};
+
+use super::{chapters, download_image, html, lang, parts_of, sanitized_title, ChapterCache, Hooks};
+use crate::book::escape;
+use crate::cover;
+use crate::error::MyError;
+use crate::file_response::Pending;
+use crate::http_client::UpstreamRateLimited;
+use crate::images::{self, GifImages, Policy, SvgImages};
+use crate::opf;
+use crate::part_cache;
+use crate::story_cache;
+use crate::tags::Tags;
+use crate::AppState;
+
+const CONTAINER: &str = r#"<?xml version='1.0' encoding='utf-8'?>
+<container xmlns="urn:oasis:names:tc:opendocument:xmlns:container" version="1.0">
+  <rootfiles>
+    <rootfile media-type="application/oebps-package+xml" full-path="OEBPS/content.opf"/>
+  </rootfiles>
+</container>
+"#;
+
+pub struct Streamed {
+    pub file_name: String,
+    pub body: Body,
+}
+
+/// Looks the story up (see `crate::story_cache`; `anonymous` as there) and
+/// starts writing its book into the returned body. Errors up to here are
+/// answered as usual.
+pub async fn start(
+    state: &AppState,
+    client: Client,
+    story_id: u64,
+    embed_images: bool,
+    identifier: String,
+    concurrency: usize,
+    anonymous: bool,
+) -> Result<Streamed, MyError> {
+    let story = story_cache::story(state, &client, story_id, anonymous)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not fetch story metadata");
+            MyError::App(AppError::MetadataFetchFailed)
+        })?;
+    let producer = state.config.branding.name.clone();
+    let title = story.title.as_deref().unwrap_or("Untitled Story");
+    let file_name = format!("{}.epub", sanitized_title(story_id, title));
+
+    let state = state.clone();
+    let (chunks, chunks_rx) = mpsc::channel::<io::Result<Bytes>>(1);
+    tokio::spawn(
+        async move {
+            let policy = Policy::new(&state.config, SvgImages::default(), GifImages::default());
+            let book = write(
+                Source {
+                    state: &state,
+                    client: &client,
+                    anonymous,
+                },
+                story,
+                embed_images,
+                concurrency,
+                Labels {
+                    identifier: &identifier,
+                    producer: &producer,
+                },
+                &policy,
+                &chunks,
+            );
+            if let Err(e) = book.await {
+                error!(error = %e, "Streamed EPUB failed");
+                // Ends the body with an error; the client sees a cut-off EPUB.
+                let _ = chunks.send(Err(io::Error::other(e.to_string()))).await;
+            }
+        }
+        .in_current_span(),
+    );
+    let body = stream::unfold(chunks_rx, |mut chunks_rx| async move {
+        let chunk = chunks_rx.recv().await?;
+        Some((chunk, chunks_rx))
+    });
+    Ok(Streamed {
+        file_name,
+        body: Body::from_stream(body),
+    })
+}
+
+/// Where a book's chapters are fetched from.
+struct Source<'a> {
+    state: &'a AppState,
+    client: &'a Client,
+    /// As for `part_cache::text`.
+    anonymous: bool,
+}
+
+/// Writes the book of `story` to `chunks`. Stops once the client is gone.
+async fn write(
+    source: Source<'_>,
+    story: StoryResponse,
+    embed_images: bool,
+    concurrency: usize,
+    labels: Labels<'_>,
+    policy: &Policy<'_>,
+    chunks: &mpsc::Sender<io::Result<Bytes>>,
+) -> Result<()> {
+    let client = source.client;
+    let parts = parts_of(&story)?;
+    let language_id = story
+        .language
+        .as_ref()
+        .and_then(|language| language.id)
+        .unwrap_or(1);
+    let mut package = Package {
+        identifier: labels.identifier,
+        producer: labels.producer,
+        title: story.title.as_deref().unwrap_or("Untitled Story"),
+        creator: story
+            .user
+            .as_ref()
+            .and_then(|user| user.username.as_deref())
+            .unwrap_or("Unknown Author"),
+        description: story.description.as_deref().unwrap_or(""),
+        subjects: Tags::new(story.tags.as_deref().unwrap_or_default()).subjects(),
+        language: lang::code(language_id),
+        direction: lang::direction(language_id),
+        cover: String::new(),
+        assets: Vec::new(),
+        chapters: Vec::new(),
+    };
+
+    let pending = Pending::default();
+    let mut zip = ZipWriter::new_stream(pending.clone());
+    add(&mut zip, "mimetype", b"application/epub+zip")?;
+    add(&mut zip, "META-INF/container.xml", CONTAINER.as_bytes())?;
+    let cover = match story.cover.as_deref() {
+        Some(cover_url) => download_image(client, cover_url)
+            .await
+            .map(|cover| (format!("cover.{}", html::image_extension(&cover)), cover)),
+        None => None,
+    };
+    let (name, cover) = cover.unwrap_or_else(|| {
+        info!("Generating a cover");
+        (
+            cover::FILE_NAME.to_string(),
+            cover::generate(package.title, package.creator),
+        )
+    });
+    add(&mut zip, &format!("OEBPS/{}", name), &cover)?;
+    let page = format!(r#"<img src="{}" alt="Cover"/>"#, name);
+    let page = xhtml("Cover", &page, false, &package);
+    add(&mut zip, "OEBPS/cover.xhtml", page.as_bytes())?;
+    package.cover = name;
+
+    let part_text = |part_id| part_cache::text(source.state, client, part_id, source.anonymous);
+    let hooks = Hooks {
+        cache: ChapterCache::new(source.state),
+        ..Hooks::default()
+    };
+    let mut chapters = chapters(client, parts, embed_images, concurrency, &part_text, &hooks);
+    while let Some((index, part_id, chapter)) = chapters.next().await {
+        let chapter = match chapter {
+            Ok(chapter) => chapter,
+            Err(e) if e.is::<UpstreamRateLimited>() => return Err(e),
+            Err(e) => {
+                warn!(part_id, error = %e, "Failed to process a chapter");
+                continue;
+            }
+        };
+        let mut body = chapter.html;
+        for (path, data) in chapter.images {
+            let (path, data) = match images::convert(&path, &data, policy) {
+                Some((converted_path, converted)) => {
+                    body = body.replace(&path, &converted_path);
+                    (converted_path, converted)
+                }
+                None => (path, data),
+            };
+            add(&mut zip, &format!("OEBPS/{}", path), &data)?;
+            package.assets.push(path);
+        }
+        let file = format!("{}.xhtml", index);
+        let page = xhtml(&chapter.title, &body, true, &package);
+        add(&mut zip, &format!("OEBPS/{}", file), page.as_bytes())?;
+        package.chapters.push((file, chapter.title));
+        if chunks.send(Ok(pending.take())).await.is_err() {
+            info!("Client left; stopping the streamed EPUB");
+            return Ok(());
+        }
+    }
+
+    add(&mut zip, "OEBPS/nav.xhtml", package.nav().as_bytes())?;
+    add(&mut zip, "OEBPS/toc.ncx", package.ncx().as_bytes())?;
+    add(&mut zip, "OEBPS/content.opf", package.opf().as_bytes())?;
+    zip.finish()?;
+    info!(chapters = package.chapters.len(), "Streamed EPUB");
+    let _ = chunks.send(Ok(pending.take())).await;
+    Ok(())
+}
+
+fn add(zip: &mut ZipWriter<StreamWriter<Pending>>, name: &str, data: &[u8]) -> Result<()> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file(name, options)?;
+    zip.write_all(data)?;
+    Ok(())
+}
+
+/// What the book is labelled with besides the story's own metadata (see
+/// `opf::label`).
+struct Labels<'a> {
+    identifier: &'a str,
+    producer: &'a str,
+}
+
+/// What the package document and navigation list, gathered while the
+/// chapters are written.
+struct Package<'a> {
+    identifier: &'a str,
+    producer: &'a str,
+    title: &'a str,
+    creator: &'a str,
+    description: &'a str,
+    subjects: Vec<String>,
+    language: &'static str,
+    direction: Direction,
+    /// File name of the cover image under `OEBPS/`.
+    cover: String,
+    /// Paths of the images under `OEBPS/`.
+    assets: Vec<String>,
+    /// File name and title of each chapter.
+    chapters: Vec<(String, String)>,
+}
+
+impl Package<'_> {
+    fn opf(&self) -> String {
+        let mut metadata = format!(
+            "<dc:identifier id=\"id\">{}</dc:identifier><dc:title>{}</dc:title><dc:creator id=\"creator\">{}</dc:creator><dc:language>{}</dc:language><meta property=\"dcterms:modified\">{}</meta>",
+            escape(self.identifier),
+            escape(self.title),
+            escape(self.creator),
+            self.language,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        if !self.description.is_empty() {
+            metadata.push_str(&format!(
+                "<dc:description>{}</dc:description>",
+                escape(self.description)
+            ));
+        }
+        for subject in &self.subjects {
+            metadata.push_str(&format!("<dc:subject>{}</dc:subject>", escape(subject)));
+        }
+        metadata.push_str(&opf::producer_metadata(self.producer));
+        let mut manifest = String::from(
+            r#"<item href="toc.ncx" id="ncx" media-type="application/x-dtbncx+xml"/><item href="nav.xhtml" id="toc" media-type="application/xhtml+xml" properties="nav"/>"#,
+        );
+        metadata.push_str(r#"<meta name="cover" content="cover-img"/>"#);
+        manifest.push_str(&format!(
+            r#"<item href="{}" id="cover-img" media-type="{}" properties="cover-image"/><item href="cover.xhtml" id="cover" media-type="application/xhtml+xml"/>"#,
+            self.cover,
+            media_type(&self.cover)
+        ));
+        for (i, path) in self.assets.iter().enumerate() {
+            manifest.push_str(&format!(
+                r#"<item href="{}" id="assets_{}" media-type="{}"/>"#,
+                path,
+                i,
+                media_type(path)
+            ));
+        }
+        let mut spine = String::from(r#"<itemref idref="toc"/>"#);
+        for (i, (file, _)) in self.chapters.iter().enumerate() {
+            manifest.push_str(&format!(
+                r#"<item href="{}" id="chap_{}" media-type="application/xhtml+xml"/>"#,
+                file, i
+            ));
+            spine.push_str(&format!(r#"<itemref idref="chap_{}"/>"#, i));
+        }
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><package xmlns="http://www.idpf.org/2007/opf" unique-identifier="id" version="3.0"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">{}</metadata><manifest>{}</manifest><spine toc="ncx" page-progression-direction="{}">{}</spine></package>"#,
+            metadata, manifest, self.direction, spine
+        )
+    }
+
+    fn nav(&self) -> String {
+        let items: String = self
+            .chapters
+            .iter()
+            .map(|(file, title)| format!(r#"<li><a href="{}">{}</a></li>"#, file, escape(title)))
+            .collect();
+        format!(
+            r#"<?xml version='1.0' encoding='utf-8'?><!DOCTYPE html><html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" lang="{lang}" xml:lang="{lang}" dir="{dir}"><head><title>{title}</title></head><body><nav epub:type="toc" id="id" role="doc-toc"><h2>{title}</h2><ul>{items}</ul></nav></body></html>"#,
+            lang = self.language,
+            dir = self.direction,
+            title = escape(self.title),
+        )
+    }
+
+    fn ncx(&self) -> String {
+        let points: String = self
+            .chapters
+            .iter()
+            .enumerate()
+            .map(|(i, (file, title))| {
+                format!(
+                    r#"<navPoint id="0-{}"><navLabel><text>{}</text></navLabel><content src="{}"></content></navPoint>"#,
+                    i,
+                    escape(title),
+                    file
+                )
+            })
+            .collect();
+        format!(
+            r#"<?xml version='1.0' encoding='utf-8'?><ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><head><meta content="{}" name="dtb:uid"/><meta content="1" name="dtb:depth"/><meta content="0" name="dtb:totalPageCount"/><meta content="0" name="dtb:maxPageNumber"/></head><docTitle><text>{}</text></docTitle><navMap>{}</navMap></ncx>"#,
+            escape(self.identifier),
+            escape(self.title),
+            points
+        )
+    }
+}
+
+/// A page of the book around `body`, headed by `title` if `heading` is set,
+/// as `iepub` writes chapters.
+fn xhtml(title: &str, body: &str, heading: bool, package: &Package) -> String {
+    let title = escape(title);
+    let heading = match heading {
+        true => format!(r#"<h1 style="text-align: center">{}</h1>"#, title),
+        false => String::new(),
+    };
+    format!(
+        r#"<?xml version='1.0' encoding='utf-8'?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" lang="{lang}" xml:lang="{lang}" dir="{dir}">
+  <head>
+    <title>{title}</title>
+</head>
+  <body>
+    {heading}
+{body}
+  </body>
+</html>"#,
+        lang = package.language,
+        dir = package.direction,
+    )
+}
+
+fn media_type(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        _ => "image/jpeg",
+    }
+}
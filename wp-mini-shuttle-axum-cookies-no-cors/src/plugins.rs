@@ -0,0 +1,155 @@
+//! `plugins`: chapter transforms a deployment adds as WebAssembly modules,
+//! run sandboxed so trying out a text filter needs no fork of the pipeline.
+//! Plugins only rewrite chapter text; formats are still written by
+//! `crate::render`. Only built with the `wasm-plugins` feature.
+//!
+//! `WASM_PLUGINS` registers them as `name=path/to/plugin.wasm`, comma
+//! separated; requests list the names to run, in order. A plugin exports its
+//! `memory`, `alloc(len: i32) -> i32` and `transform(ptr: i32, len: i32) ->
+//! i64`: it is handed each chapter's XHTML body as UTF-8 in memory it
+//! allocated, and returns where its replacement is as `ptr << 32 | len`.
+//! Plugins get no imports at all, so they cannot reach files, the network
+//! or the clock. Each book gets fresh instances, which may grow to
+//! `WASM_PLUGIN_MAX_MB` of memory and spend `WASM_PLUGIN_FUEL` (roughly
+//! instructions) on each chapter; a plugin that goes over fails the book.
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::book::Book;
+use crate::config::PluginConfig;
+
+pub struct Plugins {
+    #[cfg(feature = "wasm-plugins")]
+    runtime: Option<wasm::Runtime>,
+}
+
+impl Plugins {
+    #[cfg(feature = "wasm-plugins")]
+    pub fn load(config: &PluginConfig) -> Self {
+        let runtime = match config.modules.is_empty() {
+            true => None,
+            false => wasm::Runtime::load(config)
+                .inspect_err(|e| warn!(error = %e, "Could not load WASM plugins"))
+                .ok(),
+        };
+        Plugins { runtime }
+    }
+
+    #[cfg(not(feature = "wasm-plugins"))]
+    pub fn load(config: &PluginConfig) -> Self {
+        if !config.modules.is_empty() {
+            warn!("WASM_PLUGINS is set but the server was built without the wasm-plugins feature");
+        }
+        Plugins {}
+    }
+
+    pub fn validate(&self, names: &[String]) -> Result<(), String> {
+        match names.iter().find(|name| !self.has(name)) {
+            Some(name) => Err(format!("Unknown plugin {}", name)),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "wasm-plugins")]
+    fn has(&self, name: &str) -> bool {
+        self.runtime
+            .as_ref()
+            .is_some_and(|runtime| runtime.has(name))
+    }
+
+    #[cfg(not(feature = "wasm-plugins"))]
+    fn has(&self, _name: &str) -> bool {
+        false
+    }
+
+    /// Runs the plugins called `names` over every chapter of `book`. The names
+    /// were validated on admission.
+    pub fn apply(&self, names: &[String], book: &mut Book) -> Result<()> {
+        #[cfg(feature = "wasm-plugins")]
+        if let Some(runtime) = &self.runtime {
+            for name in names {
+                runtime.apply(name, book)?;
+            }
+        }
+        #[cfg(not(feature = "wasm-plugins"))]
+        let _ = (names, book);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+mod wasm {
+    use anyhow::{anyhow, Context, Result};
+    use std::collections::HashMap;
+    use tracing::info;
+    use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+    use crate::book::Book;
+    use crate::config::PluginConfig;
+
+    pub struct Runtime {
+        engine: Engine,
+        modules: HashMap<String, Module>,
+        fuel: u64,
+        max_bytes: usize,
+    }
+
+    impl Runtime {
+        pub fn load(config: &PluginConfig) -> Result<Self> {
+            let engine = Engine::new(Config::new().consume_fuel(true))?;
+            let mut modules = HashMap::new();
+            for (name, path) in &config.modules {
+                let module = Module::from_file(&engine, path)
+                    .map_err(|e| anyhow!("Could not load plugin {} from {}: {}", name, path, e))?;
+                modules.insert(name.clone(), module);
+            }
+            info!(plugins = modules.len(), "Loaded WASM plugins");
+            Ok(Runtime {
+                engine,
+                modules,
+                fuel: config.fuel,
+                max_bytes: config.max_bytes,
+            })
+        }
+
+        pub fn has(&self, name: &str) -> bool {
+            self.modules.contains_key(name)
+        }
+
+        pub fn apply(&self, name: &str, book: &mut Book) -> Result<()> {
+            let module = self
+                .modules
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown plugin {}", name))?;
+            let limits = StoreLimitsBuilder::new()
+                .memory_size(self.max_bytes)
+                .instances(1)
+                .build();
+            let mut store = Store::new(&self.engine, limits);
+            store.limiter(|limits: &mut StoreLimits| limits);
+            let instance = Instance::new(&mut store, module, &[])?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| anyhow!("Plugin {} exports no memory", name))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+            let transform = instance.get_typed_func::<(i32, i32), i64>(&mut store, "transform")?;
+            for chapter in &mut book.chapters {
+                store.set_fuel(self.fuel)?;
+                let input = chapter.body.as_bytes();
+                let len = i32::try_from(input.len())?;
+                let ptr = alloc.call(&mut store, len)?;
+                memory.write(&mut store, ptr as u32 as usize, input)?;
+                let result = transform.call(&mut store, (ptr, len))? as u64;
+                let (ptr, len) = ((result >> 32) as usize, (result & 0xffff_ffff) as usize);
+                let output = memory
+                    .data(&store)
+                    .get(ptr..ptr + len)
+                    .ok_or_else(|| anyhow!("Plugin {} returned text outside its memory", name))?;
+                chapter.body = String::from_utf8(output.to_vec())
+                    .with_context(|| format!("Plugin {} returned text that is not UTF-8", name))?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! How the server presents itself to the sites it reads stories from, and
+//! how hard it leans on them.
+//!
+//! Each source gets its own identity from the secrets: a user agent, a
+//! contact the site's operators can reach the deployment at, and a cap on
+//! the stories started against it per minute. Wattpad, its API and its pages
+//! (see `crate::scrape`), is the only source so far, configured with the
+//! `WATTPAD_*` secrets. Images in stories are fetched by the same clients and
+//! so go out under the same identity. Delivery targets and notification
+//! services are not sources and keep the default user agent.
+//!
+//! The cap is kept per instance: a deployment with several instances should
+//! divide its budget between them.
+
+use axum::http::HeaderMap;
+use reqwest::header::{HeaderValue, FROM};
+use reqwest::ClientBuilder;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::SourceConfig;
+use crate::{unix_now, APP_USER_AGENT};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// `builder` with the identity `source` configures.
+pub fn identify(builder: ClientBuilder, source: &SourceConfig) -> ClientBuilder {
+    let mut user_agent = source
+        .user_agent
+        .clone()
+        .unwrap_or_else(|| APP_USER_AGENT.to_string());
+    let mut headers = HeaderMap::new();
+    match source.contact.as_deref() {
+        // `From` is meant for an email address; anything else is taken for a
+        // URL, which crawlers put into their user agent.
+        Some(contact) if contact.contains('@') && !contact.contains("://") => {
+            match HeaderValue::from_str(contact) {
+                Ok(contact) => {
+                    headers.insert(FROM, contact);
+                }
+                Err(_) => warn!("Ignoring a source contact that is not a valid header value"),
+            }
+        }
+        Some(contact) => user_agent.push_str(&format!(" (+{})", contact)),
+        None => {}
+    }
+    builder.user_agent(user_agent).default_headers(headers)
+}
+
+/// The stories started against one source in the current minute.
+#[derive(Default)]
+pub struct RateLimit {
+    /// The window's first Unix second and the stories started in it.
+    window: Mutex<(u64, u32)>,
+}
+
+impl RateLimit {
+    /// Counts a story against `per_minute`; returns how long to back off if
+    /// the source has had its share for this minute.
+    pub fn check(&self, per_minute: Option<u32>) -> Result<(), Duration> {
+        let Some(per_minute) = per_minute else {
+            return Ok(());
+        };
+        let now = unix_now();
+        let mut window = self.window.lock().unwrap();
+        if now.saturating_sub(window.0) >= WINDOW.as_secs() {
+            *window = (now, 0);
+        }
+        if window.1 >= per_minute {
+            let elapsed = now.saturating_sub(window.0);
+            return Err(Duration::from_secs(WINDOW.as_secs() - elapsed));
+        }
+        window.1 += 1;
+        Ok(())
+    }
+}
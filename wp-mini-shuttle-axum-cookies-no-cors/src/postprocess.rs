@@ -0,0 +1,90 @@
+//! A deployment's own last step for every finished file, e.g. to stamp books
+//! for an archive or add its branding, before the file is returned, stored or
+//! delivered.
+//!
+//! With `POSTPROCESS_URL` set, each file is sent there (`POST`, multipart with
+//! a JSON `metadata` part and a `file` part, `Authorization: Bearer
+//! POSTPROCESS_API_KEY` when set). A `200` answer is the file to use instead,
+//! in the same format; `204` keeps the file as it is. Anything else fails the
+//! generation, so no file skips a step the operator asked for.
+
+use axum::body::Bytes;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, StatusCode};
+use serde_json::json;
+use std::time::Duration;
+use tracing::{error, info};
+use wp_mini_epub::AppError;
+
+use crate::config::PostProcessConfig;
+use crate::error::MyError;
+use crate::Epub;
+
+const POSTPROCESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `epub` as the hook returns it.
+pub async fn apply(
+    client: &Client,
+    config: &PostProcessConfig,
+    story_id: u64,
+    epub: Epub,
+) -> Result<Epub, MyError> {
+    match request(client, config, story_id, &epub).await {
+        Ok(Some(bytes)) => {
+            info!(size = bytes.len(), "Post-processing hook changed the file");
+            Ok(Epub { bytes, ..epub })
+        }
+        Ok(None) => Ok(epub),
+        Err(e) => {
+            error!(error = %e, "Post-processing hook failed");
+            Err(MyError::App(AppError::EpubGenerationFailed))
+        }
+    }
+}
+
+async fn request(
+    client: &Client,
+    config: &PostProcessConfig,
+    story_id: u64,
+    epub: &Epub,
+) -> Result<Option<Bytes>, String> {
+    let metadata = json!({
+        "storyId": story_id,
+        "title": epub.title,
+        "fileName": epub.file_name,
+        "contentType": epub.content_type,
+        "coverUrl": epub.cover_url,
+    });
+    let file = Part::stream(epub.bytes.clone())
+        .file_name(epub.file_name.clone())
+        .mime_str(epub.content_type)
+        .map_err(|e| e.to_string())?;
+    let form = Form::new()
+        .text("metadata", metadata.to_string())
+        .part("file", file);
+    let mut request = client
+        .post(&config.url)
+        .timeout(POSTPROCESS_TIMEOUT)
+        .multipart(form);
+    if let Some(key) = &config.api_key {
+        request = request.bearer_auth(key);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+    match response.status() {
+        StatusCode::NO_CONTENT => Ok(None),
+        StatusCode::OK => {
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| e.without_url().to_string())?;
+            if bytes.is_empty() {
+                return Err("Post-processing returned an empty file".into());
+            }
+            Ok(Some(bytes))
+        }
+        status => Err(format!("Post-processing responded with status {}", status)),
+    }
+}
@@ -0,0 +1,82 @@
+//! Extension settings synced through the server, so one install token gives
+//! the same configuration in every browser.
+//!
+//! The extension makes up a random install token (32 to 128 letters, digits,
+//! `-` or `_`) and sends it as `X-Install-Token`; `PUT /prefs` stores any
+//! JSON body up to `PREFS_MAX_KB` and `GET /prefs` returns it. The blob is
+//! kept in `crate::storage` under a hash of the token.
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::error::MyError;
+use crate::{encode_hex, AppState};
+
+const HEADER: &str = "x-install-token";
+const PREFIX: &str = "prefs/";
+const MIN_TOKEN_LENGTH: usize = 32;
+const MAX_TOKEN_LENGTH: usize = 128;
+
+/// The storage key for the request's install token.
+fn key(headers: &HeaderMap) -> Result<String, MyError> {
+    let token = headers
+        .get(HEADER)
+        .and_then(|token| token.to_str().ok())
+        .unwrap_or_default();
+    let valid = (MIN_TOKEN_LENGTH..=MAX_TOKEN_LENGTH).contains(&token.len())
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(MyError::Unauthorized(format!(
+            "Send an install token of {} to {} letters, digits, '-' or '_' as X-Install-Token",
+            MIN_TOKEN_LENGTH, MAX_TOKEN_LENGTH
+        )));
+    }
+    Ok(format!(
+        "{}{}",
+        PREFIX,
+        encode_hex(&Sha256::digest(token.as_bytes()))
+    ))
+}
+
+pub async fn get(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, MyError> {
+    let key = key(&headers)?;
+    let prefs = state.storage.get(&key).await.map_err(|e| {
+        error!(error = %e, "Could not read preferences");
+        MyError::Storage
+    })?;
+    let prefs = prefs
+        .ok_or_else(|| MyError::NotFound("No preferences saved for this install".to_string()))?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], prefs).into_response())
+}
+
+pub async fn put(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, MyError> {
+    state.maintenance.check().await?;
+    let key = key(&headers)?;
+    if body.len() > state.config.prefs_max_bytes {
+        return Err(MyError::InvalidRequest(format!(
+            "Preferences can be at most {} KB",
+            state.config.prefs_max_bytes / 1024
+        )));
+    }
+    if serde_json::from_slice::<Value>(&body).is_err() {
+        return Err(MyError::InvalidRequest(
+            "Preferences must be JSON".to_string(),
+        ));
+    }
+    state.storage.put(&key, body).await.map_err(|e| {
+        error!(error = %e, "Could not save preferences");
+        MyError::Storage
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
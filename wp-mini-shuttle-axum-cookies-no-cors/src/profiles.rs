@@ -0,0 +1,172 @@
+//! Saved default options per API key, so lightweight clients can send just a
+//! `storyId`.
+//!
+//! Keys are configured with `API_KEYS` and sent as `X-API-Key`. `PUT
+//! /profile` stores a JSON object of generation options (any field of a
+//! generation request except `storyId` and `cookies`), `GET` returns it and
+//! `DELETE` removes it. Generation requests with the key start from the
+//! profile; fields the request sets itself win. Profiles are kept in
+//! `crate::storage` under a hash of the key, never the key itself.
+
+use axum::extract::{FromRequest, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::config::Config;
+use crate::error::MyError;
+use crate::storage::Storage;
+use crate::{chapters, encode_hex, AppState, GenerateEpubRequest};
+
+pub const HEADER: &str = "x-api-key";
+const PREFIX: &str = "profiles/";
+const MAX_PROFILE_BYTES: usize = 16 * 1024;
+/// Fields that name one story or carry a session, not a preference.
+const REQUEST_ONLY: [&str; 2] = ["storyId", "cookies"];
+
+pub struct Profiles {
+    storage: Arc<dyn Storage>,
+    /// For `API_KEYS`, as the last reload left them.
+    config: Arc<Config>,
+}
+
+impl Profiles {
+    pub fn new(storage: Arc<dyn Storage>, config: Arc<Config>) -> Self {
+        Profiles { storage, config }
+    }
+
+    /// The hash of the request's API key; `Ok(None)` without one.
+    fn key(&self, headers: &HeaderMap) -> Result<Option<String>, MyError> {
+        let Some(key) = headers.get(HEADER) else {
+            return Ok(None);
+        };
+        let hash = hash(key.to_str().unwrap_or_default());
+        match self.config.keys().api_keys.contains(&hash) {
+            true => Ok(Some(hash)),
+            false => Err(MyError::Unauthorized("Unknown API key".to_string())),
+        }
+    }
+
+    /// Like `key`, for the routes that need one.
+    pub fn required_key(&self, headers: &HeaderMap) -> Result<String, MyError> {
+        self.key(headers)?
+            .ok_or_else(|| MyError::Unauthorized("Send an API key as X-API-Key".to_string()))
+    }
+
+    async fn load(&self, hash: &str) -> Result<Option<Map<String, Value>>, MyError> {
+        let stored = self
+            .storage
+            .get(&format!("{}{}", PREFIX, hash))
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Could not read profile");
+                MyError::Storage
+            })?;
+        Ok(stored.and_then(|bytes| serde_json::from_slice(&bytes).ok()))
+    }
+}
+
+pub fn hash(key: &str) -> String {
+    encode_hex(&Sha256::digest(key.as_bytes()))
+}
+
+/// A generation request with the defaults of its API key's profile filled in.
+pub struct Profiled(pub GenerateEpubRequest);
+
+impl FromRequest<AppState> for Profiled {
+    type Rejection = MyError;
+
+    async fn from_request(request: Request, state: &AppState) -> Result<Self, MyError> {
+        let key = state.profiles.key(request.headers())?;
+        let Json(mut body) = Json::<Map<String, Value>>::from_request(request, state)
+            .await
+            .map_err(|e| MyError::InvalidRequest(e.body_text()))?;
+        if let Some(hash) = key
+            && let Some(profile) = state.profiles.load(&hash).await?
+        {
+            for (field, value) in profile {
+                body.entry(field).or_insert(value);
+            }
+        }
+        serde_json::from_value(Value::Object(body))
+            .map(Profiled)
+            .map_err(|e| MyError::InvalidRequest(format!("Invalid request: {}", e)))
+    }
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Map<String, Value>>, MyError> {
+    let hash = state.profiles.required_key(&headers)?;
+    state
+        .profiles
+        .load(&hash)
+        .await?
+        .map(Json)
+        .ok_or_else(|| MyError::NotFound("No profile saved for this API key".to_string()))
+}
+
+pub async fn put(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(profile): Json<Map<String, Value>>,
+) -> Result<Json<Map<String, Value>>, MyError> {
+    state.maintenance.check().await?;
+    let hash = state.profiles.required_key(&headers)?;
+    if let Some(field) = REQUEST_ONLY
+        .iter()
+        .find(|field| profile.contains_key(**field))
+    {
+        return Err(MyError::InvalidRequest(format!(
+            "{} cannot be saved in a profile",
+            field
+        )));
+    }
+    let bytes = serde_json::to_vec(&profile).unwrap_or_default();
+    if bytes.len() > MAX_PROFILE_BYTES {
+        return Err(MyError::InvalidRequest(format!(
+            "Profiles can be at most {} KB",
+            MAX_PROFILE_BYTES / 1024
+        )));
+    }
+    // Only options a request would accept.
+    let mut request = profile.clone();
+    request.insert("storyId".into(), Value::from(0));
+    let request: GenerateEpubRequest = serde_json::from_value(Value::Object(request))
+        .map_err(|e| MyError::InvalidRequest(format!("Invalid profile: {}", e)))?;
+    chapters::validate(&request.chapters, state.config.allow_regex_replacements)
+        .map_err(MyError::InvalidRequest)?;
+
+    state
+        .profiles
+        .storage
+        .put(&format!("{}{}", PREFIX, hash), bytes.into())
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not save profile");
+            MyError::Storage
+        })?;
+    info!(fields = profile.len(), "Saved profile");
+    Ok(Json(profile))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, MyError> {
+    let hash = state.profiles.required_key(&headers)?;
+    state
+        .profiles
+        .storage
+        .delete(&format!("{}{}", PREFIX, hash))
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Could not delete profile");
+            MyError::Storage
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,210 @@
+//! Progress reporting for the synchronous endpoint without SSE or polling.
+//!
+//! A client that sends `Accept: multipart/x-mixed-replace` gets a multipart
+//! stream instead of the bare file: a JSON progress part every couple of
+//! seconds, and whenever the stage changes or a part of the story is
+//! downloaded, while the work runs, then a final part carrying the real
+//! response (the EPUB, a delivery receipt or an error) with its original
+//! headers. Progress parts have the `stage`, the `partsDone` out of `parts`
+//! once the story's parts are being downloaded, and the `elapsedSecs`. Each
+//! part replaces the previous one, so a `ReadableStream` reader only has to
+//! split on the boundary.
+
+use axum::body::{to_bytes, Body, Bytes};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Response;
+use futures_util::stream;
+use serde_json::json;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tracing::Instrument;
+
+use crate::pipeline::Checkpoint;
+
+const BOUNDARY: &str = "wattdownload-progress";
+const TICK: Duration = Duration::from_secs(2);
+
+/// Whether the client asked for a progress stream.
+pub fn requested(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("multipart/x-mixed-replace"))
+}
+
+/// Lets the work report which stage it is in. The default handle reports
+/// nowhere, for requests that did not ask for progress.
+#[derive(Clone, Default)]
+pub struct Progress {
+    report: Option<Arc<dyn Fn(&'static str) + Send + Sync>>,
+    /// Told the position of each part downloaded and the number of parts.
+    parts: Option<Arc<dyn Fn(usize, usize) + Send + Sync>>,
+    /// Where the parts already downloaded are kept, for work that may be
+    /// cut off and tried again (see `crate::job_budget`).
+    checkpoint: Option<Arc<Checkpoint>>,
+}
+
+impl Progress {
+    pub fn new(report: impl Fn(&'static str) + Send + Sync + 'static) -> Self {
+        Progress {
+            report: Some(Arc::new(report)),
+            parts: None,
+            checkpoint: None,
+        }
+    }
+
+    /// This handle, also reporting each part as it is downloaded; the book
+    /// is then made part by part (see `crate::drift`).
+    pub fn with_parts(self, report: impl Fn(usize, usize) + Send + Sync + 'static) -> Self {
+        Progress {
+            parts: Some(Arc::new(report)),
+            ..self
+        }
+    }
+
+    /// This handle, keeping the parts downloaded so a download tried again
+    /// resumes after them.
+    pub fn resumable(self) -> Self {
+        Progress {
+            checkpoint: Some(Arc::default()),
+            ..self
+        }
+    }
+
+    pub fn stage(&self, stage: &'static str) {
+        if let Some(report) = &self.report {
+            report(stage);
+        }
+    }
+
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_deref()
+    }
+
+    pub fn parts(&self) -> Option<&(dyn Fn(usize, usize) + Send + Sync)> {
+        self.parts.as_deref()
+    }
+
+    /// Whether the work needs to follow the download part by part.
+    pub fn by_parts(&self) -> bool {
+        self.parts.is_some() || self.checkpoint.is_some()
+    }
+}
+
+/// Runs `work` in the background and streams its progress, then its response.
+/// The work keeps running if the client goes away, so side effects such as
+/// deliveries and notifications still happen.
+pub fn respond<F, Fut>(work: F) -> Response
+where
+    F: FnOnce(Progress) -> Fut,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let (stage_tx, mut stage_rx) = watch::channel("downloading");
+    // Parts done and the number of parts, once the download reports them.
+    let (parts_tx, mut parts_rx) = watch::channel(None::<(usize, usize)>);
+    let (frames, frames_rx) = mpsc::channel::<Bytes>(8);
+    let progress = Progress::new(move |stage| {
+        stage_tx.send_replace(stage);
+    })
+    .with_parts(move |_, total| {
+        parts_tx.send_modify(|parts| {
+            let done = parts.map_or(0, |(done, _)| done);
+            *parts = Some((done + 1, total));
+        });
+    });
+    let work = work(progress);
+
+    tokio::spawn(
+        async move {
+            let started = Instant::now();
+            let mut ticks = tokio::time::interval(TICK);
+            let mut stages_open = true;
+            let mut parts_open = true;
+            tokio::pin!(work);
+
+            let response = loop {
+                tokio::select! {
+                    response = &mut work => break response,
+                    _ = ticks.tick() => {}
+                    changed = stage_rx.changed(), if stages_open => {
+                        stages_open = changed.is_ok();
+                    }
+                    changed = parts_rx.changed(), if parts_open => {
+                        parts_open = changed.is_ok();
+                    }
+                }
+                let mut frame = json!({
+                    "stage": *stage_rx.borrow_and_update(),
+                    "elapsedSecs": started.elapsed().as_secs(),
+                });
+                if let Some((done, total)) = *parts_rx.borrow_and_update() {
+                    frame["partsDone"] = done.into();
+                    frame["parts"] = total.into();
+                }
+                // A closed channel means the client left; keep working regardless.
+                let _ = frames.send(json_part(&frame)).await;
+            };
+
+            let status = response.status();
+            let (parts, body) = response.into_parts();
+            let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+
+            let summary = json!({
+                "stage": if status.is_success() { "done" } else { "failed" },
+                "status": status.as_u16(),
+                "elapsedSecs": started.elapsed().as_secs(),
+            });
+            let _ = frames.send(json_part(&summary)).await;
+            let _ = frames.send(part(&parts.headers, &body)).await;
+            let _ = frames
+                .send(Bytes::from(format!("--{}--\r\n", BOUNDARY)))
+                .await;
+        }
+        .in_current_span(),
+    );
+
+    let body = stream::unfold(frames_rx, |mut frames_rx| async move {
+        let frame = frames_rx.recv().await?;
+        Some((Ok::<_, Infallible>(frame), frames_rx))
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/x-mixed-replace; boundary={}", BOUNDARY),
+        )
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from_stream(body))
+        .unwrap()
+}
+
+fn json_part(value: &serde_json::Value) -> Bytes {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    part(&headers, value.to_string().as_bytes())
+}
+
+fn part(headers: &HeaderMap, body: &[u8]) -> Bytes {
+    let mut out = format!("--{}\r\n", BOUNDARY).into_bytes();
+    for (name, value) in headers {
+        if name == header::CONTENT_LENGTH {
+            continue;
+        }
+        out.extend_from_slice(name.as_str().as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(format!("content-length: {}\r\n\r\n", body.len()).as_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(b"\r\n");
+    Bytes::from(out)
+}
@@ -0,0 +1,106 @@
+//! Limits on how many stories one client may start, so a single caller of
+//! this public service cannot spend all of the goodwill Wattpad has for the
+//! address every request leaves from.
+//!
+//! Clients are counted twice: by address (see `abuse::client_key`, which
+//! takes the hop Shuttle's proxy appended rather than one the client can
+//! forge) and, when they send one, by their Wattpad session, which keeps a
+//! logged-in user from getting around the limit by switching networks. Only a hash of the
+//! session cookie is kept. Both are token buckets refilled over the minute,
+//! so short bursts up to the limit are fine. Checked in `crate::admit`, so
+//! every way of starting a download counts, including each story of a batch;
+//! over the limit the answer is `429` with `Retry-After`.
+//!
+//! The buckets live in each instance; behind a load balancer a client gets
+//! the limit once per instance it reaches.
+
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use sha2::{Digest, Sha256};
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use crate::config::RateLimitConfig;
+use crate::{encode_hex, Cookie};
+
+/// The session cookie Wattpad keeps its logins in.
+const SESSION_COOKIE: &str = "token";
+/// Clients whose buckets are full again are only forgotten once this many
+/// are tracked.
+const SWEEP_AT: usize = 4096;
+
+pub struct ClientLimits {
+    per_ip: Option<DefaultKeyedRateLimiter<String>>,
+    per_session: Option<DefaultKeyedRateLimiter<String>>,
+}
+
+impl ClientLimits {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let limiter = |per_minute: Option<u32>| {
+            let per_minute = NonZeroU32::new(per_minute?)?;
+            Some(RateLimiter::keyed(Quota::per_minute(per_minute)))
+        };
+        ClientLimits {
+            per_ip: limiter(config.per_ip_per_minute),
+            per_session: limiter(config.per_session_per_minute),
+        }
+    }
+
+    /// Counts a story started by `client` with `cookies`; returns how long to
+    /// back off when either is over its limit.
+    pub fn check(&self, client: &str, cookies: Option<&[Cookie]>) -> Result<(), Duration> {
+        if let Some(per_ip) = &self.per_ip {
+            check(per_ip, client.to_string())?;
+        }
+        if let Some(per_session) = &self.per_session
+            && let Some(session) = cookies.and_then(session_key)
+        {
+            check(per_session, session)?;
+        }
+        Ok(())
+    }
+}
+
+fn check(limiter: &DefaultKeyedRateLimiter<String>, key: String) -> Result<(), Duration> {
+    if limiter.len() >= SWEEP_AT {
+        limiter.retain_recent();
+    }
+    limiter.check_key(&key).map_err(|not_until| {
+        let wait = not_until.wait_time_from(DefaultClock::default().now());
+        // `Retry-After` is in whole seconds.
+        Duration::from_secs(wait.as_secs_f64().ceil() as u64)
+    })
+}
+
+fn session_key(cookies: &[Cookie]) -> Option<String> {
+    let session = cookies
+        .iter()
+        .find(|cookie| cookie.name == SESSION_COOKIE && cookie.domain.contains("wattpad.com"))?;
+    Some(encode_hex(&Sha256::digest(session.value.as_bytes())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abuse;
+    use axum::http::{HeaderMap, HeaderValue};
+
+    fn forwarded(hops: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_str(hops).unwrap());
+        headers
+    }
+
+    #[test]
+    fn forged_leftmost_hop_does_not_reset_the_bucket() {
+        let limits = ClientLimits::new(&RateLimitConfig {
+            per_ip_per_minute: Some(1),
+            per_session_per_minute: None,
+        });
+        let first = abuse::client_key(&forwarded("198.51.100.1, 203.0.113.7"));
+        assert!(limits.check(&first, None).is_ok());
+        let forged = abuse::client_key(&forwarded("198.51.100.2, 203.0.113.7"));
+        assert_eq!(first, forged);
+        assert!(limits.check(&forged, None).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! `format`: what the book is delivered as. Every generation makes an EPUB
+//! first, and with it the response cache, the chapter options and the
+//! labels; other formats are rendered from that EPUB at the end.
+
+pub mod pdf;
+pub mod text;
+
+use axum::body::Bytes;
+use tracing::{error, info};
+use wp_mini_epub::AppError;
+
+use crate::book::Book;
+use crate::chapters::ChapterOptions;
+use crate::config::Config;
+use crate::error::MyError;
+use crate::Epub;
+
+pub use api_types::Format;
+
+/// `content_type` as one of the formats' own, e.g. for a stored book; volume
+/// ZIPs are `application/zip` and anything unknown an EPUB.
+pub fn content_type(content_type: &str) -> &'static str {
+    if content_type == "application/zip" {
+        return "application/zip";
+    }
+    Format::ALL
+        .iter()
+        .map(|format| format.content_type())
+        .find(|known| *known == content_type)
+        .unwrap_or("application/epub+zip")
+}
+
+/// Volumes are separate EPUBs in a ZIP, which the other formats have no
+/// counterpart for.
+pub fn validate(format: Format, options: &ChapterOptions, config: &Config) -> Result<(), String> {
+    let splits = options.split_every_chapters.is_some() || options.split_every_words.is_some();
+    if format != Format::Epub && splits {
+        return Err(format!(
+            "splitEveryChapters and splitEveryWords cannot be combined with format {}",
+            format.extension()
+        ));
+    }
+    if format == Format::Azw3 && config.ebook_convert.is_none() {
+        return Err("This server cannot make AZW3 files".to_string());
+    }
+    Ok(())
+}
+
+/// `epub` as `format`.
+pub fn render(epub: Epub, format: Format) -> Result<Epub, MyError> {
+    let failed = |e: anyhow::Error| {
+        error!(error = %e, "Could not render book");
+        MyError::App(AppError::EpubGenerationFailed)
+    };
+    // Only the text is rendered, which the language does not change.
+    let read = |epub: &Epub| Book::read(epub.bytes.to_vec(), 0).map_err(failed);
+    let bytes = match format {
+        Format::Epub | Format::Warc | Format::Azw3 => return Ok(epub),
+        Format::Pdf => {
+            info!("Rendering PDF");
+            pdf::write(&read(&epub)?).map_err(failed)?
+        }
+        Format::Txt | Format::Md => {
+            info!("Rendering text");
+            text::write(&read(&epub)?, format == Format::Md)
+        }
+    };
+    let stem = epub.file_name.trim_end_matches(".epub");
+    Ok(Epub {
+        file_name: format!("{}.{}", stem, format.extension()),
+        content_type: format.content_type(),
+        bytes: Bytes::from(bytes),
+        ..epub
+    })
+}
@@ -0,0 +1,399 @@
+//! Books as paginated PDFs, for readers and printers that take no EPUB.
+//!
+//! Pages are A5 and set in Helvetica, one of the fonts every PDF reader has,
+//! so no font ships with the server. Those fonts only cover the characters of
+//! Windows-1252, though: text in other scripts comes out as `?`. Chapters
+//! start on a new page and make up the document outline. Their paragraphs
+//! are kept, but not their emphasis or images; a raster cover is the first
+//! page.
+
+use anyhow::Result;
+use std::io::Write;
+
+use crate::book::Book;
+use crate::images;
+use crate::tts::paragraphs_of;
+
+/// A5, in points.
+const PAGE_WIDTH: f32 = 420.0;
+const PAGE_HEIGHT: f32 = 595.0;
+const MARGIN: f32 = 50.0;
+const BODY_SIZE: f32 = 11.0;
+const HEADING_SIZE: f32 = 16.0;
+const PAGE_NUMBER_SIZE: f32 = 9.0;
+/// Line height as a multiple of the font size.
+const LEADING: f32 = 1.4;
+const PARAGRAPH_GAP: f32 = 4.0;
+/// Helvetica's advance widths for ` ` to `~`, in thousandths of the font size.
+const WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+#[derive(Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn name(self) -> &'static str {
+        match self {
+            Font::Regular => "/F1",
+            Font::Bold => "/F2",
+        }
+    }
+
+    /// Width of `text` at `size`. Bold is a little wider than regular.
+    fn width(self, text: &[u8], size: f32) -> f32 {
+        let units: u32 = text
+            .iter()
+            .map(|&byte| match byte {
+                b' '..=b'~' => WIDTHS[(byte - b' ') as usize] as u32,
+                // Dashes and the ellipsis.
+                0x85 | 0x97 => 1000,
+                0x91 | 0x92 => 222,
+                0x93 | 0x94 => 333,
+                _ => 556,
+            })
+            .sum();
+        let scale = match self {
+            Font::Regular => 1.0,
+            Font::Bold => 1.06,
+        };
+        units as f32 * size * scale / 1000.0
+    }
+}
+
+/// Pages of text as they are filled, and where the chapters start.
+struct Layout {
+    pages: Vec<Vec<u8>>,
+    /// Baseline of the next line, from the bottom of the page.
+    y: f32,
+    /// Chapter titles with the index of their first page.
+    outline: Vec<(String, usize)>,
+}
+
+impl Layout {
+    fn new_page(&mut self) {
+        self.pages.push(Vec::new());
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn paragraph(&mut self, text: &str, font: Font, size: f32, centered: bool) {
+        let text = encode(text);
+        for line in wrap(&text, font, size, PAGE_WIDTH - 2.0 * MARGIN) {
+            let height = size * LEADING;
+            if self.y - height < MARGIN {
+                self.new_page();
+            }
+            self.y -= height;
+            let x = match centered {
+                true => (PAGE_WIDTH - font.width(line, size)) / 2.0,
+                false => MARGIN,
+            };
+            let page = self.pages.last_mut().expect("a page was started");
+            show(page, font, size, x, self.y, line);
+        }
+        self.y -= PARAGRAPH_GAP;
+    }
+}
+
+/// The book as a PDF.
+pub fn write(book: &Book) -> Result<Vec<u8>> {
+    let mut layout = Layout {
+        pages: Vec::new(),
+        y: 0.0,
+        outline: Vec::new(),
+    };
+    layout.new_page();
+    layout.y = PAGE_HEIGHT * 2.0 / 3.0;
+    layout.paragraph(&book.title, Font::Bold, HEADING_SIZE * 1.5, true);
+    layout.paragraph(&book.creator, Font::Regular, HEADING_SIZE, true);
+    for chapter in &book.chapters {
+        layout.new_page();
+        layout
+            .outline
+            .push((chapter.title.clone(), layout.pages.len() - 1));
+        layout.paragraph(&chapter.title, Font::Bold, HEADING_SIZE, false);
+        layout.y -= HEADING_SIZE;
+        for paragraph in paragraphs_of(&chapter.body) {
+            let paragraph = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+            layout.paragraph(&paragraph, Font::Regular, BODY_SIZE, false);
+        }
+    }
+    // SVG covers would lose their text, as `crate::images` loads no fonts.
+    let cover = book
+        .cover
+        .as_ref()
+        .and_then(|(_, data)| images::to_jpeg(data).ok());
+
+    let mut pdf = Pdf::default();
+    let catalog = pdf.reserve();
+    let pages_id = pdf.reserve();
+    let outlines_id = pdf.reserve();
+    let regular = pdf.add(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+    let bold = pdf.add(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+    let info = pdf.add(
+        format!(
+            "<< /Title {} /Author {} /Producer (WattDownload) >>",
+            text_string(&book.title),
+            text_string(&book.creator)
+        )
+        .into_bytes(),
+    );
+    let fonts = format!("/Font << /F1 {} 0 R /F2 {} 0 R >>", regular, bold);
+
+    let mut page_ids = Vec::new();
+    if let Some((jpeg, width, height)) = cover {
+        let image = pdf.add_stream(
+            &format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode",
+                width, height
+            ),
+            &jpeg,
+        );
+        let scale = (PAGE_WIDTH / width as f32).min(PAGE_HEIGHT / height as f32);
+        let (drawn_width, drawn_height) = (width as f32 * scale, height as f32 * scale);
+        let content = format!(
+            "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im1 Do Q",
+            drawn_width,
+            drawn_height,
+            (PAGE_WIDTH - drawn_width) / 2.0,
+            (PAGE_HEIGHT - drawn_height) / 2.0
+        );
+        let content = pdf.add_stream("", content.as_bytes());
+        page_ids.push(pdf.add(
+            format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << /Im1 {} 0 R >> >> /Contents {} 0 R >>",
+                pages_id, PAGE_WIDTH, PAGE_HEIGHT, image, content
+            )
+            .into_bytes(),
+        ));
+    }
+    let first_text_page = page_ids.len();
+    for (i, mut content) in layout.pages.into_iter().enumerate() {
+        if i > 0 {
+            let number = (i + 1).to_string();
+            let x = (PAGE_WIDTH - Font::Regular.width(number.as_bytes(), PAGE_NUMBER_SIZE)) / 2.0;
+            show(
+                &mut content,
+                Font::Regular,
+                PAGE_NUMBER_SIZE,
+                x,
+                MARGIN / 2.0,
+                number.as_bytes(),
+            );
+        }
+        let content = pdf.add_stream("", &content);
+        page_ids.push(pdf.add(
+            format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << {} >> /Contents {} 0 R >>",
+                pages_id, PAGE_WIDTH, PAGE_HEIGHT, fonts, content
+            )
+            .into_bytes(),
+        ));
+    }
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    pdf.set(
+        pages_id,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            page_ids.len()
+        )
+        .into_bytes(),
+    );
+
+    let items: Vec<usize> = layout.outline.iter().map(|_| pdf.reserve()).collect();
+    for (i, (title, page)) in layout.outline.iter().enumerate() {
+        let mut item = format!(
+            "<< /Title {} /Parent {} 0 R /Dest [{} 0 R /Fit]",
+            text_string(title),
+            outlines_id,
+            page_ids[first_text_page + page]
+        );
+        if i > 0 {
+            item.push_str(&format!(" /Prev {} 0 R", items[i - 1]));
+        }
+        if let Some(next) = items.get(i + 1) {
+            item.push_str(&format!(" /Next {} 0 R", next));
+        }
+        item.push_str(" >>");
+        pdf.set(items[i], item.into_bytes());
+    }
+    let outlines = match (items.first(), items.last()) {
+        (Some(first), Some(last)) => format!(
+            "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+            first,
+            last,
+            items.len()
+        ),
+        _ => "<< /Type /Outlines /Count 0 >>".to_string(),
+    };
+    pdf.set(outlines_id, outlines.into_bytes());
+    pdf.set(
+        catalog,
+        format!(
+            "<< /Type /Catalog /Pages {} 0 R /Outlines {} 0 R /PageMode /UseOutlines >>",
+            pages_id, outlines_id
+        )
+        .into_bytes(),
+    );
+    pdf.finish(catalog, info)
+}
+
+/// Objects by number, starting from 1.
+#[derive(Default)]
+struct Pdf {
+    objects: Vec<Vec<u8>>,
+}
+
+impl Pdf {
+    /// A number for an object that is set later.
+    fn reserve(&mut self) -> usize {
+        self.objects.push(Vec::new());
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, object: Vec<u8>) {
+        self.objects[id - 1] = object;
+    }
+
+    fn add(&mut self, object: Vec<u8>) -> usize {
+        self.objects.push(object);
+        self.objects.len()
+    }
+
+    /// A stream with `data`; `entries` go into its dictionary.
+    fn add_stream(&mut self, entries: &str, data: &[u8]) -> usize {
+        let mut object = format!("<< {} /Length {} >>\nstream\n", entries, data.len()).into_bytes();
+        object.extend_from_slice(data);
+        object.extend_from_slice(b"\nendstream");
+        self.add(object)
+    }
+
+    fn finish(self, root: usize, info: usize) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        // The binary comment marks the file as binary for transfer tools.
+        out.write_all(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n")?;
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, object) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            writeln!(out, "{} 0 obj", i + 1)?;
+            out.write_all(object)?;
+            out.write_all(b"\nendobj\n")?;
+        }
+        let xref = out.len();
+        write!(
+            out,
+            "xref\n0 {}\n0000000000 65535 f \n",
+            self.objects.len() + 1
+        )?;
+        for offset in offsets {
+            writeln!(out, "{:010} 00000 n ", offset)?;
+        }
+        write!(
+            out,
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.objects.len() + 1,
+            root,
+            info,
+            xref
+        )?;
+        Ok(out)
+    }
+}
+
+/// Draws `text` with its baseline starting at `x`, `y`.
+fn show(content: &mut Vec<u8>, font: Font, size: f32, x: f32, y: f32, text: &[u8]) {
+    let _ = write!(
+        content,
+        "BT {} {} Tf {:.2} {:.2} Td (",
+        font.name(),
+        size,
+        x,
+        y
+    );
+    for &byte in text {
+        if matches!(byte, b'(' | b')' | b'\\') {
+            content.push(b'\\');
+        }
+        content.push(byte);
+    }
+    content.extend_from_slice(b") Tj ET\n");
+}
+
+/// Lines of `text` no wider than `width`, broken between words or, for words
+/// too long for a line, within them.
+fn wrap(text: &[u8], font: Font, size: f32, width: f32) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for word in text.split(|&byte| byte == b' ') {
+        let word_start = word.as_ptr() as usize - text.as_ptr() as usize;
+        let word_end = word_start + word.len();
+        if font.width(&text[start..word_end], size) <= width {
+            end = word_end;
+            continue;
+        }
+        if end > start {
+            lines.push(&text[start..end]);
+        }
+        start = word_start;
+        end = word_end;
+        while font.width(&text[start..end], size) > width && end - start > 1 {
+            let mut split = start + 1;
+            while split < end && font.width(&text[start..split + 1], size) <= width {
+                split += 1;
+            }
+            lines.push(&text[start..split]);
+            start = split;
+        }
+    }
+    if end > start {
+        lines.push(&text[start..end]);
+    }
+    lines
+}
+
+/// `text` in Windows-1252, which the base fonts use; `?` for what it lacks.
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' | '\u{A0}'..='\u{FF}' => c as u8,
+            '\u{20AC}' => 0x80,
+            '\u{201A}' => 0x82,
+            '\u{201E}' => 0x84,
+            '\u{2026}' => 0x85,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201C}' => 0x93,
+            '\u{201D}' => 0x94,
+            '\u{2022}' => 0x95,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            '\u{2122}' => 0x99,
+            c if c.is_whitespace() => b' ',
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// `text` as a PDF text string, which may hold any character (in UTF-16).
+fn text_string(text: &str) -> String {
+    let units: String = text
+        .encode_utf16()
+        .map(|unit| format!("{:04X}", unit))
+        .collect();
+    format!("<FEFF{}>", units)
+}
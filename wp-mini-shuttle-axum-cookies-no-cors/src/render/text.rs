@@ -0,0 +1,133 @@
+//! Books as one plain-text or Markdown file, for piping into text-to-speech
+//! tools and editors.
+//!
+//! The title and author come first, then each chapter under its title.
+//! Paragraphs are separated by a blank line and scene breaks (`<hr>`) become
+//! `* * *`. Markdown keeps italics and bold, with the text's own Markdown
+//! characters escaped; plain text drops them. Images are left out.
+
+use crate::book::Book;
+use crate::tts::{decode_entities, BLOCKS};
+
+const SCENE_BREAK: &str = "* * *";
+
+/// The book as plain text or, with `markdown`, as Markdown.
+pub fn write(book: &Book, markdown: bool) -> Vec<u8> {
+    let mut out = String::new();
+    match markdown {
+        true => {
+            out.push_str(&format!("# {}\n\n", escape(&book.title)));
+            if !book.creator.is_empty() {
+                out.push_str(&format!("*{}*\n\n", escape(&book.creator)));
+            }
+        }
+        false => {
+            out.push_str(&format!("{}\n", book.title));
+            if !book.creator.is_empty() {
+                out.push_str(&format!("{}\n", book.creator));
+            }
+            out.push('\n');
+        }
+    }
+    for chapter in &book.chapters {
+        match markdown {
+            true => out.push_str(&format!("\n## {}\n\n", escape(&chapter.title))),
+            false => out.push_str(&format!("\n\n{}\n\n", chapter.title)),
+        }
+        for paragraph in paragraphs(&chapter.body, markdown) {
+            out.push_str(&paragraph);
+            out.push_str("\n\n");
+        }
+    }
+    let mut out = out.trim().to_string();
+    out.push('\n');
+    out.into_bytes()
+}
+
+/// The paragraphs of `html`, with emphasis as Markdown if `markdown` is set.
+fn paragraphs(html: &str, markdown: bool) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    // Markers of emphasis opened but not followed by text yet. Markdown takes
+    // no space just inside a marker, so they go after the text's leading space
+    // and closing ones before its trailing space.
+    let mut opened = String::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        let text = decode_entities(&rest[..text_end]);
+        let text = match markdown {
+            true => escape(&text),
+            false => text,
+        };
+        let words = text.trim_start();
+        if opened.is_empty() || words.is_empty() {
+            current.push_str(&text);
+        } else {
+            current.push_str(&text[..text.len() - words.len()]);
+            current.push_str(&opened);
+            current.push_str(words);
+            opened.clear();
+        }
+
+        let tail = &rest[text_end..];
+        let tag_end = tail.find('>').map_or(tail.len(), |end| end + 1);
+        let tag = &tail[..tag_end];
+        let closing = tag.starts_with("</");
+        let name = tag
+            .trim_start_matches(['<', '/'])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let marker = match name.as_str() {
+            "i" | "em" if markdown => Some("*"),
+            "b" | "strong" if markdown => Some("**"),
+            _ => None,
+        };
+        match marker {
+            Some(marker) if !closing => opened.push_str(marker),
+            // Emphasis around nothing.
+            Some(marker) if opened.ends_with(marker) => {
+                opened.truncate(opened.len() - marker.len());
+            }
+            Some(marker) => {
+                let space = current.split_off(current.trim_end().len());
+                current.push_str(marker);
+                current.push_str(&space);
+            }
+            None if name == "hr" => {
+                end_paragraph(&mut paragraphs, &mut current);
+                paragraphs.push(SCENE_BREAK.to_string());
+            }
+            None if BLOCKS.contains(&name.as_str()) && (closing || name == "br") => {
+                end_paragraph(&mut paragraphs, &mut current);
+                opened.clear();
+            }
+            None => {}
+        }
+        rest = &tail[tag_end..];
+    }
+    end_paragraph(&mut paragraphs, &mut current);
+    paragraphs
+}
+
+fn end_paragraph(paragraphs: &mut Vec<String>, current: &mut String) {
+    let paragraph = current.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !paragraph.is_empty() {
+        paragraphs.push(paragraph);
+    }
+    current.clear();
+}
+
+/// Escapes the characters Markdown would take for markup.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,116 @@
+//! User-supplied find/replace rules for chapter text, e.g. to fix a name the
+//! author misspells throughout.
+//!
+//! Rules only touch text, never markup, and a `'` or `"` in `find` also
+//! matches its curly and entity forms, so rules work whichever quotes the
+//! chapter uses. `find` is literal unless the rule sets `regex` and the server
+//! allows it (`ALLOW_REGEX_REPLACEMENTS`).
+
+use regex::{NoExpand, Regex, RegexBuilder};
+
+use crate::book::escape;
+
+pub use api_types::Rule;
+
+const MAX_RULES: usize = 20;
+const MAX_FIND_LENGTH: usize = 200;
+const MAX_REPLACE_LENGTH: usize = 1_000;
+/// Compiled size allowed per rule.
+const SIZE_LIMIT: usize = 1 << 20;
+
+pub struct Compiled {
+    pattern: Regex,
+    /// Escaped for HTML text.
+    replace: String,
+    expand: bool,
+}
+
+pub fn compile(rules: &[Rule], allow_regex: bool) -> Result<Vec<Compiled>, String> {
+    if rules.len() > MAX_RULES {
+        return Err(format!("replacements can have at most {} rules", MAX_RULES));
+    }
+    rules
+        .iter()
+        .map(|rule| {
+            if rule.find.is_empty() || rule.find.len() > MAX_FIND_LENGTH {
+                return Err(format!(
+                    "replacements need a find of 1 to {} characters",
+                    MAX_FIND_LENGTH
+                ));
+            }
+            if rule.replace.len() > MAX_REPLACE_LENGTH {
+                return Err(format!(
+                    "replacements can replace with at most {} characters",
+                    MAX_REPLACE_LENGTH
+                ));
+            }
+            if rule.regex && !allow_regex {
+                return Err("Regex replacements are not enabled on this server".into());
+            }
+            let pattern = match rule.regex {
+                true => rule.find.clone(),
+                false => quote_tolerant(&rule.find),
+            };
+            let pattern = match rule.whole_word {
+                true => format!(r"\b(?:{})\b", pattern),
+                false => pattern,
+            };
+            let pattern = RegexBuilder::new(&pattern)
+                .case_insensitive(rule.case_insensitive)
+                .size_limit(SIZE_LIMIT)
+                .build()
+                .map_err(|e| format!("Invalid replacement pattern: {}", e))?;
+            Ok(Compiled {
+                pattern,
+                replace: escape(&rule.replace),
+                expand: rule.regex,
+            })
+        })
+        .collect()
+}
+
+/// A pattern matching `find` literally, with any kind of quote for each quote.
+fn quote_tolerant(find: &str) -> String {
+    find.chars()
+        .map(|c| match c {
+            '\'' | '\u{2018}' | '\u{2019}' => {
+                "(?:'|\u{2018}|\u{2019}|&#39;|&#x27;|&apos;)".to_string()
+            }
+            '"' | '\u{201C}' | '\u{201D}' => {
+                "(?:\"|\u{201C}|\u{201D}|&quot;|&#34;|&#x22;)".to_string()
+            }
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            c => regex::escape(c.encode_utf8(&mut [0; 4])),
+        })
+        .collect()
+}
+
+/// Applies `rules` to the text between the tags of `html`.
+pub fn apply(html: &str, rules: &[Compiled]) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while !rest.is_empty() {
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        let (text, tail) = rest.split_at(text_end);
+        out.push_str(&replace_text(text, rules));
+        let tag_end = tail.find('>').map_or(tail.len(), |end| end + 1);
+        let (tag, tail) = tail.split_at(tag_end);
+        out.push_str(tag);
+        rest = tail;
+    }
+    out
+}
+
+fn replace_text(text: &str, rules: &[Compiled]) -> String {
+    let mut text = text.to_string();
+    for rule in rules {
+        text = match rule.expand {
+            true => rule.pattern.replace_all(&text, rule.replace.as_str()),
+            false => rule.pattern.replace_all(&text, NoExpand(&rule.replace)),
+        }
+        .into_owned();
+    }
+    text
+}
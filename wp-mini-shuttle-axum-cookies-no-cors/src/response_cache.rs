@@ -0,0 +1,164 @@
+//! Reuse of anonymous EPUBs for `RESPONSE_CACHE_SECS`.
+//!
+//! Anonymous requests for the same story and image setting produce the same
+//! book, so the first one is cached in `crate::shared` and concurrent ones wait
+//! for it instead of downloading the story again (singleflight). With Redis
+//! configured this holds across instances too; without it the cache stays
+//! within `RESPONSE_CACHE_MAX_MB`. The EPUB is what is cached, so requests
+//! for other formats of the same book reuse it and are only rendered again.
+//! Requests with cookies or chapter options are never cached.
+//!
+//! Downloads say how they were served in a `Cache-Status` header (RFC 9211):
+//! `WattDownload; hit`, `WattDownload; fwd=miss`, or `WattDownload;
+//! fwd=bypass` for books the cache does not take.
+
+use axum::body::Bytes;
+use axum::http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::error::MyError;
+use crate::shared::Shared;
+use crate::{AppState, Epub};
+
+/// How long one generation may hold the lock before others stop waiting for it.
+const LOCK_TTL: Duration = Duration::from_secs(5 * 60);
+/// How long a request waits for someone else's generation of the same book.
+const MAX_WAIT: Duration = Duration::from_secs(2 * 60);
+const POLL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Bypass,
+}
+
+impl CacheStatus {
+    pub fn header(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            CacheStatus::Hit => "WattDownload; hit",
+            CacheStatus::Miss => "WattDownload; fwd=miss",
+            CacheStatus::Bypass => "WattDownload; fwd=bypass",
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    title: String,
+    file_name: String,
+    cover_url: Option<String>,
+}
+
+async fn lookup(shared: &dyn Shared, key: &str) -> Option<Epub> {
+    let fetch = async {
+        let Some(meta) = shared.get(&format!("{}:meta", key)).await? else {
+            return Ok(None);
+        };
+        let Some(bytes) = shared.get(key).await? else {
+            return Ok(None);
+        };
+        let meta: Meta = serde_json::from_slice(&meta)?;
+        Ok::<_, anyhow::Error>(Some(Epub {
+            title: meta.title,
+            file_name: meta.file_name,
+            content_type: "application/epub+zip",
+            skipped: Vec::new(),
+            cover_url: meta.cover_url,
+            bytes,
+            cache: CacheStatus::Hit,
+        }))
+    };
+    fetch.await.unwrap_or_else(|e| {
+        warn!(error = %e, "Response cache lookup failed");
+        None
+    })
+}
+
+async fn store(shared: &dyn Shared, key: &str, epub: &Epub, ttl: Duration) {
+    let meta = serde_json::to_vec(&Meta {
+        title: epub.title.clone(),
+        file_name: epub.file_name.clone(),
+        cover_url: epub.cover_url.clone(),
+    })
+    .expect("metadata serializes");
+    // The book goes in first so a reader that finds the metadata finds it too.
+    let result = async {
+        shared.set(key, epub.bytes.clone(), ttl).await?;
+        shared
+            .set(&format!("{}:meta", key), Bytes::from(meta), ttl)
+            .await
+    };
+    if let Err(e) = result.await {
+        warn!(error = %e, "Could not cache generated EPUB");
+    }
+}
+
+/// Returns the cached EPUB for the story, or runs `generate` (at most once
+/// across instances at a time) and caches its result.
+pub async fn get_or_generate<F, Fut>(
+    state: &AppState,
+    story_id: u64,
+    embed_images: bool,
+    generate: F,
+) -> Result<Epub, MyError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Epub, MyError>>,
+{
+    let Some(ttl) = state.config.response_cache_ttl else {
+        return generate().await;
+    };
+    let shared = &*state.shared;
+    let key = format!("epub:{}:{}", story_id, u8::from(embed_images));
+    if let Some(epub) = lookup(shared, &key).await {
+        info!("Serving EPUB from the response cache");
+        return Ok(epub);
+    }
+
+    let lock = format!("lock:{}", key);
+    let owner = Bytes::from(Uuid::new_v4().simple().to_string());
+    let deadline = Instant::now() + MAX_WAIT;
+    loop {
+        match shared.set_if_absent(&lock, owner.clone(), LOCK_TTL).await {
+            Ok(true) => break,
+            Ok(false) if Instant::now() < deadline => {
+                tokio::time::sleep(POLL).await;
+                if let Some(epub) = lookup(shared, &key).await {
+                    info!("Serving EPUB generated by a concurrent request");
+                    return Ok(epub);
+                }
+            }
+            Ok(false) => {
+                warn!("Gave up waiting for a concurrent generation");
+                return generate().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "Could not take the generation lock");
+                return generate().await;
+            }
+        }
+    }
+    // Someone may have finished between the first lookup and taking the lock.
+    if let Some(epub) = lookup(shared, &key).await {
+        let _ = shared.delete_if(&lock, owner).await;
+        return Ok(epub);
+    }
+
+    let result = generate().await.map(|epub| Epub {
+        cache: CacheStatus::Miss,
+        ..epub
+    });
+    if let Ok(epub) = &result {
+        store(shared, &key, epub, ttl).await;
+    }
+    if let Err(e) = shared.delete_if(&lock, owner).await {
+        warn!(error = %e, "Could not release the generation lock");
+    }
+    result
+}
@@ -0,0 +1,151 @@
+//! A way around Wattpad's API for when it refuses to serve a story: the
+//! metadata and chapters are read from the public story pages instead, as a
+//! browser sees them, and the book is made by `crate::pipeline`. Off unless
+//! the operator sets `HTML_FALLBACK=true`; `crate::drift` tries it last.
+//!
+//! The story page carries its metadata as the JSON Wattpad's own scripts
+//! start from (`window.prefetched`), in the shape the API uses. Without it
+//! the Open Graph tags give the title, description and cover, and the links
+//! to the parts give their order. Part pages show the text in `<pre>` blocks,
+//! a page of paragraphs at a time. Only what a logged-out reader sees can be
+//! read this way, unless the request came with cookies.
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::sync::LazyLock;
+use tracing::info;
+use wp_mini::types::StoryResponse;
+use wp_mini_epub::{AppError, StoryDownload};
+
+use crate::{http_client, pipeline};
+
+const BASE_URL: &str = "https://www.wattpad.com";
+/// Pages read per part at most, in case a part page keeps answering.
+const MAX_PAGES: usize = 100;
+
+static META: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<meta\s+property="og:(title|description|image)"\s+content="([^"]*)""#)
+        .expect("the meta pattern is valid")
+});
+static PART_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"href="(?:https://www\.wattpad\.com)?/(\d+)-[^"]*""#)
+        .expect("the part link pattern is valid")
+});
+static PRE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<pre[^>]*>(.*?)</pre>").expect("the text pattern is valid"));
+
+/// Same contract as `wp_mini_epub::download_story_to_memory`.
+pub async fn download_story_to_memory(
+    client: &Client,
+    story_id: u64,
+    embed_images: bool,
+    concurrent_requests: usize,
+) -> Result<StoryDownload<Vec<u8>>> {
+    let page = page(client, &format!("{}/story/{}", BASE_URL, story_id))
+        .await
+        .map_err(|_| AppError::MetadataFetchFailed)?;
+    let story = story(&page, story_id).ok_or(AppError::MetadataFetchFailed)?;
+    info!("Read story metadata from its page");
+    pipeline::assemble(
+        client,
+        story_id,
+        story,
+        embed_images,
+        concurrent_requests,
+        |part_id| part_text(client, part_id),
+        pipeline::Hooks::default(),
+    )
+    .await
+}
+
+async fn page(client: &Client, url: &str) -> Result<String> {
+    let response = http_client::send(client.get(url)).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("{} answered {}", url, response.status()));
+    }
+    Ok(response.text().await?)
+}
+
+/// The story's metadata from its page.
+fn story(page: &str, story_id: u64) -> Option<StoryResponse> {
+    let story = prefetched(page, story_id).unwrap_or_else(|| open_graph(page));
+    let story: StoryResponse = serde_json::from_value(story).ok()?;
+    story
+        .parts
+        .as_ref()
+        .is_some_and(|parts| !parts.is_empty())
+        .then_some(story)
+}
+
+/// The story object in `window.prefetched`, keyed `story.<id>.metadata`.
+fn prefetched(page: &str, story_id: u64) -> Option<Value> {
+    let start = page.find("window.prefetched")?;
+    let json = &page[start..][page[start..].find('{')?..];
+    // Only the object itself; the script goes on after it.
+    let prefetched: Value = serde_json::Deserializer::from_str(json)
+        .into_iter()
+        .next()?
+        .ok()?;
+    prefetched
+        .get(format!("story.{}.metadata", story_id))?
+        .get("data")
+        .cloned()
+}
+
+/// What a link preview would show, and the parts linked from the page.
+fn open_graph(page: &str) -> Value {
+    let mut story = json!({});
+    for captures in META.captures_iter(page) {
+        let field = match &captures[1] {
+            "title" => "title",
+            "description" => "description",
+            _ => "cover",
+        };
+        story[field] = Value::from(decode(&captures[2]));
+    }
+    let mut parts: Vec<Value> = Vec::new();
+    for captures in PART_LINK.captures_iter(page) {
+        let Ok(id) = captures[1].parse::<u64>() else {
+            continue;
+        };
+        if !parts.iter().any(|part| part["id"] == id) {
+            parts.push(json!({ "id": id }));
+        }
+    }
+    story["parts"] = Value::from(parts);
+    story
+}
+
+/// The text of `part_id`, read page by page until a page adds nothing.
+async fn part_text(client: &Client, part_id: u64) -> Result<String> {
+    let mut text = String::new();
+    let mut last = String::new();
+    for number in 1..=MAX_PAGES {
+        let url = format!("{}/{}/page/{}", BASE_URL, part_id, number);
+        let page = page(client, &url).await?;
+        let blocks: String = PRE
+            .captures_iter(&page)
+            .map(|captures| captures[1].to_string())
+            .collect();
+        // Pages past the end repeat the last one.
+        if blocks.trim().is_empty() || blocks == last {
+            break;
+        }
+        text.push_str(&blocks);
+        last = blocks;
+    }
+    if text.is_empty() {
+        return Err(AppError::DownloadFailed.into());
+    }
+    Ok(text)
+}
+
+fn decode(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
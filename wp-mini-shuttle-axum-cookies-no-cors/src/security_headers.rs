@@ -0,0 +1,60 @@
+//! Security headers added to every response, with a policy per route group.
+//!
+//! Headers a handler sets itself are left alone, so a group policy applied
+//! inside the global one wins for the headers it names.
+
+use axum::extract::State;
+use axum::http::{header, HeaderName, HeaderValue};
+use axum::response::Response;
+use std::sync::Arc;
+
+use crate::config::SecurityHeadersConfig;
+
+#[derive(Clone)]
+pub struct Policy(Arc<Vec<(HeaderName, HeaderValue)>>);
+
+impl Policy {
+    /// For JSON endpoints: nothing here is meant to be rendered or framed.
+    pub fn api(config: &SecurityHeadersConfig) -> Self {
+        let mut headers = vec![
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+            (
+                header::REFERRER_POLICY,
+                HeaderValue::from_static("no-referrer"),
+            ),
+            (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+            (
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static("default-src 'none'; frame-ancestors 'none'"),
+            ),
+        ];
+        if let Some(max_age) = config.hsts_max_age {
+            headers.push((
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&format!("max-age={}", max_age)).unwrap(),
+            ));
+        }
+        Policy(Arc::new(headers))
+    }
+
+    /// For generated files: keeps other sites from embedding them directly.
+    pub fn download(config: &SecurityHeadersConfig) -> Self {
+        let corp = HeaderValue::from_str(&config.download_resource_policy)
+            .unwrap_or(HeaderValue::from_static("same-origin"));
+        Policy(Arc::new(vec![(
+            HeaderName::from_static("cross-origin-resource-policy"),
+            corp,
+        )]))
+    }
+}
+
+pub async fn apply(State(policy): State<Policy>, mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    for (name, value) in policy.0.iter() {
+        headers.entry(name).or_insert_with(|| value.clone());
+    }
+    response
+}
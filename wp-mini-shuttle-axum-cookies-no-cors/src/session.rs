@@ -0,0 +1,79 @@
+//! `POST /validate-session`: whether the Wattpad cookies a download would be
+//! sent with are signed in, and as whom, so the extension can show who it
+//! is downloading as instead of finding out from a `401` halfway through.
+//!
+//! Takes the `cookies` of `/generate-epub` and asks Wattpad for the current
+//! user with an authenticated client made from them, as a download would.
+
+use axum::extract::State;
+use axum::Json;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, info, instrument};
+use wp_mini_epub::AppError;
+
+use crate::error::{map_upstream_error, MyError};
+use crate::{http_client, session_client, AppState, Cookie};
+
+const CURRENT_USER_URL: &str = "https://www.wattpad.com/api/v3/internal/current_user";
+
+#[derive(Deserialize)]
+pub struct SessionRequest {
+    cookies: Vec<Cookie>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+}
+
+#[instrument(skip_all)]
+pub async fn validate(
+    State(state): State<AppState>,
+    Json(request): Json<SessionRequest>,
+) -> Result<Json<SessionResponse>, MyError> {
+    let signed_out = Json(SessionResponse {
+        valid: false,
+        username: None,
+    });
+    if !request
+        .cookies
+        .iter()
+        .any(|cookie| cookie.domain.contains("wattpad.com"))
+    {
+        return Ok(signed_out);
+    }
+    let client = session_client(&state, &request.cookies)?;
+    let response = http_client::send(
+        client
+            .get(CURRENT_USER_URL)
+            .query(&[("fields", "username")]),
+    )
+    .await
+    .map_err(map_upstream_error)?;
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => return Ok(signed_out),
+        status if !status.is_success() => {
+            error!(%status, "Wattpad did not say who is signed in");
+            return Err(MyError::App(AppError::MetadataFetchFailed));
+        }
+        _ => {}
+    }
+    let user: Value = response.json().await.map_err(|e| {
+        error!(error = %e, "Could not read the current Wattpad user");
+        MyError::App(AppError::MetadataFetchFailed)
+    })?;
+    let username = user["username"]
+        .as_str()
+        .filter(|username| !username.is_empty())
+        .map(str::to_string);
+    info!(valid = username.is_some(), "Validated Wattpad session");
+    Ok(Json(SessionResponse {
+        valid: username.is_some(),
+        username,
+    }))
+}
@@ -0,0 +1,257 @@
+//! Shadow runs of the experimental pipeline (`crate::pipeline`).
+//!
+//! An operator sets a percentage with `PUT /admin/shadow`; that share of
+//! successful generations is repeated in the background with the other of
+//! the library and the pipeline, and the two EPUBs are compared by hash, size
+//! and whether they open with the same chapters: books the library made are
+//! made again with the pipeline, and books the pipeline made from the start
+//! (see `crate::drift`) with the library. Books read from the story's pages
+//! are not repeated. Differences are logged and listed by `GET /admin/shadow`;
+//! the user only ever gets the first EPUB. The setting is kept in memory and
+//! starts at 0 after a restart.
+
+use axum::extract::State;
+use axum::Json;
+use iepub::prelude::read_from_vec;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::admin::Admin;
+use crate::drift::Engine;
+use crate::error::MyError;
+use crate::{pipeline, unix_now, AppState, CONCURRENT_CHAPTER_REQUESTS};
+
+/// Shadow runs in flight at once; further samples are skipped.
+const MAX_IN_FLIGHT: usize = 2;
+/// Size difference, relative to the library's EPUB, tolerated as a match.
+const SIZE_TOLERANCE: f64 = 0.05;
+const RECENT_CAPACITY: usize = 50;
+
+#[derive(Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadowCounts {
+    runs: u64,
+    matches: u64,
+    discrepancies: u64,
+    /// The shadow run failed where the generation succeeded.
+    failures: u64,
+    /// Samples dropped because enough shadow runs were already going.
+    skipped: u64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Discrepancy {
+    /// Unix seconds.
+    at: u64,
+    story_id: u64,
+    problems: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Shadow {
+    percent: AtomicU8,
+    in_flight: AtomicUsize,
+    counts: Mutex<ShadowCounts>,
+    recent: Mutex<VecDeque<Discrepancy>>,
+}
+
+/// What gets compared of each EPUB.
+struct Summary {
+    hash: [u8; 32],
+    size: usize,
+    chapters: Option<Vec<String>>,
+}
+
+impl Summary {
+    fn of(bytes: &[u8]) -> Self {
+        Summary {
+            hash: Sha256::digest(bytes).into(),
+            size: bytes.len(),
+            chapters: read_from_vec(bytes.to_vec()).ok().map(|book| {
+                book.chapters()
+                    .map(|chapter| chapter.title().to_string())
+                    .collect()
+            }),
+        }
+    }
+}
+
+impl Shadow {
+    fn sampled(&self) -> bool {
+        let percent = self.percent.load(Ordering::Relaxed);
+        percent > 0 && (Uuid::new_v4().as_u128() % 100) < u128::from(percent)
+    }
+
+    /// Possibly repeats a generation that produced `primary` with `made_by`
+    /// with the other engine, in the background.
+    pub fn maybe_run(
+        self: &Arc<Self>,
+        client: Arc<Client>,
+        story_id: u64,
+        embed_images: bool,
+        primary: axum::body::Bytes,
+        made_by: Engine,
+    ) {
+        if made_by == Engine::Pages || !self.sampled() {
+            return;
+        }
+        if self.in_flight.fetch_add(1, Ordering::SeqCst) >= MAX_IN_FLIGHT {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.counts.lock().unwrap().skipped += 1;
+            return;
+        }
+        let shadow = self.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let result = match made_by {
+                Engine::Pipeline => {
+                    wp_mini_epub::download_story_to_memory(
+                        &client,
+                        story_id,
+                        embed_images,
+                        CONCURRENT_CHAPTER_REQUESTS,
+                        None,
+                    )
+                    .await
+                }
+                _ => {
+                    pipeline::download_story_to_memory(
+                        &client,
+                        story_id,
+                        embed_images,
+                        CONCURRENT_CHAPTER_REQUESTS,
+                    )
+                    .await
+                }
+            };
+            shadow.in_flight.fetch_sub(1, Ordering::SeqCst);
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            match result {
+                Ok(download) => shadow.compare(
+                    story_id,
+                    &Summary::of(&primary),
+                    &Summary::of(&download.epub_response),
+                    elapsed_ms,
+                ),
+                Err(e) => {
+                    warn!(story_id, ?made_by, error = %e, "Shadow run failed");
+                    let mut counts = shadow.counts.lock().unwrap();
+                    counts.runs += 1;
+                    counts.failures += 1;
+                }
+            }
+        });
+    }
+
+    fn compare(&self, story_id: u64, primary: &Summary, candidate: &Summary, elapsed_ms: u64) {
+        let mut problems = Vec::new();
+        match (&primary.chapters, &candidate.chapters) {
+            (Some(_), None) => problems.push("shadow EPUB does not open".to_string()),
+            (Some(expected), Some(actual)) if expected != actual => problems.push(format!(
+                "chapters differ: {} expected, {} found",
+                expected.len(),
+                actual.len()
+            )),
+            _ => {}
+        }
+        let delta = candidate.size as f64 - primary.size as f64;
+        if delta.abs() > primary.size as f64 * SIZE_TOLERANCE {
+            problems.push(format!(
+                "size differs: {} expected, {} found",
+                primary.size, candidate.size
+            ));
+        }
+        let identical = primary.hash == candidate.hash;
+
+        let mut counts = self.counts.lock().unwrap();
+        counts.runs += 1;
+        if problems.is_empty() {
+            counts.matches += 1;
+            info!(story_id, identical, elapsed_ms, "Shadow run matched");
+            return;
+        }
+        counts.discrepancies += 1;
+        drop(counts);
+        warn!(
+            story_id,
+            identical,
+            elapsed_ms,
+            problems = ?problems,
+            "Shadow run found a discrepancy"
+        );
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(Discrepancy {
+            at: unix_now(),
+            story_id,
+            problems,
+        });
+    }
+
+    fn report(&self) -> ShadowReport {
+        ShadowReport {
+            percent: self.percent.load(Ordering::Relaxed),
+            counts: *self.counts.lock().unwrap(),
+            recent_discrepancies: self.recent.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadowReport {
+    percent: u8,
+    counts: ShadowCounts,
+    recent_discrepancies: Vec<Discrepancy>,
+}
+
+#[derive(Deserialize)]
+pub struct ShadowRequest {
+    /// Share of generations to repeat, 0-100.
+    percent: u8,
+}
+
+pub async fn status(admin: Admin, State(state): State<AppState>) -> Json<ShadowReport> {
+    state.audit.record(&admin.subject, "view_shadow", None);
+    Json(state.shadow.report())
+}
+
+pub async fn enable(
+    admin: Admin,
+    State(state): State<AppState>,
+    Json(request): Json<ShadowRequest>,
+) -> Result<Json<ShadowReport>, MyError> {
+    if request.percent > 100 {
+        return Err(MyError::InvalidRequest(
+            "percent must be between 0 and 100".to_string(),
+        ));
+    }
+    info!(percent = request.percent, "Setting shadow mode");
+    state.audit.record(
+        &admin.subject,
+        "set_shadow",
+        Some(format!("{}%", request.percent)),
+    );
+    state
+        .shadow
+        .percent
+        .store(request.percent, Ordering::Relaxed);
+    Ok(Json(state.shadow.report()))
+}
+
+pub async fn disable(admin: Admin, State(state): State<AppState>) -> Json<ShadowReport> {
+    info!("Disabling shadow mode");
+    state.audit.record(&admin.subject, "disable_shadow", None);
+    state.shadow.percent.store(0, Ordering::Relaxed);
+    Json(state.shadow.report())
+}
@@ -0,0 +1,143 @@
+//! In-process state for a single instance, bounded in size: once the values
+//! go over `max_bytes` the least recently used ones are dropped, as a cache
+//! would. Locks and leases are a few bytes and do not get in the way.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::Shared;
+
+struct Entry {
+    value: Bytes,
+    expires: Instant,
+    used: Instant,
+}
+
+pub struct Local {
+    values: Mutex<HashMap<String, Entry>>,
+    max_bytes: usize,
+}
+
+impl Local {
+    pub fn new(max_bytes: usize) -> Self {
+        Local {
+            values: Mutex::default(),
+            max_bytes,
+        }
+    }
+
+    fn live(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        let mut values = self.values.lock().unwrap();
+        let now = Instant::now();
+        values.retain(|_, entry| entry.expires > now);
+        values
+    }
+
+    fn insert(&self, values: &mut HashMap<String, Entry>, key: &str, value: Bytes, ttl: Duration) {
+        let now = Instant::now();
+        values.insert(
+            key.to_string(),
+            Entry {
+                value,
+                expires: now + ttl,
+                used: now,
+            },
+        );
+        let mut total: usize = values.values().map(|entry| entry.value.len()).sum();
+        while total > self.max_bytes {
+            let Some(oldest) = values
+                .iter()
+                .filter(|(other, _)| other.as_str() != key)
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = values.remove(&oldest) {
+                total -= entry.value.len();
+            }
+        }
+    }
+}
+
+impl Shared for Local {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>> {
+        let value = self.live().get_mut(key).map(|entry| {
+            entry.used = Instant::now();
+            entry.value.clone()
+        });
+        Box::pin(async { Ok(value) })
+    }
+
+    fn set<'a>(&'a self, key: &'a str, value: Bytes, ttl: Duration) -> BoxFuture<'a, Result<()>> {
+        self.insert(&mut self.live(), key, value, ttl);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn set_if_absent<'a>(
+        &'a self,
+        key: &'a str,
+        value: Bytes,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>> {
+        let mut values = self.live();
+        let absent = !values.contains_key(key);
+        if absent {
+            self.insert(&mut values, key, value, ttl);
+        }
+        Box::pin(async move { Ok(absent) })
+    }
+
+    fn renew_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: Bytes,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>> {
+        let mut values = self.live();
+        let renewed = match values.get_mut(key) {
+            Some(entry) if entry.value == value => {
+                entry.expires = Instant::now() + ttl;
+                true
+            }
+            _ => false,
+        };
+        Box::pin(async move { Ok(renewed) })
+    }
+
+    fn delete_if<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>> {
+        let mut values = self.live();
+        if values.get(key).is_some_and(|entry| entry.value == value) {
+            values.remove(key);
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn increment_below<'a>(
+        &'a self,
+        key: &'a str,
+        initial: u64,
+        limit: u64,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<Option<u64>>> {
+        let mut values = self.live();
+        let count = values
+            .get(key)
+            .and_then(|entry| std::str::from_utf8(&entry.value).ok()?.parse().ok())
+            .unwrap_or(initial);
+        let incremented = (count < limit).then_some(count + 1);
+        if let Some(count) = incremented {
+            let value = Bytes::from(count.to_string());
+            match values.get_mut(key) {
+                // Kept until the count first started expires.
+                Some(entry) => entry.value = value,
+                None => self.insert(&mut values, key, value, ttl),
+            }
+        }
+        Box::pin(async move { Ok(incremented) })
+    }
+}
@@ -0,0 +1,60 @@
+//! Short-lived state that several instances behind a load balancer have to
+//! agree on: cached responses, singleflight locks, job leases, throttling
+//! decisions and download counts.
+//!
+//! Without `REDIS_URL` it is kept in process, which is all a single instance
+//! needs. Values expire on their own; nothing here has to survive a flush.
+
+mod local;
+mod redis;
+
+use anyhow::Result;
+use axum::body::Bytes;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+pub trait Shared: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>>;
+    fn set<'a>(&'a self, key: &'a str, value: Bytes, ttl: Duration) -> BoxFuture<'a, Result<()>>;
+    /// Sets `key` only if it is unset; returns whether it was.
+    fn set_if_absent<'a>(
+        &'a self,
+        key: &'a str,
+        value: Bytes,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>>;
+    /// Extends `key` by `ttl` if it still holds `value`; returns whether it did.
+    fn renew_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: Bytes,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>>;
+    /// Deletes `key` if it still holds `value`, e.g. a lock this instance owns.
+    fn delete_if<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>>;
+    /// Adds one to the count at `key` unless it has reached `limit`; returns
+    /// the new count, or `None` if it had. An unset count starts at `initial`
+    /// and expires after `ttl`.
+    fn increment_below<'a>(
+        &'a self,
+        key: &'a str,
+        initial: u64,
+        limit: u64,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<Option<u64>>>;
+}
+
+/// Redis at `redis_url`, or the process's own memory, of which the values may
+/// take up to `local_max_bytes`.
+pub async fn open(redis_url: Option<&str>, local_max_bytes: usize) -> Result<Arc<dyn Shared>> {
+    Ok(match redis_url {
+        Some(url) => {
+            let shared = redis::Redis::connect(url).await?;
+            info!("Sharing cache and throttling state through Redis");
+            Arc::new(shared)
+        }
+        None => Arc::new(local::Local::new(local_max_bytes)),
+    })
+}
@@ -0,0 +1,151 @@
+//! State in Redis, shared by every instance pointed at the same server. Keys
+//! are prefixed with `wattdownload:`; the connection reconnects on its own.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use futures_util::future::BoxFuture;
+use redis::aio::ConnectionManager;
+use redis::Script;
+use std::time::Duration;
+
+use super::Shared;
+
+const PREFIX: &str = "wattdownload:";
+const DELETE_IF: &str = r"if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0";
+const RENEW_IF: &str = r"if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0";
+const INCREMENT_BELOW: &str = r"local count = redis.call('GET', KEYS[1])
+if not count then
+    count = ARGV[1]
+    redis.call('SET', KEYS[1], count, 'PX', ARGV[3])
+end
+if tonumber(count) >= tonumber(ARGV[2]) then
+    return -1
+end
+return redis.call('INCR', KEYS[1])";
+
+pub struct Redis {
+    connection: ConnectionManager,
+    delete_if: Script,
+    renew_if: Script,
+    increment_below: Script,
+}
+
+impl Redis {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        Ok(Redis {
+            connection: ConnectionManager::new(client).await?,
+            delete_if: Script::new(DELETE_IF),
+            renew_if: Script::new(RENEW_IF),
+            increment_below: Script::new(INCREMENT_BELOW),
+        })
+    }
+}
+
+fn key(key: &str) -> String {
+    format!("{}{}", PREFIX, key)
+}
+
+fn millis(ttl: Duration) -> u64 {
+    (ttl.as_millis() as u64).max(1)
+}
+
+impl Shared for Redis {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>> {
+        Box::pin(async move {
+            let value: Option<Vec<u8>> = redis::cmd("GET")
+                .arg(self::key(key))
+                .query_async(&mut self.connection.clone())
+                .await?;
+            Ok(value.map(Bytes::from))
+        })
+    }
+
+    fn set<'a>(&'a self, key: &'a str, value: Bytes, ttl: Duration) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let _: () = redis::cmd("SET")
+                .arg(self::key(key))
+                .arg(value.as_ref())
+                .arg("PX")
+                .arg(millis(ttl))
+                .query_async(&mut self.connection.clone())
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn set_if_absent<'a>(
+        &'a self,
+        key: &'a str,
+        value: Bytes,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let set: Option<String> = redis::cmd("SET")
+                .arg(self::key(key))
+                .arg(value.as_ref())
+                .arg("NX")
+                .arg("PX")
+                .arg(millis(ttl))
+                .query_async(&mut self.connection.clone())
+                .await?;
+            Ok(set.is_some())
+        })
+    }
+
+    fn renew_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: Bytes,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let renewed: i64 = self
+                .renew_if
+                .key(self::key(key))
+                .arg(value.as_ref())
+                .arg(millis(ttl))
+                .invoke_async(&mut self.connection.clone())
+                .await?;
+            Ok(renewed == 1)
+        })
+    }
+
+    fn delete_if<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let _: i64 = self
+                .delete_if
+                .key(self::key(key))
+                .arg(value.as_ref())
+                .invoke_async(&mut self.connection.clone())
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn increment_below<'a>(
+        &'a self,
+        key: &'a str,
+        initial: u64,
+        limit: u64,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<Option<u64>>> {
+        Box::pin(async move {
+            let count: i64 = self
+                .increment_below
+                .key(self::key(key))
+                .arg(initial)
+                .arg(limit)
+                .arg(millis(ttl))
+                .invoke_async(&mut self.connection.clone())
+                .await?;
+            Ok(u64::try_from(count).ok())
+        })
+    }
+}
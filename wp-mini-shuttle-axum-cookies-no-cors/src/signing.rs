@@ -0,0 +1,71 @@
+//! Signatures on served files, so mirrors and archives can show a file came
+//! from this deployment and not just from someone holding a copy.
+//!
+//! With `ARTIFACT_SIGNING_KEY` set, every file sent whole (`/generate-epub`,
+//! job results and stored downloads; the ones with `X-Content-SHA256`) also
+//! gets `X-Content-Signature`: the hex Ed25519 signature of its exact bytes.
+//! `GET /signing-key` publishes the public key to check it against. Files
+//! streamed as they are made are not signed, as there is nothing to sign
+//! until the last byte is out.
+
+use axum::body::{self, Body};
+use axum::extract::State;
+use axum::http::HeaderName;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::error;
+use wp_mini_epub::AppError;
+
+use crate::config::Config;
+use crate::error::MyError;
+use crate::file_response::CONTENT_SHA256;
+use crate::{decode_hex, encode_hex, AppState};
+
+pub const CONTENT_SIGNATURE: HeaderName = HeaderName::from_static("x-content-signature");
+
+/// The deployment key from `ARTIFACT_SIGNING_KEY`, 32 bytes in hex.
+pub fn key(hex: &str) -> Option<SigningKey> {
+    let seed: [u8; 32] = decode_hex(hex)?.try_into().ok()?;
+    Some(SigningKey::from_bytes(&seed))
+}
+
+pub async fn apply(State(config): State<Arc<Config>>, response: Response) -> Response {
+    if !response.headers().contains_key(CONTENT_SHA256) {
+        return response;
+    }
+    let Some(key) = config.keys().artifact_signing_key.clone() else {
+        return response;
+    };
+    let (mut parts, body) = response.into_parts();
+    // Whole files are already in memory; this only takes them out of the body.
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!(error = %e, "Could not read a file to sign");
+            return MyError::App(AppError::EpubGenerationFailed).into_response();
+        }
+    };
+    let signature = encode_hex(&key.sign(&bytes).to_bytes());
+    if let Ok(signature) = signature.parse() {
+        parts.headers.insert(CONTENT_SIGNATURE, signature);
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// `GET /signing-key`
+pub async fn public_key(State(state): State<AppState>) -> Result<Response, MyError> {
+    let key = state
+        .config
+        .keys()
+        .artifact_signing_key
+        .clone()
+        .ok_or_else(|| MyError::NotFound("This server does not sign its files".to_string()))?;
+    Ok(Json(json!({
+        "algorithm": "ed25519",
+        "publicKey": encode_hex(key.verifying_key().as_bytes()),
+    }))
+    .into_response())
+}
@@ -0,0 +1,125 @@
+//! `GET /generate-epub/stream?storyId=..`: an anonymous generation that
+//! reports its progress as Server-Sent Events, for `EventSource` clients that
+//! want a progress bar rather than a spinner. `embedImages` is taken from the
+//! query as well.
+//!
+//! Events, in order: `metadata_fetched` with the title and the number of
+//! parts, `chapter_done` with the position of each part and the number of
+//! parts once it is downloaded (the book is made with `crate::pipeline` for
+//! this; none are sent for a book already made, see `crate::response_cache`),
+//! `images_embedded` once the book has its images (only when asked for), then
+//! `complete` with a download link to the book, or `error` with the status and
+//! code (see `MyError::code`) at whatever point it failed.
+
+use api_types::{ChapterDone, ImagesEmbedded, MetadataFetched, StreamComplete, StreamQuery};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use serde::Serialize;
+use serde_json::json;
+use std::convert::Infallible;
+use tokio::sync::mpsc;
+use tracing::{info, instrument, Instrument};
+use wp_mini::WattpadError;
+use wp_mini_epub::AppError;
+
+use crate::artifacts::Artifact;
+use crate::error::MyError;
+use crate::jobs::JobError;
+use crate::progress::Progress;
+use crate::story_cache;
+use crate::{admit, download, AppState, GenerateEpubRequest};
+
+#[instrument(skip_all, fields(story_id = query.story_id))]
+pub async fn generate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, MyError> {
+    let request = json!({ "storyId": query.story_id, "embedImages": query.embed_images });
+    let mut payload: GenerateEpubRequest = serde_json::from_value(request)
+        .map_err(|e| MyError::InvalidRequest(format!("Invalid request: {}", e)))?;
+    admit(&state, &headers, &mut payload, "stream").await?;
+
+    // Unbounded so parts are reported from the pipeline without waiting on
+    // the client; there are as many events as parts, and a few more.
+    let (events, events_rx) = mpsc::unbounded_channel::<Event>();
+    tokio::spawn(
+        async move {
+            if let Err(e) = run(&state, &payload, &events).await {
+                info!(
+                    status = e.status_and_message().0.as_u16(),
+                    "Streamed generation failed"
+                );
+                let _ = events.send(event("error", JobError::from(&e)));
+            }
+        }
+        .in_current_span(),
+    );
+
+    let stream = stream::unfold(events_rx, |mut events_rx| async move {
+        let event = events_rx.recv().await?;
+        Some((Ok(event), events_rx))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Generates and publishes the book, sending events along the way. Keeps
+/// going when the client leaves, like `crate::progress`.
+async fn run(
+    state: &AppState,
+    payload: &GenerateEpubRequest,
+    events: &mpsc::UnboundedSender<Event>,
+) -> Result<(), MyError> {
+    let story = story_cache::story(state, &state.anon_client, payload.story_id, true)
+        .await
+        .map_err(|e| match e {
+            WattpadError::StoryNotFound => {
+                MyError::NotFound(format!("Story {} could not be found", payload.story_id))
+            }
+            _ => MyError::App(AppError::MetadataFetchFailed),
+        })?;
+    let metadata = MetadataFetched {
+        title: story.title.clone(),
+        total: story.num_parts,
+    };
+    let _ = events.send(event("metadata_fetched", metadata));
+
+    let progress = {
+        let events = events.clone();
+        Progress::default().with_parts(move |index, total| {
+            let _ = events.send(event("chapter_done", ChapterDone { index, total }));
+        })
+    };
+    let epub = download(state, payload, &progress).await?;
+    if payload.embed_images {
+        let _ = events.send(event("images_embedded", ImagesEmbedded {}));
+    }
+
+    let size = epub.bytes.len();
+    let path = state
+        .artifacts
+        .publish(
+            Artifact {
+                file_name: epub.file_name.clone(),
+                content_type: epub.content_type.to_string(),
+                bytes: epub.bytes,
+            },
+            &payload.download_link,
+        )
+        .await?;
+    let complete = StreamComplete {
+        file_name: epub.file_name,
+        size,
+        download_url: state.config.public_url(&path),
+        skipped_chapters: epub.skipped,
+    };
+    let _ = events.send(event("complete", complete));
+    Ok(())
+}
+
+fn event(name: &'static str, data: impl Serialize) -> Event {
+    let data = serde_json::to_string(&data).expect("event data serializes");
+    Event::default().event(name).data(data)
+}
@@ -0,0 +1,183 @@
+//! The reading statistics page `statsPage` appends to a book: total length,
+//! estimated reading time, a words-per-chapter chart and when each chapter
+//! was published, in the request's language (see `crate::i18n`).
+
+use reqwest::Client;
+use std::collections::HashMap;
+use std::fmt::Write;
+use tracing::warn;
+use wp_mini::types::StoryResponse;
+
+use crate::book::{self, escape, Book, Chapter};
+use crate::i18n::{self, Lang};
+use crate::story_cache;
+use crate::AppState;
+
+/// A typical adult's silent reading speed.
+const WORDS_PER_MINUTE: usize = 238;
+const CHART_PATH: &str = "images/stats.svg";
+const CHART_WIDTH: usize = 600;
+const CHART_HEIGHT: usize = 200;
+
+/// Adds the parts' publication dates to `story`, which `wp_mini_epub` does
+/// not ask for; `anonymous` as for `story_cache::story`. The page leaves the
+/// timeline out if they cannot be fetched.
+pub async fn with_part_dates(
+    state: &AppState,
+    client: &Client,
+    mut story: StoryResponse,
+    story_id: u64,
+    anonymous: bool,
+) -> StoryResponse {
+    let dated = match story_cache::story(state, client, story_id, anonymous).await {
+        Ok(dated) => dated,
+        Err(e) => {
+            warn!(error = %e, "Could not fetch part dates for the statistics page");
+            return story;
+        }
+    };
+    let dates: HashMap<u64, String> = dated
+        .parts
+        .into_iter()
+        .flatten()
+        .filter_map(|part| Some((part.id?, part.create_date?)))
+        .collect();
+    for part in story.parts.iter_mut().flatten() {
+        if let Some(id) = part.id {
+            part.create_date = dates.get(&id).cloned();
+        }
+    }
+    story
+}
+
+/// Appends the statistics page and its chart to `book`, in `lang`.
+pub fn append(book: &mut Book, story: &StoryResponse, lang: Lang) {
+    let words: Vec<usize> = book
+        .chapters
+        .iter()
+        .map(|chapter| book::word_count(&chapter.body))
+        .collect();
+    let total: usize = words.iter().sum();
+    let minutes = total.div_ceil(WORDS_PER_MINUTE);
+
+    let summary = i18n::format(
+        lang,
+        "stats.summary",
+        &[
+            ("chapters", book.chapters.len().to_string()),
+            ("words", total.to_string()),
+        ],
+    );
+    let time = i18n::format(
+        lang,
+        "stats.readingTime",
+        &[("time", reading_time(lang, minutes))],
+    );
+    let mut body = String::new();
+    let _ = write!(body, "<p>{}</p><p>{}</p>", escape(&summary), escape(&time));
+    if !words.is_empty() {
+        let _ = write!(
+            body,
+            r#"<h2>{}</h2><p><img src="{}" alt="{}"/></p>"#,
+            escape(i18n::message(lang, "stats.wordsPerChapter")),
+            CHART_PATH,
+            escape(i18n::message(lang, "stats.chart"))
+        );
+        book.add_asset(CHART_PATH, chart(lang, &words).into_bytes());
+    }
+
+    // Chapter `N.xhtml` is the story's N-th part.
+    let parts: Vec<_> = story
+        .parts
+        .iter()
+        .flatten()
+        .filter(|part| part.id.is_some())
+        .collect();
+    let timeline: Vec<(&str, &str)> = book
+        .chapters
+        .iter()
+        .filter_map(|chapter| {
+            let date = parts
+                .get(chapter.part.checked_sub(1)?)?
+                .create_date
+                .as_deref()?;
+            Some((date.get(..10).unwrap_or(date), chapter.title.as_str()))
+        })
+        .collect();
+    if !timeline.is_empty() {
+        let _ = write!(
+            body,
+            "<h2>{}</h2><ul>",
+            escape(i18n::message(lang, "stats.timeline"))
+        );
+        for (date, title) in timeline {
+            let _ = write!(body, "<li>{}: {}</li>", date, escape(title));
+        }
+        body.push_str("</ul>");
+    }
+
+    book.chapters.push(Chapter {
+        part: 0,
+        title: i18n::message(lang, "stats.title").to_string(),
+        body,
+    });
+}
+
+fn reading_time(lang: Lang, minutes: usize) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => i18n::format(lang, "stats.minutes", &[("minutes", minutes.to_string())]),
+        (hours, minutes) => i18n::format(
+            lang,
+            "stats.hours",
+            &[
+                ("hours", hours.to_string()),
+                ("minutes", minutes.to_string()),
+            ],
+        ),
+    }
+}
+
+/// A bar per chapter, scaled to the longest.
+fn chart(lang: Lang, words: &[usize]) -> String {
+    let max = words.iter().copied().max().unwrap_or(0).max(1);
+    let bar = CHART_WIDTH as f64 / words.len() as f64;
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}" width="{}" height="{}">"#,
+        CHART_WIDTH, CHART_HEIGHT, CHART_WIDTH, CHART_HEIGHT
+    );
+    for (i, count) in words.iter().enumerate() {
+        let height = *count as f64 / max as f64 * CHART_HEIGHT as f64;
+        let title = i18n::format(
+            lang,
+            "stats.bar",
+            &[
+                ("chapter", (i + 1).to_string()),
+                ("words", count.to_string()),
+            ],
+        );
+        let _ = write!(
+            svg,
+            r##"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="#4a6fa5"><title>{}</title></rect>"##,
+            i as f64 * bar,
+            CHART_HEIGHT as f64 - height,
+            (bar * 0.8).max(0.5),
+            height,
+            escape(&title)
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_page_is_in_the_requested_language() {
+        assert_eq!(reading_time(Lang::En, 75), "1 h 15 min");
+        assert_eq!(reading_time(Lang::Pt, 5), "5 min");
+        assert!(chart(Lang::Es, &[120, 80]).contains("<title>2: 80 palabras</title>"));
+        assert!(chart(Lang::En, &[120]).contains("<title>1: 120 words</title>"));
+    }
+}
@@ -0,0 +1,100 @@
+//! One file per key under `STATE_DIR`.
+
+use anyhow::{bail, Result};
+use axum::body::Bytes;
+use futures_util::future::BoxFuture;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::fs;
+use uuid::Uuid;
+
+use super::Storage;
+
+/// Marks files that are still being written.
+const PARTIAL: &str = ".partial-";
+
+pub struct Disk {
+    root: PathBuf,
+}
+
+impl Disk {
+    pub fn new(root: &str) -> Self {
+        Disk {
+            root: PathBuf::from(root),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        {
+            bail!("invalid storage key {:?}", key);
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl Storage for Disk {
+    fn put<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            // Written aside and renamed, so readers never see half a file.
+            let partial = path.with_file_name(format!(
+                "{}{}{}",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                PARTIAL,
+                Uuid::new_v4().simple()
+            ));
+            fs::write(&partial, &value).await?;
+            fs::rename(&partial, &path).await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>> {
+        Box::pin(async move {
+            match fs::read(self.path(key)?).await {
+                Ok(bytes) => Ok(Some(Bytes::from(bytes))),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match fs::remove_file(self.path(key)?).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut pending = vec![prefix.trim_end_matches('/').to_string()];
+            while let Some(dir) = pending.pop() {
+                let mut entries = match fs::read_dir(self.path(&dir)?).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                while let Some(entry) = entries.next_entry().await? {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    let key = format!("{}/{}", dir, name);
+                    if entry.file_type().await?.is_dir() {
+                        pending.push(key);
+                    } else if !name.contains(PARTIAL) {
+                        keys.push(key);
+                    }
+                }
+            }
+            Ok(keys)
+        })
+    }
+}
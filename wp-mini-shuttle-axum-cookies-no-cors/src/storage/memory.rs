@@ -0,0 +1,42 @@
+//! Everything in process memory; gone on restart.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use futures_util::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use super::Storage;
+
+#[derive(Default)]
+pub struct Memory(Mutex<BTreeMap<String, Bytes>>);
+
+impl Storage for Memory {
+    fn put<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>> {
+        self.0.lock().unwrap().insert(key.to_string(), value);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>> {
+        let value = self.0.lock().unwrap().get(key).cloned();
+        Box::pin(async { Ok(value) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.0.lock().unwrap().remove(key);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        let keys = self
+            .0
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        Box::pin(async { Ok(keys) })
+    }
+}
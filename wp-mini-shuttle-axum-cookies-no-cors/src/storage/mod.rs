@@ -0,0 +1,41 @@
+//! Where anything that has to outlive a request is kept: stored downloads and
+//! operator state.
+//!
+//! `STORAGE_BACKEND` picks the implementation: process memory, files under
+//! `STATE_DIR` (the default), a Postgres table (e.g. Shuttle's shared database)
+//! or an S3-compatible bucket. Keys are `/`-separated paths such as
+//! `artifacts/blobs/<sha256>`.
+
+mod disk;
+mod memory;
+mod postgres;
+mod s3;
+
+use anyhow::Result;
+use axum::body::Bytes;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::config::StorageConfig;
+
+pub trait Storage: Send + Sync {
+    /// Stores `value` under `key`, replacing what was there.
+    fn put<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>>;
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>>;
+    /// Deleting a key that does not exist is not an error.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>>;
+    /// Every key under `prefix`, which ends in `/`.
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>>;
+}
+
+pub async fn open(config: &StorageConfig, state_dir: &str) -> Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match config {
+        StorageConfig::Memory => Arc::new(memory::Memory::default()),
+        StorageConfig::Disk => Arc::new(disk::Disk::new(state_dir)),
+        StorageConfig::Postgres { url } => Arc::new(postgres::Postgres::connect(url).await?),
+        StorageConfig::S3(config) => Arc::new(s3::S3::new(config)),
+    };
+    info!(backend = config.name(), "Opened storage");
+    Ok(storage)
+}
@@ -0,0 +1,106 @@
+//! A single key/value table in Postgres, such as Shuttle's shared database.
+//! The connection is re-established when it drops.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, NoTls};
+use tracing::warn;
+
+use super::Storage;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS wattdownload_storage (
+    key TEXT PRIMARY KEY,
+    value BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+)";
+
+pub struct Postgres {
+    url: String,
+    client: Mutex<Arc<Client>>,
+}
+
+impl Postgres {
+    pub async fn connect(url: &str) -> Result<Self> {
+        Ok(Postgres {
+            url: url.to_string(),
+            client: Mutex::new(Arc::new(connect(url).await?)),
+        })
+    }
+
+    async fn client(&self) -> Result<Arc<Client>> {
+        let mut client = self.client.lock().await;
+        if client.is_closed() {
+            warn!("Postgres connection lost; reconnecting");
+            *client = Arc::new(connect(&self.url).await?);
+        }
+        Ok(client.clone())
+    }
+}
+
+async fn connect(url: &str) -> Result<Client> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            warn!(error = %e, "Postgres connection closed");
+        }
+    });
+    client.batch_execute(SCHEMA).await?;
+    Ok(client)
+}
+
+impl Storage for Postgres {
+    fn put<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client()
+                .await?
+                .execute(
+                    "INSERT INTO wattdownload_storage (key, value) VALUES ($1, $2)
+                     ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = now()",
+                    &[&key, &value.as_ref()],
+                )
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>> {
+        Box::pin(async move {
+            let row = self
+                .client()
+                .await?
+                .query_opt(
+                    "SELECT value FROM wattdownload_storage WHERE key = $1",
+                    &[&key],
+                )
+                .await?;
+            Ok(row.map(|row| Bytes::from(row.get::<_, Vec<u8>>(0))))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client()
+                .await?
+                .execute("DELETE FROM wattdownload_storage WHERE key = $1", &[&key])
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let rows = self
+                .client()
+                .await?
+                .query(
+                    "SELECT key FROM wattdownload_storage WHERE starts_with(key, $1)",
+                    &[&prefix],
+                )
+                .await?;
+            Ok(rows.iter().map(|row| row.get(0)).collect())
+        })
+    }
+}
@@ -0,0 +1,229 @@
+//! Objects in an S3-compatible bucket (AWS, R2, MinIO, ...), addressed
+//! path-style and signed with AWS Signature Version 4.
+
+use anyhow::{bail, Result};
+use axum::body::Bytes;
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use sha2::{Digest, Sha256};
+
+use super::Storage;
+use crate::config::S3Config;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Characters S3 wants percent-encoded in paths and query values.
+const ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+pub struct S3 {
+    client: Client,
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, ENCODE).to_string()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl S3 {
+    pub fn new(config: &S3Config) -> Self {
+        let endpoint = config
+            .endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", config.region));
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint.as_str(), |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        S3 {
+            client: Client::new(),
+            endpoint,
+            host,
+            bucket: config.bucket.clone(),
+            region: config.region.clone(),
+            access_key_id: config.access_key_id.clone(),
+            secret_access_key: config.secret_access_key.clone(),
+        }
+    }
+
+    /// A signed request for `path` (already encoded) with `query` pairs.
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> RequestBuilder {
+        let now = Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = format!("{:x}", Sha256::digest(body));
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (encode(name), encode(value)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, self.host, payload_hash, timestamp, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            timestamp,
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let key = hmac(format!("AWS4{}", self.secret_access_key).as_bytes(), &date);
+        let key = hmac(&key, &self.region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+        let signature = hmac(&key, &string_to_sign)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        let url = match query.is_empty() {
+            true => format!("{}{}", self.endpoint, path),
+            false => format!("{}{}?{}", self.endpoint, path, query),
+        };
+        self.client
+            .request(method, url)
+            .header("x-amz-date", timestamp)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key_id, scope, signed_headers, signature
+                ),
+            )
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        let key: Vec<String> = key.split('/').map(encode).collect();
+        format!("/{}/{}", encode(&self.bucket), key.join("/"))
+    }
+}
+
+async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        bail!("S3 answered {}: {}", status, body);
+    }
+    Ok(response)
+}
+
+impl Storage for S3 {
+    fn put<'a>(&'a self, key: &'a str, value: Bytes) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let request = self.request(Method::PUT, &self.object_path(key), &[], &value);
+            check(request.body(value).send().await?).await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>>> {
+        Box::pin(async move {
+            let response = self
+                .request(Method::GET, &self.object_path(key), &[], &[])
+                .send()
+                .await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            Ok(Some(check(response).await?.bytes().await?))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let response = self
+                .request(Method::DELETE, &self.object_path(key), &[], &[])
+                .send()
+                .await?;
+            if response.status() != StatusCode::NOT_FOUND {
+                check(response).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let path = format!("/{}", encode(&self.bucket));
+            let mut keys = Vec::new();
+            let mut continuation: Option<String> = None;
+            loop {
+                let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+                if let Some(token) = continuation.as_deref() {
+                    query.push(("continuation-token", token));
+                }
+                let body = check(self.request(Method::GET, &path, &query, &[]).send().await?)
+                    .await?
+                    .text()
+                    .await?;
+                continuation = parse_listing(&body, &mut keys)?;
+                if continuation.is_none() {
+                    return Ok(keys);
+                }
+            }
+        })
+    }
+}
+
+/// Collects the `<Key>`s of a `ListObjectsV2` page; returns the token for the
+/// next page, if there is one.
+fn parse_listing(xml: &str, keys: &mut Vec<String>) -> Result<Option<String>> {
+    let mut reader = Reader::from_str(xml);
+    let mut element = Vec::new();
+    let mut next = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => element = e.name().as_ref().to_vec(),
+            Event::End(_) => element.clear(),
+            Event::Text(text) => {
+                let text = text.decode()?.into_owned();
+                match element.as_slice() {
+                    b"Key" => keys.push(text),
+                    b"NextContinuationToken" => next = Some(text),
+                    _ => {}
+                }
+            }
+            Event::Eof => return Ok(next),
+            _ => {}
+        }
+    }
+}
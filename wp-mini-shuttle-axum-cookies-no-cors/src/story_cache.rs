@@ -0,0 +1,163 @@
+//! Story metadata from Wattpad, reused for `STORY_METADATA_CACHE_SECS`.
+//!
+//! `/story/{id}/info`, `/stories/metadata`, `/estimate`, the statistics page
+//! and generation itself (its `ETag`, its size check, streamed and tolerant
+//! books) each used to ask Wattpad for the story with the fields they needed.
+//! They now ask for the fields all of them need in one request, whose raw
+//! response is kept in `crate::shared` apart from the generated books of
+//! `crate::response_cache`, so lookups of the same story within the window
+//! cost Wattpad one request. Only anonymous lookups are kept: signed-in
+//! readers may be shown parts others are not.
+//!
+//! Stories Wattpad says do not exist are remembered as missing for
+//! `STORY_NOT_FOUND_CACHE_SECS`, so clients asking for deleted IDs over and
+//! over are answered `404` without asking Wattpad again, here and on
+//! admission of a download (see `crate::admit`). Requests with cookies skip
+//! this too: a private story exists for its owner.
+
+use axum::body::Bytes;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, warn};
+use wp_mini::field::{LanguageField, PartStubField, StoryField, UserStubField};
+use wp_mini::types::StoryResponse;
+use wp_mini::WattpadError;
+
+use crate::AppState;
+
+const STORY_URL: &str = "https://www.wattpad.com/api/v3/stories/";
+
+/// Every field a lookup here is made for.
+fn fields() -> String {
+    [
+        StoryField::Title,
+        StoryField::User(vec![UserStubField::Username]),
+        StoryField::Description,
+        StoryField::Cover,
+        StoryField::Url,
+        StoryField::NumParts,
+        StoryField::Completed,
+        StoryField::Mature,
+        StoryField::Tags,
+        StoryField::ReadCount,
+        StoryField::VoteCount,
+        StoryField::ModifyDate,
+        StoryField::Language(vec![LanguageField::Id]),
+        StoryField::Parts(vec![
+            PartStubField::Id,
+            PartStubField::Title,
+            PartStubField::Length,
+            PartStubField::CreateDate,
+        ]),
+    ]
+    .iter()
+    .map(ToString::to_string)
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// The error body Wattpad's API answers with, as `wp_mini` reads it.
+#[derive(Deserialize)]
+struct ApiError {
+    code: i64,
+    #[serde(rename = "error")]
+    error_type: String,
+    message: String,
+}
+
+impl From<ApiError> for WattpadError {
+    fn from(error: ApiError) -> Self {
+        match error.code {
+            1017 => WattpadError::StoryNotFound,
+            1018 => WattpadError::PermissionDeniedNotLoggedIn,
+            1154 => WattpadError::AccessDenied,
+            _ => WattpadError::ApiError {
+                code: error.code,
+                error_type: error.error_type,
+                message: error.message,
+            },
+        }
+    }
+}
+
+/// The metadata of `story_id` as `client` sees it, from the cache when
+/// `anonymous` says `client` is not signed in. Fails as
+/// `WattpadClient::get_story_info` would.
+pub async fn story(
+    state: &AppState,
+    client: &Client,
+    story_id: u64,
+    anonymous: bool,
+) -> Result<StoryResponse, WattpadError> {
+    if anonymous && known_missing(state, story_id).await {
+        return Err(WattpadError::StoryNotFound);
+    }
+    let ttl = state.config.story_metadata_ttl.filter(|_| anonymous);
+    let key = format!("story-metadata:{}", story_id);
+    if ttl.is_some() {
+        match state.shared.get(&key).await {
+            Ok(Some(raw)) => match serde_json::from_slice(&raw) {
+                Ok(story) => {
+                    info!(story_id, "Serving story metadata from the cache");
+                    return Ok(story);
+                }
+                Err(e) => warn!(error = %e, "Could not read cached story metadata"),
+            },
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "Could not look up cached story metadata"),
+        }
+    }
+
+    let response = client
+        .get(format!("{}{}", STORY_URL, story_id))
+        .query(&[("fields", fields())])
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let error = response.json::<ApiError>().await?.into();
+        if anonymous && matches!(error, WattpadError::StoryNotFound) {
+            remember_missing(state, story_id).await;
+        }
+        return Err(error);
+    }
+    let raw: Bytes = response.bytes().await?;
+    let story = serde_json::from_slice(&raw)?;
+    if let Some(ttl) = ttl
+        && let Err(e) = state.shared.set(&key, raw, ttl).await
+    {
+        warn!(error = %e, "Could not cache story metadata");
+    }
+    Ok(story)
+}
+
+fn missing_key(story_id: u64) -> String {
+    format!("story-missing:{}", story_id)
+}
+
+/// Whether an anonymous lookup found `story_id` missing recently.
+pub async fn known_missing(state: &AppState, story_id: u64) -> bool {
+    if state.config.story_not_found_ttl.is_none() {
+        return false;
+    }
+    match state.shared.get(&missing_key(story_id)).await {
+        Ok(marker) => marker.is_some(),
+        Err(e) => {
+            warn!(error = %e, "Could not look up missing stories");
+            false
+        }
+    }
+}
+
+async fn remember_missing(state: &AppState, story_id: u64) {
+    let Some(ttl) = state.config.story_not_found_ttl else {
+        return;
+    };
+    info!(story_id, "Remembering story as missing");
+    if let Err(e) = state
+        .shared
+        .set(&missing_key(story_id), Bytes::from_static(b"1"), ttl)
+        .await
+    {
+        warn!(error = %e, "Could not remember a missing story");
+    }
+}
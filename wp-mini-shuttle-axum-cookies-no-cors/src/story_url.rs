@@ -0,0 +1,54 @@
+//! Extracting story IDs from pasted Wattpad links.
+
+use reqwest::Client;
+use wp_mini::field::PartField;
+use wp_mini::WattpadClient;
+use wp_mini_epub::AppError;
+
+pub enum StoryRef {
+    /// `wattpad.com/story/<id>-<slug>`
+    Story(u64),
+    /// `wattpad.com/<part id>-<slug>`, which must be resolved to its story.
+    Part(u64),
+}
+
+/// Finds the first Wattpad story or part link in free-form text.
+pub fn find_story_ref(text: &str) -> Option<StoryRef> {
+    text.split_whitespace().find_map(parse_story_ref)
+}
+
+fn parse_story_ref(candidate: &str) -> Option<StoryRef> {
+    let start = candidate.find("wattpad.com/")? + "wattpad.com/".len();
+    let path = &candidate[start..];
+
+    if let Some(rest) = path.strip_prefix("story/") {
+        return leading_number(rest).map(StoryRef::Story);
+    }
+    leading_number(path).map(StoryRef::Part)
+}
+
+fn leading_number(s: &str) -> Option<u64> {
+    let digits: String = s.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+/// Returns the story ID for a reference, looking up the parent story of a part.
+pub async fn resolve_story_id(client: &Client, story_ref: StoryRef) -> Result<u64, AppError> {
+    match story_ref {
+        StoryRef::Story(id) => Ok(id),
+        StoryRef::Part(part_id) => {
+            let wp_client = WattpadClient::builder()
+                .reqwest_client(client.clone())
+                .build();
+            let part = wp_client
+                .story
+                .get_part_info(part_id, Some(&[PartField::GroupId]))
+                .await
+                .map_err(|_| AppError::MetadataFetchFailed)?;
+
+            part.group_id
+                .and_then(|id| id.parse().ok())
+                .ok_or(AppError::MetadataFetchFailed)
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Weekly digests of the new chapters of `weeklyDigest` subscriptions.
+//!
+//! A check of such a subscription only keeps its new chapters (see
+//! `super::check`). When its schedule comes round (see `super::schedule`),
+//! weekly unless it has one, the key's subscriptions that are due are
+//! gathered by where they deliver to, and each destination gets one EPUB with
+//! all of their new chapters, titled by story and chapter, through the usual
+//! deliveries: an email digest is a single message with the book attached or
+//! linked. Digests are EPUBs whatever the subscriptions' `format`.
+
+use anyhow::{anyhow, Result};
+use axum::body::Bytes;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+use wp_mini::types::StoryResponse;
+
+use super::{all, save, schedule, PendingChapter, Subscription, LEASE, PREFIX};
+use crate::artifacts::LinkOptions;
+use crate::delivery::{self, Delivery, DeliveryContext, DeliveryFile};
+use crate::i18n::{self, Lang};
+use crate::pipeline::{self, Hooks};
+use crate::{part_cache, unix_now, AppState};
+
+/// Sends the digests that are due, one instance each.
+pub(super) async fn send_due(state: &AppState, instance: &Bytes) -> Result<()> {
+    let now = unix_now();
+    let mut digests: BTreeMap<(String, String), Vec<Subscription>> = BTreeMap::new();
+    for (owner, subscription) in all(&*state.storage, PREFIX).await? {
+        if subscription.active() && schedule::digest_due(&subscription, now) {
+            let destination = serde_json::to_string(&subscription.delivery)?;
+            digests
+                .entry((owner, destination))
+                .or_default()
+                .push(subscription);
+        }
+    }
+    for ((owner, _), subscriptions) in digests {
+        let lease = format!("subscription-digest:{}", subscriptions[0].id);
+        if !state
+            .shared
+            .set_if_absent(&lease, instance.clone(), LEASE)
+            .await?
+        {
+            continue;
+        }
+        if let Err(e) = send(state, &owner, subscriptions).await {
+            warn!(error = %e, "Could not send a subscription digest");
+        }
+        state.shared.delete_if(&lease, instance.clone()).await?;
+    }
+    Ok(())
+}
+
+/// Delivers the pending chapters of `subscriptions`, which share a
+/// destination, as one book, and starts their next week.
+async fn send(state: &AppState, owner: &str, mut subscriptions: Vec<Subscription>) -> Result<()> {
+    let chapters: Vec<&PendingChapter> = subscriptions
+        .iter()
+        .flat_map(|subscription| &subscription.pending)
+        .collect();
+    if !chapters.is_empty() {
+        let delivery = &subscriptions[0].delivery;
+        let lang = match delivery {
+            Delivery::Email { language, .. } => language.unwrap_or_default(),
+            _ => Lang::En,
+        };
+        let date = Utc::now().date_naive();
+        let title = i18n::format(
+            lang,
+            "digest.title",
+            &[
+                ("service_name", state.config.branding.name.clone()),
+                ("date", date.to_string()),
+            ],
+        );
+        let embed_images = subscriptions.iter().any(|s| s.embed_images);
+        let epub = anthology(state, &title, &chapters, embed_images).await?;
+        info!(
+            chapters = chapters.len(),
+            "Delivering a subscription digest"
+        );
+        delivery::deliver(
+            delivery,
+            &DeliveryContext {
+                client: &state.delivery_client,
+                config: &state.config,
+                artifacts: &state.artifacts,
+                link: &LinkOptions::default(),
+            },
+            DeliveryFile {
+                title: &title,
+                file_name: &format!("digest-{}.epub", date),
+                content_type: "application/epub+zip",
+                bytes: Bytes::from(epub),
+            },
+        )
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    }
+    let now = unix_now();
+    for subscription in &mut subscriptions {
+        subscription.pending.clear();
+        subscription.digested_at = Some(now);
+        subscription.updated_at = now;
+        save(&*state.storage, owner, subscription).await?;
+    }
+    Ok(())
+}
+
+/// A book titled `title` of `chapters`, in order, made like any other by
+/// `crate::pipeline`.
+async fn anthology(
+    state: &AppState,
+    title: &str,
+    chapters: &[&PendingChapter],
+    embed_images: bool,
+) -> Result<Vec<u8>> {
+    let parts: Vec<Value> = chapters
+        .iter()
+        .map(|chapter| {
+            json!({
+                "id": chapter.part_id,
+                "title": format!("{}: {}", chapter.story_title, chapter.title),
+            })
+        })
+        .collect();
+    let story: StoryResponse = serde_json::from_value(json!({ "title": title, "parts": parts }))?;
+    let client = &state.anon_client;
+    let book = pipeline::assemble(
+        client,
+        0,
+        story,
+        embed_images,
+        state.config.chapter_concurrency(None),
+        |part_id| part_cache::text(state, client, part_id, true),
+        Hooks::default(),
+    )
+    .await?;
+    Ok(book.epub_response)
+}
@@ -0,0 +1,124 @@
+//! `POST /hooks/story-updated`: a watcher that sees a story change before the
+//! subscriptions' schedules come round says so, and every active subscription
+//! to the story is looked at now, as its schedule would (see `super::check`).
+//!
+//! The hook takes `STORY_HOOK_SECRET` as a bearer token and is not found while
+//! that is unset. It answers `202` with how many subscriptions are being
+//! looked at; the looking happens after it answers.
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::Json;
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{all, check_leased, storage_error, PREFIX};
+use crate::error::MyError;
+use crate::AppState;
+
+pub use api_types::StoryUpdated;
+
+/// Whether `headers` carry `secret` as a bearer token, compared in constant
+/// time so the comparison does not tell how much of a guess was right.
+fn authorized(headers: &HeaderMap, secret: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(secret.as_bytes())))
+}
+
+pub async fn story_updated(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(update): Json<StoryUpdated>,
+) -> Result<(StatusCode, Json<Value>), MyError> {
+    let keys = state.config.keys();
+    let Some(secret) = keys.story_hook_secret.as_deref() else {
+        return Err(MyError::NotFound("Not found".to_string()));
+    };
+    if !authorized(&headers, secret) {
+        warn!("Rejected a story update with a missing or wrong secret");
+        return Err(MyError::Unauthorized(
+            "Send STORY_HOOK_SECRET as a bearer token".to_string(),
+        ));
+    }
+    state.maintenance.check().await?;
+
+    let subscriptions: Vec<_> = all(&*state.storage, PREFIX)
+        .await
+        .map_err(storage_error("list subscriptions"))?
+        .into_iter()
+        .filter(|(_, subscription)| {
+            subscription.story_id == update.story_id && subscription.active()
+        })
+        .collect();
+    let refreshing = subscriptions.len();
+    info!(
+        story_id = update.story_id,
+        refreshing, "Looking at the subscriptions of an updated story"
+    );
+    tokio::spawn(async move {
+        let instance = Bytes::from(Uuid::new_v4().to_string());
+        for (owner, subscription) in subscriptions {
+            if let Err(e) = check_leased(&state, &instance, &owner, subscription).await {
+                warn!(error = %e, "Could not check subscription");
+            }
+        }
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "refreshing": refreshing })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::testing;
+
+    async fn call(app: &Router, authorization: Option<&str>) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/hooks/story-updated")
+            .header("content-type", "application/json");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        let body = Body::from(json!({ "storyId": 1 }).to_string());
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn the_hook_needs_its_secret() {
+        let (app, state_dir) = testing::app(&[("STORY_HOOK_SECRET", "hush")]).await;
+        for authorization in [None, Some("Bearer hus"), Some("Bearer hushh"), Some("hush")] {
+            let (status, _) = call(&app, authorization).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+        let (status, body) = call(&app, Some("Bearer hush")).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["refreshing"], 0);
+        std::fs::remove_dir_all(state_dir).unwrap();
+
+        let (app, state_dir) = testing::app(&[]).await;
+        let (status, _) = call(&app, Some("Bearer hush")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
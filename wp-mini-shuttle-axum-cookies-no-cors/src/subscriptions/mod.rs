@@ -0,0 +1,565 @@
+//! Stories followed by an API key, whose new chapters are delivered as they
+//! come out.
+//!
+//! `POST /subscriptions` follows a story for the request's `X-API-Key` (see
+//! `crate::profiles`), `GET /subscriptions` lists the key's subscriptions and
+//! `GET`, `PUT` and `DELETE /subscriptions/{id}` read, change and delete one.
+//! The story of each active subscription is looked at on the subscription's
+//! `schedule`, in its `timeZone` (see `schedule`); once it has more parts
+//! than when it was last looked at, the whole book is made again and sent to
+//! the subscription's `delivery`, or, for `weeklyDigest` subscriptions, the
+//! new chapters are kept for the next digest (see `digest`).
+//!
+//! `POST /subscriptions/{id}/pause` stops the checks until
+//! `POST /subscriptions/{id}/resume`. Deleting only marks a subscription:
+//! `POST /subscriptions/{id}/restore` brings it back for
+//! `SUBSCRIPTION_GRACE_DAYS`. A nightly cleanup removes the subscriptions
+//! deleted for longer, and deletes those paused for longer than
+//! `SUBSCRIPTION_MAX_PAUSED_DAYS`, which are then restorable for the grace
+//! period like any other. Subscriptions are kept in `crate::storage`.
+//!
+//! Watchers that learn of a new chapter first can `POST /hooks/story-updated`
+//! to have the story's subscriptions looked at straight away (see `hook`).
+
+mod digest;
+mod hook;
+mod schedule;
+
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use chrono::{Timelike, Utc};
+use serde_json::json;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::error::MyError;
+use crate::progress::Progress;
+use crate::storage::Storage;
+use crate::{generate, story_cache, unix_now, AppState, GenerateEpubRequest};
+
+pub use api_types::{PendingChapter, Subscription, SubscriptionMode, SubscriptionRequest};
+pub use hook::story_updated;
+
+const PREFIX: &str = "subscriptions/";
+const TICK: Duration = Duration::from_secs(60);
+/// How long an instance has to check a subscription before another may.
+const LEASE: Duration = Duration::from_secs(15 * 60);
+/// The hour, UTC, from which the nightly cleanup runs.
+const CLEANUP_HOUR: u32 = 3;
+const MAX_PER_KEY: usize = 100;
+
+fn key(owner: &str, id: &str) -> String {
+    format!("{}{}/{}", PREFIX, owner, id)
+}
+
+async fn load(storage: &dyn Storage, owner: &str, id: &str) -> Result<Option<Subscription>> {
+    let Some(bytes) = storage.get(&key(owner, id)).await? else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_slice(&bytes)?))
+}
+
+async fn save(storage: &dyn Storage, owner: &str, subscription: &Subscription) -> Result<()> {
+    let bytes = serde_json::to_vec(subscription)?;
+    storage
+        .put(&key(owner, &subscription.id), bytes.into())
+        .await
+}
+
+/// Every subscription under `prefix`, with the API key hash it belongs to.
+async fn all(storage: &dyn Storage, prefix: &str) -> Result<Vec<(String, Subscription)>> {
+    let mut subscriptions = Vec::new();
+    for key in storage.list(prefix).await? {
+        let Some((owner, id)) = key
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            continue;
+        };
+        match load(storage, owner, id).await {
+            Ok(Some(subscription)) => subscriptions.push((owner.to_string(), subscription)),
+            Ok(None) => {}
+            Err(e) => warn!(key, error = %e, "Skipping unreadable subscription"),
+        }
+    }
+    Ok(subscriptions)
+}
+
+fn storage_error(action: &'static str) -> impl FnOnce(anyhow::Error) -> MyError {
+    move |e| {
+        error!(error = %e, "Could not {} subscription", action);
+        MyError::Storage
+    }
+}
+
+/// The key's subscription `id`, deleted or not.
+async fn find(state: &AppState, owner: &str, id: &str) -> Result<Subscription, MyError> {
+    load(&*state.storage, owner, id)
+        .await
+        .map_err(storage_error("read"))?
+        .ok_or_else(|| MyError::NotFound("No such subscription".to_string()))
+}
+
+/// Like `find`, for changes only subscriptions that are not deleted take.
+async fn find_live(state: &AppState, owner: &str, id: &str) -> Result<Subscription, MyError> {
+    let subscription = find(state, owner, id).await?;
+    match subscription.deleted_at {
+        Some(_) => Err(MyError::NotFound(
+            "The subscription is deleted; restore it first".to_string(),
+        )),
+        None => Ok(subscription),
+    }
+}
+
+async fn store(
+    state: &AppState,
+    owner: &str,
+    mut subscription: Subscription,
+) -> Result<Json<Subscription>, MyError> {
+    subscription.updated_at = unix_now();
+    save(&*state.storage, owner, &subscription)
+        .await
+        .map_err(storage_error("save"))?;
+    Ok(Json(subscription))
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Subscription>>, MyError> {
+    let owner = state.profiles.required_key(&headers)?;
+    let subscriptions = all(&*state.storage, &format!("{}{}/", PREFIX, owner))
+        .await
+        .map_err(storage_error("list"))?;
+    Ok(Json(subscriptions.into_iter().map(|(_, s)| s).collect()))
+}
+
+fn validate(request: &SubscriptionRequest) -> Result<(), MyError> {
+    schedule::validate(request.schedule.as_deref(), request.time_zone.as_deref())
+        .map_err(MyError::InvalidRequest)
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<SubscriptionRequest>,
+) -> Result<(StatusCode, Json<Subscription>), MyError> {
+    state.maintenance.check().await?;
+    let owner = state.profiles.required_key(&headers)?;
+    validate(&request)?;
+    let existing = state
+        .storage
+        .list(&format!("{}{}/", PREFIX, owner))
+        .await
+        .map_err(storage_error("list"))?;
+    if existing.len() >= MAX_PER_KEY {
+        return Err(MyError::InvalidRequest(format!(
+            "An API key can have at most {} subscriptions",
+            MAX_PER_KEY
+        )));
+    }
+    let now = unix_now();
+    let subscription = Subscription {
+        id: Uuid::new_v4().to_string(),
+        story_id: request.story_id,
+        delivery: request.delivery,
+        embed_images: request.embed_images,
+        format: request.format,
+        mode: request.mode,
+        schedule: request.schedule,
+        time_zone: request.time_zone,
+        created_at: now,
+        updated_at: now,
+        checked_at: None,
+        parts_seen: None,
+        pending: Vec::new(),
+        digested_at: None,
+        paused_at: None,
+        deleted_at: None,
+    };
+    info!(story_id = subscription.story_id, "Subscribing to story");
+    let subscription = store(&state, &owner, subscription).await?;
+    Ok((StatusCode::CREATED, subscription))
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Subscription>, MyError> {
+    let owner = state.profiles.required_key(&headers)?;
+    find(&state, &owner, &id).await.map(Json)
+}
+
+pub async fn put(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(request): Json<SubscriptionRequest>,
+) -> Result<Json<Subscription>, MyError> {
+    state.maintenance.check().await?;
+    let owner = state.profiles.required_key(&headers)?;
+    validate(&request)?;
+    let mut subscription = find_live(&state, &owner, &id).await?;
+    if subscription.story_id != request.story_id {
+        subscription.parts_seen = None;
+        subscription.pending.clear();
+    }
+    if request.mode == SubscriptionMode::Immediate {
+        subscription.pending.clear();
+    }
+    subscription.story_id = request.story_id;
+    subscription.delivery = request.delivery;
+    subscription.embed_images = request.embed_images;
+    subscription.format = request.format;
+    subscription.mode = request.mode;
+    subscription.schedule = request.schedule;
+    subscription.time_zone = request.time_zone;
+    store(&state, &owner, subscription).await
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Subscription>, MyError> {
+    let owner = state.profiles.required_key(&headers)?;
+    let mut subscription = find_live(&state, &owner, &id).await?;
+    subscription.deleted_at = Some(unix_now());
+    store(&state, &owner, subscription).await
+}
+
+pub async fn restore(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Subscription>, MyError> {
+    let owner = state.profiles.required_key(&headers)?;
+    let mut subscription = find(&state, &owner, &id).await?;
+    subscription.deleted_at = None;
+    store(&state, &owner, subscription).await
+}
+
+pub async fn pause(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Subscription>, MyError> {
+    let owner = state.profiles.required_key(&headers)?;
+    let mut subscription = find_live(&state, &owner, &id).await?;
+    subscription.paused_at.get_or_insert_with(unix_now);
+    store(&state, &owner, subscription).await
+}
+
+pub async fn resume(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Subscription>, MyError> {
+    let owner = state.profiles.required_key(&headers)?;
+    let mut subscription = find_live(&state, &owner, &id).await?;
+    subscription.paused_at = None;
+    store(&state, &owner, subscription).await
+}
+
+/// Removes the subscriptions deleted for longer than `grace` and deletes those
+/// paused for longer than `max_paused`, as of `now`.
+async fn cleanup(
+    storage: &dyn Storage,
+    now: u64,
+    grace: Duration,
+    max_paused: Duration,
+) -> Result<()> {
+    let older_than = |at: u64, age: Duration| now.saturating_sub(at) > age.as_secs();
+    let (mut removed, mut expired) = (0, 0);
+    for (owner, mut subscription) in all(storage, PREFIX).await? {
+        match (subscription.deleted_at, subscription.paused_at) {
+            (Some(deleted_at), _) if older_than(deleted_at, grace) => {
+                storage.delete(&key(&owner, &subscription.id)).await?;
+                removed += 1;
+            }
+            (None, Some(paused_at)) if older_than(paused_at, max_paused) => {
+                subscription.deleted_at = Some(now);
+                subscription.updated_at = now;
+                save(storage, &owner, &subscription).await?;
+                expired += 1;
+            }
+            _ => {}
+        }
+    }
+    info!(removed, expired, "Cleaned up subscriptions");
+    Ok(())
+}
+
+/// Looks at the story of `subscription` and delivers its book, or keeps its
+/// new chapters for the digest, if it has new parts; the first look only
+/// notes how many it has.
+async fn check(state: &AppState, owner: &str, mut subscription: Subscription) -> Result<()> {
+    let story = story_cache::story(state, &state.anon_client, subscription.story_id, true).await?;
+    let parts = story.parts.as_deref().unwrap_or_default();
+    let seen = subscription.parts_seen.filter(|seen| parts.len() > *seen);
+    if let Some(seen) = seen
+        && subscription.mode == SubscriptionMode::WeeklyDigest
+    {
+        let story_title = story.title.as_deref().unwrap_or_default();
+        let new = parts[seen..].iter().filter_map(|part| {
+            Some(PendingChapter {
+                part_id: part.id?,
+                story_title: story_title.to_string(),
+                title: part.title.clone().unwrap_or_default(),
+            })
+        });
+        subscription.pending.extend(new);
+    } else if seen.is_some() {
+        let parts = parts.len();
+        info!(
+            story_id = subscription.story_id,
+            parts, "Delivering new chapters of a subscribed story"
+        );
+        let request = json!({
+            "storyId": subscription.story_id,
+            "embedImages": subscription.embed_images,
+            "format": subscription.format,
+            "delivery": subscription.delivery,
+        });
+        let payload: GenerateEpubRequest = serde_json::from_value(request)?;
+        if let Err(e) = generate(state, &payload, &Progress::default()).await {
+            // Seen parts stay as they were, so the next check tries again.
+            warn!(error = %e.status_and_message().1, "Could not deliver a subscribed story");
+            subscription.checked_at = Some(unix_now());
+            return save(&*state.storage, owner, &subscription).await;
+        }
+    }
+    subscription.parts_seen = Some(parts.len());
+    subscription.checked_at = Some(unix_now());
+    save(&*state.storage, owner, &subscription).await
+}
+
+/// Checks `subscription` unless another instance already is.
+async fn check_leased(
+    state: &AppState,
+    instance: &Bytes,
+    owner: &str,
+    subscription: Subscription,
+) -> Result<()> {
+    let lease = format!("subscription-check:{}", subscription.id);
+    if !state
+        .shared
+        .set_if_absent(&lease, instance.clone(), LEASE)
+        .await?
+    {
+        return Ok(());
+    }
+    let id = subscription.id.clone();
+    if let Err(e) = check(state, owner, subscription).await {
+        warn!(subscription = %id, error = %e, "Could not check subscription");
+    }
+    state.shared.delete_if(&lease, instance.clone()).await
+}
+
+/// Checks the active subscriptions that are due, one instance each.
+async fn check_due(state: &AppState, instance: &Bytes) -> Result<()> {
+    let now = unix_now();
+    for (owner, subscription) in all(&*state.storage, PREFIX).await? {
+        if subscription.active() && schedule::check_due(&subscription, now) {
+            check_leased(state, instance, &owner, subscription).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Starts checking subscriptions, sending digests and cleaning up nightly,
+/// on this instance.
+pub fn spawn_worker(state: AppState) {
+    let instance = Bytes::from(Uuid::new_v4().to_string());
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            tick.tick().await;
+            if state.maintenance.check().await.is_ok() {
+                if let Err(e) = check_due(&state, &instance).await {
+                    warn!(error = %e, "Could not check subscriptions");
+                }
+                if let Err(e) = digest::send_due(&state, &instance).await {
+                    warn!(error = %e, "Could not send subscription digests");
+                }
+            }
+            let now = Utc::now();
+            if now.hour() < CLEANUP_HOUR {
+                continue;
+            }
+            let night = format!("subscription-cleanup:{}", now.date_naive());
+            let claimed = state
+                .shared
+                .set_if_absent(&night, instance.clone(), Duration::from_secs(86_400))
+                .await
+                .unwrap_or(false);
+            if claimed
+                && let Err(e) = cleanup(
+                    &*state.storage,
+                    unix_now(),
+                    state.config.subscription_grace,
+                    state.config.subscription_max_paused,
+                )
+                .await
+            {
+                warn!(error = %e, "Could not clean up subscriptions");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::Router;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::config::StorageConfig;
+    use crate::testing;
+
+    async fn call(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("x-api-key", "key")
+            .header("content-type", "application/json")
+            .body(body.map_or(Body::empty(), |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn request() -> Value {
+        json!({ "storyId": 1, "delivery": { "type": "email", "to": "reader@example.com" } })
+    }
+
+    #[tokio::test]
+    async fn subscriptions_pause_and_restore() {
+        let (app, state_dir) = testing::app(&[("API_KEYS", "key")]).await;
+        let (status, created) = call(&app, "POST", "/subscriptions", Some(request())).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let path = format!("/subscriptions/{}", created["id"].as_str().unwrap());
+
+        let (_, paused) = call(&app, "POST", &format!("{}/pause", path), None).await;
+        assert!(paused["pausedAt"].is_u64());
+        let (_, resumed) = call(&app, "POST", &format!("{}/resume", path), None).await;
+        assert!(resumed["pausedAt"].is_null());
+
+        let (status, deleted) = call(&app, "DELETE", &path, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(deleted["deletedAt"].is_u64());
+        let (status, _) = call(&app, "POST", &format!("{}/pause", path), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, listed) = call(&app, "GET", "/subscriptions", None).await;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+
+        let (_, restored) = call(&app, "POST", &format!("{}/restore", path), None).await;
+        assert!(restored["deletedAt"].is_null());
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn schedules_and_time_zones_are_validated() {
+        let (app, state_dir) = testing::app(&[("API_KEYS", "key")]).await;
+        let with = |field: &str, value: &str| {
+            let mut request = request();
+            request[field] = value.into();
+            request
+        };
+        for invalid in [
+            with("schedule", "daily"),
+            with("timeZone", "Nowhere/Special"),
+        ] {
+            let (status, _) = call(&app, "POST", "/subscriptions", Some(invalid)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+        let valid = with("schedule", "0 9 * * *");
+        let (status, created) = call(&app, "POST", "/subscriptions", Some(valid)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let path = format!("/subscriptions/{}", created["id"].as_str().unwrap());
+        let (status, _) = call(&app, "PUT", &path, Some(with("timeZone", "UTC+2"))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, updated) =
+            call(&app, "PUT", &path, Some(with("timeZone", "Asia/Tokyo"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["timeZone"], "Asia/Tokyo");
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscriptions_need_an_api_key() {
+        let (app, state_dir) = testing::app(&[("API_KEYS", "key")]).await;
+        let request = Request::get("/subscriptions").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+
+    fn subscription(id: &str, paused_at: Option<u64>, deleted_at: Option<u64>) -> Subscription {
+        let request: SubscriptionRequest = serde_json::from_value(request()).unwrap();
+        Subscription {
+            id: id.to_string(),
+            story_id: request.story_id,
+            delivery: request.delivery,
+            embed_images: false,
+            format: request.format,
+            mode: request.mode,
+            schedule: None,
+            time_zone: None,
+            created_at: 0,
+            updated_at: 0,
+            checked_at: None,
+            parts_seen: None,
+            pending: Vec::new(),
+            digested_at: None,
+            paused_at,
+            deleted_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn cleanup_removes_old_deletions_and_long_pauses() {
+        let storage = crate::storage::open(&StorageConfig::Memory, "")
+            .await
+            .unwrap();
+        let storage = &*storage;
+        let day = 86_400;
+        let now = 100 * day;
+        for subscription in [
+            subscription("kept", Some(now - day), None),
+            subscription("expired", Some(now - 10 * day), None),
+            subscription("restorable", None, Some(now - day)),
+            subscription("removed", None, Some(now - 10 * day)),
+        ] {
+            save(storage, "owner", &subscription).await.unwrap();
+        }
+
+        let (grace, max_paused) = (Duration::from_secs(5 * day), Duration::from_secs(5 * day));
+        cleanup(storage, now, grace, max_paused).await.unwrap();
+
+        let stored = |id| load(storage, "owner", id);
+        assert!(stored("kept").await.unwrap().unwrap().deleted_at.is_none());
+        assert_eq!(
+            stored("expired").await.unwrap().unwrap().deleted_at,
+            Some(now)
+        );
+        assert!(stored("restorable").await.unwrap().is_some());
+        assert!(stored("removed").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,181 @@
+//! When a subscription's story is looked at, or its digest sent: a `schedule`
+//! of five fields as in a crontab (minute, hour, day of month, month and day
+//! of week), read in the subscription's `timeZone`, an IANA name such as
+//! `Europe/Lisbon`. Without them, stories are looked at hourly and digests
+//! sent on Monday mornings, in UTC.
+
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use std::str::FromStr;
+
+use super::{Subscription, SubscriptionMode};
+
+/// When stories are looked at by default.
+pub const CHECKS: &str = "0 * * * *";
+/// When digests are sent by default.
+pub const DIGESTS: &str = "0 8 * * 1";
+
+/// Why `schedule` or `time_zone` cannot be used, if either cannot.
+pub fn validate(schedule: Option<&str>, time_zone: Option<&str>) -> Result<(), String> {
+    if let Some(schedule) = schedule {
+        cron(schedule)?;
+    }
+    if let Some(time_zone) = time_zone {
+        zone(time_zone)?;
+    }
+    Ok(())
+}
+
+fn cron(schedule: &str) -> Result<Cron, String> {
+    let invalid = || {
+        format!(
+            "schedule must be a cron expression of 5 fields, such as \"{}\", not {:?}",
+            DIGESTS, schedule
+        )
+    };
+    if schedule.split_whitespace().count() != 5 {
+        return Err(invalid());
+    }
+    Cron::from_str(schedule).map_err(|_| invalid())
+}
+
+fn zone(time_zone: &str) -> Result<Tz, String> {
+    Tz::from_str(time_zone).map_err(|_| {
+        format!(
+            "timeZone must be an IANA time zone such as \"Europe/Lisbon\", not {:?}",
+            time_zone
+        )
+    })
+}
+
+/// Whether `schedule`, or `default` without one, comes round in `time_zone`
+/// after `last` and by `now`, both Unix seconds.
+fn due_on(
+    schedule: Option<&str>,
+    default: &str,
+    time_zone: Option<&str>,
+    last: u64,
+    now: u64,
+) -> bool {
+    let (Ok(cron), Ok(zone)) = (
+        cron(schedule.unwrap_or(default)),
+        zone(time_zone.unwrap_or("UTC")),
+    ) else {
+        return false;
+    };
+    let Some(last) = DateTime::<Utc>::from_timestamp(last as i64, 0) else {
+        return false;
+    };
+    cron.find_next_occurrence(&zone.from_utc_datetime(&last.naive_utc()), false)
+        .is_ok_and(|next| next.timestamp() <= now as i64)
+}
+
+/// Whether the story of `subscription` is to be looked at by `now`.
+pub fn check_due(subscription: &Subscription, now: u64) -> bool {
+    let schedule = match subscription.mode {
+        SubscriptionMode::Immediate => subscription.schedule.as_deref(),
+        // Its schedule is the digest's.
+        SubscriptionMode::WeeklyDigest => None,
+    };
+    let last = subscription.checked_at.unwrap_or(subscription.created_at);
+    subscription.checked_at.is_none()
+        || due_on(
+            schedule,
+            CHECKS,
+            subscription.time_zone.as_deref(),
+            last,
+            now,
+        )
+}
+
+/// Whether the digest of `subscription` is to be sent by `now`.
+pub fn digest_due(subscription: &Subscription, now: u64) -> bool {
+    let last = subscription.digested_at.unwrap_or(subscription.created_at);
+    subscription.mode == SubscriptionMode::WeeklyDigest
+        && due_on(
+            subscription.schedule.as_deref(),
+            DIGESTS,
+            subscription.time_zone.as_deref(),
+            last,
+            now,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_and_zones_are_checked() {
+        assert!(validate(Some("30 7 * * 1-5"), Some("America/Sao_Paulo")).is_ok());
+        assert!(validate(None, None).is_ok());
+        assert!(validate(Some("every monday"), None).is_err());
+        assert!(validate(Some("61 * * * *"), None).is_err());
+        // Seconds are not taken: a story looked at every second.
+        assert!(validate(Some("* * * * * *"), None).is_err());
+        assert!(validate(None, Some("Mars/Olympus_Mons")).is_err());
+    }
+
+    fn subscription(mode: SubscriptionMode, digested_at: Option<u64>) -> Subscription {
+        serde_json::from_value(serde_json::json!({
+            "id": "id",
+            "storyId": 1,
+            "delivery": { "type": "email", "to": "reader@example.com" },
+            "embedImages": false,
+            "format": "epub",
+            "mode": mode,
+            "createdAt": 0,
+            "updatedAt": 0,
+            "checkedAt": null,
+            "partsSeen": null,
+            "digestedAt": digested_at,
+            "pausedAt": null,
+            "deletedAt": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn digests_are_weekly_by_default() {
+        // Monday 2024-01-01 00:00 UTC.
+        let monday = 1_704_067_200;
+        let (hour, week) = (3600, 7 * 24 * 3600);
+        let digest = subscription(SubscriptionMode::WeeklyDigest, Some(monday + 9 * hour));
+        assert!(!digest_due(&digest, monday + week));
+        assert!(digest_due(&digest, monday + week + 8 * hour));
+        assert!(!digest_due(
+            &subscription(SubscriptionMode::Immediate, None),
+            monday + week + 8 * hour
+        ));
+    }
+
+    #[test]
+    fn schedules_are_read_in_the_time_zone() {
+        // Monday 2024-01-01 00:00 UTC.
+        let monday = 1_704_067_200;
+        let hour = 3600;
+        // 08:00 in Lisbon is 08:00 UTC in winter, in Sao Paulo 11:00 UTC.
+        assert!(due_on(
+            None,
+            DIGESTS,
+            Some("Europe/Lisbon"),
+            monday,
+            monday + 8 * hour
+        ));
+        assert!(!due_on(
+            None,
+            DIGESTS,
+            Some("America/Sao_Paulo"),
+            monday,
+            monday + 8 * hour
+        ));
+        assert!(due_on(
+            None,
+            DIGESTS,
+            Some("America/Sao_Paulo"),
+            monday,
+            monday + 11 * hour
+        ));
+    }
+}
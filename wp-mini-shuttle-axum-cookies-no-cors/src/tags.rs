@@ -0,0 +1,108 @@
+//! Wattpad tags in a stable form for library tools.
+//!
+//! Authors tag freely (`#SciFi`, `sci-fi`, `ScienceFiction`), so tags are
+//! trimmed, lowercased and stripped of `#`, the ones that name a genre are
+//! mapped onto Wattpad's genre list, and duplicates are dropped. The result
+//! is served by `crate::metadata` and written to the OPF as `dc:subject`s.
+
+/// Genres and the tags that name them, compared without spaces, `-` or `_`.
+const GENRES: [(&str, &[&str]); 22] = [
+    ("action", &["action"]),
+    ("adventure", &["adventure"]),
+    ("chick lit", &["chicklit"]),
+    ("fanfiction", &["fanfiction", "fanfic"]),
+    (
+        "fantasy",
+        &["fantasy", "highfantasy", "urbanfantasy", "darkfantasy"],
+    ),
+    ("general fiction", &["generalfiction", "fiction"]),
+    (
+        "historical fiction",
+        &["historicalfiction", "historical", "history"],
+    ),
+    ("horror", &["horror", "scary"]),
+    ("humor", &["humor", "humour", "comedy", "funny"]),
+    (
+        "lgbtq+",
+        &["lgbt", "lgbtq", "lgbtq+", "lgbtqia", "lgbtqia+", "queer"],
+    ),
+    ("mystery", &["mystery", "detective"]),
+    ("non-fiction", &["nonfiction"]),
+    ("paranormal", &["paranormal", "supernatural"]),
+    ("poetry", &["poetry", "poem", "poems"]),
+    ("romance", &["romance", "lovestory"]),
+    ("science fiction", &["sciencefiction", "scifi"]),
+    ("short story", &["shortstory", "shortstories", "oneshot"]),
+    ("spiritual", &["spiritual", "christian", "faith"]),
+    (
+        "teen fiction",
+        &["teenfiction", "teen", "youngadult", "ya", "highschool"],
+    ),
+    ("thriller", &["thriller", "suspense"]),
+    ("vampire", &["vampire", "vampires"]),
+    ("werewolf", &["werewolf", "werewolves"]),
+];
+const MAX_TAG_LENGTH: usize = 64;
+
+pub struct Tags {
+    /// Normalized tags, in the order the story lists them; tags naming a
+    /// genre are replaced by the genre.
+    pub tags: Vec<String>,
+    /// Genres the tags name, in the order they first come up.
+    pub genres: Vec<&'static str>,
+}
+
+impl Tags {
+    pub fn new(raw: &[String]) -> Self {
+        let mut tags: Vec<String> = Vec::new();
+        let mut genres = Vec::new();
+        for tag in raw {
+            let tag = tag
+                .trim()
+                .trim_start_matches('#')
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_lowercase();
+            if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+                continue;
+            }
+            let tag = match genre_of(&tag) {
+                Some(genre) => {
+                    if !genres.contains(&genre) {
+                        genres.push(genre);
+                    }
+                    genre.to_string()
+                }
+                None => tag,
+            };
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        Tags { tags, genres }
+    }
+
+    /// Genres first, then the other tags.
+    pub fn subjects(&self) -> Vec<String> {
+        let mut subjects: Vec<String> = self.genres.iter().map(|genre| genre.to_string()).collect();
+        subjects.extend(
+            self.tags
+                .iter()
+                .filter(|tag| genre_of(tag).is_none())
+                .cloned(),
+        );
+        subjects
+    }
+}
+
+fn genre_of(tag: &str) -> Option<&'static str> {
+    let compact: String = tag
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .collect();
+    GENRES
+        .iter()
+        .find(|(_, names)| names.contains(&compact.as_str()))
+        .map(|(genre, _)| *genre)
+}
@@ -0,0 +1,211 @@
+//! Telegram bot webhook: users message the bot a story link and get the EPUB back.
+//!
+//! Register it with `setWebhook?url=<host>/telegram/webhook&secret_token=<TELEGRAM_WEBHOOK_SECRET>`.
+//!
+//! Every story asked for goes through the same admission as `/generate-epub`
+//! (maintenance, the scraping detector and the rate limits), with the chat
+//! standing in for the client's address.
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info, instrument, warn};
+use wp_mini::field::StoryField;
+use wp_mini_epub::{download_story_to_memory, AppError};
+
+use crate::chapters::ChapterOptions;
+use crate::delivery::telegram::{send_document, send_message, ChatId};
+use crate::delivery::DeliveryFile;
+use crate::error::{map_anyhow_error, MyError};
+use crate::opf;
+use crate::story_url::{find_story_ref, resolve_story_id};
+use crate::tags::Tags;
+use crate::{admit_as, AppState, GenerateEpubRequest};
+
+const SECRET_HEADER: &str = "x-telegram-bot-api-secret-token";
+const USAGE: &str = "Send me a Wattpad story or chapter link and I'll reply with the EPUB.";
+
+#[derive(Deserialize)]
+pub struct Update {
+    message: Option<IncomingMessage>,
+}
+
+#[derive(Deserialize)]
+struct IncomingMessage {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+pub async fn webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(update): Json<Update>,
+) -> StatusCode {
+    let keys = state.config.keys();
+    let Some(telegram) = keys.telegram.as_ref() else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    let authorized = telegram.webhook_secret.as_deref().is_some_and(|secret| {
+        headers
+            .get(SECRET_HEADER)
+            .is_some_and(|value| value.as_bytes() == secret.as_bytes())
+    });
+    if !authorized {
+        warn!("Rejected Telegram update with a missing or wrong secret token");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let Some(message) = update.message else {
+        return StatusCode::OK;
+    };
+
+    // Telegram redelivers updates until it gets a 2xx, so acknowledge now and
+    // do the (potentially minutes long) download in the background.
+    let chat_id = ChatId::Id(message.chat.id);
+    let text = message.text.unwrap_or_default();
+    tokio::spawn(handle_message(state, chat_id, text));
+
+    StatusCode::OK
+}
+
+#[instrument(skip(state, text), fields(chat_id = %chat_id))]
+async fn handle_message(state: AppState, chat_id: ChatId, text: String) {
+    let keys = state.config.keys();
+    let Some(telegram) = keys.telegram.as_ref() else {
+        return;
+    };
+
+    let reply = match find_story_ref(&text) {
+        None => Some(USAGE.to_string()),
+        Some(story_ref) => match send_story(&state, &chat_id, story_ref).await {
+            Ok(()) => None,
+            Err(e) => Some(e.status_and_message().1),
+        },
+    };
+
+    if let Some(reply) = reply
+        && let Err(e) = send_message(
+            &state.delivery_client,
+            &telegram.bot_token,
+            &chat_id,
+            &reply,
+        )
+        .await
+    {
+        warn!(error = %e, "Failed to reply to Telegram chat");
+    }
+}
+
+async fn send_story(
+    state: &AppState,
+    chat_id: &ChatId,
+    story_ref: crate::story_url::StoryRef,
+) -> Result<(), MyError> {
+    let keys = state.config.keys();
+    let Some(telegram) = keys.telegram.as_ref() else {
+        return Ok(());
+    };
+
+    state.maintenance.check().await?;
+    let story_id = resolve_story_id(&state.anon_client, story_ref).await?;
+    info!(story_id, "Handling Telegram download request");
+    admit(state, chat_id, story_id).await?;
+
+    let _ = send_message(
+        &state.delivery_client,
+        &telegram.bot_token,
+        chat_id,
+        &format!("Downloading story {}…", story_id),
+    )
+    .await;
+
+    let epub_result = download_story_to_memory(
+        &state.anon_client,
+        story_id,
+        true,
+        state.config.chapter_concurrency(None),
+        Some(&[StoryField::Tags]),
+    )
+    .await
+    .map_err(map_anyhow_error)?;
+    let story = &epub_result.metadata;
+    let bytes = opf::label(
+        &Bytes::from(epub_result.epub_response),
+        "application/epub+zip",
+        &opf::identifier(story_id, true, &ChapterOptions::default()),
+        &Tags::new(story.tags.as_deref().unwrap_or_default()).subjects(),
+        &state.config.branding.name,
+        None,
+    )
+    .map_err(|e| {
+        error!(error = %e, "Could not label EPUB");
+        MyError::App(AppError::EpubGenerationFailed)
+    })?;
+
+    let file_name = format!("{}.epub", epub_result.sanitized_title);
+    let title = story.title.as_deref().unwrap_or(&file_name);
+    send_document(
+        &state.delivery_client,
+        &telegram.bot_token,
+        chat_id,
+        DeliveryFile {
+            title,
+            file_name: &file_name,
+            content_type: "application/epub+zip",
+            bytes,
+        },
+        Some(title),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Admits a download of `story_id` for `chat_id` as `/generate-epub` would
+/// one from a client of its own.
+async fn admit(state: &AppState, chat_id: &ChatId, story_id: u64) -> Result<(), MyError> {
+    let request = json!({ "storyId": story_id, "embedImages": true });
+    let mut payload: GenerateEpubRequest = serde_json::from_value(request)
+        .map_err(|e| MyError::InvalidRequest(format!("Invalid request: {}", e)))?;
+    let client = format!("telegram:{}", chat_id);
+    admit_as(state, &client, &HeaderMap::new(), &mut payload, "telegram").await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn the_bot_is_rate_limited_per_chat() {
+        let (state, state_dir) = testing::state(&[("RATE_LIMIT_PER_IP_PER_MINUTE", "1")]).await;
+        assert!(admit(&state, &ChatId::Id(1), 1).await.is_ok());
+        assert!(matches!(
+            admit(&state, &ChatId::Id(1), 2).await,
+            Err(MyError::Throttled(_))
+        ));
+        assert!(admit(&state, &ChatId::Id(2), 3).await.is_ok());
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_bot_counts_towards_the_wattpad_limit() {
+        let (state, state_dir) = testing::state(&[("WATTPAD_MAX_STORIES_PER_MINUTE", "1")]).await;
+        assert!(admit(&state, &ChatId::Id(1), 1).await.is_ok());
+        assert!(matches!(
+            admit(&state, &ChatId::Id(2), 2).await,
+            Err(MyError::Throttled(_))
+        ));
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
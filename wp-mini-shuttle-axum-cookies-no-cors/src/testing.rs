@@ -0,0 +1,42 @@
+//! The whole router, or its state, for tests, configured from secrets the way Shuttle
+//! would, with its state in a directory of its own.
+
+use axum::Router;
+use shuttle_runtime::SecretStore;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::AppState;
+
+/// An empty directory for one test's `STATE_DIR`.
+pub fn state_dir() -> PathBuf {
+    let state_dir = std::env::temp_dir().join(format!("wattdownload-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(state_dir.join("state")).unwrap();
+    state_dir
+}
+
+/// The configuration `secrets` make, with `STATE_DIR` set to `state_dir`.
+pub fn config(state_dir: &std::path::Path, secrets: &[(&str, &str)]) -> Config {
+    let secrets = secrets
+        .iter()
+        .copied()
+        .chain([("STATE_DIR", state_dir.to_str().unwrap())])
+        .map(|(name, value)| (name.to_string(), value.to_string().into()))
+        .collect();
+    Config::from_secrets(&SecretStore::new(secrets)).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// The router `secrets` configure, and its state directory to remove.
+pub async fn app(secrets: &[(&str, &str)]) -> (Router, PathBuf) {
+    let state_dir = state_dir();
+    let config = config(&state_dir, secrets);
+    (crate::app(config).await, state_dir)
+}
+
+/// The state `secrets` configure, and its state directory to remove.
+pub async fn state(secrets: &[(&str, &str)]) -> (AppState, PathBuf) {
+    let state_dir = state_dir();
+    let config = config(&state_dir, secrets);
+    (crate::state(config).await, state_dir)
+}
@@ -0,0 +1,156 @@
+//! `style` on `/generate-epub`: a stylesheet for the book, for readers whose
+//! e-ink screens want larger and looser text than Wattpad's markup, which
+//! sets no typography of its own, leaves them with.
+//!
+//! Every styled book gets `base.css` (line spacing, paragraph margins,
+//! images that fit the page), then the `light`, `dark` or `sepia` theme when
+//! one is asked for, then the request's own `fontFamily`, `fontSize` (in
+//! percent of the reader's default), `lineHeight` and `justify`. The sheet
+//! is added next to the package document and linked from every page, in
+//! each volume of a split book.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use std::io::{Cursor, Read, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::opf;
+
+pub use api_types::{FontFamily, Style, Theme};
+
+const BASE: &str = include_str!("themes/base.css");
+const FILE_NAME: &str = "reader-style.css";
+const MANIFEST_ID: &str = "reader-style";
+const FONT_SIZES: std::ops::RangeInclusive<u16> = 50..=300;
+const LINE_HEIGHTS: std::ops::RangeInclusive<f32> = 1.0..=3.0;
+
+fn theme_stylesheet(theme: Theme) -> &'static str {
+    match theme {
+        Theme::Light => include_str!("themes/light.css"),
+        Theme::Dark => include_str!("themes/dark.css"),
+        Theme::Sepia => include_str!("themes/sepia.css"),
+    }
+}
+
+fn family_css(family: FontFamily) -> &'static str {
+    match family {
+        FontFamily::Serif => "serif",
+        FontFamily::SansSerif => "sans-serif",
+        FontFamily::Monospace => "monospace",
+    }
+}
+
+pub fn validate(style: &Style) -> Result<(), String> {
+    if let Some(size) = style.font_size
+        && !FONT_SIZES.contains(&size)
+    {
+        return Err(format!(
+            "style.fontSize must be between {} and {}",
+            FONT_SIZES.start(),
+            FONT_SIZES.end()
+        ));
+    }
+    if let Some(height) = style.line_height
+        && !LINE_HEIGHTS.contains(&height)
+    {
+        return Err(format!(
+            "style.lineHeight must be between {} and {}",
+            LINE_HEIGHTS.start(),
+            LINE_HEIGHTS.end()
+        ));
+    }
+    Ok(())
+}
+
+/// The stylesheet put in the book.
+fn stylesheet(style: &Style) -> String {
+    let mut css = BASE.to_string();
+    if let Some(theme) = style.theme {
+        css.push('\n');
+        css.push_str(theme_stylesheet(theme));
+    }
+    let mut body = Vec::new();
+    if let Some(family) = style.font_family {
+        body.push(format!("font-family: {};", family_css(family)));
+    }
+    if let Some(height) = style.line_height {
+        body.push(format!("line-height: {};", height));
+    }
+    if let Some(justify) = style.justify {
+        body.push(match justify {
+            true => "text-align: justify;".to_string(),
+            false => "text-align: left;".to_string(),
+        });
+    }
+    if let Some(size) = style.font_size {
+        css.push_str(&format!("\nhtml {{\n  font-size: {}%;\n}}\n", size));
+    }
+    if !body.is_empty() {
+        css.push_str(&format!("\nbody {{\n  {}\n}}\n", body.join("\n  ")));
+    }
+    css
+}
+
+/// `bytes`, a book or volume ZIP of `content_type`, with `style` added to
+/// every book in it.
+pub fn apply(bytes: &Bytes, content_type: &str, style: &Style) -> Result<Bytes> {
+    let css = stylesheet(style);
+    opf::each_book(bytes, content_type, |book, _| restyle(&book, &css))
+}
+
+fn restyle(book: &[u8], css: &str) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(book))?;
+    let Some(opf_name) = archive.file_names().find(|name| name.ends_with(".opf")) else {
+        return Ok(book.to_vec());
+    };
+    // Where the package document is, with its trailing slash; `href`s in the
+    // manifest are relative to it.
+    let root = opf_name
+        .rfind('/')
+        .map_or("", |end| &opf_name[..=end])
+        .to_string();
+    let mut out = ZipWriter::new(Cursor::new(Vec::new()));
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for i in 0..archive.len() {
+        let name = archive.by_index_raw(i)?.name().to_string();
+        let page = name.ends_with(".xhtml") || name.ends_with(".html");
+        let Some(href) = name
+            .strip_prefix(root.as_str())
+            .filter(|_| page || name.ends_with(".opf"))
+        else {
+            out.raw_copy_file(archive.by_index_raw(i)?)?;
+            continue;
+        };
+        let mut text = String::new();
+        archive.by_index(i)?.read_to_string(&mut text)?;
+        match page {
+            true => {
+                let link = format!(
+                    r#"<link href="{}{}" rel="stylesheet" type="text/css"/>"#,
+                    "../".repeat(href.matches('/').count()),
+                    FILE_NAME
+                );
+                insert_before(&mut text, "</head>", &link);
+            }
+            false => {
+                let item = format!(
+                    r#"<item id="{}" href="{}" media-type="text/css"/>"#,
+                    MANIFEST_ID, FILE_NAME
+                );
+                insert_before(&mut text, "</manifest>", &item);
+            }
+        }
+        out.start_file(name, stored)?;
+        out.write_all(text.as_bytes())?;
+    }
+    out.start_file(format!("{}{}", root, FILE_NAME), stored)?;
+    out.write_all(css.as_bytes())?;
+    Ok(out.finish()?.into_inner())
+}
+
+fn insert_before(text: &mut String, end: &str, content: &str) {
+    if let Some(at) = text.find(end) {
+        text.insert_str(at, content);
+    }
+}
@@ -0,0 +1,191 @@
+//! `GET /story/{id}/chapters/{part_id}/text`: a chapter as plain text for
+//! text-to-speech readers.
+//!
+//! Paragraphs are separated by a blank line, the title comes first, and
+//! punctuation is normalized to what speech engines read well: straight
+//! quotes, `...` for ellipses, commas for dashes between words, no runs of
+//! `!!!`, and no scene break lines such as `***`.
+
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use regex::Regex;
+use std::sync::LazyLock;
+use wp_mini::field::PartField;
+use wp_mini::{WattpadClient, WattpadError};
+use wp_mini_epub::AppError;
+
+use crate::abuse;
+use crate::error::MyError;
+use crate::part_cache;
+use crate::AppState;
+
+/// Elements whose end starts a new paragraph.
+pub const BLOCKS: [&str; 12] = [
+    "p",
+    "div",
+    "br",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "blockquote",
+    "hr",
+];
+
+static DASH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\s*[\u{2013}\u{2014}\u{2015}]+\s*|\s+-{1,2}\s+|(\w)--(\w)")
+        .expect("the dash pattern is valid")
+});
+static REPEATED: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([!?])[!?]*").expect("the repeat pattern is valid"));
+static SPACES: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\s+").expect("the space pattern is valid"));
+
+pub async fn text(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((story_id, part_id)): Path<(u64, u64)>,
+) -> Result<Response, MyError> {
+    state
+        .scraping
+        .check(&*state.shared, &abuse::client_key(&headers), story_id)
+        .await
+        .map_err(MyError::Throttled)?;
+
+    let wattpad = WattpadClient::builder()
+        .reqwest_client((*state.anon_client).clone())
+        .build();
+    let part = wattpad
+        .story
+        .get_part_info(part_id, Some(&[PartField::Title, PartField::GroupId]))
+        .await
+        .map_err(|e| match e {
+            WattpadError::StoryNotFound => {
+                MyError::NotFound(format!("Part {} could not be found", part_id))
+            }
+            _ => MyError::App(AppError::MetadataFetchFailed),
+        })?;
+    if part.group_id.and_then(|id| id.parse().ok()) != Some(story_id) {
+        return Err(MyError::NotFound(format!(
+            "Part {} is not part of story {}",
+            part_id, story_id
+        )));
+    }
+    let html = part_cache::text(&state, &state.anon_client, part_id, true)
+        .await
+        .map_err(|_| MyError::App(AppError::DownloadFailed))?;
+
+    let mut paragraphs: Vec<String> = part.title.iter().map(|title| normalize(title)).collect();
+    paragraphs.extend(
+        paragraphs_of(&html)
+            .iter()
+            .map(|paragraph| normalize(paragraph))
+            .filter(|paragraph| paragraph.chars().any(char::is_alphanumeric)),
+    );
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        paragraphs.join("\n\n"),
+    )
+        .into_response())
+}
+
+/// The text of `html`, split at block elements.
+pub fn paragraphs_of(html: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        current.push_str(&decode_entities(&rest[..text_end]));
+        let tail = &rest[text_end..];
+        let tag_end = tail.find('>').map_or(tail.len(), |end| end + 1);
+        let name: String = tail[..tag_end]
+            .trim_start_matches(['<', '/'])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if BLOCKS.contains(&name.as_str()) && !current.trim().is_empty() {
+            paragraphs.push(std::mem::take(&mut current));
+        }
+        rest = &tail[tag_end..];
+    }
+    if !current.trim().is_empty() {
+        paragraphs.push(current);
+    }
+    paragraphs
+}
+
+pub fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        let decoded = tail.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &tail[1..end];
+            let c = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => ' ',
+                _ => {
+                    let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end + 1))
+        });
+        match decoded {
+            Some((c, length)) => {
+                out.push(c);
+                rest = &tail[length..];
+            }
+            None => {
+                out.push('&');
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `text` with punctuation a speech engine reads well.
+fn normalize(text: &str) -> String {
+    let text: String = text
+        .chars()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201B}' | '\u{2032}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201F}' | '\u{2033}' | '\u{00AB}' | '\u{00BB}' => '"',
+            '\u{00A0}' | '\u{2009}' | '\u{200A}' => ' ',
+            c => c,
+        })
+        .collect();
+    let text = text.replace('\u{2026}', "...");
+    let text = DASH.replace_all(&text, |captures: &regex::Captures| {
+        match (captures.get(1), captures.get(2)) {
+            (Some(before), Some(after)) => format!("{}, {}", before.as_str(), after.as_str()),
+            _ => ", ".to_string(),
+        }
+    });
+    let text = REPEATED.replace_all(&text, |captures: &regex::Captures| {
+        let run = &captures[0];
+        match (run.contains('?'), run.contains('!')) {
+            (true, true) => "?!".to_string(),
+            _ => captures[1].to_string(),
+        }
+    });
+    // A dash that opened or closed the text.
+    let text = text.trim().trim_matches([',', ' ']);
+    SPACES.replace_all(text, " ").into_owned()
+}
@@ -0,0 +1,233 @@
+//! Resumable uploads following the tus 1.0 core protocol (plus the `creation`
+//! and `termination` extensions), for endpoints that take a file from the user.
+//!
+//! `POST /uploads` with `Upload-Length` creates an upload and returns its
+//! `Location`; the client then `PATCH`es chunks at `Upload-Offset` and, after a
+//! dropped connection, asks `HEAD` for the offset to resume from. Uploads are
+//! kept in memory by id until nobody has touched them for a while.
+
+use api_types::ErrorBody;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::AppState;
+
+const TUS_VERSION: &str = "1.0.0";
+pub const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+pub const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+pub const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+
+struct Upload {
+    length: usize,
+    /// Raw `Upload-Metadata`, e.g. `filename <base64>`.
+    metadata: Option<String>,
+    bytes: Vec<u8>,
+    touched_at: Instant,
+}
+
+pub struct UploadStore {
+    uploads: Mutex<HashMap<String, Upload>>,
+    max_upload_bytes: usize,
+    /// Uploads nobody has touched for this long are dropped.
+    ttl: Duration,
+}
+
+impl UploadStore {
+    pub fn new(max_upload_bytes: usize, ttl: Duration) -> Self {
+        UploadStore {
+            uploads: Mutex::new(HashMap::new()),
+            max_upload_bytes,
+            ttl,
+        }
+    }
+}
+
+fn tus_response(status: StatusCode) -> Response {
+    let mut response = status.into_response();
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+fn reject(status: StatusCode, message: &str) -> Response {
+    let body = Json(ErrorBody {
+        error: message.to_string(),
+    });
+    let mut response = (status, body).into_response();
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+fn header_number(headers: &HeaderMap, name: &HeaderName) -> Option<usize> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// tus requires every request but `OPTIONS` to name the protocol version;
+/// returns the rejection for those that don't.
+fn check_version(headers: &HeaderMap) -> Option<Response> {
+    match headers.get(TUS_RESUMABLE) {
+        Some(version) if version == TUS_VERSION => None,
+        _ => Some(reject(
+            StatusCode::PRECONDITION_FAILED,
+            "Tus-Resumable: 1.0.0 is required",
+        )),
+    }
+}
+
+pub async fn options(State(state): State<AppState>) -> Response {
+    let mut response = tus_response(StatusCode::NO_CONTENT);
+    let headers = response.headers_mut();
+    headers.insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+    headers.insert(
+        "tus-extension",
+        HeaderValue::from_static("creation,termination"),
+    );
+    headers.insert("tus-max-size", state.uploads.max_upload_bytes.into());
+    response
+}
+
+pub async fn create(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(response) = check_version(&headers) {
+        return response;
+    }
+    if let Err(e) = state.maintenance.check().await {
+        return e.into_response();
+    }
+    let Some(length) = header_number(&headers, &UPLOAD_LENGTH) else {
+        return reject(StatusCode::BAD_REQUEST, "Upload-Length is required");
+    };
+    if length > state.uploads.max_upload_bytes {
+        return reject(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Upload-Length exceeds Tus-Max-Size",
+        );
+    }
+
+    let id = Uuid::new_v4().simple().to_string();
+    {
+        let mut uploads = state.uploads.uploads.lock().unwrap();
+        uploads.retain(|_, upload| upload.touched_at.elapsed() < state.uploads.ttl);
+        uploads.insert(
+            id.clone(),
+            Upload {
+                length,
+                metadata: headers
+                    .get(UPLOAD_METADATA)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string),
+                bytes: Vec::new(),
+                touched_at: Instant::now(),
+            },
+        );
+    }
+    info!(upload_id = %id, length, "Created upload");
+
+    let location = state.config.public_url(&format!("/uploads/{}", id));
+    let mut response = tus_response(StatusCode::CREATED);
+    if let Ok(location) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    response
+}
+
+pub async fn head(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(response) = check_version(&headers) {
+        return response;
+    }
+    let uploads = state.uploads.uploads.lock().unwrap();
+    let Some(upload) = uploads.get(&id) else {
+        return tus_response(StatusCode::NOT_FOUND);
+    };
+
+    let mut response = tus_response(StatusCode::OK);
+    let headers = response.headers_mut();
+    headers.insert(UPLOAD_OFFSET, upload.bytes.len().into());
+    headers.insert(UPLOAD_LENGTH, upload.length.into());
+    if let Some(metadata) = upload
+        .metadata
+        .as_deref()
+        .and_then(|metadata| HeaderValue::from_str(metadata).ok())
+    {
+        headers.insert(UPLOAD_METADATA, metadata);
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+pub async fn patch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    chunk: Bytes,
+) -> Response {
+    if let Some(response) = check_version(&headers) {
+        return response;
+    }
+    if headers.get(header::CONTENT_TYPE).map(|v| v.as_bytes())
+        != Some(b"application/offset+octet-stream")
+    {
+        return reject(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/offset+octet-stream",
+        );
+    }
+    let Some(offset) = header_number(&headers, &UPLOAD_OFFSET) else {
+        return reject(StatusCode::BAD_REQUEST, "Upload-Offset is required");
+    };
+
+    let mut uploads = state.uploads.uploads.lock().unwrap();
+    let Some(upload) = uploads.get_mut(&id) else {
+        return tus_response(StatusCode::NOT_FOUND);
+    };
+    if offset != upload.bytes.len() {
+        return reject(
+            StatusCode::CONFLICT,
+            "Upload-Offset does not match the current offset",
+        );
+    }
+    if offset + chunk.len() > upload.length {
+        return reject(StatusCode::BAD_REQUEST, "Chunk runs past Upload-Length");
+    }
+
+    upload.bytes.extend_from_slice(&chunk);
+    upload.touched_at = Instant::now();
+    if upload.bytes.len() == upload.length {
+        info!(upload_id = %id, "Upload complete");
+    }
+
+    let mut response = tus_response(StatusCode::NO_CONTENT);
+    response
+        .headers_mut()
+        .insert(UPLOAD_OFFSET, upload.bytes.len().into());
+    response
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(response) = check_version(&headers) {
+        return response;
+    }
+    match state.uploads.uploads.lock().unwrap().remove(&id) {
+        Some(_) => tus_response(StatusCode::NO_CONTENT),
+        None => tus_response(StatusCode::NOT_FOUND),
+    }
+}
@@ -0,0 +1,192 @@
+//! Anonymous counts of which request options are used, so maintainers can
+//! tell which features matter. Off unless the operator sets
+//! `USAGE_METRICS=true`; `GET /metrics` then serves them in the Prometheus
+//! text format, and does not exist otherwise.
+//!
+//! Only totals are kept: no story IDs, no client addresses, no option
+//! values beyond a few fixed choices, and sizes only as coarse buckets.
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::error::MyError;
+use crate::images::{GifImages, SvgImages};
+use crate::{AppState, Epub, GenerateEpubRequest};
+
+/// Upper bounds of the book size buckets, in MB.
+const SIZE_BUCKETS_MB: [usize; 4] = [1, 10, 25, 50];
+
+/// Counters by metric and label value.
+#[derive(Default)]
+pub struct Usage {
+    enabled: bool,
+    counts: Mutex<BTreeMap<(&'static str, String), u64>>,
+}
+
+impl Usage {
+    pub fn new(enabled: bool) -> Self {
+        Usage {
+            enabled,
+            ..Usage::default()
+        }
+    }
+
+    fn add(&self, metric: &'static str, label: impl Into<String>) {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry((metric, label.into()))
+            .or_default() += 1;
+    }
+
+    /// Counts an admitted request to `endpoint` and each option it uses.
+    pub fn request(&self, endpoint: &'static str, payload: &GenerateEpubRequest) {
+        if !self.enabled {
+            return;
+        }
+        self.add("requests", endpoint);
+        let chapters = &payload.chapters;
+        let options = [
+            ("embedImages", payload.embed_images),
+            ("cookies", payload.cookies.is_some()),
+            ("coverImage", payload.cover_image.is_some()),
+            ("style", payload.style.is_some()),
+            (
+                "splitEveryChapters",
+                chapters.split_every_chapters.is_some(),
+            ),
+            ("splitEveryWords", chapters.split_every_words.is_some()),
+            ("partOrder", chapters.part_order.is_some()),
+            (
+                "excludeTitlePatterns",
+                !chapters.exclude_title_patterns.is_empty(),
+            ),
+            ("mergeSplitChapters", chapters.merge_split_chapters),
+            ("replacements", !chapters.replacements.is_empty()),
+            (
+                "regexReplacements",
+                chapters.replacements.iter().any(|rule| rule.regex),
+            ),
+            ("plugins", !chapters.plugins.is_empty()),
+            ("altText", chapters.alt_text),
+            ("statsPage", chapters.stats_page),
+            ("noGeneratedCover", chapters.no_generated_cover),
+            ("svgImages=keep", chapters.svg_images == SvgImages::Keep),
+            (
+                "svgImages=rasterize",
+                chapters.svg_images == SvgImages::Rasterize,
+            ),
+            (
+                "gifImages=firstFrame",
+                chapters.gif_images == GifImages::FirstFrame,
+            ),
+        ];
+        for (option, used) in options {
+            if used {
+                self.add("options", option);
+            }
+        }
+        if let Some(delivery) = &payload.delivery {
+            self.add("deliveries", delivery.target());
+        }
+        for notification in &payload.notifications {
+            self.add("notifications", notification.target());
+        }
+    }
+
+    /// Counts a piece of an upstream response that was not as expected (see
+    /// `crate::drift`).
+    pub fn drift(&self, field: &'static str) {
+        if self.enabled {
+            self.add("drift", field);
+        }
+    }
+
+    /// Counts a generated book by format and size bucket.
+    pub fn book(&self, epub: &Epub) {
+        if !self.enabled {
+            return;
+        }
+        self.add("books", epub.content_type);
+        let mb = epub.bytes.len() / (1024 * 1024);
+        let bucket = match SIZE_BUCKETS_MB.iter().find(|max| mb < **max) {
+            Some(max) => format!("<{}MB", max),
+            None => format!(">={}MB", SIZE_BUCKETS_MB[SIZE_BUCKETS_MB.len() - 1]),
+        };
+        self.add("book_sizes", bucket);
+    }
+
+    fn render(&self) -> String {
+        let counts = self.counts.lock().unwrap();
+        let mut out = String::new();
+        let mut last = None;
+        for ((metric, label), count) in counts.iter() {
+            let (name, label_name, help) = describe(metric);
+            if last != Some(metric) {
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                last = Some(metric);
+            }
+            let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+            let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label_name, label, count);
+        }
+        out
+    }
+}
+
+/// Prometheus name, label name and help text of `metric`.
+fn describe(metric: &str) -> (&'static str, &'static str, &'static str) {
+    match metric {
+        "requests" => (
+            "wattdownload_requests_total",
+            "endpoint",
+            "Admitted generation requests.",
+        ),
+        "options" => (
+            "wattdownload_option_requests_total",
+            "option",
+            "Requests using each option.",
+        ),
+        "deliveries" => (
+            "wattdownload_delivery_requests_total",
+            "target",
+            "Requests delivering to each target.",
+        ),
+        "notifications" => (
+            "wattdownload_notifications_total",
+            "target",
+            "Requested notifications by target.",
+        ),
+        "books" => (
+            "wattdownload_books_total",
+            "content_type",
+            "Generated books by format.",
+        ),
+        "drift" => (
+            "wattdownload_upstream_drift_total",
+            "field",
+            "Upstream responses missing something books are made from.",
+        ),
+        _ => (
+            "wattdownload_book_sizes_total",
+            "size",
+            "Generated books by size.",
+        ),
+    }
+}
+
+pub async fn metrics(State(state): State<AppState>) -> Result<Response, MyError> {
+    if !state.usage.enabled {
+        return Err(MyError::NotFound("Not found".to_string()));
+    }
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.usage.render(),
+    )
+        .into_response())
+}
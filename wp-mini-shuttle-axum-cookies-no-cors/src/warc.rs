@@ -0,0 +1,180 @@
+//! `format: "warc"`: the book together with what Wattpad sent for it, as a
+//! WARC file for archives that have to show where a copy came from.
+//!
+//! `wp_mini_epub` makes its requests out of sight, so once the EPUB is made
+//! the story's metadata and the text of each of its parts are fetched again
+//! through the same client, and each exchange is recorded as a `request` and
+//! a `response` record, with the response as it came over the wire. The
+//! EPUB follows as a `resource` record named by its `dc:identifier` (see
+//! `crate::opf`). A part edited in between the two fetches differs from the
+//! book, which the record dates show. Records carry SHA-256 block digests.
+//! Session cookies are sent but never recorded.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use chrono::{SecondsFormat, Utc};
+use futures_util::stream::{self, StreamExt};
+use reqwest::Client;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use tracing::{error, info};
+use uuid::Uuid;
+use wp_mini_epub::AppError;
+
+use crate::error::{map_upstream_error, MyError};
+use crate::http_client::{self, UpstreamRateLimited};
+use crate::{encode_hex, opf, Epub, GenerateEpubRequest};
+
+const BASE_URL: &str = "https://www.wattpad.com";
+/// What the book is made from, as `wp_mini_epub` asks for it.
+const STORY_FIELDS: &str = "id,title,description,cover,mature,completed,language(id),user(name),tags,parts(id,title,createDate,modifyDate,length)";
+
+/// `epub` with the upstream responses it was made from, as a WARC file.
+pub async fn archive(
+    client: &Client,
+    payload: &GenerateEpubRequest,
+    epub: Epub,
+    concurrency: usize,
+) -> Result<Epub, MyError> {
+    info!("Archiving upstream responses");
+    let failed = |e: anyhow::Error| {
+        error!(error = %e, "Could not archive upstream responses");
+        match e.is::<UpstreamRateLimited>() {
+            true => map_upstream_error(e),
+            false => MyError::App(AppError::DownloadFailed),
+        }
+    };
+    let mut warc = Vec::new();
+    record(
+        &mut warc,
+        "warcinfo",
+        None,
+        "application/warc-fields",
+        format!(
+            "software: WattDownload\r\nformat: WARC File Format 1.1\r\nstory-id: {}\r\n",
+            payload.story_id
+        )
+        .as_bytes(),
+    );
+
+    let story_url = format!(
+        "{}/api/v3/stories/{}?fields={}",
+        BASE_URL, payload.story_id, STORY_FIELDS
+    );
+    let story = exchange(&mut warc, client, &story_url)
+        .await
+        .map_err(failed)?;
+    let parts: Vec<u64> = serde_json::from_slice::<Value>(&story)
+        .ok()
+        .and_then(|story| {
+            let parts = story.get("parts")?.as_array()?.clone();
+            Some(
+                parts
+                    .iter()
+                    .filter_map(|part| part["id"].as_u64())
+                    .collect(),
+            )
+        })
+        .unwrap_or_default();
+    let fetches: Vec<_> = parts
+        .iter()
+        .map(|part_id| {
+            let url = format!("{}/apiv2/?m=storytext&id={}", BASE_URL, part_id);
+            async move {
+                let response = fetch(client, &url).await;
+                (url, response)
+            }
+        })
+        .collect();
+    let mut fetches = stream::iter(fetches).buffered(concurrency);
+    while let Some((url, response)) = fetches.next().await {
+        let (head, body) = response.map_err(failed)?;
+        write_exchange(&mut warc, &url, &head, &body);
+    }
+
+    let identifier = opf::identifier(payload.story_id, payload.embed_images, &payload.chapters);
+    record(
+        &mut warc,
+        "resource",
+        Some(&identifier),
+        epub.content_type,
+        &epub.bytes,
+    );
+    info!(parts = parts.len(), "Archived upstream responses");
+    let stem = epub.file_name.trim_end_matches(".epub");
+    Ok(Epub {
+        file_name: format!("{}.warc", stem),
+        content_type: "application/warc",
+        bytes: Bytes::from(warc),
+        ..epub
+    })
+}
+
+/// Fetches `url`, records the exchange and returns the response body.
+async fn exchange(warc: &mut Vec<u8>, client: &Client, url: &str) -> Result<Bytes> {
+    let (head, body) = fetch(client, url).await?;
+    write_exchange(warc, url, &head, &body);
+    Ok(body)
+}
+
+/// The status line and headers of the response to `url`, as HTTP/1.1 puts
+/// them, and its body.
+async fn fetch(client: &Client, url: &str) -> Result<(Vec<u8>, Bytes)> {
+    let response = http_client::send(client.get(url)).await?;
+    let mut head = Vec::new();
+    write!(
+        head,
+        "HTTP/1.1 {} {}\r\n",
+        response.status().as_u16(),
+        response.status().canonical_reason().unwrap_or_default()
+    )?;
+    for (name, value) in response.headers() {
+        head.extend_from_slice(name.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    Ok((head, response.bytes().await?))
+}
+
+fn write_exchange(warc: &mut Vec<u8>, url: &str, head: &[u8], body: &[u8]) {
+    let target = url.strip_prefix(BASE_URL).unwrap_or(url);
+    let request = format!("GET {} HTTP/1.1\r\nHost: www.wattpad.com\r\n\r\n", target);
+    record(
+        warc,
+        "request",
+        Some(url),
+        "application/http;msgtype=request",
+        request.as_bytes(),
+    );
+    record(
+        warc,
+        "response",
+        Some(url),
+        "application/http;msgtype=response",
+        &[head, body].concat(),
+    );
+}
+
+fn record(warc: &mut Vec<u8>, kind: &str, target: Option<&str>, content_type: &str, block: &[u8]) {
+    let mut header = format!(
+        "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: <urn:uuid:{}>\r\nWARC-Date: {}\r\n",
+        kind,
+        Uuid::new_v4(),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    if let Some(target) = target {
+        header.push_str(&format!("WARC-Target-URI: {}\r\n", target));
+    }
+    header.push_str(&format!(
+        "WARC-Block-Digest: sha256:{}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        encode_hex(&Sha256::digest(block)),
+        content_type,
+        block.len()
+    ));
+    warc.extend_from_slice(header.as_bytes());
+    warc.extend_from_slice(block);
+    warc.extend_from_slice(b"\r\n\r\n");
+}
@@ -0,0 +1,113 @@
+//! `GET /`: a page to paste a story link into and download it, for people
+//! without the extension. It only calls the public API (`/generate-epub`, and
+//! `/jobs` for stories too big to wait for), so it has no access the API
+//! does not give anyone. `WEB_UI=false` leaves these routes out for
+//! deployments that only serve the API.
+//!
+//! The page, script and stylesheet are built into the binary; the script is
+//! served as a file of its own so the page's CSP needs no `unsafe-inline`.
+//! The page and stylesheet are filled in with the deployment's branding
+//! (see `BrandingConfig`) once, when the routes are built, and the page in
+//! each language of `crate::i18n`; each request gets the one it negotiates.
+
+use axum::http::{header, HeaderMap, HeaderValue, Uri};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::book::escape;
+use crate::config::BrandingConfig;
+use crate::i18n::{self, Lang};
+use crate::AppState;
+
+const CONTENT_SECURITY_POLICY: HeaderValue = HeaderValue::from_static(
+    "default-src 'none'; script-src 'self'; style-src 'self'; connect-src 'self'; \
+     form-action 'none'; base-uri 'none'; frame-ancestors 'none'",
+);
+
+pub fn routes(branding: &BrandingConfig) -> Router<AppState> {
+    let pages: Arc<HashMap<Lang, String>> = Arc::new(
+        Lang::ALL
+            .into_iter()
+            .map(|lang| (lang, page(branding, lang)))
+            .collect(),
+    );
+    let stylesheet =
+        include_str!("web_ui/app.css").replace("{accent_color}", &branding.accent_color);
+    Router::new()
+        .route(
+            "/",
+            get(move |headers: HeaderMap, uri: Uri| {
+                let lang = i18n::negotiate(&headers, uri.query());
+                index(pages[&lang].clone(), lang)
+            }),
+        )
+        .route("/app.js", get(script))
+        .route("/app.css", get(move || styles(stylesheet)))
+}
+
+fn page(branding: &BrandingConfig, lang: Lang) -> String {
+    let mut footer = Vec::new();
+    if let Some(notice) = &branding.footer {
+        footer.push(escape(notice));
+    }
+    if let Some(email) = &branding.contact_email {
+        let email = escape(email).replace('"', "&quot;");
+        footer.push(format!(
+            r#"{0}: <a href="mailto:{1}">{1}</a>"#,
+            escape(i18n::message(lang, "ui.contact")),
+            email
+        ));
+    }
+    let footer = match footer.is_empty() {
+        true => String::new(),
+        false => format!("  <footer>{}</footer>\n", footer.join(" · ")),
+    };
+    let script: HashMap<&str, &str> = i18n::messages(lang, "script.").collect();
+    // `<` escaped, so no message can close the script element.
+    let script = serde_json::to_string(&script)
+        .expect("messages serialize")
+        .replace('<', "\\u003c");
+    let page = i18n::messages(lang, "ui.").fold(
+        include_str!("web_ui/index.html").to_string(),
+        |page, (key, message)| page.replace(&format!("{{{}}}", key), &escape(message)),
+    );
+    page.replace("{lang}", lang.code())
+        .replace("{messages}", &script)
+        .replace("{service_name}", &escape(&branding.name))
+        .replace("{footer}", &footer)
+}
+
+async fn index(page: String, lang: Lang) -> impl IntoResponse {
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            ),
+            (header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY),
+            (
+                header::CONTENT_LANGUAGE,
+                HeaderValue::from_static(lang.code()),
+            ),
+            (header::VARY, HeaderValue::from_static("accept-language")),
+        ],
+        page,
+    )
+}
+
+async fn script() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/javascript; charset=utf-8")],
+        include_str!("web_ui/app.js"),
+    )
+}
+
+async fn styles(stylesheet: String) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/css; charset=utf-8")],
+        stylesheet,
+    )
+}
@@ -0,0 +1,128 @@
+"use strict";
+
+const form = document.getElementById("download");
+const status = document.getElementById("status");
+// The page's language's messages, filled in by the server.
+const messages = JSON.parse(document.getElementById("messages").textContent);
+
+// Errors come back in the page's language, which `?lang=` may have picked.
+const language = { "Accept-Language": document.documentElement.lang };
+
+function t(key, args = {}) {
+  return Object.entries(args).reduce(
+    (message, [name, value]) => message.replace(`{${name}}`, value),
+    messages[key] || key,
+  );
+}
+
+function show(message, isError) {
+  status.textContent = message;
+  status.classList.toggle("error", Boolean(isError));
+}
+
+// A story link or a bare story ID. Links to single parts cannot be told
+// apart from other pages without asking Wattpad, so they are refused.
+function storyId(text) {
+  const trimmed = text.trim();
+  if (/^\d+$/.test(trimmed)) {
+    return Number(trimmed);
+  }
+  const match = trimmed.match(/wattpad\.com\/story\/(\d+)/);
+  return match ? Number(match[1]) : null;
+}
+
+function fileName(response) {
+  const disposition = response.headers.get("content-disposition") || "";
+  const encoded = disposition.match(/filename\*=UTF-8''([^;]+)/);
+  if (encoded) {
+    return decodeURIComponent(encoded[1]);
+  }
+  const plain = disposition.match(/filename="([^"]+)"/);
+  return plain ? plain[1] : "story";
+}
+
+function save(blob, name) {
+  const url = URL.createObjectURL(blob);
+  const link = document.createElement("a");
+  link.href = url;
+  link.download = name;
+  document.body.appendChild(link);
+  link.click();
+  link.remove();
+  setTimeout(() => URL.revokeObjectURL(url), 60000);
+}
+
+async function errorOf(response) {
+  try {
+    const body = await response.json();
+    return body.error || response.statusText;
+  } catch {
+    return response.statusText;
+  }
+}
+
+// Large stories are turned into a job; waits for it and fetches the file.
+async function awaitJob(job) {
+  let since = job.version;
+  while (job.state !== "completed" && job.state !== "failed") {
+    show(job.stage ? t("script.working", { stage: job.stage }) : t("script.waiting"));
+    const response = await fetch(`/jobs/${job.id}?wait=30s&since=${since}`, { headers: language });
+    if (!response.ok) {
+      throw new Error(await errorOf(response));
+    }
+    job = await response.json();
+    since = job.version;
+  }
+  if (job.state === "failed") {
+    throw new Error(job.error ? job.error.error : t("script.failed"));
+  }
+  const response = await fetch(`/jobs/${job.id}/download`, { headers: language });
+  if (!response.ok) {
+    throw new Error(await errorOf(response));
+  }
+  save(await response.blob(), job.result.fileName);
+}
+
+// Filled in by `/go` links.
+const prefilled = new URLSearchParams(location.search).get("storyId");
+if (prefilled) {
+  form.elements.story.value = prefilled;
+}
+
+form.addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const data = new FormData(form);
+  const id = storyId(data.get("story"));
+  if (id === null) {
+    show(t("script.notAStory"), true);
+    return;
+  }
+  const button = form.querySelector("button");
+  button.disabled = true;
+  show(t("script.downloading"));
+  try {
+    const response = await fetch("/generate-epub", {
+      method: "POST",
+      headers: { ...language, "Content-Type": "application/json" },
+      body: JSON.stringify({
+        storyId: id,
+        format: data.get("format"),
+        embedImages: data.has("embedImages"),
+        mergeSplitChapters: data.has("mergeSplitChapters"),
+        statsPage: data.has("statsPage"),
+      }),
+    });
+    if (response.status === 202) {
+      await awaitJob(await response.json());
+    } else if (response.ok) {
+      save(await response.blob(), fileName(response));
+    } else {
+      throw new Error(await errorOf(response));
+    }
+    show(t("script.done"));
+  } catch (error) {
+    show(error.message, true);
+  } finally {
+    button.disabled = false;
+  }
+});